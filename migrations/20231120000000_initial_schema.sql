@@ -0,0 +1,27 @@
+-- Tables that existed before migrations were tracked; IF NOT EXISTS keeps this
+-- safe to apply against databases that were set up by hand.
+
+CREATE TABLE IF NOT EXISTS users (
+    id SERIAL PRIMARY KEY,
+    name VARCHAR(100) NOT NULL,
+    email VARCHAR(255) NOT NULL UNIQUE,
+    password VARCHAR(100) NOT NULL,
+    verified BOOLEAN NOT NULL DEFAULT FALSE,
+    verification_code VARCHAR(255),
+    created_at TIMESTAMP DEFAULT NOW(),
+    updated_at TIMESTAMP DEFAULT NOW()
+);
+
+CREATE TABLE IF NOT EXISTS categories (
+    id SERIAL PRIMARY KEY,
+    name VARCHAR(255) NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS products (
+    id SERIAL PRIMARY KEY,
+    name VARCHAR(255) NOT NULL,
+    description TEXT NOT NULL,
+    price DOUBLE PRECISION NOT NULL,
+    category_name VARCHAR(255) NOT NULL,
+    available BOOLEAN NOT NULL DEFAULT TRUE
+);
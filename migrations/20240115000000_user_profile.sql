@@ -0,0 +1,9 @@
+ALTER TABLE users
+    ADD COLUMN IF NOT EXISTS phone VARCHAR(20),
+    ADD COLUMN IF NOT EXISTS locale VARCHAR(35),
+    ADD COLUMN IF NOT EXISTS currency CHAR(3),
+    ADD COLUMN IF NOT EXISTS marketing_email_consent BOOLEAN NOT NULL DEFAULT FALSE,
+    ADD COLUMN IF NOT EXISTS marketing_sms_consent BOOLEAN NOT NULL DEFAULT FALSE,
+    -- When either consent last changed, as proof of when it was given or withdrawn.
+    ADD COLUMN IF NOT EXISTS marketing_consent_updated_at TIMESTAMPTZ,
+    ADD COLUMN IF NOT EXISTS avatar_key VARCHAR(255);
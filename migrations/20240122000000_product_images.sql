@@ -0,0 +1,17 @@
+CREATE TABLE IF NOT EXISTS product_images (
+    id SERIAL PRIMARY KEY,
+    product_id INTEGER NOT NULL REFERENCES products (id) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    alt_text VARCHAR(255) NOT NULL DEFAULT '',
+    -- Blobs live under `<key_prefix>/original.<original_ext>` and
+    -- `<key_prefix>/<size>.<thumbnail_ext>` for each of `thumbnail_sizes`.
+    key_prefix VARCHAR(255) NOT NULL,
+    original_ext VARCHAR(10) NOT NULL,
+    thumbnail_ext VARCHAR(10) NOT NULL,
+    thumbnail_sizes INTEGER[] NOT NULL,
+    width INTEGER NOT NULL,
+    height INTEGER NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    -- Deferred so a reorder can rewrite every position in one transaction.
+    CONSTRAINT product_images_position_key UNIQUE (product_id, position) DEFERRABLE INITIALLY DEFERRED
+);
@@ -0,0 +1,4 @@
+-- Stable identifier used by bulk imports. Optional so existing rows stay valid.
+ALTER TABLE products ADD COLUMN IF NOT EXISTS sku VARCHAR(64);
+
+CREATE UNIQUE INDEX IF NOT EXISTS products_sku_key ON products (sku);
@@ -0,0 +1,3 @@
+-- Bumped on every write; sent as the ETag so concurrent edits can be detected.
+ALTER TABLE products ADD COLUMN IF NOT EXISTS version INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE categories ADD COLUMN IF NOT EXISTS version INTEGER NOT NULL DEFAULT 1;
@@ -0,0 +1,51 @@
+-- Drafts and unpublished products are only visible to catalog managers. The
+-- publishing scheduler flips the status at `publish_at` and `unpublish_at`.
+ALTER TABLE products
+    ADD COLUMN IF NOT EXISTS status VARCHAR(20) NOT NULL DEFAULT 'published'
+        CHECK (status IN ('draft', 'published', 'unpublished')),
+    ADD COLUMN IF NOT EXISTS publish_at TIMESTAMPTZ,
+    ADD COLUMN IF NOT EXISTS unpublish_at TIMESTAMPTZ;
+
+CREATE INDEX IF NOT EXISTS products_publish_at_idx ON products (publish_at)
+    WHERE publish_at IS NOT NULL;
+CREATE INDEX IF NOT EXISTS products_unpublish_at_idx ON products (unpublish_at)
+    WHERE unpublish_at IS NOT NULL;
+
+-- One row per status change, however it happened. No foreign key, so the
+-- history outlives purged products.
+CREATE TABLE IF NOT EXISTS product_events (
+    id BIGSERIAL PRIMARY KEY,
+    product_id INTEGER NOT NULL,
+    status VARCHAR(20) NOT NULL,
+    previous_status VARCHAR(20),
+    occurred_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS product_events_product_id_idx ON product_events (product_id);
+
+-- A trigger, so imports, batches and the scheduler cannot forget to record
+-- anything. Listeners on the `product_events` channel are told right away.
+CREATE OR REPLACE FUNCTION record_product_status_change() RETURNS trigger AS $$
+DECLARE
+    event_id BIGINT;
+BEGIN
+    IF TG_OP = 'UPDATE' AND NEW.status = OLD.status THEN
+        RETURN NEW;
+    END IF;
+
+    INSERT INTO product_events (product_id, status, previous_status)
+    VALUES (NEW.id, NEW.status, CASE WHEN TG_OP = 'UPDATE' THEN OLD.status END)
+    RETURNING id INTO event_id;
+
+    PERFORM pg_notify(
+        'product_events',
+        json_build_object('id', event_id, 'product_id', NEW.id, 'status', NEW.status)::text
+    );
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS products_status_change ON products;
+CREATE TRIGGER products_status_change
+    AFTER INSERT OR UPDATE OF status ON products
+    FOR EACH ROW EXECUTE FUNCTION record_product_status_change();
@@ -0,0 +1,65 @@
+-- Full before/after snapshots of every change to a product or category.
+-- Writers tag their transaction with `shopping.actor` (e.g. `user:3` or
+-- `api_key:5`) and `shopping.change_reason`; background jobs leave the actor
+-- empty. No foreign keys, so the history outlives purged items.
+CREATE TABLE IF NOT EXISTS catalog_revisions (
+    id BIGSERIAL PRIMARY KEY,
+    entity VARCHAR(20) NOT NULL CHECK (entity IN ('product', 'category')),
+    entity_id INTEGER NOT NULL,
+    action VARCHAR(20) NOT NULL,
+    actor VARCHAR(64),
+    reason VARCHAR(255),
+    before JSONB,
+    after JSONB,
+    changed_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS catalog_revisions_entity_idx ON catalog_revisions (entity, entity_id, id);
+CREATE INDEX IF NOT EXISTS catalog_revisions_actor_idx ON catalog_revisions (actor, id);
+
+CREATE OR REPLACE FUNCTION record_catalog_revision() RETURNS trigger AS $$
+DECLARE
+    old_row JSONB := CASE WHEN TG_OP <> 'INSERT' THEN to_jsonb(OLD) END;
+    new_row JSONB := CASE WHEN TG_OP <> 'DELETE' THEN to_jsonb(NEW) END;
+    change VARCHAR(20);
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        change := 'created';
+    ELSIF TG_OP = 'DELETE' THEN
+        change := 'purged';
+    ELSIF (old_row - 'version') = (new_row - 'version') THEN
+        RETURN NULL;
+    ELSIF OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL THEN
+        change := 'deleted';
+    ELSIF OLD.archived_at IS NULL AND NEW.archived_at IS NOT NULL THEN
+        change := 'archived';
+    ELSIF (OLD.deleted_at IS NOT NULL AND NEW.deleted_at IS NULL)
+        OR (OLD.archived_at IS NOT NULL AND NEW.archived_at IS NULL) THEN
+        change := 'restored';
+    ELSE
+        change := 'updated';
+    END IF;
+
+    INSERT INTO catalog_revisions (entity, entity_id, action, actor, reason, before, after)
+    VALUES (
+        TG_ARGV[0],
+        (COALESCE(new_row, old_row)->>'id')::INTEGER,
+        change,
+        NULLIF(current_setting('shopping.actor', true), ''),
+        NULLIF(current_setting('shopping.change_reason', true), ''),
+        old_row,
+        new_row
+    );
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS products_revision ON products;
+CREATE TRIGGER products_revision
+    AFTER INSERT OR UPDATE OR DELETE ON products
+    FOR EACH ROW EXECUTE FUNCTION record_catalog_revision('product');
+
+DROP TRIGGER IF EXISTS categories_revision ON categories;
+CREATE TRIGGER categories_revision
+    AFTER INSERT OR UPDATE OR DELETE ON categories
+    FOR EACH ROW EXECUTE FUNCTION record_catalog_revision('category');
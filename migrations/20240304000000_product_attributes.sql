@@ -0,0 +1,19 @@
+-- Typed attributes defined per category. Products keep their values in
+-- `attributes`, keyed by `category_attributes.key` and checked on write.
+CREATE TABLE IF NOT EXISTS category_attributes (
+    id SERIAL PRIMARY KEY,
+    category_id INTEGER NOT NULL REFERENCES categories (id) ON DELETE CASCADE,
+    key VARCHAR(64) NOT NULL,
+    label VARCHAR(255) NOT NULL,
+    kind VARCHAR(20) NOT NULL CHECK (kind IN ('enum', 'number', 'bool', 'text')),
+    -- The allowed values of an `enum` attribute; empty for the other kinds.
+    options TEXT[] NOT NULL DEFAULT '{}',
+    unit VARCHAR(20),
+    filterable BOOLEAN NOT NULL DEFAULT TRUE,
+    position INTEGER NOT NULL DEFAULT 0,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (category_id, key)
+);
+
+ALTER TABLE products ADD COLUMN IF NOT EXISTS attributes JSONB NOT NULL DEFAULT '{}';
+CREATE INDEX IF NOT EXISTS products_attributes_idx ON products USING GIN (attributes);
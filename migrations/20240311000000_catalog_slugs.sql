@@ -0,0 +1,113 @@
+-- Human-readable addresses for products and categories. A slug is generated
+-- from the name when none is given and kept when the name changes; when it is
+-- edited, the old one is remembered so its URLs can redirect.
+ALTER TABLE products ADD COLUMN IF NOT EXISTS slug VARCHAR(255);
+ALTER TABLE products ADD COLUMN IF NOT EXISTS meta_title VARCHAR(255);
+ALTER TABLE products ADD COLUMN IF NOT EXISTS meta_description TEXT;
+ALTER TABLE categories ADD COLUMN IF NOT EXISTS slug VARCHAR(255);
+ALTER TABLE categories ADD COLUMN IF NOT EXISTS meta_title VARCHAR(255);
+ALTER TABLE categories ADD COLUMN IF NOT EXISTS meta_description TEXT;
+
+CREATE TABLE IF NOT EXISTS slug_redirects (
+    entity VARCHAR(20) NOT NULL CHECK (entity IN ('product', 'category')),
+    old_slug VARCHAR(255) NOT NULL,
+    entity_id INTEGER NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (entity, old_slug)
+);
+
+-- Lowercase ASCII letters and digits separated by single dashes.
+CREATE OR REPLACE FUNCTION slugify(value TEXT) RETURNS TEXT AS $$
+    SELECT COALESCE(
+        NULLIF(trim(BOTH '-' FROM left(trim(BOTH '-' FROM regexp_replace(lower(value), '[^a-z0-9]+', '-', 'g')), 80)), ''),
+        'item'
+    );
+$$ LANGUAGE SQL IMMUTABLE;
+
+-- Fills in a missing slug from the name, adding `-2`, `-3`, ... until it is
+-- neither taken nor still redirecting somewhere.
+CREATE OR REPLACE FUNCTION assign_slug() RETURNS trigger AS $$
+DECLARE
+    base TEXT;
+    candidate TEXT;
+    n INTEGER := 1;
+    free BOOLEAN;
+BEGIN
+    IF NEW.slug IS NOT NULL THEN
+        RETURN NEW;
+    END IF;
+    base := slugify(NEW.name);
+    candidate := base;
+    LOOP
+        EXECUTE format(
+            'SELECT NOT EXISTS (SELECT 1 FROM %I WHERE slug = $1 AND id <> $3)
+                AND NOT EXISTS (SELECT 1 FROM slug_redirects WHERE entity = $2 AND old_slug = $1)',
+            TG_TABLE_NAME
+        ) INTO free USING candidate, TG_ARGV[0], NEW.id;
+        EXIT WHEN free;
+        n := n + 1;
+        candidate := base || '-' || n;
+    END LOOP;
+    NEW.slug := candidate;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS products_assign_slug ON products;
+CREATE TRIGGER products_assign_slug
+    BEFORE INSERT OR UPDATE OF slug ON products
+    FOR EACH ROW EXECUTE FUNCTION assign_slug('product');
+
+DROP TRIGGER IF EXISTS categories_assign_slug ON categories;
+CREATE TRIGGER categories_assign_slug
+    BEFORE INSERT OR UPDATE OF slug ON categories
+    FOR EACH ROW EXECUTE FUNCTION assign_slug('category');
+
+-- Existing rows get their slugs from `assign_slug`, one at a time in id order,
+-- so names that slugify alike ("Foo", "foo!", "Foo 2") cannot end up with the
+-- same suffixed slug. No revision is recorded for them.
+ALTER TABLE products DISABLE TRIGGER products_revision;
+ALTER TABLE categories DISABLE TRIGGER categories_revision;
+DO $$
+DECLARE
+    row_id INTEGER;
+BEGIN
+    FOR row_id IN SELECT id FROM products WHERE slug IS NULL ORDER BY id LOOP
+        UPDATE products SET slug = NULL WHERE id = row_id;
+    END LOOP;
+    FOR row_id IN SELECT id FROM categories WHERE slug IS NULL ORDER BY id LOOP
+        UPDATE categories SET slug = NULL WHERE id = row_id;
+    END LOOP;
+END;
+$$;
+ALTER TABLE products ENABLE TRIGGER products_revision;
+ALTER TABLE categories ENABLE TRIGGER categories_revision;
+
+ALTER TABLE products ALTER COLUMN slug SET NOT NULL;
+ALTER TABLE categories ALTER COLUMN slug SET NOT NULL;
+CREATE UNIQUE INDEX IF NOT EXISTS products_slug_idx ON products (slug);
+CREATE UNIQUE INDEX IF NOT EXISTS categories_slug_idx ON categories (slug);
+
+-- Remembers a replaced slug, and forgets a redirect once its slug is in use again.
+CREATE OR REPLACE FUNCTION record_slug_change() RETURNS trigger AS $$
+BEGIN
+    IF TG_OP = 'UPDATE' AND NEW.slug IS DISTINCT FROM OLD.slug THEN
+        INSERT INTO slug_redirects (entity, old_slug, entity_id)
+        VALUES (TG_ARGV[0], OLD.slug, NEW.id)
+        ON CONFLICT (entity, old_slug) DO UPDATE
+            SET entity_id = EXCLUDED.entity_id, created_at = NOW();
+    END IF;
+    DELETE FROM slug_redirects WHERE entity = TG_ARGV[0] AND old_slug = NEW.slug;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS products_slug_change ON products;
+CREATE TRIGGER products_slug_change
+    AFTER INSERT OR UPDATE OF slug ON products
+    FOR EACH ROW EXECUTE FUNCTION record_slug_change('product');
+
+DROP TRIGGER IF EXISTS categories_slug_change ON categories;
+CREATE TRIGGER categories_slug_change
+    AFTER INSERT OR UPDATE OF slug ON categories
+    FOR EACH ROW EXECUTE FUNCTION record_slug_change('category');
@@ -0,0 +1,25 @@
+-- Curated links between products, in display order per kind.
+CREATE TABLE IF NOT EXISTS product_relations (
+    product_id INTEGER NOT NULL REFERENCES products (id) ON DELETE CASCADE,
+    kind VARCHAR(20) NOT NULL CHECK (kind IN ('related', 'upsell', 'cross_sell', 'accessory')),
+    related_id INTEGER NOT NULL REFERENCES products (id) ON DELETE CASCADE,
+    position INTEGER NOT NULL DEFAULT 0,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (product_id, kind, related_id),
+    CHECK (product_id <> related_id)
+);
+
+CREATE INDEX IF NOT EXISTS product_relations_related_idx ON product_relations (related_id);
+
+-- A product with items is a bundle, sold at its own price. Components cannot
+-- be purged while a bundle still contains them.
+CREATE TABLE IF NOT EXISTS bundle_items (
+    bundle_id INTEGER NOT NULL REFERENCES products (id) ON DELETE CASCADE,
+    component_id INTEGER NOT NULL REFERENCES products (id) ON DELETE RESTRICT,
+    quantity INTEGER NOT NULL CHECK (quantity > 0),
+    position INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (bundle_id, component_id),
+    CHECK (bundle_id <> component_id)
+);
+
+CREATE INDEX IF NOT EXISTS bundle_items_component_idx ON bundle_items (component_id);
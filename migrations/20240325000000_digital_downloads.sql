@@ -0,0 +1,34 @@
+ALTER TABLE products ADD COLUMN IF NOT EXISTS kind VARCHAR(20) NOT NULL DEFAULT 'physical'
+    CHECK (kind IN ('physical', 'digital'));
+
+-- Files delivered with a digital product. They live under the blob store's
+-- private prefix, which the public media route refuses to serve.
+CREATE TABLE IF NOT EXISTS product_files (
+    id SERIAL PRIMARY KEY,
+    product_id INTEGER NOT NULL REFERENCES products (id) ON DELETE CASCADE,
+    blob_key VARCHAR(255) NOT NULL UNIQUE,
+    file_name VARCHAR(255) NOT NULL,
+    content_type VARCHAR(255) NOT NULL,
+    size_bytes BIGINT NOT NULL,
+    sha256 CHAR(64) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS product_files_product_idx ON product_files (product_id);
+
+-- The right to download a digital product's files, created once its order is
+-- paid. Products with grants are kept by the purge job so buyers keep access.
+CREATE TABLE IF NOT EXISTS download_grants (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    product_id INTEGER NOT NULL REFERENCES products (id) ON DELETE RESTRICT,
+    order_reference VARCHAR(255) NOT NULL,
+    max_downloads INTEGER NOT NULL CHECK (max_downloads > 0),
+    downloads_used INTEGER NOT NULL DEFAULT 0,
+    expires_at TIMESTAMPTZ,
+    revoked_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (order_reference, product_id)
+);
+
+CREATE INDEX IF NOT EXISTS download_grants_user_idx ON download_grants (user_id, id);
@@ -2,13 +2,32 @@ pub mod v1 {
     pub mod products{
         pub mod products_routes;
         pub mod products_handler;
+        pub mod product_admin_routes;
+        pub mod product_archive_handler;
+        pub mod product_files_handler;
+        pub mod product_images_handler;
+        pub mod product_import_handler;
+        pub mod product_publishing;
+        pub mod product_relations_handler;
+        pub mod product_revisions_handler;
+        pub mod thumbnails;
         mod products_model;
     }
     pub mod category{
         pub mod category_routes;
         pub mod category_handler;
+        pub mod category_admin_routes;
+        pub mod category_archive_handler;
+        pub mod category_attributes_handler;
+        pub mod category_revisions_handler;
         mod category_model;
     }
+    pub mod attributes;
+    pub mod batch;
+    pub mod lifecycle;
+    pub mod precondition;
+    pub mod revisions;
+    pub mod slugs;
     pub mod v_route;
 }
 
@@ -16,9 +35,44 @@ pub mod login {
     pub mod email;
     pub mod handler;
     pub mod model;
+    pub mod oidc;
     pub mod response;
     pub mod login_route;
+    pub mod throttle;
+    pub mod two_factor;
 }
 
+pub mod account {
+    pub mod account_route;
+    pub mod handler;
+    pub mod profile;
+}
+
+pub mod addresses {
+    pub mod addresses_route;
+    pub mod handler;
+    pub mod model;
+}
+
+pub mod api_keys {
+    pub mod api_keys_route;
+    pub mod handler;
+    pub mod model;
+}
+
+pub mod downloads {
+    pub mod downloads_route;
+    pub mod handler;
+    pub mod model;
+}
+
+pub mod health {
+    pub mod handler;
+    pub mod health_route;
+}
+
+pub mod client_ip;
 pub mod config;
-pub mod jwt_auth;
\ No newline at end of file
+pub mod jwt_auth;
+pub mod jwt_keys;
+pub mod rate_limit;
\ No newline at end of file
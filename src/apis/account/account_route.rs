@@ -0,0 +1,31 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::DefaultBodyLimit,
+    middleware,
+    routing::{delete, get, post, put},
+    Router,
+};
+
+use crate::apis::{
+    account::handler::{cancel_deletion_handler, delete_account_handler, export_handler},
+    account::profile::{delete_avatar_handler, update_profile_handler, upload_avatar_handler},
+    jwt_auth::auth,
+};
+
+use crate::AppState;
+
+pub fn account_router(app_state: Arc<AppState>) -> Router {
+    Router::new()
+        .route("/me", delete(delete_account_handler).patch(update_profile_handler))
+        .route(
+            "/me/avatar",
+            put(upload_avatar_handler)
+                .delete(delete_avatar_handler)
+                .layer(DefaultBodyLimit::max(app_state.config.upload_max_bytes)),
+        )
+        .route("/me/export", get(export_handler))
+        .route("/me/deletion/cancel", post(cancel_deletion_handler))
+        .route_layer(middleware::from_fn_with_state(app_state.clone(), auth))
+        .with_state(app_state)
+}
@@ -0,0 +1,671 @@
+use std::io::{Cursor, Write};
+use std::sync::Arc;
+
+use axum::{
+    extract::{Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::PgPool;
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+use crate::apis::addresses::model::Address;
+use crate::apis::client_ip::ClientIp;
+use crate::apis::login::{
+    email::send_email,
+    handler::{filter_user_record, notify_lockout, password_matches, too_many_attempts},
+    model::User,
+    response::ErrorResponse,
+    two_factor::verify_second_factor,
+};
+use crate::blob_store::{self, BlobStore};
+
+use crate::AppState;
+
+type HandlerError = (StatusCode, Json<ErrorResponse>);
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Json,
+    Zip,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExportQuery {
+    #[serde(default)]
+    pub format: ExportFormat,
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+struct IdentityExport {
+    provider: String,
+    subject: String,
+    email: Option<String>,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+struct ApiKeyExport {
+    name: String,
+    prefix: String,
+    scopes: Vec<String>,
+    created_at: DateTime<Utc>,
+    revoked_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+struct DownloadExport {
+    order_reference: String,
+    product_id: i32,
+    product_name: Option<String>,
+    max_downloads: i32,
+    downloads_used: i32,
+    expires_at: Option<DateTime<Utc>>,
+    revoked_at: Option<DateTime<Utc>>,
+    created_at: DateTime<Utc>,
+}
+
+/// Proof that the caller is the account holder and not someone with a stolen
+/// session: the current password, or a TOTP or backup code when two-factor
+/// authentication is on.
+#[derive(Deserialize)]
+pub struct DeleteAccountSchema {
+    #[serde(default)]
+    pub password: Option<String>,
+    #[serde(default)]
+    pub code: Option<String>,
+}
+
+/// Everything we store about the caller, as one JSON document or as a zip
+/// with one file per section.
+///
+/// Session tokens are not listed: they are stateless and never stored.
+pub async fn export_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Query(query): Query<ExportQuery>,
+) -> Result<Response, HandlerError> {
+    let sections = collect_export(&data.db, &user).await.map_err(database_error)?;
+    tracing::info!(user_id = user.id, "exported account data");
+
+    match query.format {
+        ExportFormat::Json => {
+            let document: serde_json::Map<String, serde_json::Value> = sections
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value))
+                .collect();
+            let disposition = format!("attachment; filename=\"account-export-{}.json\"", user.id);
+            Ok(([(header::CONTENT_DISPOSITION, disposition)], Json(document)).into_response())
+        }
+        ExportFormat::Zip => {
+            let mut files = Vec::new();
+            if let Some(key) = &user.avatar_key {
+                let avatar = data.blobs.get(key).await.map_err(|e| {
+                    tracing::error!(error = %e, key, "could not read blob");
+                    fail(StatusCode::INTERNAL_SERVER_ERROR, "Could not read your avatar")
+                })?;
+                if let Some(avatar) = avatar {
+                    let name = key.rsplit_once('.').map_or("bin", |(_, ext)| ext);
+                    files.push((format!("avatar.{}", name), avatar.to_vec()));
+                }
+            }
+
+            let archive = zip_sections(&sections, &files).map_err(|e| {
+                fail(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    &format!("Could not build the export archive: {}", e),
+                )
+            })?;
+            let disposition = format!("attachment; filename=\"account-export-{}.zip\"", user.id);
+            Ok((
+                [
+                    (header::CONTENT_TYPE, "application/zip".to_string()),
+                    (header::CONTENT_DISPOSITION, disposition),
+                ],
+                archive,
+            )
+                .into_response())
+        }
+    }
+}
+
+/// Schedules the account for anonymization once the grace period is over.
+/// Until then the user can still sign in and cancel. Failed confirmations count
+/// towards the login lockout, so the endpoint cannot be used to guess passwords.
+pub async fn delete_account_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    ClientIp(ip): ClientIp,
+    Json(body): Json<DeleteAccountSchema>,
+) -> Result<Response, Response> {
+    if body.password.is_none() && body.code.is_none() {
+        return Err(fail(
+            StatusCode::BAD_REQUEST,
+            "Confirm with your current password or an authentication code",
+        )
+        .into_response());
+    }
+    if let Some(wait) = data
+        .login_throttle
+        .retry_after(ip, &user.email)
+        .await
+        .map_err(|e| database_error(e).into_response())?
+    {
+        return Err(too_many_attempts(wait));
+    }
+
+    let mut confirmed = body
+        .password
+        .as_deref()
+        .is_some_and(|password| password_matches(&user, password));
+    if let (false, true, Some(code)) = (confirmed, user.totp_enabled, body.code.as_deref()) {
+        confirmed = verify_second_factor(&data, &user, code)
+            .await
+            .map_err(IntoResponse::into_response)?;
+    }
+    if !confirmed {
+        let locked = data
+            .login_throttle
+            .record_failure(ip, &user.email)
+            .await
+            .map_err(|e| database_error(e).into_response())?;
+        if locked {
+            tracing::warn!(user_id = user.id, %ip, "account locked after repeated failed deletion confirmations");
+            notify_lockout(data.clone(), user.email.clone());
+        }
+        return Err(fail(StatusCode::FORBIDDEN, "Incorrect password or authentication code").into_response());
+    }
+    data.login_throttle
+        .record_success(&user.email)
+        .await
+        .map_err(|e| database_error(e).into_response())?;
+
+    let grace_period = chrono::Duration::from_std(data.config.account_deletion_grace_period)
+        .unwrap_or_else(|_| chrono::Duration::days(14));
+
+    let scheduled_at: DateTime<Utc> = sqlx::query_scalar(
+        "UPDATE users SET deletion_scheduled_at = COALESCE(deletion_scheduled_at, $2), updated_at = NOW() WHERE id = $1 RETURNING deletion_scheduled_at",
+    )
+    .bind(user.id)
+    .bind(Utc::now() + grace_period)
+    .fetch_one(&data.db)
+    .await
+    .map_err(|e| database_error(e).into_response())?;
+
+    if user.deletion_scheduled_at.is_none() {
+        tracing::info!(user_id = user.id, %scheduled_at, "account deletion requested");
+        notify_deletion(data.clone(), user.email.clone(), scheduled_at);
+    }
+
+    let json_response = json!({
+        "status": "success",
+        "message": "Your account will be deleted unless you cancel before the scheduled time",
+        "deletion_scheduled_at": scheduled_at,
+    });
+
+    Ok((StatusCode::ACCEPTED, Json(json_response)).into_response())
+}
+
+pub async fn cancel_deletion_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, HandlerError> {
+    if user.deletion_scheduled_at.is_none() {
+        return Err(fail(StatusCode::CONFLICT, "Your account is not scheduled for deletion"));
+    }
+
+    sqlx::query("UPDATE users SET deletion_scheduled_at = NULL, updated_at = NOW() WHERE id = $1")
+        .bind(user.id)
+        .execute(&data.db)
+        .await
+        .map_err(database_error)?;
+
+    tracing::info!(user_id = user.id, "account deletion cancelled");
+    Ok(Json(json!({
+        "status": "success",
+        "message": "Account deletion cancelled",
+    })))
+}
+
+/// Anonymizes every account whose grace period has run out. The `users` row
+/// is kept, stripped of personal data, so records pointing at it stay intact;
+/// addresses, linked identities, backup codes, throttle entries and avatars
+/// are removed, and download grants are revoked.
+pub async fn purge_due_accounts(db: &PgPool, blobs: &dyn BlobStore) -> Result<u64, sqlx::Error> {
+    let mut tx = db.begin().await?;
+
+    let due: Vec<(i32, String, Option<String>)> = sqlx::query_as(
+        "SELECT id, email, avatar_key FROM users WHERE deletion_scheduled_at <= NOW() AND deleted_at IS NULL FOR UPDATE SKIP LOCKED",
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    if due.is_empty() {
+        return Ok(0);
+    }
+
+    let ids: Vec<i32> = due.iter().map(|(id, _, _)| *id).collect();
+    let throttle_keys: Vec<String> = due
+        .iter()
+        .map(|(_, email, _)| format!("email:{}", email.to_ascii_lowercase()))
+        .collect();
+
+    sqlx::query("DELETE FROM user_identities WHERE user_id = ANY($1)")
+        .bind(&ids)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("DELETE FROM addresses WHERE user_id = ANY($1)")
+        .bind(&ids)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("DELETE FROM user_backup_codes WHERE user_id = ANY($1)")
+        .bind(&ids)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("DELETE FROM login_attempts WHERE key = ANY($1)")
+        .bind(&throttle_keys)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("UPDATE api_keys SET created_by = NULL WHERE created_by = ANY($1)")
+        .bind(&ids)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("UPDATE download_grants SET revoked_at = COALESCE(revoked_at, NOW()) WHERE user_id = ANY($1)")
+        .bind(&ids)
+        .execute(&mut *tx)
+        .await?;
+
+    // `!` is not a valid password hash, so no password can ever match it.
+    let anonymized = sqlx::query(
+        r#"UPDATE users SET
+            name = 'Deleted user',
+            email = 'deleted-' || id || '@invalid',
+            password = '!',
+            verified = FALSE,
+            verification_code = NULL,
+            role = 'user',
+            totp_secret = NULL,
+            totp_enabled = FALSE,
+            totp_last_used_step = NULL,
+            phone = NULL,
+            locale = NULL,
+            currency = NULL,
+            marketing_email_consent = FALSE,
+            marketing_sms_consent = FALSE,
+            marketing_consent_updated_at = NULL,
+            avatar_key = NULL,
+            deletion_scheduled_at = NULL,
+            deleted_at = NOW(),
+            updated_at = NOW()
+        WHERE id = ANY($1)"#,
+    )
+    .bind(&ids)
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+
+    tx.commit().await?;
+
+    // Only once the rows no longer point at them; a leftover file is harmless.
+    for (id, _, avatar_key) in due {
+        if let Some(key) = avatar_key {
+            if let Err(e) = blobs.delete(&key).await {
+                tracing::warn!(error = %e, key, "could not delete blob");
+            }
+        }
+        tracing::info!(user_id = id, "account anonymized");
+    }
+    Ok(anonymized)
+}
+
+async fn collect_export(
+    db: &PgPool,
+    user: &User,
+) -> Result<Vec<(&'static str, serde_json::Value)>, sqlx::Error> {
+    let identities: Vec<IdentityExport> = sqlx::query_as(
+        "SELECT provider, subject, email, created_at FROM user_identities WHERE user_id = $1 ORDER BY created_at",
+    )
+    .bind(user.id)
+    .fetch_all(db)
+    .await?;
+
+    let addresses: Vec<Address> =
+        sqlx::query_as("SELECT * FROM addresses WHERE user_id = $1 ORDER BY created_at")
+            .bind(user.id)
+            .fetch_all(db)
+            .await?;
+
+    let backup_codes: Vec<(Option<DateTime<Utc>>,)> =
+        sqlx::query_as("SELECT used_at FROM user_backup_codes WHERE user_id = $1 ORDER BY id")
+            .bind(user.id)
+            .fetch_all(db)
+            .await?;
+
+    let api_keys: Vec<ApiKeyExport> = sqlx::query_as(
+        "SELECT name, prefix, scopes, created_at, revoked_at FROM api_keys WHERE created_by = $1 ORDER BY created_at",
+    )
+    .bind(user.id)
+    .fetch_all(db)
+    .await?;
+
+    let downloads: Vec<DownloadExport> = sqlx::query_as(
+        r#"SELECT g.order_reference, g.product_id, p.name AS product_name, g.max_downloads,
+            g.downloads_used, g.expires_at, g.revoked_at, g.created_at
+        FROM download_grants g LEFT JOIN products p ON p.id = g.product_id
+        WHERE g.user_id = $1 ORDER BY g.created_at"#,
+    )
+    .bind(user.id)
+    .fetch_all(db)
+    .await?;
+
+    let profile = filter_user_record(user);
+    Ok(vec![
+        (
+            "export",
+            json!({
+                "exported_at": Utc::now(),
+                "user_id": user.id,
+            }),
+        ),
+        (
+            "profile",
+            json!({
+                "id": user.id,
+                "name": profile.name,
+                "email": profile.email,
+                "role": user.role,
+                "verified": profile.verified,
+                "created_at": profile.createdAt,
+                "updated_at": profile.updatedAt,
+                "phone": user.phone,
+                "locale": user.locale,
+                "currency": user.currency,
+                "marketing_email_consent": user.marketing_email_consent,
+                "marketing_sms_consent": user.marketing_sms_consent,
+                "marketing_consent_updated_at": user.marketing_consent_updated_at,
+                "avatar_url": user.avatar_key.as_deref().map(blob_store::url),
+                "deletion_scheduled_at": user.deletion_scheduled_at,
+            }),
+        ),
+        ("addresses", json!(addresses)),
+        ("identities", json!(identities)),
+        (
+            "two_factor",
+            json!({
+                "enabled": user.totp_enabled,
+                "backup_codes_remaining": backup_codes.iter().filter(|(used_at,)| used_at.is_none()).count(),
+                "backup_codes_used_at": backup_codes.iter().filter_map(|(used_at,)| *used_at).collect::<Vec<_>>(),
+            }),
+        ),
+        ("api_keys_created", json!(api_keys)),
+        ("downloads", json!(downloads)),
+    ])
+}
+
+/// One JSON file per section, plus `files` stored as they are.
+fn zip_sections(
+    sections: &[(&str, serde_json::Value)],
+    files: &[(String, Vec<u8>)],
+) -> zip::result::ZipResult<Vec<u8>> {
+    let mut archive = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    for (name, value) in sections {
+        archive.start_file(format!("{}.json", name), options)?;
+        let contents = serde_json::to_vec_pretty(value).map_err(std::io::Error::from)?;
+        archive.write_all(&contents)?;
+    }
+    for (name, contents) in files {
+        archive.start_file(name.as_str(), options)?;
+        archive.write_all(contents)?;
+    }
+
+    Ok(archive.finish()?.into_inner())
+}
+
+fn notify_deletion(data: Arc<AppState>, email: String, scheduled_at: DateTime<Utc>) {
+    let body = format!(
+        "We received a request to delete your account. It will be deleted on {}. If you did not ask for this, sign in and cancel the deletion before then.",
+        scheduled_at.format("%Y-%m-%d %H:%M UTC")
+    );
+
+    tokio::task::spawn_blocking(move || {
+        let sent = send_email(&data.config, email, "Your account is scheduled for deletion", body).is_ok();
+        data.metrics.record_email(sent);
+    });
+}
+
+fn fail(status: StatusCode, message: &str) -> HandlerError {
+    let error_response = ErrorResponse {
+        status: "fail",
+        message: message.to_string(),
+    };
+    (status, Json(error_response))
+}
+
+fn database_error(e: sqlx::Error) -> HandlerError {
+    let error_response = ErrorResponse {
+        status: "error",
+        message: format!("Database error: {}", e),
+    };
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+    use crate::test_support::{example_config, json_response, ScratchDb};
+
+    const IP: &str = "203.0.113.7";
+
+    async fn load(db: &ScratchDb, id: i32) -> User {
+        sqlx::query_as("SELECT * FROM users WHERE id = $1")
+            .bind(id)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap()
+    }
+
+    fn confirmation(password: Option<&str>) -> Json<DeleteAccountSchema> {
+        Json(DeleteAccountSchema {
+            password: password.map(str::to_string),
+            code: None,
+        })
+    }
+
+    #[test]
+    fn zips_one_file_per_section() {
+        let sections = [("profile", json!({"name": "Ada"})), ("addresses", json!([]))];
+        let files = [("avatar.png".to_string(), b"png".to_vec())];
+
+        let archive = zip_sections(&sections, &files).unwrap();
+
+        let mut archive = zip::ZipArchive::new(Cursor::new(archive)).unwrap();
+        let mut names: Vec<_> = archive.file_names().map(str::to_string).collect();
+        names.sort();
+        assert_eq!(names, ["addresses.json", "avatar.png", "profile.json"]);
+        let mut profile = String::new();
+        archive.by_name("profile.json").unwrap().read_to_string(&mut profile).unwrap();
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&profile).unwrap(), json!({"name": "Ada"}));
+    }
+
+    #[tokio::test]
+    async fn schedules_deletion_once_confirmed_and_can_cancel_it() {
+        let Some(db) = ScratchDb::migrated().await else {
+            return;
+        };
+        let mut config = example_config();
+        config.smtp_host = "127.0.0.1".to_string();
+        config.smtp_port = 9;
+        let data = db.state(config);
+        let id = db.user("ada@example.com", "correct horse", "user").await;
+        let delete = |password: Option<&'static str>| {
+            let data = data.clone();
+            let db = &db;
+            async move {
+                let response = delete_account_handler(
+                    State(data),
+                    Extension(load(db, id).await),
+                    ClientIp(IP.parse().unwrap()),
+                    confirmation(password),
+                )
+                .await
+                .unwrap_or_else(|response| response);
+                json_response(response).await
+            }
+        };
+
+        assert_eq!(delete(None).await.0, StatusCode::BAD_REQUEST);
+        assert_eq!(delete(Some("wrong")).await.0, StatusCode::FORBIDDEN);
+        assert_eq!(delete(Some("correct horse")).await.0, StatusCode::TOO_MANY_REQUESTS, "failures count towards the login backoff");
+        assert_eq!(load(&db, id).await.deletion_scheduled_at, None);
+        data.login_throttle.record_success("ada@example.com").await.unwrap();
+
+        let (status, body) = delete(Some("correct horse")).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        let scheduled_at = load(&db, id).await.deletion_scheduled_at.unwrap();
+        let grace_period = chrono::Duration::from_std(data.config.account_deletion_grace_period).unwrap();
+        assert!((scheduled_at - Utc::now() - grace_period).num_seconds().abs() < 60);
+        assert_eq!(body["deletion_scheduled_at"], json!(scheduled_at));
+
+        let (_, body) = delete(Some("correct horse")).await;
+        assert_eq!(body["deletion_scheduled_at"], json!(scheduled_at), "asking again keeps the date");
+
+        cancel_deletion_handler(State(data.clone()), Extension(load(&db, id).await))
+            .await
+            .map_err(|(status, _)| status)
+            .unwrap();
+        assert_eq!(load(&db, id).await.deletion_scheduled_at, None);
+        let Err((status, _)) = cancel_deletion_handler(State(data.clone()), Extension(load(&db, id).await)).await else {
+            panic!("cancelled a deletion that was not scheduled");
+        };
+        assert_eq!(status, StatusCode::CONFLICT);
+        db.drop().await;
+    }
+
+    #[tokio::test]
+    async fn anonymizes_only_accounts_whose_grace_period_is_over() {
+        let Some(db) = ScratchDb::migrated().await else {
+            return;
+        };
+        let data = db.state(example_config());
+        let due = db.user("ada@example.com", "correct horse", "admin").await;
+        let later = db.user("grace@example.com", "correct horse", "user").await;
+        sqlx::query(
+            r#"UPDATE users SET phone = '+15550100199', marketing_email_consent = TRUE,
+                deletion_scheduled_at = CASE WHEN id = $1 THEN NOW() - interval '1 minute' ELSE NOW() + interval '1 day' END"#,
+        )
+        .bind(due)
+        .execute(&db.pool)
+        .await
+        .unwrap();
+        for id in [due, later] {
+            sqlx::query("INSERT INTO addresses (user_id, recipient_name, line1, city, country) VALUES ($1, 'Ada', '1 Main St', 'London', 'GB')")
+                .bind(id)
+                .execute(&db.pool)
+                .await
+                .unwrap();
+            sqlx::query("INSERT INTO user_backup_codes (user_id, code_hash) VALUES ($1, 'hash')")
+                .bind(id)
+                .execute(&db.pool)
+                .await
+                .unwrap();
+        }
+        sqlx::query("INSERT INTO login_attempts (key, failures, window_started_at, last_failure_at) VALUES ('email:ada@example.com', 1, NOW(), NOW())")
+            .execute(&db.pool)
+            .await
+            .unwrap();
+
+        assert_eq!(purge_due_accounts(&db.pool, data.blobs.as_ref()).await.unwrap(), 1);
+        assert_eq!(purge_due_accounts(&db.pool, data.blobs.as_ref()).await.unwrap(), 0);
+
+        let user = load(&db, due).await;
+        assert_eq!(user.name, "Deleted user");
+        assert_eq!(user.email, format!("deleted-{}@invalid", due));
+        assert_eq!(user.role, "user");
+        assert_eq!(user.phone, None);
+        assert!(!user.marketing_email_consent);
+        assert!(user.deleted_at.is_some());
+        assert!(!password_matches(&user, "correct horse"));
+        let counts: (i64, i64, i64) = sqlx::query_as(
+            r#"SELECT (SELECT COUNT(*) FROM addresses WHERE user_id = $1),
+                (SELECT COUNT(*) FROM user_backup_codes WHERE user_id = $1),
+                (SELECT COUNT(*) FROM login_attempts)"#,
+        )
+        .bind(due)
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+        assert_eq!(counts, (0, 0, 0));
+
+        let kept = load(&db, later).await;
+        assert_eq!(kept.email, "grace@example.com");
+        assert!(password_matches(&kept, "correct horse"));
+        let addresses: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM addresses WHERE user_id = $1")
+            .bind(later)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(addresses, 1);
+        db.drop().await;
+    }
+
+    #[tokio::test]
+    async fn exports_and_revokes_download_grants() {
+        let Some(db) = ScratchDb::migrated().await else {
+            return;
+        };
+        let data = db.state(example_config());
+        let id = db.user("buyer@example.com", "password", "user").await;
+        sqlx::query("INSERT INTO categories (name) VALUES ('Books')")
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        sqlx::query(
+            r#"WITH product AS (
+                INSERT INTO products (name, description, price, category_name, kind)
+                VALUES ('Guide', '', 1, 'Books', 'digital') RETURNING id
+            )
+            INSERT INTO download_grants (user_id, product_id, order_reference, max_downloads)
+            SELECT $1, id, 'order-1', 3 FROM product"#,
+        )
+        .bind(id)
+        .execute(&db.pool)
+        .await
+        .unwrap();
+        let user = || {
+            sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
+                .bind(id)
+                .fetch_one(&db.pool)
+        };
+
+        let sections = collect_export(&db.pool, &user().await.unwrap()).await.unwrap();
+        let (_, downloads) = sections.iter().find(|(name, _)| *name == "downloads").unwrap();
+        assert_eq!(downloads[0]["order_reference"], "order-1");
+        assert_eq!(downloads[0]["product_name"], "Guide");
+        assert_eq!(downloads[0]["revoked_at"], serde_json::Value::Null);
+
+        sqlx::query("UPDATE users SET deletion_scheduled_at = NOW() WHERE id = $1")
+            .bind(id)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(purge_due_accounts(&db.pool, data.blobs.as_ref()).await.unwrap(), 1);
+
+        let revoked: bool = sqlx::query_scalar("SELECT revoked_at IS NOT NULL FROM download_grants WHERE user_id = $1")
+            .bind(id)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert!(revoked);
+        assert_eq!(user().await.unwrap().email, format!("deleted-{}@invalid", id));
+        db.drop().await;
+    }
+}
@@ -0,0 +1,395 @@
+use std::sync::{Arc, OnceLock};
+
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use regex::Regex;
+use uuid::Uuid;
+
+use crate::apis::login::{
+    handler::filter_user_record,
+    model::{UpdateProfileSchema, User},
+    response::{ErrorResponse, UserData, UserResponse},
+};
+use crate::apis::v1::products::thumbnails;
+use crate::blob_store::image_extension;
+
+use crate::AppState;
+
+/// Longest edge of a stored avatar; larger uploads are scaled down.
+const AVATAR_SIZE: u32 = 512;
+
+type HandlerError = (StatusCode, Json<ErrorResponse>);
+
+/// Partial profile update. Email and password have their own flows.
+pub async fn update_profile_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Json(body): Json<UpdateProfileSchema>,
+) -> Result<impl IntoResponse, HandlerError> {
+    let body = validate(body).map_err(|e| fail(StatusCode::BAD_REQUEST, &e))?;
+
+    // SET expressions see the row as it was, so the consent timestamp only moves
+    // when a consent flag actually changes.
+    let updated: User = sqlx::query_as(
+        r#"UPDATE users SET
+            name = COALESCE($2, name),
+            phone = CASE WHEN $3 THEN $4 ELSE phone END,
+            locale = CASE WHEN $5 THEN $6 ELSE locale END,
+            currency = CASE WHEN $7 THEN $8 ELSE currency END,
+            marketing_email_consent = COALESCE($9, marketing_email_consent),
+            marketing_sms_consent = COALESCE($10, marketing_sms_consent),
+            marketing_consent_updated_at = CASE
+                WHEN COALESCE($9, marketing_email_consent) <> marketing_email_consent
+                    OR COALESCE($10, marketing_sms_consent) <> marketing_sms_consent
+                THEN NOW()
+                ELSE marketing_consent_updated_at
+            END,
+            updated_at = NOW()
+        WHERE id = $1
+        RETURNING *"#,
+    )
+    .bind(user.id)
+    .bind(&body.name)
+    .bind(body.phone.is_some())
+    .bind(body.phone.flatten())
+    .bind(body.locale.is_some())
+    .bind(body.locale.flatten())
+    .bind(body.currency.is_some())
+    .bind(body.currency.flatten())
+    .bind(body.marketing_email_consent)
+    .bind(body.marketing_sms_consent)
+    .fetch_one(&data.db)
+    .await
+    .map_err(database_error)?;
+
+    Ok(Json(user_response(&updated)))
+}
+
+/// Replaces the avatar with the image in the request body (PNG, JPEG, GIF or WebP).
+/// The image is decoded and encoded afresh, which drops its metadata (camera
+/// EXIF data and GPS position included), and scaled down to [`AVATAR_SIZE`].
+pub async fn upload_avatar_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    body: Bytes,
+) -> Result<impl IntoResponse, HandlerError> {
+    if image_extension(&body).is_none() {
+        return Err(fail(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "The avatar must be a PNG, JPEG, GIF or WebP image",
+        ));
+    }
+
+    let rendered = tokio::task::spawn_blocking(move || thumbnails::render(&body, &[AVATAR_SIZE]))
+        .await
+        .map_err(|e| {
+            tracing::error!(error = %e, "avatar task failed");
+            fail(StatusCode::INTERNAL_SERVER_ERROR, "Could not process the image")
+        })?
+        .map_err(|e| {
+            fail(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                &format!("The image could not be read: {}", e),
+            )
+        })?;
+    let Some((_, avatar)) = rendered.images.into_iter().next() else {
+        unreachable!("one size was requested");
+    };
+
+    let key = format!("avatars/{}/{}.{}", user.id, Uuid::new_v4(), rendered.extension);
+    data.blobs.put(&key, Bytes::from(avatar)).await.map_err(storage_error)?;
+
+    let updated: User = sqlx::query_as(
+        "UPDATE users SET avatar_key = $2, updated_at = NOW() WHERE id = $1 RETURNING *",
+    )
+    .bind(user.id)
+    .bind(&key)
+    .fetch_one(&data.db)
+    .await
+    .map_err(database_error)?;
+
+    if let Some(old_key) = &user.avatar_key {
+        remove_blob(&data, old_key).await;
+    }
+
+    Ok(Json(user_response(&updated)))
+}
+
+pub async fn delete_avatar_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, HandlerError> {
+    let Some(old_key) = &user.avatar_key else {
+        return Err(fail(StatusCode::NOT_FOUND, "You have no avatar"));
+    };
+
+    sqlx::query("UPDATE users SET avatar_key = NULL, updated_at = NOW() WHERE id = $1")
+        .bind(user.id)
+        .execute(&data.db)
+        .await
+        .map_err(database_error)?;
+
+    remove_blob(&data, old_key).await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// The profile no longer points at the blob, so failing to remove it only wastes space.
+async fn remove_blob(data: &AppState, key: &str) {
+    if let Err(e) = data.blobs.delete(key).await {
+        tracing::warn!(error = %e, key, "could not delete blob");
+    }
+}
+
+fn validate(mut body: UpdateProfileSchema) -> Result<UpdateProfileSchema, String> {
+    static LOCALE: OnceLock<Regex> = OnceLock::new();
+    static PHONE: OnceLock<Regex> = OnceLock::new();
+
+    if let Some(name) = &body.name {
+        let name = name.trim();
+        if name.is_empty() || name.chars().count() > 100 {
+            return Err("name must be between 1 and 100 characters".to_string());
+        }
+        body.name = Some(name.to_string());
+    }
+
+    // Stored in E.164, so `+1 (555) 010-0199` becomes `+15550100199`.
+    if let Some(Some(phone)) = &body.phone {
+        let phone: String = phone
+            .chars()
+            .filter(|c| !matches!(c, ' ' | '-' | '(' | ')' | '.'))
+            .collect();
+        let pattern = PHONE.get_or_init(|| Regex::new(r"^\+[1-9]\d{6,14}$").unwrap());
+        if !pattern.is_match(&phone) {
+            return Err("phone must be an international number, e.g. `+15550100199`".to_string());
+        }
+        body.phone = Some(Some(phone));
+    }
+
+    if let Some(Some(locale)) = &body.locale {
+        let locale = locale.trim().to_string();
+        let pattern =
+            LOCALE.get_or_init(|| Regex::new(r"^[a-z]{2,3}(-[A-Za-z0-9]{2,8})*$").unwrap());
+        if locale.len() > 35 || !pattern.is_match(&locale) {
+            return Err("locale must be a language tag, e.g. `en-US`".to_string());
+        }
+        body.locale = Some(Some(locale));
+    }
+
+    if let Some(Some(currency)) = &body.currency {
+        let currency = currency.trim().to_ascii_uppercase();
+        if currency.len() != 3 || !currency.bytes().all(|b| b.is_ascii_uppercase()) {
+            return Err("currency must be a three-letter ISO 4217 code, e.g. `EUR`".to_string());
+        }
+        body.currency = Some(Some(currency));
+    }
+
+    Ok(body)
+}
+
+fn user_response(user: &User) -> UserResponse {
+    UserResponse {
+        status: "success".to_string(),
+        data: UserData {
+            user: filter_user_record(user),
+        },
+    }
+}
+
+fn fail(status: StatusCode, message: &str) -> HandlerError {
+    let error_response = ErrorResponse {
+        status: "fail",
+        message: message.to_string(),
+    };
+    (status, Json(error_response))
+}
+
+fn storage_error(e: std::io::Error) -> HandlerError {
+    tracing::error!(error = %e, "could not store blob");
+    let error_response = ErrorResponse {
+        status: "error",
+        message: "Could not store the file".to_string(),
+    };
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+}
+
+fn database_error(e: sqlx::Error) -> HandlerError {
+    let error_response = ErrorResponse {
+        status: "error",
+        message: format!("Database error: {}", e),
+    };
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{codecs::jpeg::JpegEncoder, RgbImage};
+
+    use super::*;
+    use crate::test_support::{example_config, json_response, ScratchDb};
+
+    fn profile(body: serde_json::Value) -> UpdateProfileSchema {
+        serde_json::from_value(body).unwrap()
+    }
+
+    /// A JPEG carrying an EXIF segment with `marker` in it.
+    fn jpeg_with_exif(width: u32, height: u32, marker: &[u8]) -> Vec<u8> {
+        let mut jpeg = Vec::new();
+        RgbImage::new(width, height)
+            .write_with_encoder(JpegEncoder::new(&mut jpeg))
+            .unwrap();
+        let mut segment = vec![0xFF, 0xE1];
+        segment.extend_from_slice(&(2 + 6 + marker.len() as u16).to_be_bytes());
+        segment.extend_from_slice(b"Exif\0\0");
+        segment.extend_from_slice(marker);
+        jpeg.splice(2..2, segment);
+        jpeg
+    }
+
+    #[test]
+    fn normalizes_profile_fields() {
+        let body = validate(profile(serde_json::json!({
+            "name": "  Ada  ",
+            "phone": "+1 (555) 010-0199",
+            "locale": " en-US ",
+            "currency": "eur",
+        })))
+        .unwrap();
+
+        assert_eq!(body.name.as_deref(), Some("Ada"));
+        assert_eq!(body.phone, Some(Some("+15550100199".to_string())));
+        assert_eq!(body.locale, Some(Some("en-US".to_string())));
+        assert_eq!(body.currency, Some(Some("EUR".to_string())));
+    }
+
+    #[test]
+    fn tells_cleared_fields_from_missing_ones() {
+        let body = validate(profile(serde_json::json!({"phone": null}))).unwrap();
+
+        assert_eq!(body.phone, Some(None));
+        assert_eq!(body.locale, None);
+    }
+
+    #[test]
+    fn rejects_invalid_profile_fields() {
+        for (body, message) in [
+            (serde_json::json!({"name": "   "}), "name must be between 1 and 100 characters"),
+            (serde_json::json!({"phone": "555-0199"}), "phone must be an international number, e.g. `+15550100199`"),
+            (serde_json::json!({"locale": "English"}), "locale must be a language tag, e.g. `en-US`"),
+            (serde_json::json!({"currency": "EURO"}), "currency must be a three-letter ISO 4217 code, e.g. `EUR`"),
+        ] {
+            assert_eq!(validate(profile(body.clone())).unwrap_err(), message, "{}", body);
+        }
+    }
+
+    #[tokio::test]
+    async fn moves_the_consent_timestamp_only_when_consent_changes() {
+        let Some(db) = ScratchDb::migrated().await else {
+            return;
+        };
+        let data = db.state(example_config());
+        let id = db.user("ada@example.com", "password", "user").await;
+        let update = |body: serde_json::Value| {
+            let data = data.clone();
+            let db = db.pool.clone();
+            async move {
+                let user: User = sqlx::query_as("SELECT * FROM users WHERE id = $1")
+                    .bind(id)
+                    .fetch_one(&db)
+                    .await
+                    .unwrap();
+                let response = update_profile_handler(State(data), Extension(user), Json(profile(body)))
+                    .await
+                    .map_err(|(status, _)| status)
+                    .unwrap();
+                let (_, body) = json_response(response.into_response()).await;
+                sqlx::query_scalar::<_, Option<chrono::DateTime<chrono::Utc>>>(
+                    "SELECT marketing_consent_updated_at FROM users WHERE id = $1",
+                )
+                .bind(id)
+                .fetch_one(&db)
+                .await
+                .map(|at| (body, at))
+                .unwrap()
+            }
+        };
+
+        let (_, at) = update(serde_json::json!({"name": "Ada"})).await;
+        assert_eq!(at, None, "no consent given yet");
+
+        let (_, given) = update(serde_json::json!({"marketing_email_consent": true})).await;
+        assert!(given.is_some());
+
+        let (_, at) = update(serde_json::json!({"marketing_email_consent": true, "name": "Ada L"})).await;
+        assert_eq!(at, given, "unchanged consent");
+
+        let (_, withdrawn) = update(serde_json::json!({"marketing_email_consent": false})).await;
+        assert!(withdrawn > given);
+        db.drop().await;
+    }
+
+    #[tokio::test]
+    async fn strips_metadata_from_avatars_and_scales_them_down() {
+        let Some(db) = ScratchDb::migrated().await else {
+            return;
+        };
+        let data = db.state(example_config());
+        let id = db.user("ada@example.com", "password", "user").await;
+        let user: User = sqlx::query_as("SELECT * FROM users WHERE id = $1")
+            .bind(id)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        let upload = jpeg_with_exif(1200, 600, b"GPS 51.5007 -0.1246");
+        assert!(image::load_from_memory(&upload).is_ok());
+
+        let response = upload_avatar_handler(State(data.clone()), Extension(user), Bytes::from(upload))
+            .await
+            .map_err(|(status, _)| status)
+            .unwrap();
+        assert_eq!(response.into_response().status(), StatusCode::OK);
+
+        let key: String = sqlx::query_scalar("SELECT avatar_key FROM users WHERE id = $1")
+            .bind(id)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert!(key.ends_with(".jpg"), "{}", key);
+        let stored = data.blobs.get(&key).await.unwrap().unwrap();
+        assert!(!stored.windows(4).any(|window| window == b"Exif"));
+        assert!(!stored.windows(3).any(|window| window == b"GPS"));
+        let avatar = image::load_from_memory(&stored).unwrap();
+        assert_eq!((avatar.width(), avatar.height()), (AVATAR_SIZE, AVATAR_SIZE / 2));
+        db.drop().await;
+    }
+
+    #[tokio::test]
+    async fn rejects_avatars_that_are_not_images() {
+        let Some(db) = ScratchDb::migrated().await else {
+            return;
+        };
+        let data = db.state(example_config());
+        let id = db.user("ada@example.com", "password", "user").await;
+        let user: User = sqlx::query_as("SELECT * FROM users WHERE id = $1")
+            .bind(id)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+
+        let Err((status, _)) = upload_avatar_handler(State(data.clone()), Extension(user.clone()), Bytes::from_static(b"%PDF-1.7")).await else {
+            panic!("a PDF was accepted");
+        };
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        let truncated = Bytes::from(jpeg_with_exif(64, 64, b"x")[..40].to_vec());
+        let Err((status, _)) = upload_avatar_handler(State(data), Extension(user), truncated).await else {
+            panic!("a truncated JPEG was accepted");
+        };
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        db.drop().await;
+    }
+}
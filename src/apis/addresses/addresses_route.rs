@@ -0,0 +1,29 @@
+use std::sync::Arc;
+
+use axum::{middleware, routing::get, Router};
+
+use crate::apis::{
+    addresses::handler::{
+        create_address_handler, delete_address_handler, get_address_handler,
+        list_addresses_handler, update_address_handler,
+    },
+    jwt_auth::auth,
+};
+
+use crate::AppState;
+
+pub fn addresses_router(app_state: Arc<AppState>) -> Router {
+    Router::new()
+        .route(
+            "/me/addresses",
+            get(list_addresses_handler).post(create_address_handler),
+        )
+        .route(
+            "/me/addresses/:id",
+            get(get_address_handler)
+                .put(update_address_handler)
+                .delete(delete_address_handler),
+        )
+        .route_layer(middleware::from_fn_with_state(app_state.clone(), auth))
+        .with_state(app_state)
+}
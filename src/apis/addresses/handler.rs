@@ -0,0 +1,293 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use serde_json::json;
+use sqlx::{Postgres, Transaction};
+
+use crate::apis::addresses::model::{Address, AddressSchema};
+use crate::apis::login::{model::User, response::ErrorResponse};
+
+use crate::AppState;
+
+const MAX_ADDRESSES: i64 = 50;
+
+type HandlerError = (StatusCode, Json<ErrorResponse>);
+
+pub async fn list_addresses_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, HandlerError> {
+    let addresses: Vec<Address> = sqlx::query_as(
+        "SELECT * FROM addresses WHERE user_id = $1 ORDER BY is_default_shipping DESC, is_default_billing DESC, created_at",
+    )
+    .bind(user.id)
+    .fetch_all(&data.db)
+    .await
+    .map_err(database_error)?;
+
+    let json_response = json!({
+        "status": "success",
+        "results": addresses.len(),
+        "data": addresses,
+    });
+
+    Ok(Json(json_response))
+}
+
+pub async fn get_address_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Path(id): Path<i32>,
+) -> Result<impl IntoResponse, HandlerError> {
+    let address = find_address(&data, user.id, id).await?;
+
+    Ok(Json(json!({ "status": "success", "data": address })))
+}
+
+/// The user's first address becomes their default for both shipping and billing.
+pub async fn create_address_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Json(body): Json<AddressSchema>,
+) -> Result<impl IntoResponse, HandlerError> {
+    let body = body.validate().map_err(|e| fail(StatusCode::BAD_REQUEST, &e))?;
+
+    let mut tx = data.db.begin().await.map_err(database_error)?;
+    lock_user(&mut tx, user.id).await?;
+
+    let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM addresses WHERE user_id = $1")
+        .bind(user.id)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(database_error)?;
+    if count >= MAX_ADDRESSES {
+        return Err(fail(
+            StatusCode::CONFLICT,
+            &format!("You can save at most {} addresses", MAX_ADDRESSES),
+        ));
+    }
+
+    let first = count == 0;
+    let default_shipping = body.is_default_shipping.unwrap_or(first);
+    let default_billing = body.is_default_billing.unwrap_or(first);
+    clear_defaults(&mut tx, user.id, None, default_shipping, default_billing).await?;
+
+    let address: Address = sqlx::query_as(
+        r#"INSERT INTO addresses
+            (user_id, label, recipient_name, company, line1, line2, city, region, postal_code, country, phone, is_default_shipping, is_default_billing)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
+        RETURNING *"#,
+    )
+    .bind(user.id)
+    .bind(&body.label)
+    .bind(&body.recipient_name)
+    .bind(&body.company)
+    .bind(&body.line1)
+    .bind(&body.line2)
+    .bind(&body.city)
+    .bind(&body.region)
+    .bind(&body.postal_code)
+    .bind(&body.country)
+    .bind(&body.phone)
+    .bind(default_shipping)
+    .bind(default_billing)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(database_error)?;
+
+    tx.commit().await.map_err(database_error)?;
+
+    Ok((StatusCode::CREATED, Json(json!({ "status": "success", "data": address }))))
+}
+
+pub async fn update_address_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Path(id): Path<i32>,
+    Json(body): Json<AddressSchema>,
+) -> Result<impl IntoResponse, HandlerError> {
+    let body = body.validate().map_err(|e| fail(StatusCode::BAD_REQUEST, &e))?;
+    let current = find_address(&data, user.id, id).await?;
+
+    let default_shipping = body.is_default_shipping.unwrap_or(current.is_default_shipping);
+    let default_billing = body.is_default_billing.unwrap_or(current.is_default_billing);
+
+    let mut tx = data.db.begin().await.map_err(database_error)?;
+    lock_user(&mut tx, user.id).await?;
+    clear_defaults(&mut tx, user.id, Some(id), default_shipping, default_billing).await?;
+
+    let address: Address = sqlx::query_as(
+        r#"UPDATE addresses SET
+            label = $3, recipient_name = $4, company = $5, line1 = $6, line2 = $7, city = $8,
+            region = $9, postal_code = $10, country = $11, phone = $12,
+            is_default_shipping = $13, is_default_billing = $14, updated_at = NOW()
+        WHERE id = $1 AND user_id = $2
+        RETURNING *"#,
+    )
+    .bind(id)
+    .bind(user.id)
+    .bind(&body.label)
+    .bind(&body.recipient_name)
+    .bind(&body.company)
+    .bind(&body.line1)
+    .bind(&body.line2)
+    .bind(&body.city)
+    .bind(&body.region)
+    .bind(&body.postal_code)
+    .bind(&body.country)
+    .bind(&body.phone)
+    .bind(default_shipping)
+    .bind(default_billing)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(database_error)?;
+
+    tx.commit().await.map_err(database_error)?;
+
+    Ok(Json(json!({ "status": "success", "data": address })))
+}
+
+pub async fn delete_address_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Path(id): Path<i32>,
+) -> Result<impl IntoResponse, HandlerError> {
+    let result = sqlx::query("DELETE FROM addresses WHERE id = $1 AND user_id = $2")
+        .bind(id)
+        .bind(user.id)
+        .execute(&data.db)
+        .await
+        .map_err(database_error)?;
+
+    if result.rows_affected() == 0 {
+        return Err(not_found(id));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Other users' addresses are reported as missing rather than forbidden.
+async fn find_address(data: &AppState, user_id: i32, id: i32) -> Result<Address, HandlerError> {
+    sqlx::query_as("SELECT * FROM addresses WHERE id = $1 AND user_id = $2")
+        .bind(id)
+        .bind(user_id)
+        .fetch_optional(&data.db)
+        .await
+        .map_err(database_error)?
+        .ok_or_else(|| not_found(id))
+}
+
+/// Serializes changes to one user's addresses, so concurrent requests cannot
+/// both count zero addresses or both take over a default.
+async fn lock_user(tx: &mut Transaction<'_, Postgres>, user_id: i32) -> Result<(), HandlerError> {
+    sqlx::query("SELECT id FROM users WHERE id = $1 FOR UPDATE")
+        .bind(user_id)
+        .execute(&mut **tx)
+        .await
+        .map_err(database_error)?;
+    Ok(())
+}
+
+/// Unsets the current defaults that `except` is about to take over.
+async fn clear_defaults(
+    tx: &mut Transaction<'_, Postgres>,
+    user_id: i32,
+    except: Option<i32>,
+    shipping: bool,
+    billing: bool,
+) -> Result<(), HandlerError> {
+    if !shipping && !billing {
+        return Ok(());
+    }
+
+    sqlx::query(
+        r#"UPDATE addresses SET
+            is_default_shipping = is_default_shipping AND NOT $3,
+            is_default_billing = is_default_billing AND NOT $4
+        WHERE user_id = $1 AND id IS DISTINCT FROM $2 AND (is_default_shipping OR is_default_billing)"#,
+    )
+    .bind(user_id)
+    .bind(except)
+    .bind(shipping)
+    .bind(billing)
+    .execute(&mut **tx)
+    .await
+    .map_err(database_error)?;
+
+    Ok(())
+}
+
+fn not_found(id: i32) -> HandlerError {
+    fail(StatusCode::NOT_FOUND, &format!("No address with id {}", id))
+}
+
+fn fail(status: StatusCode, message: &str) -> HandlerError {
+    let error_response = ErrorResponse {
+        status: "fail",
+        message: message.to_string(),
+    };
+    (status, Json(error_response))
+}
+
+fn database_error(e: sqlx::Error) -> HandlerError {
+    let error_response = ErrorResponse {
+        status: "error",
+        message: format!("Database error: {}", e),
+    };
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{example_config, ScratchDb};
+
+    #[tokio::test]
+    async fn makes_only_one_of_concurrent_first_addresses_the_default() {
+        let Some(db) = ScratchDb::migrated().await else {
+            return;
+        };
+        let data = db.state(example_config());
+        let id = db.user("ada@example.com", "password", "user").await;
+        let user: User = sqlx::query_as("SELECT * FROM users WHERE id = $1")
+            .bind(id)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+
+        let requests = (0..8).map(|i| {
+            let body: AddressSchema = serde_json::from_value(json!({
+                "recipient_name": "Ada Lovelace",
+                "line1": format!("{} Main Street", i),
+                "city": "Springfield",
+                "postal_code": "12345",
+                "country": "US",
+            }))
+            .unwrap();
+            let (data, user) = (data.clone(), user.clone());
+            tokio::spawn(async move {
+                create_address_handler(State(data), Extension(user), Json(body))
+                    .await
+                    .map(|_| ())
+                    .map_err(|(status, _)| status)
+            })
+        });
+        for request in futures_util::future::join_all(requests).await {
+            assert_eq!(request.unwrap(), Ok(()));
+        }
+
+        let defaults: (i64, i64) = sqlx::query_as(
+            "SELECT COUNT(*) FILTER (WHERE is_default_shipping), COUNT(*) FILTER (WHERE is_default_billing) FROM addresses WHERE user_id = $1",
+        )
+        .bind(id)
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+        assert_eq!(defaults, (1, 1));
+        db.drop().await;
+    }
+}
@@ -0,0 +1,208 @@
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct Address {
+    pub id: i32,
+    pub user_id: i32,
+    pub label: Option<String>,
+    pub recipient_name: String,
+    pub company: Option<String>,
+    pub line1: String,
+    pub line2: Option<String>,
+    pub city: String,
+    pub region: Option<String>,
+    pub postal_code: Option<String>,
+    pub country: String,
+    pub phone: Option<String>,
+    pub is_default_shipping: bool,
+    pub is_default_billing: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Body of both create and update. Leaving a default flag out keeps it as is
+/// (or, for a user's first address, makes it the default).
+#[derive(Debug, Deserialize)]
+pub struct AddressSchema {
+    pub label: Option<String>,
+    pub recipient_name: String,
+    pub company: Option<String>,
+    pub line1: String,
+    pub line2: Option<String>,
+    pub city: String,
+    pub region: Option<String>,
+    pub postal_code: Option<String>,
+    pub country: String,
+    pub phone: Option<String>,
+    pub is_default_shipping: Option<bool>,
+    pub is_default_billing: Option<bool>,
+}
+
+/// Postal code formats for the countries we ship to most. Codes for other
+/// countries are only checked for length and characters.
+const POSTAL_CODE_FORMATS: &[(&str, &str)] = &[
+    ("AT", r"^\d{4}$"),
+    ("AU", r"^\d{4}$"),
+    ("BE", r"^\d{4}$"),
+    ("BR", r"^\d{5}-?\d{3}$"),
+    ("CA", r"^[A-Z]\d[A-Z] ?\d[A-Z]\d$"),
+    ("CH", r"^\d{4}$"),
+    ("DE", r"^\d{5}$"),
+    ("DK", r"^\d{4}$"),
+    ("ES", r"^\d{5}$"),
+    ("FR", r"^\d{5}$"),
+    ("GB", r"^[A-Z]{1,2}\d[A-Z\d]? ?\d[A-Z]{2}$"),
+    ("IE", r"^[A-Z]\d[\dW] ?[A-Z\d]{4}$"),
+    ("IN", r"^\d{6}$"),
+    ("IT", r"^\d{5}$"),
+    ("JP", r"^\d{3}-?\d{4}$"),
+    ("MX", r"^\d{5}$"),
+    ("NL", r"^\d{4} ?[A-Z]{2}$"),
+    ("NO", r"^\d{4}$"),
+    ("NZ", r"^\d{4}$"),
+    ("PL", r"^\d{2}-\d{3}$"),
+    ("PT", r"^\d{4}-\d{3}$"),
+    ("SE", r"^\d{3} ?\d{2}$"),
+    ("US", r"^\d{5}(-\d{4})?$"),
+];
+
+fn postal_code_formats() -> &'static HashMap<&'static str, Regex> {
+    static FORMATS: OnceLock<HashMap<&'static str, Regex>> = OnceLock::new();
+    FORMATS.get_or_init(|| {
+        POSTAL_CODE_FORMATS
+            .iter()
+            .map(|(country, pattern)| (*country, Regex::new(pattern).unwrap()))
+            .collect()
+    })
+}
+
+impl AddressSchema {
+    /// Trims and normalizes every field, or says what is wrong with the first bad one.
+    pub fn validate(mut self) -> Result<Self, String> {
+        self.recipient_name = required(&self.recipient_name, "recipient_name", 100)?;
+        self.line1 = required(&self.line1, "line1", 200)?;
+        self.city = required(&self.city, "city", 100)?;
+        self.label = optional(self.label, "label", 50)?;
+        self.company = optional(self.company, "company", 100)?;
+        self.line2 = optional(self.line2, "line2", 200)?;
+        self.region = optional(self.region, "region", 100)?;
+        self.phone = optional(self.phone, "phone", 30)?;
+
+        let country = self.country.trim().to_ascii_uppercase();
+        if country.len() != 2 || !country.bytes().all(|b| b.is_ascii_uppercase()) {
+            return Err("country must be a two-letter ISO 3166 code, e.g. `US`".to_string());
+        }
+
+        let postal_code = optional(self.postal_code, "postal_code", 20)?
+            .map(|code| code.to_ascii_uppercase());
+        match (postal_code_formats().get(country.as_str()), &postal_code) {
+            (Some(_), None) => return Err(format!("postal_code is required for {}", country)),
+            (Some(format), Some(code)) if !format.is_match(code) => {
+                return Err(format!("`{}` is not a valid postal code for {}", code, country))
+            }
+            (None, Some(code))
+                if !code.chars().all(|c| c.is_ascii_alphanumeric() || c == ' ' || c == '-') =>
+            {
+                return Err(format!("`{}` is not a valid postal code", code))
+            }
+            _ => {}
+        }
+
+        self.country = country;
+        self.postal_code = postal_code;
+        Ok(self)
+    }
+}
+
+fn required(value: &str, field: &str, max_len: usize) -> Result<String, String> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Err(format!("{} is required", field));
+    }
+    if value.chars().count() > max_len {
+        return Err(format!("{} must be at most {} characters", field, max_len));
+    }
+    Ok(value.to_string())
+}
+
+fn optional(value: Option<String>, field: &str, max_len: usize) -> Result<Option<String>, String> {
+    match value.as_deref().map(str::trim) {
+        None | Some("") => Ok(None),
+        Some(value) => required(value, field, max_len).map(Some),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(country: &str, postal_code: Option<&str>) -> AddressSchema {
+        AddressSchema {
+            label: None,
+            recipient_name: "Ada Lovelace".to_string(),
+            company: None,
+            line1: "1 Main Street".to_string(),
+            line2: None,
+            city: "Springfield".to_string(),
+            region: None,
+            postal_code: postal_code.map(str::to_string),
+            country: country.to_string(),
+            phone: None,
+            is_default_shipping: None,
+            is_default_billing: None,
+        }
+    }
+
+    #[test]
+    fn accepts_postal_codes_in_each_country_format() {
+        let cases = [
+            ("US", "12345", "12345"),
+            ("us", "12345-6789", "12345-6789"),
+            ("GB", "sw1a 1aa", "SW1A 1AA"),
+            ("GB", "M1 1AE", "M1 1AE"),
+            ("CA", "k1a0b1", "K1A0B1"),
+            ("NL", "1234 AB", "1234 AB"),
+            ("JP", "100-0001", "100-0001"),
+            ("PL", "00-950", "00-950"),
+            ("SE", "123 45", "123 45"),
+            ("IE", "D02 X285", "D02 X285"),
+            ("BR", "01310-100", "01310-100"),
+            // No known format: only the characters are checked.
+            ("AR", "C1425", "C1425"),
+        ];
+        for (country, code, normalized) in cases {
+            let address = address(country, Some(&format!(" {} ", code)))
+                .validate()
+                .unwrap_or_else(|e| panic!("{} {}: {}", country, code, e));
+            assert_eq!(address.postal_code.as_deref(), Some(normalized), "{} {}", country, code);
+            assert_eq!(address.country, country.to_ascii_uppercase());
+        }
+    }
+
+    #[test]
+    fn rejects_postal_codes_that_do_not_match_the_country() {
+        let cases = [
+            ("US", Some("1234"), "`1234` is not a valid postal code for US"),
+            ("DE", Some("1234"), "`1234` is not a valid postal code for DE"),
+            ("GB", Some("12345"), "`12345` is not a valid postal code for GB"),
+            ("PL", Some("00950"), "`00950` is not a valid postal code for PL"),
+            ("FR", None, "postal_code is required for FR"),
+            ("FR", Some("  "), "postal_code is required for FR"),
+            ("AR", Some("C1425#"), "`C1425#` is not a valid postal code"),
+            ("USA", Some("12345"), "country must be a two-letter ISO 3166 code, e.g. `US`"),
+        ];
+        for (country, code, message) in cases {
+            assert_eq!(address(country, code).validate().unwrap_err(), message, "{} {:?}", country, code);
+        }
+    }
+
+    #[test]
+    fn leaves_the_postal_code_optional_where_no_format_is_known() {
+        assert_eq!(address("HK", None).validate().unwrap().postal_code, None);
+    }
+}
@@ -0,0 +1,28 @@
+use std::sync::Arc;
+
+use axum::{
+    middleware,
+    routing::{delete, get, post},
+    Router,
+};
+
+use crate::apis::{
+    api_keys::handler::{
+        create_api_key_handler, list_api_keys_handler, revoke_api_key_handler,
+        rotate_api_key_handler,
+    },
+    jwt_auth::{auth, require_admin},
+};
+
+use crate::AppState;
+
+/// Key management needs an admin session; API keys cannot manage API keys.
+pub fn api_keys_router(app_state: Arc<AppState>) -> Router {
+    Router::new()
+        .route("/admin/api-keys", get(list_api_keys_handler).post(create_api_key_handler))
+        .route("/admin/api-keys/:id/rotate", post(rotate_api_key_handler))
+        .route("/admin/api-keys/:id", delete(revoke_api_key_handler))
+        .route_layer(middleware::from_fn(require_admin))
+        .route_layer(middleware::from_fn_with_state(app_state.clone(), auth))
+        .with_state(app_state)
+}
@@ -0,0 +1,376 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use chrono::{DateTime, Utc};
+use rand::{distributions::Alphanumeric, rngs::OsRng, Rng};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use sqlx::{PgExecutor, PgPool};
+
+use crate::apis::api_keys::model::{
+    ApiKey, CreateApiKeySchema, RotateApiKeySchema, KEY_PREFIX, SCOPES,
+};
+use crate::apis::config::parse_duration;
+use crate::apis::login::{model::User, response::ErrorResponse};
+
+use crate::AppState;
+
+const PREFIX_LEN: usize = 8;
+const SECRET_LEN: usize = 32;
+
+type HandlerError = (StatusCode, Json<ErrorResponse>);
+
+pub async fn list_api_keys_handler(
+    State(data): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, HandlerError> {
+    let keys: Vec<ApiKey> = sqlx::query_as("SELECT * FROM api_keys ORDER BY created_at DESC")
+        .fetch_all(&data.db)
+        .await
+        .map_err(database_error)?;
+
+    let json_response = json!({
+        "status": "success",
+        "results": keys.len(),
+        "data": keys,
+    });
+
+    Ok(Json(json_response))
+}
+
+/// Issues a key. The plain key is only ever part of this response.
+pub async fn create_api_key_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Json(body): Json<CreateApiKeySchema>,
+) -> Result<impl IntoResponse, HandlerError> {
+    let name = body.name.trim();
+    if name.is_empty() || name.chars().count() > 100 {
+        return Err(fail(StatusCode::BAD_REQUEST, "Name must be between 1 and 100 characters"));
+    }
+    validate_scopes(&body.scopes)?;
+    let expires_at = match &body.expires_in {
+        Some(expires_in) => Some(Utc::now() + to_chrono(expires_in, "expires_in")?),
+        None => None,
+    };
+
+    let (key, api_key) = insert_key(&data.db, name, &body.scopes, expires_at, user.id).await?;
+    tracing::info!(api_key_id = api_key.id, user_id = user.id, "issued API key");
+
+    Ok((StatusCode::CREATED, Json(issued(key, api_key))))
+}
+
+/// Replaces a key with a new one carrying the same name, scopes and expiry.
+/// The old key stops working after `grace_period`, so callers can switch over.
+pub async fn rotate_api_key_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Path(id): Path<i32>,
+    body: Option<Json<RotateApiKeySchema>>,
+) -> Result<impl IntoResponse, HandlerError> {
+    let body = body.map(|Json(body)| body).unwrap_or_default();
+    let grace_period = match &body.grace_period {
+        Some(grace_period) => to_chrono(grace_period, "grace_period")?,
+        None => chrono::Duration::zero(),
+    };
+
+    // Locked, so two rotations of the same key cannot both replace it.
+    let mut tx = data.db.begin().await.map_err(database_error)?;
+    let old: ApiKey =
+        sqlx::query_as("SELECT * FROM api_keys WHERE id = $1 AND revoked_at IS NULL FOR UPDATE")
+            .bind(id)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(database_error)?
+            .ok_or_else(|| fail(StatusCode::NOT_FOUND, &format!("No active API key with id {}", id)))?;
+
+    let (key, api_key) =
+        insert_key(&mut *tx, &old.name, &old.scopes, old.expires_at, user.id).await?;
+
+    sqlx::query("UPDATE api_keys SET revoked_at = $2 WHERE id = $1")
+        .bind(old.id)
+        .bind(Utc::now() + grace_period)
+        .execute(&mut *tx)
+        .await
+        .map_err(database_error)?;
+    tx.commit().await.map_err(database_error)?;
+
+    tracing::info!(
+        api_key_id = api_key.id,
+        replaced = old.id,
+        user_id = user.id,
+        "rotated API key"
+    );
+
+    Ok((StatusCode::CREATED, Json(issued(key, api_key))))
+}
+
+pub async fn revoke_api_key_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Path(id): Path<i32>,
+) -> Result<impl IntoResponse, HandlerError> {
+    let result = sqlx::query(
+        "UPDATE api_keys SET revoked_at = NOW() WHERE id = $1 AND (revoked_at IS NULL OR revoked_at > NOW())",
+    )
+    .bind(id)
+    .execute(&data.db)
+    .await
+    .map_err(database_error)?;
+
+    if result.rows_affected() == 0 {
+        return Err(fail(StatusCode::NOT_FOUND, &format!("No active API key with id {}", id)));
+    }
+
+    tracing::info!(api_key_id = id, user_id = user.id, "revoked API key");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Looks up a presented key, recording that it was used. Revoked and expired
+/// keys are treated as unknown. `last_used_at` is only written once a minute,
+/// so a busy key does not cost a write per request.
+pub async fn find_active_key(db: &PgPool, key: &str) -> Result<Option<ApiKey>, sqlx::Error> {
+    let api_key: Option<ApiKey> = sqlx::query_as(
+        r#"SELECT * FROM api_keys
+        WHERE key_hash = $1
+            AND (revoked_at IS NULL OR revoked_at > NOW())
+            AND (expires_at IS NULL OR expires_at > NOW())"#,
+    )
+    .bind(hash_key(key))
+    .fetch_optional(db)
+    .await?;
+
+    if let Some(api_key) = &api_key {
+        let stale = api_key
+            .last_used_at
+            .is_none_or(|used| used < Utc::now() - chrono::Duration::minutes(1));
+        if stale {
+            sqlx::query(
+                "UPDATE api_keys SET last_used_at = NOW() WHERE id = $1 AND (last_used_at IS NULL OR last_used_at < NOW() - interval '1 minute')",
+            )
+            .bind(api_key.id)
+            .execute(db)
+            .await?;
+        }
+    }
+    Ok(api_key)
+}
+
+/// The id of the active key `key`, without recording a use.
+pub async fn active_key_id(db: &PgPool, key: &str) -> Result<Option<i32>, sqlx::Error> {
+    sqlx::query_scalar(
+        r#"SELECT id FROM api_keys
+        WHERE key_hash = $1
+            AND (revoked_at IS NULL OR revoked_at > NOW())
+            AND (expires_at IS NULL OR expires_at > NOW())"#,
+    )
+    .bind(hash_key(key))
+    .fetch_optional(db)
+    .await
+}
+
+/// Keys are long random strings, so a fast hash is enough; unlike passwords
+/// they cannot be guessed from a dictionary.
+fn hash_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+/// Returns `shp_<prefix>_<secret>` together with the part that is stored in clear.
+fn generate_key() -> (String, String) {
+    let random = |len| -> String {
+        OsRng
+            .sample_iter(&Alphanumeric)
+            .take(len)
+            .map(char::from)
+            .collect()
+    };
+    let prefix = format!("{}{}", KEY_PREFIX, random(PREFIX_LEN));
+    let key = format!("{}_{}", prefix, random(SECRET_LEN));
+    (key, prefix)
+}
+
+async fn insert_key(
+    db: impl PgExecutor<'_>,
+    name: &str,
+    scopes: &[String],
+    expires_at: Option<DateTime<Utc>>,
+    created_by: i32,
+) -> Result<(String, ApiKey), HandlerError> {
+    let (key, prefix) = generate_key();
+    let api_key = sqlx::query_as(
+        "INSERT INTO api_keys (name, prefix, key_hash, scopes, expires_at, created_by) VALUES ($1, $2, $3, $4, $5, $6) RETURNING *",
+    )
+    .bind(name)
+    .bind(prefix)
+    .bind(hash_key(&key))
+    .bind(scopes)
+    .bind(expires_at)
+    .bind(created_by)
+    .fetch_one(db)
+    .await
+    .map_err(database_error)?;
+
+    Ok((key, api_key))
+}
+
+fn issued(key: String, api_key: ApiKey) -> serde_json::Value {
+    json!({
+        "status": "success",
+        "message": "Store this key now, it will not be shown again",
+        "key": key,
+        "data": api_key,
+    })
+}
+
+fn validate_scopes(scopes: &[String]) -> Result<(), HandlerError> {
+    if scopes.is_empty() {
+        return Err(fail(StatusCode::BAD_REQUEST, "At least one scope is required"));
+    }
+    if let Some(unknown) = scopes.iter().find(|scope| !SCOPES.contains(&scope.as_str())) {
+        return Err(fail(
+            StatusCode::BAD_REQUEST,
+            &format!("Unknown scope `{}`, expected one of: {}", unknown, SCOPES.join(", ")),
+        ));
+    }
+    Ok(())
+}
+
+fn to_chrono(value: &str, field: &str) -> Result<chrono::Duration, HandlerError> {
+    parse_duration(value)
+        .ok()
+        .and_then(|duration| chrono::Duration::from_std(duration).ok())
+        .ok_or_else(|| {
+            fail(
+                StatusCode::BAD_REQUEST,
+                &format!("Invalid {} `{}`, expected e.g. `90d` or `12h`", field, value),
+            )
+        })
+}
+
+fn fail(status: StatusCode, message: &str) -> HandlerError {
+    let error_response = ErrorResponse {
+        status: "fail",
+        message: message.to_string(),
+    };
+    (status, Json(error_response))
+}
+
+fn database_error(e: sqlx::Error) -> HandlerError {
+    let error_response = ErrorResponse {
+        status: "error",
+        message: format!("Database error: {}", e),
+    };
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{example_config, json_response, ScratchDb};
+
+    #[test]
+    fn generates_prefixed_keys_whose_prefix_is_stored_in_clear() {
+        let (key, prefix) = generate_key();
+
+        assert!(key.starts_with(&format!("{}_", prefix)));
+        assert_eq!(prefix.len(), KEY_PREFIX.len() + PREFIX_LEN);
+        assert_eq!(key.len(), prefix.len() + 1 + SECRET_LEN);
+        assert_ne!(generate_key().0, key);
+    }
+
+    #[test]
+    fn hashes_keys_with_sha256() {
+        assert_eq!(
+            hash_key("shp_abc"),
+            "f85905c7910b06aa8d1c43542e3afb49dcba680a7627f69ed4caabc4bd638895"
+        );
+        assert_ne!(hash_key("shp_abc"), hash_key("shp_abd"));
+    }
+
+    #[test]
+    fn accepts_only_known_scopes() {
+        let scopes = |scopes: &[&str]| scopes.iter().map(|scope| scope.to_string()).collect::<Vec<_>>();
+
+        assert!(validate_scopes(&scopes(&["catalog:read", "orders:write"])).is_ok());
+        assert!(validate_scopes(&scopes(&[])).is_err());
+        let (status, Json(error)) = validate_scopes(&scopes(&["catalog:read", "admin"])).unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(error.message.starts_with("Unknown scope `admin`"));
+    }
+
+    #[tokio::test]
+    async fn rotates_a_key_once_and_keeps_the_old_one_for_the_grace_period() {
+        let Some(db) = ScratchDb::migrated().await else {
+            return;
+        };
+        let data = db.state(example_config());
+        let admin = db.user("admin@example.com", "password", "admin").await;
+        let user: User = sqlx::query_as("SELECT * FROM users WHERE id = $1")
+            .bind(admin)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        let (id, old_key) = db.api_key(&["catalog:read"]).await;
+        let rotate = |grace_period: &str| {
+            let body = RotateApiKeySchema {
+                grace_period: Some(grace_period.to_string()),
+            };
+            rotate_api_key_handler(
+                State(data.clone()),
+                Extension(user.clone()),
+                Path(id),
+                Some(Json(body)),
+            )
+        };
+
+        let (status, body) = json_response(rotate("1h").await.unwrap().into_response()).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(body["data"]["scopes"], json!(["catalog:read"]));
+        let new_key = body["key"].as_str().unwrap();
+        assert!(find_active_key(&db.pool, new_key).await.unwrap().is_some());
+        assert!(find_active_key(&db.pool, &old_key).await.unwrap().is_some(), "still in its grace period");
+
+        let Err((status, _)) = rotate("1h").await else {
+            panic!("a replaced key cannot be rotated again");
+        };
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        db.drop().await;
+    }
+
+    #[tokio::test]
+    async fn records_a_use_at_most_once_a_minute() {
+        let Some(db) = ScratchDb::migrated().await else {
+            return;
+        };
+        let (id, key) = db.api_key(&["catalog:read"]).await;
+        let last_used = || {
+            sqlx::query_scalar::<_, Option<DateTime<Utc>>>("SELECT last_used_at FROM api_keys WHERE id = $1")
+                .bind(id)
+                .fetch_one(&db.pool)
+        };
+        let set_last_used = |ago: &'static str| {
+            sqlx::query("UPDATE api_keys SET last_used_at = NOW() - $2::interval WHERE id = $1")
+                .bind(id)
+                .bind(ago)
+                .execute(&db.pool)
+        };
+
+        find_active_key(&db.pool, &key).await.unwrap().unwrap();
+        assert!(last_used().await.unwrap().is_some(), "the first use is recorded");
+
+        set_last_used("30 seconds").await.unwrap();
+        let recent = last_used().await.unwrap();
+        find_active_key(&db.pool, &key).await.unwrap().unwrap();
+        assert_eq!(last_used().await.unwrap(), recent);
+
+        set_last_used("2 minutes").await.unwrap();
+        let stale = last_used().await.unwrap();
+        find_active_key(&db.pool, &key).await.unwrap().unwrap();
+        assert!(last_used().await.unwrap() > stale);
+        db.drop().await;
+    }
+}
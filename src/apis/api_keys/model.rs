@@ -0,0 +1,35 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Every scope a key can be granted.
+pub const SCOPES: &[&str] = &["catalog:read", "catalog:write", "orders:read", "orders:write"];
+
+/// Prepended to every key so leaked keys are easy to spot (and to grep for).
+pub const KEY_PREFIX: &str = "shp_";
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct ApiKey {
+    pub id: i32,
+    pub name: String,
+    pub prefix: String,
+    pub scopes: Vec<String>,
+    pub created_by: Option<i32>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateApiKeySchema {
+    pub name: String,
+    pub scopes: Vec<String>,
+    /// e.g. `90d`; keys without one never expire.
+    pub expires_in: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct RotateApiKeySchema {
+    /// How long the old key keeps working, e.g. `1h`. Defaults to not at all.
+    pub grace_period: Option<String>,
+}
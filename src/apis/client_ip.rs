@@ -0,0 +1,83 @@
+use std::convert::Infallible;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts},
+    http::{request::Parts, Extensions, HeaderMap},
+};
+
+use crate::AppState;
+
+/// Address of the caller. Proxy headers are only honoured when
+/// `trust_forwarded_headers` is set, otherwise anyone could pick their own IP.
+#[derive(Debug, Clone, Copy)]
+pub struct ClientIp(pub IpAddr);
+
+#[async_trait]
+impl FromRequestParts<Arc<AppState>> for ClientIp {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(ClientIp(client_ip(
+            &parts.headers,
+            &parts.extensions,
+            state.config.trust_forwarded_headers,
+        )))
+    }
+}
+
+/// Behind a trusted proxy, the rightmost `X-Forwarded-For` entry is the one
+/// the proxy appended; anything to its left came from the caller.
+pub fn client_ip(headers: &HeaderMap, extensions: &Extensions, trust_forwarded: bool) -> IpAddr {
+    if trust_forwarded {
+        let forwarded = headers
+            .get("x-forwarded-for")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.rsplit(',').next())
+            .or_else(|| headers.get("x-real-ip").and_then(|value| value.to_str().ok()))
+            .and_then(|value| value.trim().parse::<IpAddr>().ok());
+
+        if let Some(ip) = forwarded {
+            return ip;
+        }
+    }
+
+    extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip())
+        .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip_from(forwarded_for: &str, trust_forwarded: bool) -> IpAddr {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", forwarded_for.parse().unwrap());
+        let mut extensions = Extensions::new();
+        extensions.insert(ConnectInfo(SocketAddr::from(([10, 0, 0, 1], 4000))));
+        client_ip(&headers, &extensions, trust_forwarded)
+    }
+
+    #[test]
+    fn takes_the_address_the_proxy_appended() {
+        assert_eq!(ip_from("203.0.113.7", true), IpAddr::from([203, 0, 113, 7]));
+        assert_eq!(
+            ip_from("198.51.100.1, 203.0.113.7", true),
+            IpAddr::from([203, 0, 113, 7]),
+            "the caller chose the first entry"
+        );
+    }
+
+    #[test]
+    fn ignores_forwarded_headers_unless_trusted() {
+        assert_eq!(ip_from("203.0.113.7", false), IpAddr::from([10, 0, 0, 1]));
+        assert_eq!(ip_from("not an address", true), IpAddr::from([10, 0, 0, 1]));
+    }
+}
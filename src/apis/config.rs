@@ -1,39 +1,809 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use axum::http::HeaderValue;
+use lettre::message::Mailbox;
+
+use crate::apis::jwt_keys::{JwtAlgorithm, PreviousKey};
+use crate::apis::login::throttle::AttemptStoreKind;
+use crate::apis::rate_limit::{Quota, RouteQuota};
+use crate::blob_store::BlobStoreKind;
+
+/// Runtime settings, merged from (lowest to highest precedence) built-in defaults,
+/// a TOML file, environment variables and `--key value` command line flags.
 #[derive(Debug, Clone)]
 pub struct Config {
+    pub bind_addr: SocketAddr,
+    pub shutdown_timeout: Duration,
+    pub health_check_timeout: Duration,
+    pub health_email_check_interval: Duration,
+    pub cors_origins: Vec<HeaderValue>,
+    pub trust_forwarded_headers: bool,
     pub database_url: String,
-    pub jwt_secret: String,
-    pub jwt_expires_in: String,
-    pub jwt_maxage: i32,
+    pub database_max_connections: u32,
+    pub database_slow_statement_threshold: Duration,
+    pub database_run_migrations: bool,
+    pub log_level: String,
+    pub log_format: LogFormat,
+    pub jwt_algorithm: JwtAlgorithm,
+    pub jwt_secret: Option<String>,
+    pub jwt_private_key_file: Option<PathBuf>,
+    pub jwt_key_id: String,
+    pub jwt_previous_keys: Vec<PreviousKey>,
+    pub jwt_issuer: String,
+    pub jwt_audience: String,
+    pub jwt_expires_in: Duration,
+    pub jwt_maxage: Duration,
+    pub totp_issuer: String,
+    pub oidc: Option<OidcConfig>,
+    pub login_throttle_store: AttemptStoreKind,
+    pub login_max_failures: i32,
+    pub login_ip_max_failures: i32,
+    pub login_failure_window: Duration,
+    pub login_lockout_duration: Duration,
+    pub login_backoff_base: Duration,
+    pub account_deletion_grace_period: Duration,
+    pub blob_store_kind: BlobStoreKind,
+    pub blob_store_path: PathBuf,
+    pub blob_store_s3: Option<S3Config>,
+    pub upload_max_bytes: usize,
+    pub product_image_thumbnail_sizes: Vec<u32>,
+    pub product_import_max_bytes: usize,
+    pub catalog_purge_retention: Duration,
+    pub product_publish_interval: Duration,
+    /// Signed download links are disabled when unset.
+    pub download_secret: Option<String>,
+    pub download_link_ttl: Duration,
+    pub download_max_downloads: i32,
+    pub download_max_bytes: usize,
+    pub rate_limit_enabled: bool,
+    pub rate_limit_anonymous: Quota,
+    pub rate_limit_authenticated: Quota,
+    pub rate_limit_admin: Quota,
+    pub rate_limit_api_key: Quota,
+    pub rate_limit_routes: Vec<RouteQuota>,
     pub smtp_host: String,
     pub smtp_port: u16,
     pub smtp_user: String,
     pub smtp_pass: String,
-    pub smtp_from: String,
+    pub smtp_from: Mailbox,
+}
+
+struct Setting {
+    key: &'static str,
+    env: &'static str,
+    default: Option<&'static str>,
+}
+
+const SETTINGS: &[Setting] = &[
+    Setting { key: "bind_addr", env: "BIND_ADDR", default: Some("127.0.0.1:8000") },
+    Setting { key: "shutdown_timeout", env: "SHUTDOWN_TIMEOUT", default: Some("30s") },
+    Setting { key: "health_check_timeout", env: "HEALTH_CHECK_TIMEOUT", default: Some("2s") },
+    Setting { key: "health_email_check_interval", env: "HEALTH_EMAIL_CHECK_INTERVAL", default: Some("1m") },
+    Setting { key: "cors_origins", env: "CORS_ORIGINS", default: Some("http://localhost:3000") },
+    Setting { key: "trust_forwarded_headers", env: "TRUST_FORWARDED_HEADERS", default: Some("false") },
+    Setting { key: "database_url", env: "DATABASE_URL", default: None },
+    Setting { key: "database_max_connections", env: "DATABASE_MAX_CONNECTIONS", default: Some("50") },
+    Setting { key: "database_slow_statement_threshold", env: "DATABASE_SLOW_STATEMENT_THRESHOLD", default: Some("1s") },
+    Setting { key: "database_run_migrations", env: "DATABASE_RUN_MIGRATIONS", default: Some("true") },
+    Setting { key: "log_level", env: "LOG_LEVEL", default: Some("info") },
+    Setting { key: "log_format", env: "LOG_FORMAT", default: Some("json") },
+    Setting { key: "jwt_algorithm", env: "JWT_ALGORITHM", default: Some("HS256") },
+    Setting { key: "jwt_secret", env: "JWT_SECRET", default: None },
+    Setting { key: "jwt_private_key_file", env: "JWT_PRIVATE_KEY_FILE", default: None },
+    Setting { key: "jwt_key_id", env: "JWT_KEY_ID", default: Some("default") },
+    Setting { key: "jwt_previous_keys", env: "JWT_PREVIOUS_KEYS", default: None },
+    Setting { key: "jwt_issuer", env: "JWT_ISSUER", default: Some("shopping") },
+    Setting { key: "jwt_audience", env: "JWT_AUDIENCE", default: Some("shopping") },
+    Setting { key: "jwt_expires_in", env: "JWT_EXPIRED_IN", default: Some("60m") },
+    Setting { key: "jwt_maxage", env: "JWT_MAXAGE", default: Some("60m") },
+    Setting { key: "totp_issuer", env: "TOTP_ISSUER", default: Some("Shopping") },
+    Setting { key: "oidc_enabled", env: "OIDC_ENABLED", default: Some("false") },
+    Setting { key: "oidc_provider", env: "OIDC_PROVIDER", default: Some("oidc") },
+    Setting { key: "oidc_issuer_url", env: "OIDC_ISSUER_URL", default: None },
+    Setting { key: "oidc_client_id", env: "OIDC_CLIENT_ID", default: None },
+    Setting { key: "oidc_client_secret", env: "OIDC_CLIENT_SECRET", default: None },
+    Setting { key: "oidc_redirect_url", env: "OIDC_REDIRECT_URL", default: None },
+    Setting { key: "oidc_scopes", env: "OIDC_SCOPES", default: Some("openid,email,profile") },
+    Setting { key: "login_throttle_store", env: "LOGIN_THROTTLE_STORE", default: Some("memory") },
+    Setting { key: "login_max_failures", env: "LOGIN_MAX_FAILURES", default: Some("5") },
+    Setting { key: "login_ip_max_failures", env: "LOGIN_IP_MAX_FAILURES", default: Some("50") },
+    Setting { key: "login_failure_window", env: "LOGIN_FAILURE_WINDOW", default: Some("15m") },
+    Setting { key: "login_lockout_duration", env: "LOGIN_LOCKOUT_DURATION", default: Some("15m") },
+    Setting { key: "login_backoff_base", env: "LOGIN_BACKOFF_BASE", default: Some("1s") },
+    Setting { key: "account_deletion_grace_period", env: "ACCOUNT_DELETION_GRACE_PERIOD", default: Some("14d") },
+    Setting { key: "blob_store_kind", env: "BLOB_STORE_KIND", default: Some("local") },
+    Setting { key: "blob_store_path", env: "BLOB_STORE_PATH", default: Some("data/blobs") },
+    Setting { key: "blob_store_s3_endpoint", env: "BLOB_STORE_S3_ENDPOINT", default: None },
+    Setting { key: "blob_store_s3_bucket", env: "BLOB_STORE_S3_BUCKET", default: None },
+    Setting { key: "blob_store_s3_region", env: "BLOB_STORE_S3_REGION", default: Some("us-east-1") },
+    Setting { key: "blob_store_s3_access_key_id", env: "BLOB_STORE_S3_ACCESS_KEY_ID", default: None },
+    Setting { key: "blob_store_s3_secret_access_key", env: "BLOB_STORE_S3_SECRET_ACCESS_KEY", default: None },
+    Setting { key: "blob_store_s3_path_style", env: "BLOB_STORE_S3_PATH_STYLE", default: Some("false") },
+    Setting { key: "upload_max_bytes", env: "UPLOAD_MAX_BYTES", default: Some("5242880") },
+    Setting { key: "product_image_thumbnail_sizes", env: "PRODUCT_IMAGE_THUMBNAIL_SIZES", default: Some("160,480,1024") },
+    Setting { key: "product_import_max_bytes", env: "PRODUCT_IMPORT_MAX_BYTES", default: Some("20971520") },
+    Setting { key: "catalog_purge_retention", env: "CATALOG_PURGE_RETENTION", default: Some("30d") },
+    Setting { key: "product_publish_interval", env: "PRODUCT_PUBLISH_INTERVAL", default: Some("1m") },
+    Setting { key: "download_secret", env: "DOWNLOAD_SECRET", default: None },
+    Setting { key: "download_link_ttl", env: "DOWNLOAD_LINK_TTL", default: Some("15m") },
+    Setting { key: "download_max_downloads", env: "DOWNLOAD_MAX_DOWNLOADS", default: Some("5") },
+    Setting { key: "download_max_bytes", env: "DOWNLOAD_MAX_BYTES", default: Some("524288000") },
+    Setting { key: "rate_limit_enabled", env: "RATE_LIMIT_ENABLED", default: Some("true") },
+    Setting { key: "rate_limit_anonymous", env: "RATE_LIMIT_ANONYMOUS", default: Some("60/1m") },
+    Setting { key: "rate_limit_authenticated", env: "RATE_LIMIT_AUTHENTICATED", default: Some("300/1m") },
+    Setting { key: "rate_limit_admin", env: "RATE_LIMIT_ADMIN", default: Some("1000/1m") },
+    Setting { key: "rate_limit_api_key", env: "RATE_LIMIT_API_KEY", default: Some("1000/1m") },
+    Setting { key: "rate_limit_routes", env: "RATE_LIMIT_ROUTES", default: Some("POST /auth/register=5/1h") },
+    Setting { key: "smtp_host", env: "SMTP_HOST", default: None },
+    Setting { key: "smtp_port", env: "SMTP_PORT", default: Some("587") },
+    Setting { key: "smtp_user", env: "SMTP_USER", default: None },
+    Setting { key: "smtp_pass", env: "SMTP_PASS", default: None },
+    Setting { key: "smtp_from", env: "SMTP_FROM", default: None },
+];
+
+/// An external identity provider, only present when `oidc_enabled` is set.
+#[derive(Debug, Clone)]
+pub struct OidcConfig {
+    /// Stored with each linked identity, so keep it stable once users have signed in.
+    pub provider_name: String,
+    pub issuer_url: String,
+    pub client_id: String,
+    pub client_secret: String,
+    pub redirect_url: String,
+    pub scopes: Vec<String>,
+}
+
+/// Bucket settings, only present when `blob_store_kind` is `s3`.
+#[derive(Clone)]
+pub struct S3Config {
+    pub endpoint: String,
+    pub bucket: String,
+    pub region: String,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub path_style: bool,
+}
+
+/// Keeps the secret out of logs.
+impl fmt::Debug for S3Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("S3Config")
+            .field("endpoint", &self.endpoint)
+            .field("bucket", &self.bucket)
+            .field("region", &self.region)
+            .field("access_key_id", &self.access_key_id)
+            .field("path_style", &self.path_style)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Json,
+    Pretty,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(LogFormat::Json),
+            "pretty" => Ok(LogFormat::Pretty),
+            _ => Err("expected `json` or `pretty`".to_string()),
+        }
+    }
+}
+
+const DEFAULT_CONFIG_FILE: &str = "config.toml";
+
+/// Deletion requests must be honoured within 30 days; leave the purge job a day of slack.
+const MAX_DELETION_GRACE_PERIOD: Duration = Duration::from_secs(29 * 24 * 60 * 60);
+
+/// Every problem found while loading the configuration, reported together.
+#[derive(Debug)]
+pub struct ConfigError(pub Vec<String>);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid configuration ({} problem(s)):", self.0.len())?;
+        for error in &self.0 {
+            write!(f, "\n  - {}", error)?;
+        }
+        Ok(())
+    }
 }
 
+impl std::error::Error for ConfigError {}
+
 impl Config {
-    pub fn init() -> Config {
-        let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-        let jwt_secret = std::env::var("JWT_SECRET").expect("JWT_SECRET must be set");
-        let jwt_expires_in = std::env::var("JWT_EXPIRED_IN").expect("JWT_EXPIRED_IN must be set");
-        let jwt_maxage = std::env::var("JWT_MAXAGE").expect("JWT_MAXAGE must be set");
-
-        let smtp_host = std::env::var("SMTP_HOST").expect("SMTP_HOST must be set");
-        let smtp_port = std::env::var("SMTP_PORT").expect("SMTP_PORT must be set");
-        let smtp_user = std::env::var("SMTP_USER").expect("SMTP_USER must be set");
-        let smtp_pass = std::env::var("SMTP_PASS").expect("SMTP_PASS must be set");
-        let smtp_from = std::env::var("SMTP_FROM").expect("SMTP_FROM must be set");
-
-        Config {
-            database_url,
-            jwt_secret,
-            jwt_expires_in,
-            jwt_maxage: jwt_maxage.parse::<i32>().unwrap(),
-            smtp_host,
-            smtp_pass,
-            smtp_user,
-            smtp_port: smtp_port.parse::<u16>().unwrap(),
-            smtp_from,
+    pub fn load() -> Result<Config, ConfigError> {
+        let args: Vec<String> = std::env::args().skip(1).collect();
+        Self::load_from(&args)
+    }
+
+    pub fn load_from(args: &[String]) -> Result<Config, ConfigError> {
+        Self::load_from_env(args, &std::env::vars().collect())
+    }
+
+    /// Like [`Config::load_from`], reading environment variables from `env`
+    /// instead of the process environment.
+    pub fn load_from_env(args: &[String], env: &HashMap<String, String>) -> Result<Config, ConfigError> {
+        let mut loader = Loader::default();
+
+        for setting in SETTINGS {
+            if let Some(default) = setting.default {
+                loader.values.insert(setting.key.to_string(), default.to_string());
+            }
+        }
+
+        let cli = loader.parse_args(args);
+
+        let explicit_file = cli
+            .get("config")
+            .cloned()
+            .or_else(|| env.get("CONFIG_FILE").cloned());
+        match explicit_file {
+            Some(path) => loader.merge_file(&path),
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => loader.merge_file(DEFAULT_CONFIG_FILE),
+            None => {}
+        }
+
+        for setting in SETTINGS {
+            if let Some(value) = env.get(setting.env) {
+                loader.values.insert(setting.key.to_string(), value.clone());
+            }
+        }
+
+        for (key, value) in cli {
+            if key != "config" {
+                loader.values.insert(key, value);
+            }
+        }
+
+        let bind_addr = loader.parse("bind_addr");
+        let shutdown_timeout = loader.duration("shutdown_timeout");
+        let health_check_timeout = loader.duration("health_check_timeout");
+        let health_email_check_interval = loader.duration("health_email_check_interval");
+        let cors_origins = loader.cors_origins("cors_origins");
+        let trust_forwarded_headers = loader.parse("trust_forwarded_headers");
+        let database_url = loader.string("database_url");
+        let database_max_connections = loader.parse("database_max_connections");
+        let database_slow_statement_threshold = loader.duration("database_slow_statement_threshold");
+        let database_run_migrations = loader.parse("database_run_migrations");
+        let log_level = loader.log_filter("log_level");
+        let log_format = loader.parse("log_format");
+        let jwt_algorithm = loader.parse("jwt_algorithm");
+        let (jwt_secret, jwt_private_key_file) = loader.jwt_signing_key(jwt_algorithm);
+        let jwt_key_id = loader.string("jwt_key_id");
+        let jwt_previous_keys = loader.parse_list("jwt_previous_keys");
+        let jwt_issuer = loader.string("jwt_issuer");
+        let jwt_audience = loader.string("jwt_audience");
+        let jwt_expires_in = loader.duration("jwt_expires_in");
+        let jwt_maxage = loader.duration("jwt_maxage");
+        let totp_issuer = loader.totp_issuer("totp_issuer");
+        let oidc = loader.oidc();
+        let login_throttle_store = loader.parse("login_throttle_store");
+        let login_max_failures = loader.parse("login_max_failures");
+        let login_ip_max_failures = loader.parse("login_ip_max_failures");
+        let login_failure_window = loader.duration("login_failure_window");
+        let login_lockout_duration = loader.duration("login_lockout_duration");
+        let login_backoff_base = loader.duration("login_backoff_base");
+        let account_deletion_grace_period =
+            loader.duration_at_most("account_deletion_grace_period", MAX_DELETION_GRACE_PERIOD);
+        let blob_store_kind = loader.parse("blob_store_kind");
+        let blob_store_path = loader.parse("blob_store_path");
+        let blob_store_s3 = loader.s3(blob_store_kind);
+        let upload_max_bytes = loader.parse("upload_max_bytes");
+        let product_image_thumbnail_sizes = loader.thumbnail_sizes("product_image_thumbnail_sizes");
+        let product_import_max_bytes = loader.parse("product_import_max_bytes");
+        let catalog_purge_retention = loader.duration("catalog_purge_retention");
+        let product_publish_interval = loader.duration("product_publish_interval");
+        let download_secret = loader.optional("download_secret");
+        let download_link_ttl = loader.duration("download_link_ttl");
+        let download_max_downloads = loader.parse("download_max_downloads");
+        let download_max_bytes = loader.parse("download_max_bytes");
+        let rate_limit_enabled = loader.parse("rate_limit_enabled");
+        let rate_limit_anonymous = loader.parse("rate_limit_anonymous");
+        let rate_limit_authenticated = loader.parse("rate_limit_authenticated");
+        let rate_limit_admin = loader.parse("rate_limit_admin");
+        let rate_limit_api_key = loader.parse("rate_limit_api_key");
+        let rate_limit_routes = loader.parse_list("rate_limit_routes");
+        let smtp_host = loader.string("smtp_host");
+        let smtp_port = loader.parse("smtp_port");
+        let smtp_user = loader.string("smtp_user");
+        let smtp_pass = loader.string("smtp_pass");
+        let smtp_from = loader.parse("smtp_from");
+
+        let config = (|| {
+            Some(Config {
+                bind_addr: bind_addr?,
+                shutdown_timeout: shutdown_timeout?,
+                health_check_timeout: health_check_timeout?,
+                health_email_check_interval: health_email_check_interval?,
+                cors_origins: cors_origins?,
+                trust_forwarded_headers: trust_forwarded_headers?,
+                database_url: database_url?,
+                database_max_connections: database_max_connections?,
+                database_slow_statement_threshold: database_slow_statement_threshold?,
+                database_run_migrations: database_run_migrations?,
+                log_level: log_level?,
+                log_format: log_format?,
+                jwt_algorithm: jwt_algorithm?,
+                jwt_secret,
+                jwt_private_key_file,
+                jwt_key_id: jwt_key_id?,
+                jwt_previous_keys: jwt_previous_keys?,
+                jwt_issuer: jwt_issuer?,
+                jwt_audience: jwt_audience?,
+                jwt_expires_in: jwt_expires_in?,
+                jwt_maxage: jwt_maxage?,
+                totp_issuer: totp_issuer?,
+                oidc: oidc?,
+                login_throttle_store: login_throttle_store?,
+                login_max_failures: login_max_failures?,
+                login_ip_max_failures: login_ip_max_failures?,
+                login_failure_window: login_failure_window?,
+                login_lockout_duration: login_lockout_duration?,
+                login_backoff_base: login_backoff_base?,
+                account_deletion_grace_period: account_deletion_grace_period?,
+                blob_store_kind: blob_store_kind?,
+                blob_store_path: blob_store_path?,
+                blob_store_s3: blob_store_s3?,
+                upload_max_bytes: upload_max_bytes?,
+                product_image_thumbnail_sizes: product_image_thumbnail_sizes?,
+                product_import_max_bytes: product_import_max_bytes?,
+                catalog_purge_retention: catalog_purge_retention?,
+                product_publish_interval: product_publish_interval?,
+                download_secret,
+                download_link_ttl: download_link_ttl?,
+                download_max_downloads: download_max_downloads?,
+                download_max_bytes: download_max_bytes?,
+                rate_limit_enabled: rate_limit_enabled?,
+                rate_limit_anonymous: rate_limit_anonymous?,
+                rate_limit_authenticated: rate_limit_authenticated?,
+                rate_limit_admin: rate_limit_admin?,
+                rate_limit_api_key: rate_limit_api_key?,
+                rate_limit_routes: rate_limit_routes?,
+                smtp_host: smtp_host?,
+                smtp_port: smtp_port?,
+                smtp_user: smtp_user?,
+                smtp_pass: smtp_pass?,
+                smtp_from: smtp_from?,
+            })
+        })();
+
+        match config {
+            Some(config) if loader.errors.is_empty() => Ok(config),
+            _ => Err(ConfigError(loader.errors)),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Loader {
+    values: HashMap<String, String>,
+    errors: Vec<String>,
+}
+
+impl Loader {
+    fn is_known(key: &str) -> bool {
+        SETTINGS.iter().any(|setting| setting.key == key)
+    }
+
+    fn env_name(key: &str) -> &'static str {
+        SETTINGS
+            .iter()
+            .find(|setting| setting.key == key)
+            .map(|setting| setting.env)
+            .unwrap_or("")
+    }
+
+    /// Accepts `--some-key value` and `--some-key=value`; `--config` selects the TOML file.
+    fn parse_args(&mut self, args: &[String]) -> HashMap<String, String> {
+        let mut values = HashMap::new();
+        let mut args = args.iter();
+
+        while let Some(arg) = args.next() {
+            let Some(flag) = arg.strip_prefix("--") else {
+                self.errors.push(format!("unexpected argument `{}`", arg));
+                continue;
+            };
+
+            let (name, value) = match flag.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (flag, args.next().cloned()),
+            };
+            let key = name.replace('-', "_");
+
+            if key != "config" && !Self::is_known(&key) {
+                self.errors.push(format!("unknown flag `--{}`", name));
+                continue;
+            }
+            match value {
+                Some(value) => {
+                    values.insert(key, value);
+                }
+                None => self.errors.push(format!("flag `--{}` requires a value", name)),
+            }
+        }
+
+        values
+    }
+
+    /// Nested tables are flattened with `_`, so `[smtp] host = ".."` sets `smtp_host`.
+    fn merge_file(&mut self, path: &str) {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) => {
+                self.errors.push(format!("could not read config file `{}`: {}", path, e));
+                return;
+            }
+        };
+
+        match contents.parse::<toml::Table>() {
+            Ok(table) => self.merge_table(path, "", &table),
+            Err(e) => self.errors.push(format!("could not parse config file `{}`: {}", path, e)),
         }
     }
+
+    fn merge_table(&mut self, path: &str, prefix: &str, table: &toml::Table) {
+        for (name, value) in table {
+            let key = if prefix.is_empty() {
+                name.to_owned()
+            } else {
+                format!("{}_{}", prefix, name)
+            };
+
+            if let toml::Value::Table(nested) = value {
+                self.merge_table(path, &key, nested);
+            } else if Self::is_known(&key) {
+                self.values.insert(key, toml_to_string(value));
+            } else {
+                self.errors.push(format!("unknown setting `{}` in `{}`", key, path));
+            }
+        }
+    }
+
+    fn string(&mut self, key: &str) -> Option<String> {
+        match self.values.get(key) {
+            Some(value) if !value.trim().is_empty() => Some(value.to_owned()),
+            _ => {
+                self.errors
+                    .push(format!("{} ({}) must be set", key, Self::env_name(key)));
+                None
+            }
+        }
+    }
+
+    fn parse<T>(&mut self, key: &str) -> Option<T>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        let value = self.string(key)?;
+        value
+            .trim()
+            .parse::<T>()
+            .map_err(|e| self.errors.push(format!("{}: invalid value `{}`: {}", key, value, e)))
+            .ok()
+    }
+
+    fn duration(&mut self, key: &str) -> Option<Duration> {
+        let value = self.string(key)?;
+        parse_duration(&value)
+            .map_err(|e| self.errors.push(format!("{}: invalid duration `{}`: {}", key, value, e)))
+            .ok()
+    }
+
+    fn duration_at_most(&mut self, key: &str, max: Duration) -> Option<Duration> {
+        let duration = self.duration(key)?;
+        if duration > max {
+            self.errors.push(format!(
+                "{}: must not exceed {} days",
+                key,
+                max.as_secs() / (24 * 60 * 60)
+            ));
+            return None;
+        }
+        Some(duration)
+    }
+
+    /// Authenticator apps split the label on `:`, so the issuer must not contain one.
+    fn totp_issuer(&mut self, key: &str) -> Option<String> {
+        let value = self.string(key)?;
+        if value.contains(':') {
+            self.errors.push(format!("{}: `{}` must not contain `:`", key, value));
+            return None;
+        }
+        Some(value)
+    }
+
+    /// HS256 needs `jwt_secret`, the asymmetric algorithms a private key file.
+    fn jwt_signing_key(
+        &mut self,
+        algorithm: Option<JwtAlgorithm>,
+    ) -> (Option<String>, Option<PathBuf>) {
+        let secret = self.optional("jwt_secret");
+        let key_file = self.optional("jwt_private_key_file").map(PathBuf::from);
+
+        match algorithm {
+            Some(JwtAlgorithm::Hs256) if secret.is_none() => {
+                self.string("jwt_secret");
+            }
+            Some(JwtAlgorithm::Rs256 | JwtAlgorithm::EdDsa) if key_file.is_none() => {
+                self.string("jwt_private_key_file");
+            }
+            _ => {}
+        }
+        (secret, key_file)
+    }
+
+    fn optional(&self, key: &str) -> Option<String> {
+        self.values
+            .get(key)
+            .filter(|value| !value.trim().is_empty())
+            .cloned()
+    }
+
+    /// The provider settings are only required once `oidc_enabled` is set.
+    fn oidc(&mut self) -> Option<Option<OidcConfig>> {
+        if !self.parse::<bool>("oidc_enabled")? {
+            return Some(None);
+        }
+
+        let provider_name = self.string("oidc_provider");
+        let issuer_url = self.url("oidc_issuer_url");
+        let client_id = self.string("oidc_client_id");
+        let client_secret = self.string("oidc_client_secret");
+        let redirect_url = self.url("oidc_redirect_url");
+        let scopes = self.list("oidc_scopes");
+
+        Some(Some(OidcConfig {
+            provider_name: provider_name?,
+            issuer_url: issuer_url?,
+            client_id: client_id?,
+            client_secret: client_secret?,
+            redirect_url: redirect_url?,
+            scopes: scopes?,
+        }))
+    }
+
+    /// The bucket settings are only required when blobs are kept in S3.
+    fn s3(&mut self, kind: Option<BlobStoreKind>) -> Option<Option<S3Config>> {
+        if kind? != BlobStoreKind::S3 {
+            return Some(None);
+        }
+
+        let endpoint = self.url("blob_store_s3_endpoint");
+        let bucket = self.string("blob_store_s3_bucket");
+        let region = self.string("blob_store_s3_region");
+        let access_key_id = self.string("blob_store_s3_access_key_id");
+        let secret_access_key = self.string("blob_store_s3_secret_access_key");
+        let path_style = self.parse("blob_store_s3_path_style");
+
+        Some(Some(S3Config {
+            endpoint: endpoint?,
+            bucket: bucket?,
+            region: region?,
+            access_key_id: access_key_id?,
+            secret_access_key: secret_access_key?,
+            path_style: path_style?,
+        }))
+    }
+
+    /// Longest edges in pixels; at least one, none larger than the images we decode.
+    fn thumbnail_sizes(&mut self, key: &str) -> Option<Vec<u32>> {
+        let mut sizes: Vec<u32> = self.parse_list(key)?;
+        sizes.sort_unstable();
+        sizes.dedup();
+        if sizes.is_empty() || sizes.iter().any(|&size| size == 0 || size > 4096) {
+            self.errors
+                .push(format!("{}: expected one or more sizes between 1 and 4096", key));
+            return None;
+        }
+        Some(sizes)
+    }
+
+    fn url(&mut self, key: &str) -> Option<String> {
+        let value = self.string(key)?;
+        if !(value.starts_with("http://") || value.starts_with("https://")) {
+            self.errors.push(format!("{}: `{}` is not an http(s) URL", key, value));
+            return None;
+        }
+        Some(value)
+    }
+
+    fn log_filter(&mut self, key: &str) -> Option<String> {
+        let value = self.string(key)?;
+        tracing_subscriber::EnvFilter::try_new(&value)
+            .map_err(|e| self.errors.push(format!("{}: invalid filter `{}`: {}", key, value, e)))
+            .ok()?;
+        Some(value)
+    }
+
+    fn list(&mut self, key: &str) -> Option<Vec<String>> {
+        let value = self.string(key)?;
+        Some(
+            value
+                .split(',')
+                .map(|item| item.trim().to_string())
+                .filter(|item| !item.is_empty())
+                .collect(),
+        )
+    }
+
+    /// Like [`Loader::list`], but parses every item and allows the list to be empty.
+    fn parse_list<T>(&mut self, key: &str) -> Option<Vec<T>>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        let value = self.values.get(key).cloned().unwrap_or_default();
+        let mut parsed = Vec::new();
+        for item in value.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+            match item.parse::<T>() {
+                Ok(item) => parsed.push(item),
+                Err(e) => self
+                    .errors
+                    .push(format!("{}: invalid entry `{}`: {}", key, item, e)),
+            }
+        }
+        Some(parsed)
+    }
+
+    fn cors_origins(&mut self, key: &str) -> Option<Vec<HeaderValue>> {
+        let origins = self.list(key)?;
+        let mut parsed = Vec::with_capacity(origins.len());
+        for origin in origins {
+            match origin.parse::<HeaderValue>() {
+                Ok(value) if origin.starts_with("http://") || origin.starts_with("https://") => {
+                    parsed.push(value)
+                }
+                _ => self
+                    .errors
+                    .push(format!("{}: `{}` is not a valid origin", key, origin)),
+            }
+        }
+        Some(parsed)
+    }
+}
+
+fn toml_to_string(value: &toml::Value) -> String {
+    match value {
+        toml::Value::String(s) => s.to_owned(),
+        toml::Value::Array(items) => items
+            .iter()
+            .map(toml_to_string)
+            .collect::<Vec<_>>()
+            .join(","),
+        other => other.to_string(),
+    }
+}
+
+/// Parses durations such as `30s`, `15m`, `1h` or `7d`. A bare number is read as minutes,
+/// which keeps the old `JWT_MAXAGE=60` style values working.
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (amount, unit) = value.split_at(split);
+
+    let amount: u64 = amount
+        .parse()
+        .map_err(|_| "expected a number followed by s, m, h or d".to_string())?;
+    let seconds = match unit.trim() {
+        "s" => 1,
+        "" | "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        other => return Err(format!("unknown unit `{}`", other)),
+    };
+
+    amount
+        .checked_mul(seconds)
+        .map(Duration::from_secs)
+        .ok_or_else(|| "too long".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::example_config;
+
+    use super::*;
+
+    #[test]
+    fn loads_the_example_config() {
+        let config = example_config();
+
+        assert_eq!(config.cors_origins, ["http://localhost:3000"]);
+        assert!(!config.trust_forwarded_headers);
+        assert_eq!(config.download_secret.as_deref(), Some("change-me-too"));
+    }
+
+    #[test]
+    fn starts_without_a_download_secret() {
+        let args = ["--config".to_string(), "config.example.toml".to_string()];
+        let env = HashMap::from([("DOWNLOAD_SECRET".to_string(), String::new())]);
+
+        let config = Config::load_from_env(&args, &env).unwrap_or_else(|e| panic!("{}", e));
+
+        assert_eq!(config.download_secret, None);
+    }
+
+    fn merged(contents: &str) -> Loader {
+        let mut loader = Loader::default();
+        loader.merge_table("test.toml", "", &contents.parse::<toml::Table>().unwrap());
+        loader
+    }
+
+    #[test]
+    fn flattens_nested_tables() {
+        let loader = merged(
+            r#"
+            bind_addr = "0.0.0.0:8000"
+            cors_origins = ["http://a.example", "https://b.example"]
+            [database]
+            max_connections = 5
+            "#,
+        );
+
+        assert!(loader.errors.is_empty(), "{:?}", loader.errors);
+        assert_eq!(loader.values["bind_addr"], "0.0.0.0:8000");
+        assert_eq!(loader.values["database_max_connections"], "5");
+        assert_eq!(loader.values["cors_origins"], "http://a.example,https://b.example");
+    }
+
+    #[test]
+    fn reports_every_unknown_setting() {
+        let loader = merged(
+            r#"
+            colour = "blue"
+            [database]
+            size = 5
+            "#,
+        );
+
+        assert_eq!(
+            loader.errors,
+            [
+                "unknown setting `colour` in `test.toml`",
+                "unknown setting `database_size` in `test.toml`",
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_command_line_flags() {
+        let mut loader = Loader::default();
+        let args: Vec<String> = ["--smtp-port", "2525", "--bind-addr=0.0.0.0:9000", "--colour", "blue", "stray"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+
+        let values = loader.parse_args(&args);
+
+        assert_eq!(values["smtp_port"], "2525");
+        assert_eq!(values["bind_addr"], "0.0.0.0:9000");
+        assert_eq!(loader.errors, ["unknown flag `--colour`", "unexpected argument `stray`"]);
+    }
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("30s"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("15m"), Ok(Duration::from_secs(15 * 60)));
+        assert_eq!(parse_duration("60"), Ok(Duration::from_secs(60 * 60)));
+        assert_eq!(parse_duration(" 2h "), Ok(Duration::from_secs(2 * 60 * 60)));
+        assert_eq!(parse_duration("7d"), Ok(Duration::from_secs(7 * 24 * 60 * 60)));
+        assert!(parse_duration("1w").is_err());
+        assert_eq!(parse_duration("99999999999999999d"), Err("too long".to_string()));
+        assert!(parse_duration("99999999999999999999999").is_err());
+        assert!(parse_duration("m").is_err());
+    }
 }
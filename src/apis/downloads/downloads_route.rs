@@ -0,0 +1,92 @@
+use std::sync::Arc;
+
+use axum::{
+    http::{Request, StatusCode},
+    middleware::{self, Next},
+    response::Response,
+    routing::{get, post},
+    Extension, Json, Router,
+};
+
+use crate::apis::{
+    downloads::handler::{
+        create_grant_handler, download_handler, list_downloads_handler, revoke_grant_handler,
+    },
+    jwt_auth::{auth, authenticate, require_catalog_manager, Principal},
+    login::response::ErrorResponse,
+};
+
+use crate::AppState;
+
+pub fn downloads_router(app_state: Arc<AppState>) -> Router {
+    let mine = Router::new()
+        .route("/me/downloads", get(list_downloads_handler))
+        .route_layer(middleware::from_fn_with_state(app_state.clone(), auth));
+    // Authorised by the link's signature rather than a session.
+    let public = Router::new().route("/downloads/:grant_id/:file_id", get(download_handler));
+    // Called by the order flow with an API key, or by an administrator.
+    let grants = Router::new()
+        .route("/admin/download-grants", post(create_grant_handler))
+        .route("/admin/download-grants/:id/revoke", post(revoke_grant_handler))
+        .route_layer(middleware::from_fn(orders_scope))
+        .route_layer(middleware::from_fn(require_catalog_manager))
+        .route_layer(middleware::from_fn_with_state(app_state.clone(), authenticate));
+
+    mine.merge(public).merge(grants).with_state(app_state)
+}
+
+/// Grants follow orders, so keys need `orders:write`.
+async fn orders_scope<B>(
+    Extension(principal): Extension<Principal>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    if !principal.has_scope("orders:write") {
+        tracing::info!(%principal, scope = "orders:write", "missing scope");
+        let json_error = ErrorResponse {
+            status: "fail",
+            message: "This API key lacks the `orders:write` scope".to_string(),
+        };
+        return Err((StatusCode::FORBIDDEN, Json(json_error)));
+    }
+    Ok(next.run(req).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{example_config, serve, session_token, ScratchDb};
+
+    #[tokio::test]
+    async fn grants_need_an_administrator_or_a_key_with_orders_write() {
+        let Some(db) = ScratchDb::migrated().await else {
+            return;
+        };
+        let data = db.state(example_config());
+        let url = serve(downloads_router(data.clone())).await;
+        let admin = db.user("admin@example.com", "password", "admin").await;
+        let customer = db.user("customer@example.com", "password", "user").await;
+        let (_, orders_key) = db.api_key(&["orders:write"]).await;
+        let (_, catalog_key) = db.api_key(&["catalog:write", "orders:read"]).await;
+
+        let client = reqwest::Client::new();
+        let grant = |authorization: String| {
+            client
+                .post(format!("{}/admin/download-grants/1/revoke", url))
+                .header("authorization", authorization)
+                .send()
+        };
+        let status = |response: reqwest::Result<reqwest::Response>| response.unwrap().status();
+
+        // Past the checks, the handler reports the missing grant.
+        let admin_token = session_token(&data, admin, "admin");
+        assert_eq!(status(grant(format!("Bearer {}", admin_token)).await), 404);
+        assert_eq!(status(grant(format!("ApiKey {}", orders_key)).await), 404);
+
+        let customer_token = session_token(&data, customer, "user");
+        assert_eq!(status(grant(format!("Bearer {}", customer_token)).await), 403);
+        assert_eq!(status(grant(format!("ApiKey {}", catalog_key)).await), 403);
+        assert_eq!(status(grant("ApiKey shp_unknown_secret".to_string()).await), 401);
+        db.drop().await;
+    }
+}
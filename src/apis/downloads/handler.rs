@@ -0,0 +1,325 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use axum::{
+    body::StreamBody,
+    extract::{Path, Query, State},
+    http::{header, HeaderValue, StatusCode},
+    response::IntoResponse,
+    Extension, Json,
+};
+use chrono::Utc;
+use serde_json::json;
+
+use crate::apis::downloads::model::{
+    download_url, verify_link, CreateGrantSchema, DownloadGrant, DownloadQuery, ProductFile,
+    MAX_DOWNLOADS,
+};
+use crate::apis::login::{model::User, response::ErrorResponse};
+use crate::apis::v1::lifecycle::NOT_DELETED;
+
+use crate::AppState;
+
+type HandlerError = (StatusCode, Json<ErrorResponse>);
+
+/// The caller's purchases of digital products, newest first. Grants that can
+/// still be used come with a fresh link for each file, unless links are
+/// disabled because `download_secret` is unset.
+pub async fn list_downloads_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, HandlerError> {
+    let grants: Vec<DownloadGrant> =
+        sqlx::query_as("SELECT * FROM download_grants WHERE user_id = $1 ORDER BY id DESC")
+            .bind(user.id)
+            .fetch_all(&data.db)
+            .await
+            .map_err(database_error)?;
+    let product_ids: Vec<i32> = grants.iter().map(|grant| grant.product_id).collect();
+
+    let names: HashMap<i32, (String, String)> =
+        sqlx::query_as::<_, (i32, String, String)>("SELECT id, name, slug FROM products WHERE id = ANY($1)")
+            .bind(&product_ids)
+            .fetch_all(&data.db)
+            .await
+            .map_err(database_error)?
+            .into_iter()
+            .map(|(id, name, slug)| (id, (name, slug)))
+            .collect();
+    let mut files: HashMap<i32, Vec<ProductFile>> = HashMap::new();
+    let rows: Vec<ProductFile> =
+        sqlx::query_as("SELECT * FROM product_files WHERE product_id = ANY($1) ORDER BY id")
+            .bind(&product_ids)
+            .fetch_all(&data.db)
+            .await
+            .map_err(database_error)?;
+    for file in rows {
+        files.entry(file.product_id).or_default().push(file);
+    }
+
+    let now = Utc::now();
+    let link_expires = now
+        + chrono::Duration::from_std(data.config.download_link_ttl)
+            .unwrap_or(chrono::Duration::zero());
+    let downloads: Vec<_> = grants
+        .iter()
+        .map(|grant| {
+            let usable = grant.is_usable(now);
+            let (name, slug) = names.get(&grant.product_id).cloned().unwrap_or_default();
+            let files: Vec<_> = files
+                .get(&grant.product_id)
+                .map(Vec::as_slice)
+                .unwrap_or_default()
+                .iter()
+                .map(|file| {
+                    let url = data
+                        .config
+                        .download_secret
+                        .as_deref()
+                        .filter(|_| usable)
+                        .map(|secret| download_url(secret, grant.id, file.id, link_expires.timestamp()));
+                    json!({
+                        "id": file.id,
+                        "file_name": file.file_name,
+                        "content_type": file.content_type,
+                        "size_bytes": file.size_bytes,
+                        "sha256": file.sha256,
+                        "url": url,
+                        "url_expires_at": url.is_some().then_some(link_expires),
+                    })
+                })
+                .collect();
+            json!({
+                "id": grant.id,
+                "order_reference": grant.order_reference,
+                "product": {"id": grant.product_id, "name": name, "slug": slug},
+                "max_downloads": grant.max_downloads,
+                "remaining_downloads": grant.remaining(),
+                "expires_at": grant.expires_at,
+                "revoked": grant.revoked_at.is_some(),
+                "files": files,
+            })
+        })
+        .collect();
+
+    Ok(Json(json!({
+        "status": "success",
+        "results": downloads.len(),
+        "data": downloads,
+    })))
+}
+
+/// Serves a file through a signed link from [`list_downloads_handler`]. No
+/// session is needed; the signature stands in for one. Each download counts
+/// against the grant's limit.
+pub async fn download_handler(
+    State(data): State<Arc<AppState>>,
+    Path((grant_id, file_id)): Path<(i32, i32)>,
+    Query(query): Query<DownloadQuery>,
+) -> Result<impl IntoResponse, HandlerError> {
+    let Some(secret) = data.config.download_secret.as_deref() else {
+        return Err(fail(StatusCode::NOT_FOUND, "Download links are disabled"));
+    };
+    if !verify_link(secret, grant_id, file_id, query.expires, &query.signature) {
+        return Err(fail(StatusCode::FORBIDDEN, "Invalid download link"));
+    }
+    if query.expires < Utc::now().timestamp() {
+        return Err(fail(StatusCode::GONE, "This download link has expired"));
+    }
+
+    let file: ProductFile = sqlx::query_as(
+        r#"SELECT f.* FROM product_files f
+        JOIN download_grants g ON g.product_id = f.product_id
+        WHERE g.id = $1 AND f.id = $2"#,
+    )
+    .bind(grant_id)
+    .bind(file_id)
+    .fetch_optional(&data.db)
+    .await
+    .map_err(database_error)?
+    .ok_or_else(|| fail(StatusCode::NOT_FOUND, "Download not found"))?;
+
+    // Counted up front in one statement, so concurrent downloads cannot overrun
+    // the limit and no row stays locked while the file is sent.
+    let user_id: i32 = sqlx::query_scalar(
+        r#"UPDATE download_grants SET downloads_used = downloads_used + 1
+        WHERE id = $1
+            AND revoked_at IS NULL
+            AND (expires_at IS NULL OR expires_at > NOW())
+            AND downloads_used < max_downloads
+        RETURNING user_id"#,
+    )
+    .bind(grant_id)
+    .fetch_optional(&data.db)
+    .await
+    .map_err(database_error)?
+    .ok_or_else(|| {
+        fail(
+            StatusCode::FORBIDDEN,
+            "This purchase has no downloads left, has expired or was revoked",
+        )
+    })?;
+
+    let stream = match data.blobs.get_stream(&file.blob_key).await {
+        Ok(Some(stream)) => stream,
+        failed => {
+            refund_download(&data, grant_id).await;
+            return Err(match failed {
+                Err(e) => {
+                    tracing::error!(error = %e, key = file.blob_key, "could not read download");
+                    fail(StatusCode::INTERNAL_SERVER_ERROR, "Could not read file")
+                }
+                _ => {
+                    tracing::error!(key = file.blob_key, "download missing from the blob store");
+                    fail(StatusCode::NOT_FOUND, "Download not found")
+                }
+            });
+        }
+    };
+
+    tracing::info!(grant_id, file_id, user_id, "file downloaded");
+    let content_type = HeaderValue::from_str(&file.content_type)
+        .unwrap_or(HeaderValue::from_static("application/octet-stream"));
+    let disposition = HeaderValue::from_str(&format!(
+        "attachment; filename=\"{}\"",
+        header_file_name(&file.file_name)
+    ))
+    .unwrap_or(HeaderValue::from_static("attachment"));
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type),
+            (header::CONTENT_LENGTH, HeaderValue::from(file.size_bytes)),
+            (header::CONTENT_DISPOSITION, disposition),
+            (header::CACHE_CONTROL, HeaderValue::from_static("private, no-store")),
+            (header::X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff")),
+        ],
+        StreamBody::new(stream),
+    ))
+}
+
+/// Gives a user the right to download a digital product's files; called once
+/// the order that bought it is paid. One grant per product and order.
+pub async fn create_grant_handler(
+    State(data): State<Arc<AppState>>,
+    Json(body): Json<CreateGrantSchema>,
+) -> Result<impl IntoResponse, HandlerError> {
+    let order_reference = body.order_reference.trim();
+    if order_reference.is_empty() || order_reference.chars().count() > 255 {
+        return Err(fail(
+            StatusCode::BAD_REQUEST,
+            "order_reference must be between 1 and 255 characters",
+        ));
+    }
+    let max_downloads = body.max_downloads.unwrap_or(data.config.download_max_downloads);
+    if !(1..=MAX_DOWNLOADS).contains(&max_downloads) {
+        return Err(fail(
+            StatusCode::BAD_REQUEST,
+            &format!("max_downloads must be between 1 and {}", MAX_DOWNLOADS),
+        ));
+    }
+
+    let digital: Option<bool> = sqlx::query_scalar(&format!(
+        "SELECT kind = 'digital' FROM products WHERE id = $1 AND {}",
+        NOT_DELETED
+    ))
+    .bind(body.product_id)
+    .fetch_optional(&data.db)
+    .await
+    .map_err(database_error)?;
+    match digital {
+        None => return Err(fail(StatusCode::NOT_FOUND, "Product not found")),
+        Some(false) => {
+            return Err(fail(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Only digital products can be downloaded",
+            ))
+        }
+        Some(true) => {}
+    }
+
+    let grant: DownloadGrant = sqlx::query_as(
+        r#"INSERT INTO download_grants (user_id, product_id, order_reference, max_downloads, expires_at)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING *"#,
+    )
+    .bind(body.user_id)
+    .bind(body.product_id)
+    .bind(order_reference)
+    .bind(max_downloads)
+    .bind(body.expires_at)
+    .fetch_one(&data.db)
+    .await
+    .map_err(|e| match e.as_database_error().and_then(|e| e.code()).as_deref() {
+        Some("23505") => fail(
+            StatusCode::CONFLICT,
+            "This order already grants downloads of the product",
+        ),
+        Some("23503") => fail(StatusCode::NOT_FOUND, "User not found"),
+        _ => database_error(e),
+    })?;
+
+    tracing::info!(grant_id = grant.id, user_id = grant.user_id, product_id = grant.product_id, "download grant created");
+    Ok((
+        StatusCode::CREATED,
+        Json(json!({"status": "success", "data": grant})),
+    ))
+}
+
+/// Stops a grant's links from working, e.g. after a refund.
+pub async fn revoke_grant_handler(
+    State(data): State<Arc<AppState>>,
+    Path(id): Path<i32>,
+) -> Result<impl IntoResponse, HandlerError> {
+    let grant: DownloadGrant = sqlx::query_as(
+        "UPDATE download_grants SET revoked_at = COALESCE(revoked_at, NOW()) WHERE id = $1 RETURNING *",
+    )
+    .bind(id)
+    .fetch_optional(&data.db)
+    .await
+    .map_err(database_error)?
+    .ok_or_else(|| fail(StatusCode::NOT_FOUND, "Download grant not found"))?;
+
+    tracing::info!(grant_id = id, "download grant revoked");
+    Ok(Json(json!({"status": "success", "data": grant})))
+}
+
+/// Gives back a download that never started because the file could not be read.
+async fn refund_download(data: &AppState, grant_id: i32) {
+    if let Err(e) = sqlx::query(
+        "UPDATE download_grants SET downloads_used = GREATEST(downloads_used - 1, 0) WHERE id = $1",
+    )
+    .bind(grant_id)
+    .execute(&data.db)
+    .await
+    {
+        tracing::warn!(error = %e, grant_id, "could not refund a failed download");
+    }
+}
+
+/// Quotes and non-ASCII characters would break the `Content-Disposition` header.
+fn header_file_name(file_name: &str) -> String {
+    file_name
+        .chars()
+        .map(|c| match c {
+            ' ' | '.' | '-' | '_' | '(' | ')' => c,
+            c if c.is_ascii_alphanumeric() => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+fn fail(status: StatusCode, message: &str) -> HandlerError {
+    let error_response = ErrorResponse {
+        status: "fail",
+        message: message.to_string(),
+    };
+    (status, Json(error_response))
+}
+
+fn database_error(e: sqlx::Error) -> HandlerError {
+    let error_response = ErrorResponse {
+        status: "error",
+        message: format!("Database error: {}", e),
+    };
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+}
@@ -0,0 +1,167 @@
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::routes::API_PREFIX;
+
+/// Upper bound for a grant's `max_downloads`.
+pub const MAX_DOWNLOADS: i32 = 1000;
+
+/// A file delivered with a digital product.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct ProductFile {
+    pub id: i32,
+    pub product_id: i32,
+    /// Under the blob store's private prefix; never handed out.
+    #[serde(skip)]
+    pub blob_key: String,
+    pub file_name: String,
+    pub content_type: String,
+    pub size_bytes: i64,
+    /// Hex-encoded, so buyers can check what they downloaded.
+    pub sha256: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct DownloadGrant {
+    pub id: i32,
+    pub user_id: i32,
+    pub product_id: i32,
+    /// The paid order the grant was created for.
+    pub order_reference: String,
+    /// Counted across all of the product's files.
+    pub max_downloads: i32,
+    pub downloads_used: i32,
+    /// Grants without one last as long as the downloads.
+    pub expires_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl DownloadGrant {
+    pub fn remaining(&self) -> i32 {
+        (self.max_downloads - self.downloads_used).max(0)
+    }
+
+    pub fn is_usable(&self, now: DateTime<Utc>) -> bool {
+        self.revoked_at.is_none()
+            && self.expires_at.is_none_or(|expires_at| expires_at > now)
+            && self.remaining() > 0
+    }
+}
+
+/// Sent by the order flow once an order containing a digital product is paid.
+#[derive(Debug, Deserialize)]
+pub struct CreateGrantSchema {
+    pub user_id: i32,
+    pub product_id: i32,
+    pub order_reference: String,
+    /// Defaults to the `download_max_downloads` setting.
+    pub max_downloads: Option<i32>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// The signed part of a download link.
+#[derive(Debug, Deserialize)]
+pub struct DownloadQuery {
+    /// Unix seconds.
+    pub expires: i64,
+    pub signature: String,
+}
+
+/// A link to one file of a grant, valid until `expires`. Anyone holding it can
+/// use it, so it is only ever shown to the grant's owner.
+pub fn download_url(secret: &str, grant_id: i32, file_id: i32, expires: i64) -> String {
+    format!(
+        "{}/downloads/{}/{}?expires={}&signature={}",
+        API_PREFIX,
+        grant_id,
+        file_id,
+        expires,
+        hex::encode(link_mac(secret, grant_id, file_id, expires).finalize().into_bytes())
+    )
+}
+
+/// Checks the signature in constant time.
+pub fn verify_link(secret: &str, grant_id: i32, file_id: i32, expires: i64, signature: &str) -> bool {
+    let Ok(signature) = hex::decode(signature) else {
+        return false;
+    };
+    link_mac(secret, grant_id, file_id, expires)
+        .verify_slice(&signature)
+        .is_ok()
+}
+
+fn link_mac(secret: &str, grant_id: i32, file_id: i32, expires: i64) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(format!("{}/{}/{}", grant_id, file_id, expires).as_bytes());
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+
+    const SECRET: &str = "download-secret";
+
+    fn signature(url: &str) -> &str {
+        url.rsplit_once("signature=").unwrap().1
+    }
+
+    fn grant(max_downloads: i32, downloads_used: i32) -> DownloadGrant {
+        DownloadGrant {
+            id: 1,
+            user_id: 2,
+            product_id: 3,
+            order_reference: "order-1".to_string(),
+            max_downloads,
+            downloads_used,
+            expires_at: None,
+            revoked_at: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn verifies_links_it_signed() {
+        let url = download_url(SECRET, 1, 2, 1_700_000_000);
+
+        assert!(url.starts_with(&format!("{}/downloads/1/2?expires=1700000000&", API_PREFIX)));
+        assert!(verify_link(SECRET, 1, 2, 1_700_000_000, signature(&url)));
+    }
+
+    #[test]
+    fn rejects_altered_links() {
+        let url = download_url(SECRET, 1, 2, 1_700_000_000);
+        let signature = signature(&url);
+
+        assert!(!verify_link(SECRET, 1, 3, 1_700_000_000, signature));
+        assert!(!verify_link(SECRET, 4, 2, 1_700_000_000, signature));
+        assert!(!verify_link(SECRET, 1, 2, 1_800_000_000, signature));
+        assert!(!verify_link("another-secret", 1, 2, 1_700_000_000, signature));
+        assert!(!verify_link(SECRET, 1, 2, 1_700_000_000, &signature[..32]));
+        assert!(!verify_link(SECRET, 1, 2, 1_700_000_000, "not hex"));
+    }
+
+    #[test]
+    fn grants_are_usable_until_used_up_expired_or_revoked() {
+        let now = Utc::now();
+        assert!(grant(3, 2).is_usable(now));
+        assert!(!grant(3, 3).is_usable(now));
+        assert_eq!(grant(3, 5).remaining(), 0);
+
+        let mut expiring = grant(3, 0);
+        expiring.expires_at = Some(now + Duration::hours(1));
+        assert!(expiring.is_usable(now));
+        assert!(!expiring.is_usable(now + Duration::hours(1)));
+
+        let mut revoked = grant(3, 0);
+        revoked.revoked_at = Some(now);
+        assert!(!revoked.is_usable(now));
+    }
+}
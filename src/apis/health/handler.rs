@@ -0,0 +1,226 @@
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use serde::Serialize;
+use serde_json::json;
+use sqlx::migrate::Migrate;
+
+use crate::apis::login::email::smtp_transport;
+use crate::{AppState, MIGRATOR};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ComponentStatus {
+    pub status: &'static str,
+    pub latency_ms: u128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+impl ComponentStatus {
+    fn is_up(&self) -> bool {
+        self.status == "up"
+    }
+}
+
+/// The last SMTP check, reused for `health_email_check_interval` so that
+/// frequent readiness probes do not each open a connection to the server.
+#[derive(Default)]
+pub struct EmailProbe(Mutex<Option<(Instant, ComponentStatus)>>);
+
+/// The process is running and able to answer; dependencies are not consulted.
+pub async fn liveness_handler() -> impl IntoResponse {
+    Json(json!({"status": "alive"}))
+}
+
+/// Ready to take traffic only when the database answers within
+/// `health_check_timeout` and is fully migrated. Requests can be served while
+/// email is down, so that only makes the instance `degraded`.
+pub async fn readiness_handler(State(data): State<Arc<AppState>>) -> impl IntoResponse {
+    let timeout = data.config.health_check_timeout;
+
+    let (database, migrations, email) = tokio::join!(
+        probe(timeout, check_database(&data)),
+        probe(timeout, check_migrations(&data)),
+        email_status(&data, timeout),
+    );
+
+    let ready = database.is_up() && migrations.is_up();
+    let (status, summary) = match (ready, email.is_up()) {
+        (false, _) => (StatusCode::SERVICE_UNAVAILABLE, "not_ready"),
+        (true, false) => (StatusCode::OK, "degraded"),
+        (true, true) => (StatusCode::OK, "ready"),
+    };
+
+    let json_response = json!({
+        "status": summary,
+        "components": {
+            "database": database,
+            "migrations": migrations,
+            "email": email,
+        }
+    });
+
+    (status, Json(json_response))
+}
+
+async fn probe<F>(timeout: Duration, check: F) -> ComponentStatus
+where
+    F: Future<Output = Result<Option<String>, String>>,
+{
+    let start = Instant::now();
+    let outcome = tokio::time::timeout(timeout, check).await;
+    let latency_ms = start.elapsed().as_millis();
+
+    let (status, message) = match outcome {
+        Ok(Ok(message)) => ("up", message),
+        Ok(Err(message)) => ("down", Some(message)),
+        Err(_) => ("down", Some(format!("timed out after {:?}", timeout))),
+    };
+
+    ComponentStatus {
+        status,
+        latency_ms,
+        message,
+    }
+}
+
+async fn check_database(data: &AppState) -> Result<Option<String>, String> {
+    sqlx::query("SELECT 1")
+        .execute(&data.db)
+        .await
+        .map(|_| None)
+        .map_err(|e| format!("Database error: {}", e))
+}
+
+async fn check_migrations(data: &AppState) -> Result<Option<String>, String> {
+    let mut conn = data
+        .db
+        .acquire()
+        .await
+        .map_err(|e| format!("Database error: {}", e))?;
+    let applied = conn
+        .list_applied_migrations()
+        .await
+        .map_err(|e| format!("Could not read applied migrations: {}", e))?;
+
+    let pending: Vec<i64> = MIGRATOR
+        .iter()
+        .filter(|migration| !migration.migration_type.is_down_migration())
+        .filter(|migration| !applied.iter().any(|a| a.version == migration.version))
+        .map(|migration| migration.version)
+        .collect();
+
+    if pending.is_empty() {
+        Ok(Some(format!("{} applied", applied.len())))
+    } else {
+        Err(format!("pending migrations: {:?}", pending))
+    }
+}
+
+async fn email_status(data: &Arc<AppState>, timeout: Duration) -> ComponentStatus {
+    if let Some((checked_at, status)) = &*data.email_probe.0.lock().unwrap() {
+        if checked_at.elapsed() < data.config.health_email_check_interval {
+            return status.clone();
+        }
+    }
+
+    let status = probe(timeout, check_email(data.clone(), timeout)).await;
+    *data.email_probe.0.lock().unwrap() = Some((Instant::now(), status.clone()));
+    status
+}
+
+async fn check_email(data: Arc<AppState>, timeout: Duration) -> Result<Option<String>, String> {
+    let reachable = tokio::task::spawn_blocking(move || {
+        smtp_transport(&data.config)
+            .map_err(|e| e.to_string())?
+            .timeout(Some(timeout))
+            .build()
+            .test_connection()
+            .map_err(|e| e.to_string())
+    })
+    .await
+    .map_err(|e| e.to_string())??;
+
+    if reachable {
+        Ok(None)
+    } else {
+        Err("SMTP server did not accept the connection".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{example_config, json_response, ScratchDb};
+
+    /// Nothing listens on the discard port, so the SMTP check fails at once.
+    fn config_without_smtp() -> crate::apis::config::Config {
+        let mut config = example_config();
+        config.smtp_host = "127.0.0.1".to_string();
+        config.smtp_port = 9;
+        config
+    }
+
+    async fn readiness(data: &Arc<AppState>) -> (StatusCode, serde_json::Value) {
+        json_response(readiness_handler(State(data.clone())).await.into_response()).await
+    }
+
+    #[tokio::test]
+    async fn stays_ready_but_degraded_while_email_is_down() {
+        let Some(db) = ScratchDb::migrated().await else {
+            return;
+        };
+        let data = db.state(config_without_smtp());
+
+        let (status, body) = readiness(&data).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "degraded");
+        assert_eq!(body["components"]["database"]["status"], "up");
+        assert_eq!(body["components"]["migrations"]["status"], "up");
+        assert_eq!(body["components"]["email"]["status"], "down");
+        db.drop().await;
+    }
+
+    #[tokio::test]
+    async fn reuses_the_last_email_check_for_the_interval() {
+        let Some(db) = ScratchDb::migrated().await else {
+            return;
+        };
+        let data = db.state(config_without_smtp());
+        let up = ComponentStatus {
+            status: "up",
+            latency_ms: 0,
+            message: None,
+        };
+        *data.email_probe.0.lock().unwrap() = Some((Instant::now(), up.clone()));
+
+        let (_, body) = readiness(&data).await;
+        assert_eq!(body["status"], "ready", "the cached check is used");
+
+        let expired = Instant::now() - data.config.health_email_check_interval;
+        *data.email_probe.0.lock().unwrap() = Some((expired, up));
+        let (_, body) = readiness(&data).await;
+        assert_eq!(body["status"], "degraded", "an expired check is run again");
+        db.drop().await;
+    }
+
+    #[tokio::test]
+    async fn is_not_ready_with_pending_migrations() {
+        let Some(db) = ScratchDb::create().await else {
+            return;
+        };
+        let latest = MIGRATOR.iter().map(|migration| migration.version).max().unwrap();
+        db.migrate_before(latest).await;
+        let data = db.state(config_without_smtp());
+
+        let (status, body) = readiness(&data).await;
+
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["status"], "not_ready");
+        assert_eq!(body["components"]["migrations"]["message"], format!("pending migrations: [{}]", latest));
+        db.drop().await;
+    }
+}
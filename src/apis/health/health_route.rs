@@ -0,0 +1,14 @@
+use std::sync::Arc;
+
+use axum::{routing::get, Router};
+
+use crate::apis::health::handler::{liveness_handler, readiness_handler};
+
+use crate::AppState;
+
+pub fn health_router(app_state: Arc<AppState>) -> Router {
+    Router::new()
+        .route("/health/live", get(liveness_handler))
+        .route("/health/ready", get(readiness_handler))
+        .with_state(app_state)
+}
@@ -1,16 +1,17 @@
+use std::fmt;
 use std::sync::Arc;
 
 use axum::{
     extract::State,
-    http::{header, Request, StatusCode},
+    http::{header, HeaderMap, Request, StatusCode},
     middleware::Next,
-    response::IntoResponse,
-    Json,
+    response::{IntoResponse, Response},
+    Extension, Json,
 };
 
 use axum_extra::extract::cookie::CookieJar;
-use jsonwebtoken::{decode, DecodingKey, Validation};
 
+use crate::apis::api_keys::{handler::find_active_key, model::ApiKey};
 use crate::apis::login::{
     model::{TokenClaims, User},
     response::ErrorResponse,
@@ -18,51 +19,146 @@ use crate::apis::login::{
 
 use crate::AppState;
 
+/// Who is making the request on routes behind [`authenticate`].
+#[derive(Debug, Clone)]
+pub enum Principal {
+    User(User),
+    ApiKey(ApiKey),
+}
+
+impl Principal {
+    /// Users keep their full access; keys only get what they were issued with.
+    pub fn has_scope(&self, scope: &str) -> bool {
+        match self {
+            Principal::User(_) => true,
+            Principal::ApiKey(key) => key.scopes.iter().any(|granted| granted == scope),
+        }
+    }
+}
+
+impl fmt::Display for Principal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Principal::User(user) => write!(f, "user:{}", user.id),
+            Principal::ApiKey(key) => write!(f, "api_key:{}", key.id),
+        }
+    }
+}
+
+/// Requires a logged-in user and makes it available as `Extension<User>`.
 pub async fn auth<B>(
     cookie_jar: CookieJar,
     State(data): State<Arc<AppState>>,
     mut req: Request<B>,
     next: Next<B>,
 ) -> Result<impl IntoResponse, (StatusCode, Json<ErrorResponse>)> {
-    let token = cookie_jar
-        .get("token")
-        .map(|cookie| cookie.value().to_string())
-        .or_else(|| {
-            req.headers()
-                .get(header::AUTHORIZATION)
-                .and_then(|auth_header| auth_header.to_str().ok())
-                .and_then(|auth_value| {
-                    if auth_value.starts_with("Bearer ") {
-                        Some(auth_value[7..].to_owned())
-                    } else {
-                        None
-                    }
-                })
-        });
+    let user = session_user(&cookie_jar, req.headers(), &data).await?;
+
+    tracing::Span::current().record("user_id", user.id);
+    req.extensions_mut().insert(user);
+    Ok(next.run(req).await)
+}
 
-    let token = token.ok_or_else(|| {
+/// Like [`auth`], but also accepts `Authorization: ApiKey ...`. Inserts a
+/// [`Principal`] either way, and the `User` as well for sessions.
+pub async fn authenticate<B>(
+    cookie_jar: CookieJar,
+    State(data): State<Arc<AppState>>,
+    mut req: Request<B>,
+    next: Next<B>,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    if let Some(key) = presented_api_key(req.headers()) {
+        let api_key = find_active_key(&data.db, &key)
+            .await
+            .map_err(|e| {
+                let json_error = ErrorResponse {
+                    status: "fail",
+                    message: format!("Error fetching API key from database: {}", e),
+                };
+                (StatusCode::INTERNAL_SERVER_ERROR, Json(json_error))
+            })?
+            .ok_or_else(|| {
+                let json_error = ErrorResponse {
+                    status: "fail",
+                    message: "Invalid API key".to_string(),
+                };
+                (StatusCode::UNAUTHORIZED, Json(json_error))
+            })?;
+
+        tracing::Span::current().record("api_key_id", api_key.id);
+        req.extensions_mut().insert(Principal::ApiKey(api_key));
+        return Ok(next.run(req).await);
+    }
+
+    let user = session_user(&cookie_jar, req.headers(), &data).await?;
+
+    tracing::Span::current().record("user_id", user.id);
+    req.extensions_mut().insert(Principal::User(user.clone()));
+    req.extensions_mut().insert(user);
+    Ok(next.run(req).await)
+}
+
+/// Goes after [`auth`]; lets only users with the `admin` role through.
+pub async fn require_admin<B>(
+    Extension(user): Extension<User>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    if user.role != "admin" {
         let json_error = ErrorResponse {
             status: "fail",
-            message: "You are not logged in, please provide token".to_string(),
+            message: "This action requires an administrator".to_string(),
         };
-        (StatusCode::UNAUTHORIZED, Json(json_error))
-    })?;
+        return Err((StatusCode::FORBIDDEN, Json(json_error)));
+    }
+    Ok(next.run(req).await)
+}
 
-    let claims = decode::<TokenClaims>(
-        &token,
-        &DecodingKey::from_secret(data.config.jwt_secret.as_ref()),
-        &Validation::default(),
-    )
-    .map_err(|_| {
+/// Goes after [`authenticate`]; lets through administrators and API keys,
+/// which only administrators can issue.
+pub async fn require_catalog_manager<B>(
+    Extension(principal): Extension<Principal>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    if let Principal::User(user) = &principal {
+        if user.role != "admin" {
+            let json_error = ErrorResponse {
+                status: "fail",
+                message: "This action requires an administrator".to_string(),
+            };
+            return Err((StatusCode::FORBIDDEN, Json(json_error)));
+        }
+    }
+    Ok(next.run(req).await)
+}
+
+async fn session_user(
+    cookie_jar: &CookieJar,
+    headers: &HeaderMap,
+    data: &AppState,
+) -> Result<User, (StatusCode, Json<ErrorResponse>)> {
+    let token = request_token(cookie_jar, headers).ok_or_else(|| {
         let json_error = ErrorResponse {
             status: "fail",
-            message: "Invalid token".to_string(),
+            message: "You are not logged in, please provide token".to_string(),
         };
         (StatusCode::UNAUTHORIZED, Json(json_error))
-    })?
-    .claims;
+    })?;
 
-    let user: Option<User> = match sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
+    // Scoped tokens (e.g. a pending 2FA login) are not sessions.
+    let claims = decode_token(&token, data)
+        .ok()
+        .filter(|claims| claims.scope.is_none())
+        .ok_or_else(|| {
+            let json_error = ErrorResponse {
+                status: "fail",
+                message: "Invalid token".to_string(),
+            };
+            (StatusCode::UNAUTHORIZED, Json(json_error))
+        })?;
+
+    let user: Option<User> = match sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1 AND deleted_at IS NULL")
         .bind(claims.sub)
         .fetch_optional(&data.db)
         .await
@@ -77,14 +173,101 @@ pub async fn auth<B>(
         }
     };
 
-    let user = user.ok_or_else(|| {
+    user.ok_or_else(|| {
         let json_error = ErrorResponse {
             status: "fail",
             message: "The user belonging to this token no longer exists".to_string(),
         };
         (StatusCode::UNAUTHORIZED, Json(json_error))
-    })?;
+    })
+}
 
-    req.extensions_mut().insert(user);
-    Ok(next.run(req).await)
+/// The key from an `Authorization: ApiKey ...` header.
+pub fn presented_api_key(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|auth_header| auth_header.to_str().ok())
+        .and_then(|auth_value| auth_value.strip_prefix("ApiKey "))
+        .map(|key| key.trim().to_owned())
+}
+
+/// The session token from the `token` cookie, falling back to a `Bearer` header.
+pub fn request_token(cookie_jar: &CookieJar, headers: &HeaderMap) -> Option<String> {
+    cookie_jar
+        .get("token")
+        .map(|cookie| cookie.value().to_string())
+        .or_else(|| {
+            headers
+                .get(header::AUTHORIZATION)
+                .and_then(|auth_header| auth_header.to_str().ok())
+                .and_then(|auth_value| auth_value.strip_prefix("Bearer "))
+                .map(|token| token.to_owned())
+        })
+}
+
+pub fn decode_token(token: &str, data: &AppState) -> jsonwebtoken::errors::Result<TokenClaims> {
+    data.jwt_keys.decode(token, data.jwt_keys.audience())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+
+    fn api_key(scopes: &[&str]) -> Principal {
+        Principal::ApiKey(ApiKey {
+            id: 7,
+            name: "warehouse".to_string(),
+            prefix: "shp_abcdefgh".to_string(),
+            scopes: scopes.iter().map(|scope| scope.to_string()).collect(),
+            created_by: Some(1),
+            created_at: Utc::now(),
+            last_used_at: None,
+            expires_at: None,
+            revoked_at: None,
+        })
+    }
+
+    #[test]
+    fn api_keys_get_only_their_scopes() {
+        let key = api_key(&["catalog:read"]);
+
+        assert!(key.has_scope("catalog:read"));
+        assert!(!key.has_scope("catalog:write"));
+        assert!(!key.has_scope("catalog"));
+        assert!(!api_key(&[]).has_scope("catalog:read"));
+        assert_eq!(key.to_string(), "api_key:7");
+    }
+
+    #[test]
+    fn users_have_every_scope() {
+        let user = Principal::User(User {
+            id: 3,
+            name: "Ada".to_string(),
+            email: "ada@example.com".to_string(),
+            password: String::new(),
+            created_at: None,
+            updated_at: None,
+            verified: true,
+            verification_code: None,
+            role: "user".to_string(),
+            totp_secret: None,
+            totp_enabled: false,
+            totp_last_used_step: None,
+            deletion_scheduled_at: None,
+            deleted_at: None,
+            phone: None,
+            locale: None,
+            currency: None,
+            marketing_email_consent: false,
+            marketing_sms_consent: false,
+            marketing_consent_updated_at: None,
+            avatar_key: None,
+        });
+
+        assert!(user.has_scope("catalog:write"));
+        assert!(user.has_scope("orders:write"));
+        assert_eq!(user.to_string(), "user:3");
+    }
 }
@@ -0,0 +1,435 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context};
+use axum::{extract::State, http::header, response::IntoResponse, Json};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ed25519_dalek::pkcs8::{DecodePrivateKey, DecodePublicKey};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use rsa::pkcs1::{DecodeRsaPrivateKey, DecodeRsaPublicKey};
+use rsa::traits::PublicKeyParts;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::json;
+
+use crate::apis::config::Config;
+use crate::AppState;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JwtAlgorithm {
+    Hs256,
+    Rs256,
+    EdDsa,
+}
+
+impl FromStr for JwtAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "HS256" => Ok(JwtAlgorithm::Hs256),
+            "RS256" => Ok(JwtAlgorithm::Rs256),
+            "EdDSA" => Ok(JwtAlgorithm::EdDsa),
+            _ => Err("expected `HS256`, `RS256` or `EdDSA`".to_string()),
+        }
+    }
+}
+
+/// A key that is no longer used for signing but still accepted, written as
+/// `kid=path/to/key.pem` (either the public or the old private key will do) or,
+/// for a shared secret, `kid=hs256:<secret>`.
+#[derive(Clone)]
+pub struct PreviousKey {
+    pub kid: String,
+    pub source: PreviousKeySource,
+}
+
+#[derive(Clone)]
+pub enum PreviousKeySource {
+    Pem(PathBuf),
+    Hs256Secret(String),
+}
+
+const HS256_PREFIX: &str = "hs256:";
+
+impl FromStr for PreviousKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kid, value) = s
+            .split_once('=')
+            .ok_or_else(|| "expected `<kid>=<path to PEM file>` or `<kid>=hs256:<secret>`".to_string())?;
+        let (kid, value) = (kid.trim(), value.trim());
+        if kid.is_empty() || value.is_empty() {
+            return Err("both the key id and the key are required".to_string());
+        }
+        let source = match value.strip_prefix(HS256_PREFIX) {
+            Some("") => return Err("the HS256 secret is empty".to_string()),
+            Some(secret) => PreviousKeySource::Hs256Secret(secret.to_string()),
+            None => PreviousKeySource::Pem(PathBuf::from(value)),
+        };
+        Ok(PreviousKey {
+            kid: kid.to_string(),
+            source,
+        })
+    }
+}
+
+/// Keeps shared secrets out of logs.
+impl fmt::Debug for PreviousKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("PreviousKey");
+        debug.field("kid", &self.kid);
+        match &self.source {
+            PreviousKeySource::Pem(path) => debug.field("path", path).finish(),
+            PreviousKeySource::Hs256Secret(_) => debug.finish_non_exhaustive(),
+        }
+    }
+}
+
+/// Signs tokens with the active key and verifies them against every key that
+/// might still have tokens in circulation, picked by the `kid` header.
+///
+/// Rotating: make the new key active and list the old one under
+/// `jwt_previous_keys` until `jwt_expires_in` has passed, then drop it.
+pub struct JwtKeys {
+    kid: String,
+    algorithm: Algorithm,
+    encoding_key: EncodingKey,
+    /// The active key first, then the previous ones in configuration order.
+    decoding_keys: Vec<(String, Algorithm, DecodingKey)>,
+    jwks: serde_json::Value,
+    issuer: String,
+    audience: String,
+}
+
+/// The public half of a key, as published in the JWKS.
+enum PublicKey {
+    Rsa { n: Vec<u8>, e: Vec<u8> },
+    Ed25519 { x: [u8; 32] },
+}
+
+impl PublicKey {
+    /// Accepts public keys (SPKI or PKCS#1) as well as private keys (PKCS#8 or PKCS#1).
+    fn from_pem(pem: &str) -> Option<PublicKey> {
+        let rsa = rsa::RsaPublicKey::from_public_key_pem(pem)
+            .or_else(|_| rsa::RsaPublicKey::from_pkcs1_pem(pem))
+            .ok()
+            .or_else(|| {
+                rsa::RsaPrivateKey::from_pkcs8_pem(pem)
+                    .or_else(|_| rsa::RsaPrivateKey::from_pkcs1_pem(pem))
+                    .ok()
+                    .map(|key| key.to_public_key())
+            });
+        if let Some(key) = rsa {
+            return Some(PublicKey::Rsa {
+                n: key.n().to_bytes_be(),
+                e: key.e().to_bytes_be(),
+            });
+        }
+
+        ed25519_dalek::VerifyingKey::from_public_key_pem(pem)
+            .ok()
+            .or_else(|| {
+                ed25519_dalek::SigningKey::from_pkcs8_pem(pem)
+                    .ok()
+                    .map(|key| key.verifying_key())
+            })
+            .map(|key| PublicKey::Ed25519 { x: key.to_bytes() })
+    }
+
+    fn algorithm(&self) -> Algorithm {
+        match self {
+            PublicKey::Rsa { .. } => Algorithm::RS256,
+            PublicKey::Ed25519 { .. } => Algorithm::EdDSA,
+        }
+    }
+
+    fn decoding_key(&self) -> anyhow::Result<DecodingKey> {
+        let key = match self {
+            PublicKey::Rsa { n, e } => {
+                DecodingKey::from_rsa_components(&URL_SAFE_NO_PAD.encode(n), &URL_SAFE_NO_PAD.encode(e))?
+            }
+            PublicKey::Ed25519 { x } => DecodingKey::from_ed_components(&URL_SAFE_NO_PAD.encode(x))?,
+        };
+        Ok(key)
+    }
+
+    fn jwk(&self, kid: &str) -> serde_json::Value {
+        match self {
+            PublicKey::Rsa { n, e } => json!({
+                "kty": "RSA",
+                "use": "sig",
+                "alg": "RS256",
+                "kid": kid,
+                "n": URL_SAFE_NO_PAD.encode(n),
+                "e": URL_SAFE_NO_PAD.encode(e),
+            }),
+            PublicKey::Ed25519 { x } => json!({
+                "kty": "OKP",
+                "crv": "Ed25519",
+                "use": "sig",
+                "alg": "EdDSA",
+                "kid": kid,
+                "x": URL_SAFE_NO_PAD.encode(x),
+            }),
+        }
+    }
+}
+
+impl JwtKeys {
+    pub fn new(config: &Config) -> anyhow::Result<Self> {
+        let mut decoding_keys = Vec::new();
+        let mut jwks = Vec::new();
+
+        let (algorithm, encoding_key) = match config.jwt_algorithm {
+            // A shared secret is never published, so the JWKS stays empty.
+            JwtAlgorithm::Hs256 => {
+                let secret = config
+                    .jwt_secret
+                    .as_deref()
+                    .ok_or_else(|| anyhow!("jwt_secret is required for HS256"))?;
+                decoding_keys.push((
+                    config.jwt_key_id.clone(),
+                    Algorithm::HS256,
+                    DecodingKey::from_secret(secret.as_bytes()),
+                ));
+                (Algorithm::HS256, EncodingKey::from_secret(secret.as_bytes()))
+            }
+            JwtAlgorithm::Rs256 | JwtAlgorithm::EdDsa => {
+                let path = config
+                    .jwt_private_key_file
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("jwt_private_key_file is required for RS256 and EdDSA"))?;
+                let pem = std::fs::read_to_string(path)
+                    .with_context(|| format!("Could not read {}", path.display()))?;
+                let public_key = PublicKey::from_pem(&pem)
+                    .with_context(|| format!("{} is not an RSA or Ed25519 private key", path.display()))?;
+
+                let expected = match config.jwt_algorithm {
+                    JwtAlgorithm::Rs256 => Algorithm::RS256,
+                    _ => Algorithm::EdDSA,
+                };
+                if public_key.algorithm() != expected {
+                    return Err(anyhow!(
+                        "{} does not hold a key for {:?}",
+                        path.display(),
+                        expected
+                    ));
+                }
+                let encoding_key = match expected {
+                    Algorithm::RS256 => EncodingKey::from_rsa_pem(pem.as_bytes()),
+                    _ => EncodingKey::from_ed_pem(pem.as_bytes()),
+                }
+                .with_context(|| format!("Could not load the signing key from {}", path.display()))?;
+
+                jwks.push(public_key.jwk(&config.jwt_key_id));
+                decoding_keys.push((config.jwt_key_id.clone(), expected, public_key.decoding_key()?));
+                (expected, encoding_key)
+            }
+        };
+
+        for previous in &config.jwt_previous_keys {
+            if decoding_keys.iter().any(|(kid, _, _)| *kid == previous.kid) {
+                return Err(anyhow!("Key id `{}` is used more than once", previous.kid));
+            }
+            match &previous.source {
+                // Lets sessions signed with a shared secret survive a switch to
+                // RS256 or EdDSA. Like the active secret, it is never published.
+                PreviousKeySource::Hs256Secret(secret) => decoding_keys.push((
+                    previous.kid.clone(),
+                    Algorithm::HS256,
+                    DecodingKey::from_secret(secret.as_bytes()),
+                )),
+                PreviousKeySource::Pem(path) => {
+                    let pem = std::fs::read_to_string(path)
+                        .with_context(|| format!("Could not read {}", path.display()))?;
+                    let public_key = PublicKey::from_pem(&pem)
+                        .with_context(|| format!("{} is not an RSA or Ed25519 key", path.display()))?;
+
+                    jwks.push(public_key.jwk(&previous.kid));
+                    decoding_keys.push((
+                        previous.kid.clone(),
+                        public_key.algorithm(),
+                        public_key.decoding_key()?,
+                    ));
+                }
+            }
+        }
+
+        Ok(JwtKeys {
+            kid: config.jwt_key_id.clone(),
+            algorithm,
+            encoding_key,
+            decoding_keys,
+            jwks: json!({ "keys": jwks }),
+            issuer: config.jwt_issuer.clone(),
+            audience: config.jwt_audience.clone(),
+        })
+    }
+
+    pub fn issuer(&self) -> &str {
+        &self.issuer
+    }
+
+    pub fn audience(&self) -> &str {
+        &self.audience
+    }
+
+    pub fn encode<T: Serialize>(&self, claims: &T) -> jsonwebtoken::errors::Result<String> {
+        let mut header = Header::new(self.algorithm);
+        header.kid = Some(self.kid.clone());
+        jsonwebtoken::encode(&header, claims, &self.encoding_key)
+    }
+
+    /// Verifies the signature, expiry, issuer and the given audience.
+    ///
+    /// Tokens signed before key ids, issuers and audiences were introduced have
+    /// none of them. They are checked against every key of their algorithm, and
+    /// accepted without `iss` and `aud` until they expire. Every token signed
+    /// now carries both, so one meant for another audience is still refused.
+    pub fn decode<T: DeserializeOwned>(
+        &self,
+        token: &str,
+        audience: &str,
+    ) -> jsonwebtoken::errors::Result<T> {
+        let header = jsonwebtoken::decode_header(token)?;
+        let candidates: Vec<_> = match header.kid.as_deref() {
+            Some(kid) => self
+                .decoding_keys
+                .iter()
+                .filter(|(key_id, _, _)| key_id == kid)
+                .collect(),
+            None => self
+                .decoding_keys
+                .iter()
+                .filter(|(_, algorithm, _)| *algorithm == header.alg)
+                .collect(),
+        };
+
+        let mut result = Err(jsonwebtoken::errors::ErrorKind::InvalidKeyFormat.into());
+        for (_, algorithm, key) in candidates {
+            let mut validation = Validation::new(*algorithm);
+            validation.set_issuer(&[&self.issuer]);
+            validation.set_audience(&[audience]);
+            validation.set_required_spec_claims(&["exp"]);
+
+            result = jsonwebtoken::decode::<T>(token, key, &validation).map(|token_data| token_data.claims);
+            if result.is_ok() {
+                break;
+            }
+        }
+        result
+    }
+}
+
+/// Public keys for services that verify our tokens themselves.
+pub async fn jwks_handler(State(data): State<Arc<AppState>>) -> impl IntoResponse {
+    (
+        [(header::CACHE_CONTROL, "public, max-age=300")],
+        Json(data.jwt_keys.jwks.clone()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::pkcs8::{spki::der::pem::LineEnding, EncodePrivateKey};
+    use serde_json::Value;
+
+    use crate::test_support::example_config;
+
+    use super::*;
+
+    const OLD_SECRET: &str = "old-secret";
+
+    /// Signs with a fresh Ed25519 key and still accepts the HS256 secret used
+    /// before the switch, as `default`.
+    fn rotated_keys() -> JwtKeys {
+        let mut config = example_config();
+
+        let path = std::env::temp_dir().join(format!("jwt-{}.pem", uuid::Uuid::new_v4()));
+        let signing_key = ed25519_dalek::SigningKey::from_bytes(&[7; 32]);
+        std::fs::write(&path, signing_key.to_pkcs8_pem(LineEnding::LF).unwrap().as_bytes()).unwrap();
+
+        config.jwt_algorithm = JwtAlgorithm::EdDsa;
+        config.jwt_private_key_file = Some(path.clone());
+        config.jwt_key_id = "jwt-2024-01".to_string();
+        config.jwt_previous_keys = vec![format!("default=hs256:{}", OLD_SECRET).parse().unwrap()];
+        let keys = JwtKeys::new(&config).unwrap();
+        std::fs::remove_file(path).unwrap();
+        keys
+    }
+
+    fn hs256_token(kid: Option<&str>, claims: Value) -> String {
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = kid.map(str::to_string);
+        jsonwebtoken::encode(&header, &claims, &EncodingKey::from_secret(OLD_SECRET.as_bytes())).unwrap()
+    }
+
+    fn exp() -> i64 {
+        chrono::Utc::now().timestamp() + 600
+    }
+
+    #[test]
+    fn verifies_tokens_signed_with_the_active_key() {
+        let keys = rotated_keys();
+        let token = keys
+            .encode(&json!({"sub": "1", "exp": exp(), "iss": keys.issuer(), "aud": keys.audience()}))
+            .unwrap();
+
+        let claims: Value = keys.decode(&token, keys.audience()).unwrap();
+        assert_eq!(claims["sub"], "1");
+        assert!(keys.decode::<Value>(&token, "another-audience").is_err());
+        assert_eq!(keys.jwks["keys"].as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn accepts_tokens_signed_with_a_previous_hs256_secret() {
+        let keys = rotated_keys();
+        let token = hs256_token(
+            Some("default"),
+            json!({"sub": "1", "exp": exp(), "iss": keys.issuer(), "aud": keys.audience()}),
+        );
+
+        assert!(keys.decode::<Value>(&token, keys.audience()).is_ok());
+        assert!(keys.decode::<Value>(&token, "another-audience").is_err());
+    }
+
+    #[test]
+    fn accepts_tokens_from_before_key_ids_issuers_and_audiences() {
+        let keys = rotated_keys();
+        let token = hs256_token(None, json!({"sub": "1", "exp": exp()}));
+
+        assert!(keys.decode::<Value>(&token, keys.audience()).is_ok());
+    }
+
+    #[test]
+    fn rejects_unknown_and_expired_tokens() {
+        let keys = rotated_keys();
+
+        let unknown_kid = hs256_token(Some("jwt-2023-01"), json!({"sub": "1", "exp": exp()}));
+        assert!(keys.decode::<Value>(&unknown_kid, keys.audience()).is_err());
+
+        let expired = hs256_token(None, json!({"sub": "1", "exp": exp() - 3600}));
+        assert!(keys.decode::<Value>(&expired, keys.audience()).is_err());
+
+        let other_issuer = hs256_token(None, json!({"sub": "1", "exp": exp(), "iss": "elsewhere"}));
+        assert!(keys.decode::<Value>(&other_issuer, keys.audience()).is_err());
+    }
+
+    #[test]
+    fn parses_previous_keys() {
+        let pem: PreviousKey = "jwt-2023-10=keys/jwt-2023-10.pem".parse().unwrap();
+        assert_eq!(pem.kid, "jwt-2023-10");
+        assert!(matches!(pem.source, PreviousKeySource::Pem(path) if path == std::path::Path::new("keys/jwt-2023-10.pem")));
+
+        let secret: PreviousKey = "default=hs256:s3cret".parse().unwrap();
+        assert!(matches!(&secret.source, PreviousKeySource::Hs256Secret(s) if s == "s3cret"));
+        assert!(!format!("{:?}", secret).contains("s3cret"));
+
+        assert!("default=hs256:".parse::<PreviousKey>().is_err());
+        assert!("keys/jwt-2023-10.pem".parse::<PreviousKey>().is_err());
+        assert!("=keys/jwt-2023-10.pem".parse::<PreviousKey>().is_err());
+    }
+}
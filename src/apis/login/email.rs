@@ -1,25 +1,19 @@
 use std::error::Error;
 use crate::apis::config::Config;
 
-use lettre::transport::smtp::authentication::Credentials;
+use lettre::transport::smtp::{authentication::Credentials, SmtpTransportBuilder};
 use lettre::{Message, SmtpTransport, Transport};
 
-pub fn send_email(email: String, body: String) -> Result<(), Box<dyn Error>> {
-    let config = Config::init();
-
+pub fn send_email(config: &Config, email: String, subject: &str, body: String) -> Result<(), Box<dyn Error>> {
     // Create the email message
     let email = Message::builder()
-        .from(config.smtp_from.parse().unwrap())
-        .to(email.parse().unwrap())
-        .subject("Shopping verification code")
-        .body(body.to_string())
-        .unwrap();
+        .from(config.smtp_from.clone())
+        .to(email.parse()?)
+        .subject(subject)
+        .body(body.to_string())?;
 
     // Create the SMTP transport with authentication
-    let smtp_transport = SmtpTransport::relay(&config.smtp_host)
-        .unwrap()
-        .credentials(Credentials::new(config.smtp_user, config.smtp_pass))
-        .build();
+    let smtp_transport = smtp_transport(config)?.build();
 
     // Send the email
     smtp_transport
@@ -28,4 +22,10 @@ pub fn send_email(email: String, body: String) -> Result<(), Box<dyn Error>> {
 
     Ok(()) // Return Ok(()) if everything is successful
 
-}
\ No newline at end of file
+}
+
+pub fn smtp_transport(config: &Config) -> Result<SmtpTransportBuilder, Box<dyn Error>> {
+    Ok(SmtpTransport::relay(&config.smtp_host)?
+        .port(config.smtp_port)
+        .credentials(Credentials::new(config.smtp_user.clone(), config.smtp_pass.clone())))
+}
@@ -9,15 +9,19 @@ use axum::{
 };
 use axum_extra::extract::cookie::{Cookie, SameSite};
 use chrono::{DateTime, Utc};
-use jsonwebtoken::{encode, EncodingKey, Header};
 use rand::{rngs::OsRng, Rng};
 use serde_json::json;
 
+use crate::apis::client_ip::ClientIp;
 use crate::apis::login::{
-    model::{LoginUserSchema, RegisterUserSchema, TokenClaims, User},
-    response::{ErrorResponse, FilteredUser},
+    model::{
+        LoginUserSchema, RegisterUserSchema, TokenClaims, User, TWO_FACTOR_PENDING_SCOPE,
+        TWO_FACTOR_PENDING_TTL_SECS,
+    },
+    response::{ErrorResponse, FilteredUser, UserData, UserResponse},
 };
 
+use crate::blob_store;
 use crate::AppState;
 
 use super::email::send_email;
@@ -94,12 +98,16 @@ pub async fn register_user_handler(
         };
         (
             StatusCode::INTERNAL_SERVER_ERROR,
-            Json(error_response.into()),
+            Json(error_response),
         )
     })?;
 
+    data.metrics.registrations.inc();
+
     //  Create an Email instance
-    if let Err(_) = send_email(email.clone(), email_body) {
+    let sent = send_email(&data.config, email.clone(), "Shopping verification code", email_body).is_ok();
+    data.metrics.record_email(sent);
+    if !sent {
         let json_error = ErrorResponse {
             status: "fail",
             message: "Something bad happened while sending the verification code".to_string(),
@@ -126,11 +134,31 @@ pub async fn register_user_handler(
 
 pub async fn login_user_handler(
     State(data): State<Arc<AppState>>,
+    ClientIp(ip): ClientIp,
     Json(body): Json<LoginUserSchema>,
-) -> Result<impl IntoResponse, (StatusCode, Json<ErrorResponse>)> {
+) -> Result<impl IntoResponse, axum::response::Response> {
     let email = body.email.to_ascii_lowercase();
-    let user: User = sqlx::query_as("SELECT * FROM users WHERE email = $1")
-        .bind(email)
+
+    let throttle_error = |e: sqlx::Error| {
+        let error_response = ErrorResponse {
+            status: "error",
+            message: format!("Database error: {}", e),
+        };
+        (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response)).into_response()
+    };
+
+    if let Some(wait) = data
+        .login_throttle
+        .retry_after(ip, &email)
+        .await
+        .map_err(throttle_error)?
+    {
+        data.metrics.record_login("throttled");
+        return Err(too_many_attempts(wait));
+    }
+
+    let user: Option<User> = sqlx::query_as("SELECT * FROM users WHERE email = $1")
+        .bind(&email)
         .fetch_optional(&data.db)
         .await
         .map_err(|e| {
@@ -138,58 +166,84 @@ pub async fn login_user_handler(
                 status: "error",
                 message: format!("Database error: {}", e),
             };
-            (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
-        })?
-        .ok_or_else(|| {
-            let error_response = ErrorResponse {
-                status: "fail",
-                message: "Invalid email or password".to_string(),
-            };
-            (StatusCode::BAD_REQUEST, Json(error_response))
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response)).into_response()
         })?;
 
-    if !user.verified {
+    let invalid_credentials = || {
         let error_response = ErrorResponse {
             status: "fail",
-            message: "Please verify your email before you can log in".to_string(),
+            message: "Invalid email or password".to_string(),
         };
-        return Err((StatusCode::BAD_REQUEST, Json(error_response)));
-    }
+        (StatusCode::BAD_REQUEST, Json(error_response)).into_response()
+    };
 
-    let is_valid = match PasswordHash::new(&user.password) {
-        Ok(parsed_hash) => Argon2::default()
-            .verify_password(body.password.as_bytes(), &parsed_hash)
-            .map_or(false, |_| true),
-        Err(_) => false,
+    let Some(user) = user else {
+        data.metrics.record_login("unknown_user");
+        data.login_throttle
+            .record_failure(ip, &email)
+            .await
+            .map_err(throttle_error)?;
+        return Err(invalid_credentials());
     };
 
-    if !is_valid {
+    if !password_matches(&user, &body.password) {
+        data.metrics.record_login("invalid_password");
+        let locked = data
+            .login_throttle
+            .record_failure(ip, &email)
+            .await
+            .map_err(throttle_error)?;
+        if locked {
+            tracing::warn!(user_id = user.id, %ip, "account locked after repeated failed logins");
+            notify_lockout(data.clone(), user.email.clone());
+        }
+        return Err(invalid_credentials());
+    }
+
+    // Only after the password, so the answer does not reveal which
+    // addresses have an unverified account.
+    if !user.verified {
+        data.metrics.record_login("unverified");
         let error_response = ErrorResponse {
             status: "fail",
-            message: "Invalid email or password".to_string(),
+            message: "Please verify your email before you can log in".to_string(),
         };
-        return Err((StatusCode::BAD_REQUEST, Json(error_response)));
+        return Err((StatusCode::BAD_REQUEST, Json(error_response)).into_response());
+    }
+
+    data.login_throttle
+        .record_success(&email)
+        .await
+        .map_err(throttle_error)?;
+
+    if user.totp_enabled {
+        return Ok(pending_two_factor_response(&data, &user));
     }
 
+    data.metrics.record_login("success");
+    Ok(session_response(&data, &user))
+}
+
+/// Issues the session JWT, both in the body and as the `token` cookie.
+pub fn session_response(data: &AppState, user: &User) -> axum::response::Response {
     let now = chrono::Utc::now();
     let iat = now.timestamp() as usize;
-    let exp = (now + chrono::Duration::minutes(60)).timestamp() as usize;
+    let exp = iat + data.config.jwt_expires_in.as_secs() as usize;
     let claims: TokenClaims = TokenClaims {
         sub: user.id,
+        role: user.role.clone(),
+        scope: None,
+        iss: data.jwt_keys.issuer().to_string(),
+        aud: data.jwt_keys.audience().to_string(),
         exp,
         iat,
     };
 
-    let token = encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(data.config.jwt_secret.as_ref()),
-    )
-    .unwrap();
+    let token = data.jwt_keys.encode(&claims).unwrap();
 
     let cookie = Cookie::build("token", token.to_owned())
         .path("/")
-        .max_age(time::Duration::hours(1))
+        .max_age(time::Duration::seconds(data.config.jwt_maxage.as_secs() as i64))
         .same_site(SameSite::Lax)
         .http_only(true)
         .finish();
@@ -198,7 +252,57 @@ pub async fn login_user_handler(
     response
         .headers_mut()
         .insert(header::SET_COOKIE, cookie.to_string().parse().unwrap());
-    Ok(response)
+    response.into_response()
+}
+
+/// Asks for the second factor instead of starting a session.
+pub fn pending_two_factor_response(data: &AppState, user: &User) -> axum::response::Response {
+    let json_response = json!({
+        "status": "2fa_required",
+        "message": "Enter the code from your authenticator app or a backup code",
+        "pending_token": pending_two_factor_token(data, user),
+    });
+    Json(json_response).into_response()
+}
+
+/// Short-lived token proving the password step succeeded; only `/auth/2fa/verify` accepts it.
+fn pending_two_factor_token(data: &AppState, user: &User) -> String {
+    let iat = chrono::Utc::now().timestamp() as usize;
+    let claims = TokenClaims {
+        sub: user.id,
+        role: user.role.clone(),
+        scope: Some(TWO_FACTOR_PENDING_SCOPE.to_string()),
+        iss: data.jwt_keys.issuer().to_string(),
+        aud: data.jwt_keys.audience().to_string(),
+        exp: iat + TWO_FACTOR_PENDING_TTL_SECS,
+        iat,
+    };
+
+    data.jwt_keys.encode(&claims).unwrap()
+}
+
+/// Accounts created through single sign-on have an unusable hash, so no password matches.
+pub fn password_matches(user: &User, password: &str) -> bool {
+    match PasswordHash::new(&user.password) {
+        Ok(parsed_hash) => Argon2::default()
+            .verify_password(password.as_bytes(), &parsed_hash)
+            .is_ok(),
+        Err(_) => false,
+    }
+}
+
+pub fn too_many_attempts(wait: std::time::Duration) -> axum::response::Response {
+    let retry_after = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+    let error_response = ErrorResponse {
+        status: "fail",
+        message: format!("Too many login attempts, please try again in {} seconds", retry_after),
+    };
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(header::RETRY_AFTER, retry_after.to_string())],
+        Json(error_response),
+    )
+        .into_response()
 }
 
 pub async fn verify_email_handler(
@@ -275,30 +379,100 @@ pub async fn logout_handler() -> Result<impl IntoResponse, (StatusCode, Json<ser
 pub async fn get_me_handler(
     Extension(user): Extension<User>,
 ) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
-    let json_response = serde_json::json!({
-        "status":  "success",
-        "data": serde_json::json!({
-            "user": filter_user_record(&user)
-        })
-    });
+    let json_response = UserResponse {
+        status: "success".to_string(),
+        data: UserData {
+            user: filter_user_record(&user),
+        },
+    };
 
     Ok(Json(json_response))
 }
 
-fn filter_user_record(user: &User) -> FilteredUser {
+pub fn filter_user_record(user: &User) -> FilteredUser {
     let created_at_utc: DateTime<Utc> = DateTime::from_naive_utc_and_offset(user.created_at.unwrap(), Utc);
     let updated_at_utc: DateTime<Utc> = DateTime::from_naive_utc_and_offset(user.updated_at.unwrap(), Utc);
     FilteredUser {
+        id: user.id,
         email: user.email.to_owned(),
         name: user.name.to_owned(),
+        role: user.role.to_owned(),
+        photo: user.avatar_key.as_deref().map(blob_store::url),
+        phone: user.phone.to_owned(),
+        locale: user.locale.to_owned(),
+        currency: user.currency.to_owned(),
+        marketingEmailConsent: user.marketing_email_consent,
+        marketingSmsConsent: user.marketing_sms_consent,
         verified: user.verified,
         createdAt: created_at_utc,
         updatedAt: updated_at_utc,
+        deletionScheduledAt: user.deletion_scheduled_at,
     }
 }
 
+/// Tells the account owner about the lock; sent in the background so the
+/// attacker's response time does not reveal whether the account exists.
+pub fn notify_lockout(data: Arc<AppState>, email: String) {
+    let minutes = data.login_throttle.lockout().as_secs().div_ceil(60);
+    let body = format!(
+        "We noticed several failed attempts to sign in to your account, so it has been locked for {} minute(s). If this was not you, we recommend changing your password once the lock expires.",
+        minutes
+    );
+
+    tokio::task::spawn_blocking(move || {
+        let sent = send_email(&data.config, email, "Your account has been temporarily locked", body).is_ok();
+        data.metrics.record_email(sent);
+    });
+}
+
 fn gen_rand_num() -> u32 {
     let mut rng = rand::thread_rng();
     let random_number: u32 = rng.gen_range(100_000..1_000_000);
     random_number
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use super::*;
+    use crate::test_support::{example_config, json_response, ScratchDb};
+
+    const IP: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+    async fn login(data: &Arc<AppState>, email: &str, password: &str) -> (StatusCode, serde_json::Value) {
+        let body = LoginUserSchema {
+            email: email.to_string(),
+            password: password.to_string(),
+        };
+        let response = match login_user_handler(State(data.clone()), ClientIp(IP), Json(body)).await {
+            Ok(response) => response.into_response(),
+            Err(response) => response,
+        };
+        json_response(response).await
+    }
+
+    #[tokio::test]
+    async fn checks_the_password_before_reporting_an_unverified_account() {
+        let Some(db) = ScratchDb::migrated().await else {
+            return;
+        };
+        let data = db.state(example_config());
+        db.user("new@example.com", "password", "user").await;
+        sqlx::query("UPDATE users SET verified = FALSE")
+            .execute(&db.pool)
+            .await
+            .unwrap();
+
+        let (status, body) = login(&data, "new@example.com", "wrong").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["message"], "Invalid email or password");
+        assert!(data.login_throttle.retry_after(IP, "new@example.com").await.unwrap().is_some());
+
+        data.login_throttle.record_success("new@example.com").await.unwrap();
+        let (status, body) = login(&data, "new@example.com", "password").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["message"], "Please verify your email before you can log in");
+        db.drop().await;
+    }
+}
@@ -11,6 +11,7 @@ use crate::apis::{
         get_me_handler, health_checker_handler, login_user_handler, logout_handler,
         register_user_handler, verify_email_handler,
     },
+    login::{oidc, two_factor},
     jwt_auth::auth,
 };
 
@@ -30,6 +31,24 @@ pub fn login_router(app_state: Arc<AppState>) -> Router {
             "/auth/verifyemail/:verification_code",
             get(verify_email_handler),
         )
+        .route("/auth/oidc/login", get(oidc::oidc_login_handler))
+        .route("/auth/oidc/callback", get(oidc::oidc_callback_handler))
+        .route("/auth/2fa/verify", post(two_factor::verify_handler))
+        .route(
+            "/auth/2fa/enroll",
+            post(two_factor::enroll_handler)
+                .route_layer(middleware::from_fn_with_state(app_state.clone(), auth)),
+        )
+        .route(
+            "/auth/2fa/confirm",
+            post(two_factor::confirm_handler)
+                .route_layer(middleware::from_fn_with_state(app_state.clone(), auth)),
+        )
+        .route(
+            "/auth/2fa/disable",
+            post(two_factor::disable_handler)
+                .route_layer(middleware::from_fn_with_state(app_state.clone(), auth)),
+        )
         .route(
             "/me",
             get(get_me_handler)
@@ -1,5 +1,5 @@
 use chrono::prelude::*;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 
 #[allow(non_snake_case)]
 #[derive(Debug, Deserialize, sqlx::FromRow, Serialize, Clone)]
@@ -14,15 +14,41 @@ pub struct User {
     pub updated_at: Option<NaiveDateTime>,
     pub verified: bool,
     pub verification_code: Option<String>,
+    pub role: String,
+    pub totp_secret: Option<String>,
+    pub totp_enabled: bool,
+    pub totp_last_used_step: Option<i64>,
+    pub deletion_scheduled_at: Option<DateTime<Utc>>,
+    pub deleted_at: Option<DateTime<Utc>>,
+    pub phone: Option<String>,
+    pub locale: Option<String>,
+    pub currency: Option<String>,
+    pub marketing_email_consent: bool,
+    pub marketing_sms_consent: bool,
+    pub marketing_consent_updated_at: Option<DateTime<Utc>>,
+    pub avatar_key: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TokenClaims {
     pub sub: i32,
+    #[serde(default)]
+    pub role: String,
+    /// Set on restricted tokens, e.g. [`TWO_FACTOR_PENDING_SCOPE`]; absent on session tokens.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    /// Missing from tokens signed before issuers and audiences were checked.
+    #[serde(default)]
+    pub iss: String,
+    #[serde(default)]
+    pub aud: String,
     pub iat: usize,
     pub exp: usize,
 }
 
+pub const TWO_FACTOR_PENDING_SCOPE: &str = "2fa_pending";
+pub const TWO_FACTOR_PENDING_TTL_SECS: usize = 5 * 60;
+
 #[derive(Debug, Deserialize)]
 pub struct RegisterUserSchema {
     pub name: String,
@@ -34,4 +60,39 @@ pub struct RegisterUserSchema {
 pub struct LoginUserSchema {
     pub email: String,
     pub password: String,
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TwoFactorCodeSchema {
+    pub code: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TwoFactorVerifySchema {
+    pub pending_token: String,
+    pub code: String,
+}
+
+/// Body of `PATCH /me`. Absent fields are left alone; `null` clears the
+/// optional ones.
+#[derive(Debug, Deserialize)]
+pub struct UpdateProfileSchema {
+    pub name: Option<String>,
+    #[serde(default, deserialize_with = "present")]
+    pub phone: Option<Option<String>>,
+    #[serde(default, deserialize_with = "present")]
+    pub locale: Option<Option<String>>,
+    #[serde(default, deserialize_with = "present")]
+    pub currency: Option<Option<String>>,
+    pub marketing_email_consent: Option<bool>,
+    pub marketing_sms_consent: Option<bool>,
+}
+
+/// Tells an explicit `null` (`Some(None)`) apart from a missing field (`None`).
+fn present<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
@@ -0,0 +1,509 @@
+use std::sync::Arc;
+
+use argon2::{password_hash::SaltString, Argon2, PasswordHasher};
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Redirect, Response},
+    Json,
+};
+use axum_extra::extract::cookie::{Cookie, CookieJar, SameSite};
+use openidconnect::core::{CoreClient, CoreProviderMetadata, CoreResponseType};
+use openidconnect::reqwest::async_http_client;
+use openidconnect::{
+    AuthenticationFlow, AuthorizationCode, ClientId, ClientSecret, CsrfToken, IssuerUrl, Nonce,
+    PkceCodeChallenge, PkceCodeVerifier, RedirectUrl, Scope, TokenResponse,
+};
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use tokio::sync::OnceCell;
+
+use crate::apis::config::OidcConfig;
+use crate::apis::login::{
+    handler::{pending_two_factor_response, session_response},
+    model::User,
+    response::ErrorResponse,
+};
+
+use crate::routes::API_PREFIX;
+use crate::AppState;
+
+const FLOW_COOKIE: &str = "oidc_flow";
+const FLOW_TTL_SECS: usize = 10 * 60;
+
+type HandlerError = (StatusCode, Json<ErrorResponse>);
+
+/// The configured identity provider. Discovery runs on first use and is then
+/// cached, so the API still starts while the provider is unreachable.
+pub struct OidcClient {
+    config: Option<OidcConfig>,
+    client: OnceCell<CoreClient>,
+}
+
+impl OidcClient {
+    pub fn new(config: Option<OidcConfig>) -> Self {
+        OidcClient {
+            config,
+            client: OnceCell::new(),
+        }
+    }
+
+    async fn client(&self) -> Result<(&OidcConfig, &CoreClient), HandlerError> {
+        let config = self
+            .config
+            .as_ref()
+            .ok_or_else(|| fail(StatusCode::NOT_FOUND, "Single sign-on is not configured"))?;
+
+        let client = self
+            .client
+            .get_or_try_init(|| async {
+                let issuer = IssuerUrl::new(config.issuer_url.clone())
+                    .map_err(|e| format!("Invalid issuer URL: {}", e))?;
+                let metadata = CoreProviderMetadata::discover_async(issuer, async_http_client)
+                    .await
+                    .map_err(|e| format!("Provider discovery failed: {}", e))?;
+                let redirect_url = RedirectUrl::new(config.redirect_url.clone())
+                    .map_err(|e| format!("Invalid redirect URL: {}", e))?;
+
+                Ok::<_, String>(
+                    CoreClient::from_provider_metadata(
+                        metadata,
+                        ClientId::new(config.client_id.clone()),
+                        Some(ClientSecret::new(config.client_secret.clone())),
+                    )
+                    .set_redirect_uri(redirect_url),
+                )
+            })
+            .await
+            .map_err(|e| {
+                tracing::error!(error = %e, "could not set up the OIDC client");
+                fail(StatusCode::BAD_GATEWAY, "The identity provider is unavailable")
+            })?;
+
+        Ok((config, client))
+    }
+
+    /// Redeems the authorization code and checks the ID token that comes back.
+    async fn identity(
+        &self,
+        code: String,
+        pkce_verifier: String,
+        nonce: String,
+    ) -> Result<Identity, HandlerError> {
+        let (config, client) = self.client().await?;
+
+        let token_response = client
+            .exchange_code(AuthorizationCode::new(code))
+            .set_pkce_verifier(PkceCodeVerifier::new(pkce_verifier))
+            .request_async(async_http_client)
+            .await
+            .map_err(|e| {
+                tracing::warn!(error = %e, "OIDC code exchange failed");
+                fail(StatusCode::UNAUTHORIZED, "The identity provider rejected the sign-in")
+            })?;
+
+        let id_token = token_response
+            .id_token()
+            .ok_or_else(|| fail(StatusCode::UNAUTHORIZED, "The identity provider did not return an ID token"))?;
+        let claims = id_token
+            .claims(&client.id_token_verifier(), &Nonce::new(nonce))
+            .map_err(|e| {
+                tracing::warn!(error = %e, "OIDC ID token rejected");
+                fail(StatusCode::UNAUTHORIZED, "Invalid ID token")
+            })?;
+
+        Ok(Identity {
+            provider: config.provider_name.clone(),
+            subject: claims.subject().to_string(),
+            email: claims.email().map(|email| email.to_ascii_lowercase()),
+            email_verified: claims.email_verified().unwrap_or(false),
+            name: claims
+                .name()
+                .and_then(|name| name.get(None))
+                .map(|name| name.to_string()),
+        })
+    }
+}
+
+/// What we must remember between the redirect to the provider and its callback.
+#[derive(Debug, Serialize, Deserialize)]
+struct FlowClaims {
+    iss: String,
+    aud: String,
+    state: String,
+    nonce: String,
+    pkce_verifier: String,
+    exp: usize,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CallbackQuery {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+}
+
+/// Sends the browser to the provider's authorization endpoint.
+pub async fn oidc_login_handler(
+    State(data): State<Arc<AppState>>,
+    cookie_jar: CookieJar,
+) -> Result<impl IntoResponse, HandlerError> {
+    let (config, client) = data.oidc.client().await?;
+
+    let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+    let mut request = client
+        .authorize_url(
+            AuthenticationFlow::<CoreResponseType>::AuthorizationCode,
+            CsrfToken::new_random,
+            Nonce::new_random,
+        )
+        .set_pkce_challenge(pkce_challenge);
+    for scope in config.scopes.iter().filter(|scope| scope.as_str() != "openid") {
+        request = request.add_scope(Scope::new(scope.clone()));
+    }
+    let (auth_url, state, nonce) = request.url();
+
+    let flow = FlowClaims {
+        iss: data.jwt_keys.issuer().to_string(),
+        aud: flow_audience(&data),
+        state: state.secret().clone(),
+        nonce: nonce.secret().clone(),
+        pkce_verifier: pkce_verifier.secret().clone(),
+        exp: chrono::Utc::now().timestamp() as usize + FLOW_TTL_SECS,
+    };
+    let flow_token = data
+        .jwt_keys
+        .encode(&flow)
+        .map_err(|e| fail(StatusCode::INTERNAL_SERVER_ERROR, &format!("Could not start sign-in: {}", e)))?;
+
+    let cookie = Cookie::build(FLOW_COOKIE, flow_token)
+        .path(flow_cookie_path())
+        .max_age(time::Duration::seconds(FLOW_TTL_SECS as i64))
+        .same_site(SameSite::Lax)
+        .http_only(true)
+        .finish();
+
+    Ok((cookie_jar.add(cookie), Redirect::to(auth_url.as_str())))
+}
+
+/// Completes the code flow, then links or creates the local account and
+/// answers exactly like `/auth/login` does.
+pub async fn oidc_callback_handler(
+    State(data): State<Arc<AppState>>,
+    cookie_jar: CookieJar,
+    Query(query): Query<CallbackQuery>,
+) -> Result<Response, HandlerError> {
+    data.oidc.client().await?;
+
+    if let Some(error) = query.error {
+        let message = query.error_description.unwrap_or(error);
+        return Err(fail(StatusCode::UNAUTHORIZED, &format!("Sign-in was not completed: {}", message)));
+    }
+
+    let flow = cookie_jar
+        .get(FLOW_COOKIE)
+        .and_then(|cookie| {
+            data.jwt_keys
+                .decode::<FlowClaims>(cookie.value(), &flow_audience(&data))
+                .ok()
+        })
+        .ok_or_else(|| fail(StatusCode::BAD_REQUEST, "Sign-in session expired, please start again"))?;
+
+    if query.state.as_deref() != Some(flow.state.as_str()) {
+        return Err(fail(StatusCode::BAD_REQUEST, "Sign-in state mismatch, please start again"));
+    }
+    let code = query
+        .code
+        .ok_or_else(|| fail(StatusCode::BAD_REQUEST, "Missing authorization code"))?;
+
+    let identity = data.oidc.identity(code, flow.pkce_verifier, flow.nonce).await?;
+    let user = link_identity(&data, identity).await?;
+    let cookie_jar = cookie_jar.remove(Cookie::build(FLOW_COOKIE, "").path(flow_cookie_path()).finish());
+
+    if user.totp_enabled {
+        return Ok((cookie_jar, pending_two_factor_response(&data, &user)).into_response());
+    }
+
+    data.metrics.record_login("success");
+    Ok((cookie_jar, session_response(&data, &user)).into_response())
+}
+
+#[derive(Debug)]
+struct Identity {
+    provider: String,
+    subject: String,
+    email: Option<String>,
+    email_verified: bool,
+    name: Option<String>,
+}
+
+/// Finds the user behind an external identity. Unknown identities are linked to
+/// the account with the same email, or get a new account, but only when the
+/// provider vouches for the email address.
+async fn link_identity(data: &AppState, identity: Identity) -> Result<User, HandlerError> {
+    let linked: Option<User> = sqlx::query_as(
+        "SELECT users.* FROM users JOIN user_identities ON user_identities.user_id = users.id WHERE user_identities.provider = $1 AND user_identities.subject = $2",
+    )
+    .bind(&identity.provider)
+    .bind(&identity.subject)
+    .fetch_optional(&data.db)
+    .await
+    .map_err(database_error)?;
+
+    if let Some(user) = linked {
+        return Ok(user);
+    }
+
+    let email = match identity.email {
+        Some(email) if identity.email_verified => email,
+        _ => {
+            return Err(fail(
+                StatusCode::FORBIDDEN,
+                "The identity provider did not confirm your email address",
+            ))
+        }
+    };
+
+    let mut tx = data.db.begin().await.map_err(database_error)?;
+
+    let existing: Option<User> = sqlx::query_as("SELECT * FROM users WHERE email = $1 FOR UPDATE")
+        .bind(&email)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(database_error)?;
+
+    let user = match existing {
+        Some(user) if user.verified => user,
+        // The provider has proven ownership of the address, which is what our own
+        // verification email would have done. Whoever registered the account never
+        // did, so the password and second factor they set up must stop working.
+        // Unverified accounts cannot log in, so there are no sessions to end.
+        Some(user) => {
+            sqlx::query("DELETE FROM user_backup_codes WHERE user_id = $1")
+                .bind(user.id)
+                .execute(&mut *tx)
+                .await
+                .map_err(database_error)?;
+            tracing::warn!(user_id = user.id, "reset credentials of an unverified account claimed through SSO");
+            sqlx::query_as(
+                r#"UPDATE users SET
+                    verified = TRUE,
+                    verification_code = NULL,
+                    password = $2,
+                    totp_secret = NULL,
+                    totp_enabled = FALSE,
+                    totp_last_used_step = NULL,
+                    updated_at = NOW()
+                WHERE id = $1
+                RETURNING *"#,
+            )
+            .bind(user.id)
+            .bind(unusable_password()?)
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(database_error)?
+        }
+        None => {
+            let name: String = identity
+                .name
+                .unwrap_or_else(|| email.split('@').next().unwrap_or_default().to_string())
+                .chars()
+                .take(100)
+                .collect();
+            let user: User = sqlx::query_as(
+                "INSERT INTO users (name, email, password, verified) VALUES ($1, $2, $3, TRUE) RETURNING *",
+            )
+            .bind(name)
+            .bind(&email)
+            .bind(unusable_password()?)
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(database_error)?;
+            data.metrics.registrations.inc();
+            user
+        }
+    };
+
+    sqlx::query("INSERT INTO user_identities (user_id, provider, subject, email) VALUES ($1, $2, $3, $4)")
+        .bind(user.id)
+        .bind(&identity.provider)
+        .bind(&identity.subject)
+        .bind(&email)
+        .execute(&mut *tx)
+        .await
+        .map_err(database_error)?;
+
+    tx.commit().await.map_err(database_error)?;
+
+    tracing::info!(user_id = user.id, provider = %identity.provider, "linked external identity");
+    Ok(user)
+}
+
+/// Accounts created or claimed through SSO get a hash of random bytes nobody knows, so
+/// password login stays impossible until the user sets one.
+fn unusable_password() -> Result<String, HandlerError> {
+    let mut random = [0u8; 32];
+    OsRng.fill_bytes(&mut random);
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(&random, &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| {
+            fail(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("Error while hashing password: {}", e),
+            )
+        })
+}
+
+/// Keeps flow tokens from being accepted as sessions, and vice versa.
+fn flow_audience(data: &AppState) -> String {
+    format!("{}#oidc-flow", data.jwt_keys.audience())
+}
+
+/// The flow cookie is only needed by the callback, so keep it off every other request.
+fn flow_cookie_path() -> String {
+    format!("{}/auth/oidc", API_PREFIX)
+}
+
+fn fail(status: StatusCode, message: &str) -> HandlerError {
+    let error_response = ErrorResponse {
+        status: "fail",
+        message: message.to_string(),
+    };
+    (status, Json(error_response))
+}
+
+fn database_error(e: sqlx::Error) -> HandlerError {
+    let error_response = ErrorResponse {
+        status: "error",
+        message: format!("Database error: {}", e),
+    };
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{routing::get, routing::post, Json, Router};
+    use chrono::{Duration, Utc};
+    use openidconnect::core::{
+        CoreIdToken, CoreIdTokenClaims, CoreIdTokenFields, CoreJsonWebKeySet,
+        CoreJwsSigningAlgorithm, CoreRsaPrivateSigningKey, CoreSubjectIdentifierType,
+        CoreTokenResponse, CoreTokenType,
+    };
+    use openidconnect::{
+        AccessToken, Audience, AuthUrl, EmptyAdditionalClaims, EmptyAdditionalProviderMetadata,
+        EmptyExtraTokenFields, EndUserEmail, JsonWebKeyId, JsonWebKeySetUrl, PrivateSigningKey,
+        ResponseTypes, StandardClaims, SubjectIdentifier, TokenUrl,
+    };
+    use rsa::pkcs1::{EncodeRsaPrivateKey, LineEnding};
+    use serde_json::Value;
+
+    use super::*;
+
+    const CLIENT_ID: &str = "shopping-test";
+    const NONCE: &str = "expected-nonce";
+
+    /// A provider that answers every code exchange with an ID token for
+    /// `Alice@Example.com`, whose address it vouches for.
+    async fn mock_issuer() -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let issuer = format!("http://{}", listener.local_addr().unwrap());
+
+        let pem = rsa::RsaPrivateKey::new(&mut OsRng, 2048)
+            .unwrap()
+            .to_pkcs1_pem(LineEnding::LF)
+            .unwrap();
+        let key = CoreRsaPrivateSigningKey::from_pem(&pem, Some(JsonWebKeyId::new("test".into()))).unwrap();
+        let jwks = serde_json::to_value(CoreJsonWebKeySet::new(vec![key.as_verification_key()])).unwrap();
+        let metadata = serde_json::to_value(
+            CoreProviderMetadata::new(
+                IssuerUrl::new(issuer.clone()).unwrap(),
+                AuthUrl::new(format!("{}/authorize", issuer)).unwrap(),
+                JsonWebKeySetUrl::new(format!("{}/jwks", issuer)).unwrap(),
+                vec![ResponseTypes::new(vec![CoreResponseType::Code])],
+                vec![CoreSubjectIdentifierType::Public],
+                vec![CoreJwsSigningAlgorithm::RsaSsaPkcs1V15Sha256],
+                EmptyAdditionalProviderMetadata {},
+            )
+            .set_token_endpoint(Some(TokenUrl::new(format!("{}/token", issuer)).unwrap())),
+        )
+        .unwrap();
+
+        let claims = CoreIdTokenClaims::new(
+            IssuerUrl::new(issuer.clone()).unwrap(),
+            vec![Audience::new(CLIENT_ID.into())],
+            Utc::now() + Duration::minutes(5),
+            Utc::now(),
+            StandardClaims::new(SubjectIdentifier::new("alice-123".into()))
+                .set_email(Some(EndUserEmail::new("Alice@Example.com".into())))
+                .set_email_verified(Some(true)),
+            EmptyAdditionalClaims {},
+        )
+        .set_nonce(Some(Nonce::new(NONCE.into())));
+        let id_token =
+            CoreIdToken::new(claims, &key, CoreJwsSigningAlgorithm::RsaSsaPkcs1V15Sha256, None, None).unwrap();
+        let token_response = serde_json::to_value(CoreTokenResponse::new(
+            AccessToken::new("access-token".into()),
+            CoreTokenType::Bearer,
+            CoreIdTokenFields::new(Some(id_token), EmptyExtraTokenFields {}),
+        ))
+        .unwrap();
+
+        let json = |value: Value| move || async move { Json(value) };
+        let app = Router::new()
+            .route("/.well-known/openid-configuration", get(json(metadata)))
+            .route("/jwks", get(json(jwks)))
+            .route("/token", post(json(token_response)));
+        tokio::spawn(axum::Server::from_tcp(listener).unwrap().serve(app.into_make_service()));
+        issuer
+    }
+
+    fn client(issuer: String) -> OidcClient {
+        OidcClient::new(Some(OidcConfig {
+            provider_name: "mock".to_string(),
+            issuer_url: issuer,
+            client_id: CLIENT_ID.to_string(),
+            client_secret: "secret".to_string(),
+            redirect_url: "http://localhost/callback".to_string(),
+            scopes: vec!["openid".to_string(), "email".to_string()],
+        }))
+    }
+
+    #[tokio::test]
+    async fn reads_the_identity_from_the_id_token() {
+        let client = client(mock_issuer().await);
+
+        let identity = client
+            .identity("code".into(), "verifier".into(), NONCE.into())
+            .await
+            .unwrap();
+
+        assert_eq!(identity.provider, "mock");
+        assert_eq!(identity.subject, "alice-123");
+        assert_eq!(identity.email.as_deref(), Some("alice@example.com"));
+        assert!(identity.email_verified);
+    }
+
+    #[tokio::test]
+    async fn rejects_an_id_token_for_another_sign_in() {
+        let client = client(mock_issuer().await);
+
+        let (status, _) = client
+            .identity("code".into(), "verifier".into(), "other-nonce".into())
+            .await
+            .unwrap_err();
+
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn reports_an_unreachable_provider() {
+        let client = client("http://127.0.0.1:9".to_string());
+
+        let (status, _) = client.client().await.unwrap_err();
+
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+    }
+}
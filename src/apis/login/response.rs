@@ -4,11 +4,21 @@ use serde::Serialize;
 #[allow(non_snake_case)]
 #[derive(Debug, Serialize)]
 pub struct FilteredUser {
+    pub id: i32,
     pub name: String,
     pub email: String,
+    pub role: String,
+    pub photo: Option<String>,
+    pub phone: Option<String>,
+    pub locale: Option<String>,
+    pub currency: Option<String>,
+    pub marketingEmailConsent: bool,
+    pub marketingSmsConsent: bool,
     pub verified: bool,
     pub createdAt: DateTime<Utc>,
     pub updatedAt: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deletionScheduledAt: Option<DateTime<Utc>>,
 }
 
 impl FilteredUser {
@@ -0,0 +1,393 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+
+use crate::apis::config::Config;
+
+/// Failed login bookkeeping for one key (`ip:..` or `email:..`).
+#[derive(Debug, Clone, Copy, sqlx::FromRow)]
+pub struct AttemptState {
+    pub failures: i32,
+    pub window_started_at: DateTime<Utc>,
+    pub last_failure_at: DateTime<Utc>,
+    pub locked_until: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttemptStoreKind {
+    Memory,
+    Postgres,
+}
+
+impl FromStr for AttemptStoreKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "memory" => Ok(AttemptStoreKind::Memory),
+            "postgres" => Ok(AttemptStoreKind::Postgres),
+            _ => Err("expected `memory` or `postgres`".to_string()),
+        }
+    }
+}
+
+/// Where attempts are counted. The in-memory store is per process; use the
+/// Postgres store when several instances sit behind one load balancer.
+#[derive(Clone)]
+enum AttemptStore {
+    Memory(Arc<Mutex<HashMap<String, AttemptState>>>),
+    Postgres(PgPool),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Policy {
+    max_failures: i32,
+    ip_max_failures: i32,
+    window: Duration,
+    lockout: Duration,
+    backoff_base: Duration,
+}
+
+#[derive(Clone)]
+pub struct LoginThrottle {
+    store: AttemptStore,
+    policy: Policy,
+}
+
+impl LoginThrottle {
+    pub fn new(config: &Config, db: PgPool) -> Self {
+        let store = match config.login_throttle_store {
+            AttemptStoreKind::Memory => AttemptStore::Memory(Arc::default()),
+            AttemptStoreKind::Postgres => AttemptStore::Postgres(db),
+        };
+
+        LoginThrottle {
+            store,
+            policy: Policy {
+                max_failures: config.login_max_failures,
+                ip_max_failures: config.login_ip_max_failures,
+                window: config.login_failure_window,
+                lockout: config.login_lockout_duration,
+                backoff_base: config.login_backoff_base,
+            },
+        }
+    }
+
+    /// How long the caller has to wait before trying again, if at all.
+    pub async fn retry_after(&self, ip: IpAddr, email: &str) -> Result<Option<Duration>, sqlx::Error> {
+        let now = Utc::now();
+        let mut wait = chrono::Duration::zero();
+
+        if let Some(state) = self.store.get(&ip_key(ip)).await? {
+            wait = wait.max(lock_remaining(&state, now));
+        }
+
+        if let Some(state) = self.store.get(&email_key(email)).await? {
+            wait = wait.max(lock_remaining(&state, now));
+
+            if state.failures > 0 && state.window_started_at > now - to_chrono(self.policy.window) {
+                let next_attempt = state.last_failure_at + to_chrono(self.backoff(state.failures));
+                wait = wait.max(next_attempt - now);
+            }
+        }
+
+        Ok(wait.to_std().ok().filter(|wait| !wait.is_zero()))
+    }
+
+    /// Counts a failed attempt against both keys. Returns `true` when this
+    /// failure locked the account, so the owner can be told about it.
+    pub async fn record_failure(&self, ip: IpAddr, email: &str) -> Result<bool, sqlx::Error> {
+        self.fail(&ip_key(ip), self.policy.ip_max_failures).await?;
+        self.fail(&email_key(email), self.policy.max_failures).await
+    }
+
+    /// A successful login forgives earlier mistakes on that account, but not on the IP.
+    pub async fn record_success(&self, email: &str) -> Result<(), sqlx::Error> {
+        self.store.remove(&email_key(email)).await
+    }
+
+    /// Drops entries whose window and lock have both run out.
+    pub async fn prune(&self) -> Result<u64, sqlx::Error> {
+        let cutoff = Utc::now() - to_chrono(self.policy.window);
+        self.store.prune(cutoff, Utc::now()).await
+    }
+
+    pub fn lockout(&self) -> Duration {
+        self.policy.lockout
+    }
+
+    async fn fail(&self, key: &str, max_failures: i32) -> Result<bool, sqlx::Error> {
+        let now = Utc::now();
+        let window_start = now - to_chrono(self.policy.window);
+        let state = self.store.increment(key, now, window_start).await?;
+
+        if state.failures >= max_failures {
+            self.store.lock(key, now, now + to_chrono(self.policy.lockout)).await?;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    /// Doubles the wait after every failure, starting at `backoff_base` and
+    /// never exceeding the lockout itself.
+    fn backoff(&self, failures: i32) -> Duration {
+        let doublings = (failures - 1).clamp(0, 16) as u32;
+        self.policy
+            .backoff_base
+            .saturating_mul(1 << doublings)
+            .min(self.policy.lockout)
+    }
+}
+
+impl AttemptStore {
+    async fn get(&self, key: &str) -> Result<Option<AttemptState>, sqlx::Error> {
+        match self {
+            AttemptStore::Memory(map) => Ok(map.lock().unwrap().get(key).copied()),
+            AttemptStore::Postgres(db) => {
+                sqlx::query_as(
+                    "SELECT failures, window_started_at, last_failure_at, locked_until FROM login_attempts WHERE key = $1",
+                )
+                .bind(key)
+                .fetch_optional(db)
+                .await
+            }
+        }
+    }
+
+    /// Adds one failure, starting a fresh window if the previous one expired.
+    async fn increment(
+        &self,
+        key: &str,
+        now: DateTime<Utc>,
+        window_start: DateTime<Utc>,
+    ) -> Result<AttemptState, sqlx::Error> {
+        match self {
+            AttemptStore::Memory(map) => {
+                let mut map = map.lock().unwrap();
+                let state = map.entry(key.to_string()).or_insert(AttemptState {
+                    failures: 0,
+                    window_started_at: now,
+                    last_failure_at: now,
+                    locked_until: None,
+                });
+                if state.window_started_at < window_start {
+                    state.failures = 0;
+                    state.window_started_at = now;
+                }
+                state.failures += 1;
+                state.last_failure_at = now;
+                Ok(*state)
+            }
+            AttemptStore::Postgres(db) => {
+                sqlx::query_as(
+                    r#"INSERT INTO login_attempts (key, failures, window_started_at, last_failure_at)
+                    VALUES ($1, 1, $2, $2)
+                    ON CONFLICT (key) DO UPDATE SET
+                        failures = CASE WHEN login_attempts.window_started_at < $3 THEN 1 ELSE login_attempts.failures + 1 END,
+                        window_started_at = CASE WHEN login_attempts.window_started_at < $3 THEN $2 ELSE login_attempts.window_started_at END,
+                        last_failure_at = $2
+                    RETURNING failures, window_started_at, last_failure_at, locked_until"#,
+                )
+                .bind(key)
+                .bind(now)
+                .bind(window_start)
+                .fetch_one(db)
+                .await
+            }
+        }
+    }
+
+    /// Locks the key and resets its counter, so the backoff starts over once the lock lapses.
+    async fn lock(
+        &self,
+        key: &str,
+        now: DateTime<Utc>,
+        until: DateTime<Utc>,
+    ) -> Result<(), sqlx::Error> {
+        match self {
+            AttemptStore::Memory(map) => {
+                if let Some(state) = map.lock().unwrap().get_mut(key) {
+                    state.failures = 0;
+                    state.window_started_at = now;
+                    state.locked_until = Some(until);
+                }
+                Ok(())
+            }
+            AttemptStore::Postgres(db) => {
+                sqlx::query(
+                    "UPDATE login_attempts SET failures = 0, window_started_at = $2, locked_until = $3 WHERE key = $1",
+                )
+                .bind(key)
+                .bind(now)
+                .bind(until)
+                .execute(db)
+                .await
+                .map(|_| ())
+            }
+        }
+    }
+
+    async fn remove(&self, key: &str) -> Result<(), sqlx::Error> {
+        match self {
+            AttemptStore::Memory(map) => {
+                map.lock().unwrap().remove(key);
+                Ok(())
+            }
+            AttemptStore::Postgres(db) => sqlx::query("DELETE FROM login_attempts WHERE key = $1")
+                .bind(key)
+                .execute(db)
+                .await
+                .map(|_| ()),
+        }
+    }
+
+    async fn prune(&self, cutoff: DateTime<Utc>, now: DateTime<Utc>) -> Result<u64, sqlx::Error> {
+        match self {
+            AttemptStore::Memory(map) => {
+                let mut map = map.lock().unwrap();
+                let before = map.len();
+                map.retain(|_, state| {
+                    state.window_started_at >= cutoff || state.locked_until.is_some_and(|until| until > now)
+                });
+                Ok((before - map.len()) as u64)
+            }
+            AttemptStore::Postgres(db) => sqlx::query(
+                "DELETE FROM login_attempts WHERE window_started_at < $1 AND (locked_until IS NULL OR locked_until < $2)",
+            )
+            .bind(cutoff)
+            .bind(now)
+            .execute(db)
+            .await
+            .map(|result| result.rows_affected()),
+        }
+    }
+}
+
+fn ip_key(ip: IpAddr) -> String {
+    format!("ip:{}", ip)
+}
+
+fn email_key(email: &str) -> String {
+    format!("email:{}", email.to_ascii_lowercase())
+}
+
+fn lock_remaining(state: &AttemptState, now: DateTime<Utc>) -> chrono::Duration {
+    state
+        .locked_until
+        .map(|until| until - now)
+        .unwrap_or_else(chrono::Duration::zero)
+}
+
+fn to_chrono(duration: Duration) -> chrono::Duration {
+    chrono::Duration::from_std(duration).unwrap_or_else(|_| chrono::Duration::days(365))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::ScratchDb;
+
+    const IP: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(203, 0, 113, 7));
+
+    fn throttle(store: AttemptStore) -> LoginThrottle {
+        LoginThrottle {
+            store,
+            policy: Policy {
+                max_failures: 3,
+                ip_max_failures: 5,
+                window: Duration::from_secs(15 * 60),
+                lockout: Duration::from_secs(15 * 60),
+                backoff_base: Duration::from_secs(1),
+            },
+        }
+    }
+
+    fn in_memory() -> LoginThrottle {
+        throttle(AttemptStore::Memory(Arc::default()))
+    }
+
+    async fn locks_after_max_failures(throttle: LoginThrottle) {
+        assert!(!throttle.record_failure(IP, "a@example.com").await.unwrap());
+        assert!(!throttle.record_failure(IP, "A@example.com").await.unwrap(), "emails are case-insensitive");
+        assert!(throttle.record_failure(IP, "a@example.com").await.unwrap());
+
+        let wait = throttle.retry_after(IP, "a@example.com").await.unwrap().unwrap();
+        assert!(wait > Duration::from_secs(14 * 60), "{:?}", wait);
+        assert_eq!(throttle.retry_after(IP, "b@example.com").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn locks_the_account_after_max_failures() {
+        locks_after_max_failures(in_memory()).await;
+    }
+
+    #[tokio::test]
+    async fn locks_the_account_after_max_failures_in_postgres() {
+        let Some(db) = ScratchDb::migrated().await else {
+            return;
+        };
+        locks_after_max_failures(throttle(AttemptStore::Postgres(db.pool.clone()))).await;
+        db.drop().await;
+    }
+
+    #[tokio::test]
+    async fn locks_the_ip_across_accounts() {
+        let throttle = in_memory();
+        for i in 0..4 {
+            assert!(!throttle.record_failure(IP, &format!("{}@example.com", i)).await.unwrap());
+        }
+        throttle.record_failure(IP, "4@example.com").await.unwrap();
+
+        assert!(throttle.retry_after(IP, "new@example.com").await.unwrap().is_some());
+        let elsewhere = IpAddr::V4(std::net::Ipv4Addr::new(198, 51, 100, 1));
+        assert_eq!(throttle.retry_after(elsewhere, "new@example.com").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn starts_a_new_window_once_the_old_one_expires() {
+        let throttle = in_memory();
+        throttle.record_failure(IP, "a@example.com").await.unwrap();
+        throttle.record_failure(IP, "a@example.com").await.unwrap();
+        let AttemptStore::Memory(map) = &throttle.store else {
+            unreachable!()
+        };
+        for state in map.lock().unwrap().values_mut() {
+            state.window_started_at -= chrono::Duration::minutes(16);
+            state.last_failure_at -= chrono::Duration::minutes(16);
+        }
+        assert_eq!(throttle.retry_after(IP, "a@example.com").await.unwrap(), None);
+
+        assert!(!throttle.record_failure(IP, "a@example.com").await.unwrap());
+        assert_eq!(map.lock().unwrap()["email:a@example.com"].failures, 1);
+    }
+
+    #[tokio::test]
+    async fn forgets_the_account_failures_on_success_but_not_the_ip() {
+        let throttle = in_memory();
+        throttle.record_failure(IP, "a@example.com").await.unwrap();
+        throttle.record_failure(IP, "a@example.com").await.unwrap();
+        assert!(throttle.retry_after(IP, "a@example.com").await.unwrap().is_some(), "backing off");
+
+        throttle.record_success("A@example.com").await.unwrap();
+
+        assert_eq!(throttle.retry_after(IP, "a@example.com").await.unwrap(), None);
+        assert!(!throttle.record_failure(IP, "a@example.com").await.unwrap());
+        let AttemptStore::Memory(map) = &throttle.store else {
+            unreachable!()
+        };
+        assert_eq!(map.lock().unwrap()["ip:203.0.113.7"].failures, 3);
+    }
+
+    #[test]
+    fn doubles_the_backoff_up_to_the_lockout() {
+        let throttle = in_memory();
+        assert_eq!(throttle.backoff(1), Duration::from_secs(1));
+        assert_eq!(throttle.backoff(4), Duration::from_secs(8));
+        assert_eq!(throttle.backoff(40), Duration::from_secs(15 * 60));
+    }
+}
@@ -0,0 +1,412 @@
+use std::sync::Arc;
+
+use argon2::{password_hash::SaltString, Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use rand::{rngs::OsRng, Rng};
+use serde_json::json;
+use totp_rs::{Algorithm, Secret, TOTP};
+
+use crate::apis::client_ip::ClientIp;
+use crate::apis::jwt_auth::decode_token;
+use crate::apis::login::{
+    handler::{notify_lockout, session_response, too_many_attempts},
+    model::{TwoFactorCodeSchema, TwoFactorVerifySchema, User, TWO_FACTOR_PENDING_SCOPE},
+    response::ErrorResponse,
+};
+
+use crate::AppState;
+
+const TOTP_DIGITS: usize = 6;
+const TOTP_STEP_SECS: u64 = 30;
+const BACKUP_CODE_COUNT: usize = 10;
+const BACKUP_CODE_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+
+type HandlerError = (StatusCode, Json<ErrorResponse>);
+
+/// Starts enrollment: stores a fresh secret (not yet active) and returns the
+/// `otpauth://` URI for the authenticator app.
+pub async fn enroll_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, HandlerError> {
+    if user.totp_enabled {
+        return Err(fail(StatusCode::CONFLICT, "Two-factor authentication is already enabled"));
+    }
+
+    let secret = match Secret::generate_secret().to_encoded() {
+        Secret::Encoded(secret) => secret,
+        Secret::Raw(_) => unreachable!("to_encoded always returns an encoded secret"),
+    };
+    let totp = build_totp(&data.config.totp_issuer, &secret, &user.email)
+        .map_err(|e| fail(StatusCode::INTERNAL_SERVER_ERROR, &e))?;
+
+    sqlx::query("UPDATE users SET totp_secret = $1, totp_last_used_step = NULL, updated_at = NOW() WHERE id = $2")
+        .bind(&secret)
+        .bind(user.id)
+        .execute(&data.db)
+        .await
+        .map_err(database_error)?;
+
+    let json_response = json!({
+        "status": "success",
+        "data": {
+            "secret": secret,
+            "provisioning_uri": totp.get_url(),
+        }
+    });
+
+    Ok(Json(json_response))
+}
+
+/// Activates 2FA once the user proves their app produces valid codes, and hands
+/// out the backup codes. This is the only time the backup codes are shown.
+pub async fn confirm_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Json(body): Json<TwoFactorCodeSchema>,
+) -> Result<impl IntoResponse, HandlerError> {
+    if user.totp_enabled {
+        return Err(fail(StatusCode::CONFLICT, "Two-factor authentication is already enabled"));
+    }
+    if user.totp_secret.is_none() {
+        return Err(fail(StatusCode::BAD_REQUEST, "Start two-factor enrollment first"));
+    }
+
+    if !verify_totp(&data, &user, &body.code).await? {
+        return Err(fail(StatusCode::BAD_REQUEST, "Invalid authentication code"));
+    }
+
+    let codes: Vec<String> = (0..BACKUP_CODE_COUNT).map(|_| gen_backup_code()).collect();
+    let mut hashes = Vec::with_capacity(codes.len());
+    for code in &codes {
+        let salt = SaltString::generate(&mut OsRng);
+        let hash = Argon2::default()
+            .hash_password(normalize_backup_code(code).as_bytes(), &salt)
+            .map_err(|e| {
+                fail(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    &format!("Error while hashing backup code: {}", e),
+                )
+            })?;
+        hashes.push(hash.to_string());
+    }
+
+    let mut tx = data.db.begin().await.map_err(database_error)?;
+    sqlx::query("UPDATE users SET totp_enabled = TRUE, updated_at = NOW() WHERE id = $1")
+        .bind(user.id)
+        .execute(&mut *tx)
+        .await
+        .map_err(database_error)?;
+    sqlx::query("DELETE FROM user_backup_codes WHERE user_id = $1")
+        .bind(user.id)
+        .execute(&mut *tx)
+        .await
+        .map_err(database_error)?;
+    for hash in &hashes {
+        sqlx::query("INSERT INTO user_backup_codes (user_id, code_hash) VALUES ($1, $2)")
+            .bind(user.id)
+            .bind(hash)
+            .execute(&mut *tx)
+            .await
+            .map_err(database_error)?;
+    }
+    tx.commit().await.map_err(database_error)?;
+
+    let json_response = json!({
+        "status": "success",
+        "data": { "backup_codes": codes }
+    });
+
+    Ok(Json(json_response))
+}
+
+/// Turns 2FA off; needs a current code or an unused backup code.
+pub async fn disable_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Json(body): Json<TwoFactorCodeSchema>,
+) -> Result<impl IntoResponse, HandlerError> {
+    if !user.totp_enabled {
+        return Err(fail(StatusCode::BAD_REQUEST, "Two-factor authentication is not enabled"));
+    }
+
+    if !verify_second_factor(&data, &user, &body.code).await? {
+        return Err(fail(StatusCode::BAD_REQUEST, "Invalid authentication code"));
+    }
+
+    let mut tx = data.db.begin().await.map_err(database_error)?;
+    sqlx::query(
+        "UPDATE users SET totp_enabled = FALSE, totp_secret = NULL, totp_last_used_step = NULL, updated_at = NOW() WHERE id = $1",
+    )
+    .bind(user.id)
+    .execute(&mut *tx)
+    .await
+    .map_err(database_error)?;
+    sqlx::query("DELETE FROM user_backup_codes WHERE user_id = $1")
+        .bind(user.id)
+        .execute(&mut *tx)
+        .await
+        .map_err(database_error)?;
+    tx.commit().await.map_err(database_error)?;
+
+    Ok(Json(json!({"status": "success"})))
+}
+
+/// Second login step: trades the pending token plus a code for a session.
+pub async fn verify_handler(
+    State(data): State<Arc<AppState>>,
+    ClientIp(ip): ClientIp,
+    Json(body): Json<TwoFactorVerifySchema>,
+) -> Result<Response, Response> {
+    let claims = decode_token(&body.pending_token, &data)
+        .ok()
+        .filter(|claims| claims.scope.as_deref() == Some(TWO_FACTOR_PENDING_SCOPE))
+        .ok_or_else(|| fail(StatusCode::UNAUTHORIZED, "Invalid or expired login token").into_response())?;
+
+    let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
+        .bind(claims.sub)
+        .fetch_optional(&data.db)
+        .await
+        .map_err(|e| database_error(e).into_response())?
+        .filter(|user| user.totp_enabled)
+        .ok_or_else(|| fail(StatusCode::UNAUTHORIZED, "Invalid or expired login token").into_response())?;
+
+    if let Some(wait) = data
+        .login_throttle
+        .retry_after(ip, &user.email)
+        .await
+        .map_err(|e| database_error(e).into_response())?
+    {
+        data.metrics.record_login("throttled");
+        return Err(too_many_attempts(wait));
+    }
+
+    if !verify_second_factor(&data, &user, &body.code)
+        .await
+        .map_err(IntoResponse::into_response)?
+    {
+        data.metrics.record_login("invalid_second_factor");
+        let locked = data
+            .login_throttle
+            .record_failure(ip, &user.email)
+            .await
+            .map_err(|e| database_error(e).into_response())?;
+        if locked {
+            tracing::warn!(user_id = user.id, %ip, "account locked after repeated failed 2FA codes");
+            notify_lockout(data.clone(), user.email.clone());
+        }
+        return Err(fail(StatusCode::BAD_REQUEST, "Invalid authentication code").into_response());
+    }
+
+    data.login_throttle
+        .record_success(&user.email)
+        .await
+        .map_err(|e| database_error(e).into_response())?;
+    data.metrics.record_login("success");
+
+    Ok(session_response(&data, &user))
+}
+
+/// Accepts either a TOTP code or an unused backup code.
+pub async fn verify_second_factor(data: &AppState, user: &User, code: &str) -> Result<bool, HandlerError> {
+    let code = code.trim();
+    if code.len() == TOTP_DIGITS && code.bytes().all(|b| b.is_ascii_digit()) {
+        verify_totp(data, user, code).await
+    } else {
+        verify_backup_code(data, user, code).await
+    }
+}
+
+/// Checks the code against the previous, current and next 30s step. The matched
+/// step is recorded so the same code cannot be replayed.
+async fn verify_totp(data: &AppState, user: &User, code: &str) -> Result<bool, HandlerError> {
+    let Some(secret) = user.totp_secret.as_deref() else {
+        return Ok(false);
+    };
+    let totp = build_totp(&data.config.totp_issuer, secret, &user.email)
+        .map_err(|e| fail(StatusCode::INTERNAL_SERVER_ERROR, &e))?;
+
+    let now = chrono::Utc::now().timestamp() as u64;
+    let Some(step) = matching_step(&totp, code, now, user.totp_last_used_step) else {
+        return Ok(false);
+    };
+
+    let claimed = sqlx::query(
+        "UPDATE users SET totp_last_used_step = $1 WHERE id = $2 AND (totp_last_used_step IS NULL OR totp_last_used_step < $1)",
+    )
+    .bind(step)
+    .bind(user.id)
+    .execute(&data.db)
+    .await
+    .map_err(database_error)?;
+
+    Ok(claimed.rows_affected() == 1)
+}
+
+async fn verify_backup_code(data: &AppState, user: &User, code: &str) -> Result<bool, HandlerError> {
+    let code = normalize_backup_code(code);
+    if code.is_empty() {
+        return Ok(false);
+    }
+
+    let candidates: Vec<(i32, String)> = sqlx::query_as(
+        "SELECT id, code_hash FROM user_backup_codes WHERE user_id = $1 AND used_at IS NULL",
+    )
+    .bind(user.id)
+    .fetch_all(&data.db)
+    .await
+    .map_err(database_error)?;
+
+    for (id, hash) in candidates {
+        let matches = match PasswordHash::new(&hash) {
+            Ok(parsed_hash) => Argon2::default()
+                .verify_password(code.as_bytes(), &parsed_hash)
+                .is_ok(),
+            Err(_) => false,
+        };
+        if !matches {
+            continue;
+        }
+
+        let claimed = sqlx::query(
+            "UPDATE user_backup_codes SET used_at = NOW() WHERE id = $1 AND used_at IS NULL",
+        )
+        .bind(id)
+        .execute(&data.db)
+        .await
+        .map_err(database_error)?;
+        return Ok(claimed.rows_affected() == 1);
+    }
+
+    Ok(false)
+}
+
+/// The step of the previous, current or next 30s window whose code is `code`,
+/// skipping steps up to `last_used` so a code cannot be used twice.
+fn matching_step(totp: &TOTP, code: &str, now: u64, last_used: Option<i64>) -> Option<i64> {
+    let current_step = (now / TOTP_STEP_SECS) as i64;
+    let last_used = last_used.unwrap_or(i64::MIN);
+
+    (current_step - 1..=current_step + 1)
+        .filter(|step| *step > last_used)
+        .find(|step| constant_time_eq(totp.generate(*step as u64 * TOTP_STEP_SECS).as_bytes(), code.trim().as_bytes()))
+}
+
+fn build_totp(issuer: &str, secret: &str, email: &str) -> Result<TOTP, String> {
+    let secret = Secret::Encoded(secret.to_string())
+        .to_bytes()
+        .map_err(|e| format!("Invalid TOTP secret: {:?}", e))?;
+
+    TOTP::new(
+        Algorithm::SHA1,
+        TOTP_DIGITS,
+        1,
+        TOTP_STEP_SECS,
+        secret,
+        Some(issuer.to_string()),
+        email.to_string(),
+    )
+    .map_err(|e| format!("Could not build TOTP: {}", e))
+}
+
+/// Ten characters shown as `xxxxx-xxxxx`; ambiguous characters are left out.
+fn gen_backup_code() -> String {
+    let mut rng = rand::thread_rng();
+    let chars: String = (0..10)
+        .map(|_| BACKUP_CODE_ALPHABET[rng.gen_range(0..BACKUP_CODE_ALPHABET.len())] as char)
+        .collect();
+    format!("{}-{}", &chars[..5], &chars[5..])
+}
+
+fn normalize_backup_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn fail(status: StatusCode, message: &str) -> HandlerError {
+    let error_response = ErrorResponse {
+        status: "fail",
+        message: message.to_string(),
+    };
+    (status, Json(error_response))
+}
+
+fn database_error(e: sqlx::Error) -> HandlerError {
+    let error_response = ErrorResponse {
+        status: "error",
+        message: format!("Database error: {}", e),
+    };
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `12345678901234567890` in base32, the secret of the RFC 6238 test vectors.
+    const SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    fn totp() -> TOTP {
+        build_totp("Shop", SECRET, "user@example.com").unwrap()
+    }
+
+    #[test]
+    fn generates_the_rfc_6238_codes() {
+        // The RFC's 8-digit codes, cut to our 6 digits.
+        assert_eq!(totp().generate(59), "287082");
+        assert_eq!(totp().generate(1_111_111_109), "081804");
+    }
+
+    #[test]
+    fn accepts_codes_one_step_either_side() {
+        let totp = totp();
+        let now = 1_111_111_109;
+        let step = (now / TOTP_STEP_SECS) as i64;
+
+        for offset in [-1, 0, 1] {
+            let code = totp.generate((step + offset) as u64 * TOTP_STEP_SECS);
+            assert_eq!(matching_step(&totp, &code, now, None), Some(step + offset));
+        }
+        let stale = totp.generate((step - 2) as u64 * TOTP_STEP_SECS);
+        assert_eq!(matching_step(&totp, &stale, now, None), None);
+        assert_eq!(matching_step(&totp, "000000", now, None), None);
+    }
+
+    #[test]
+    fn refuses_codes_already_used() {
+        let totp = totp();
+        let now = 1_111_111_109;
+        let step = (now / TOTP_STEP_SECS) as i64;
+        let code = totp.generate(now);
+
+        assert_eq!(matching_step(&totp, &code, now, Some(step - 1)), Some(step));
+        assert_eq!(matching_step(&totp, &code, now, Some(step)), None);
+    }
+
+    #[test]
+    fn rejects_unusable_secrets() {
+        assert!(build_totp("Shop", "not base32!", "user@example.com").is_err());
+        assert!(build_totp("Shop", "GEZDGNBV", "user@example.com").is_err(), "secrets must be 128 bits");
+    }
+
+    #[test]
+    fn normalizes_backup_codes() {
+        let code = gen_backup_code();
+        assert_eq!(code.len(), 11);
+        assert_eq!(&code[5..6], "-");
+        assert!(code.bytes().filter(|b| *b != b'-').all(|b| BACKUP_CODE_ALPHABET.contains(&b)));
+
+        assert_eq!(normalize_backup_code(" ABCDE-fghjk\n"), "abcdefghjk");
+    }
+}
@@ -0,0 +1,408 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::{
+    extract::{MatchedPath, State},
+    http::{header, HeaderMap, HeaderValue, Method, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use axum_extra::extract::cookie::CookieJar;
+
+use crate::apis::{
+    api_keys::handler::active_key_id,
+    client_ip::client_ip,
+    config::parse_duration,
+    jwt_auth::{decode_token, presented_api_key, request_token},
+    login::response::ErrorResponse,
+};
+use crate::routes::API_PREFIX;
+use crate::AppState;
+
+/// `requests` per `period`, written as e.g. `100/1m`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quota {
+    pub requests: u32,
+    pub period: Duration,
+}
+
+impl Quota {
+    fn refill_per_sec(&self) -> f64 {
+        self.requests as f64 / self.period.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+impl FromStr for Quota {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (requests, period) = s
+            .split_once('/')
+            .ok_or_else(|| "expected `<requests>/<period>`, e.g. `100/1m`".to_string())?;
+        let requests = requests
+            .trim()
+            .parse::<u32>()
+            .map_err(|e| format!("invalid request count: {}", e))?;
+        let period = parse_duration(period)?;
+
+        if requests == 0 || period.is_zero() {
+            return Err("requests and period must both be greater than zero".to_string());
+        }
+        Ok(Quota { requests, period })
+    }
+}
+
+/// A quota that replaces the caller's usual one on a single route, written as
+/// `POST /auth/register=5/1h` (the method is optional).
+#[derive(Debug, Clone)]
+pub struct RouteQuota {
+    pub method: Option<Method>,
+    pub path: String,
+    pub quota: Quota,
+}
+
+impl FromStr for RouteQuota {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (route, quota) = s
+            .rsplit_once('=')
+            .ok_or_else(|| "expected `[METHOD] /path=<requests>/<period>`".to_string())?;
+        let quota = quota.parse()?;
+
+        let (method, path) = match route.trim().split_once(' ') {
+            Some((method, path)) => (
+                Some(
+                    method
+                        .parse::<Method>()
+                        .map_err(|e| format!("invalid method `{}`: {}", method, e))?,
+                ),
+                path.trim(),
+            ),
+            None => (None, route.trim()),
+        };
+        if !path.starts_with('/') {
+            return Err(format!("path `{}` must start with `/`", path));
+        }
+
+        Ok(RouteQuota {
+            method,
+            path: path.to_string(),
+            quota,
+        })
+    }
+}
+
+impl fmt::Display for RouteQuota {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.method {
+            Some(method) => write!(f, "{} {}", method, self.path),
+            None => write!(f, "{}", self.path),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    capacity: f64,
+    refill_per_sec: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        self.updated = now;
+    }
+}
+
+struct Decision {
+    allowed: bool,
+    limit: u32,
+    remaining: u32,
+    /// Until the bucket is full again.
+    reset: Duration,
+    /// Until the next request would be allowed.
+    retry_after: Duration,
+}
+
+/// Token buckets keyed by caller (and route, for overridden routes). Kept in
+/// memory, so each instance enforces its own share of the limit.
+#[derive(Clone, Default)]
+pub struct RateLimiter {
+    buckets: Arc<Mutex<HashMap<String, Bucket>>>,
+}
+
+impl RateLimiter {
+    fn check(&self, key: &str, quota: Quota) -> Decision {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.entry(key.to_string()).or_insert_with(|| Bucket {
+            tokens: quota.requests as f64,
+            capacity: quota.requests as f64,
+            refill_per_sec: quota.refill_per_sec(),
+            updated: now,
+        });
+        bucket.refill(now);
+
+        let allowed = bucket.tokens >= 1.0;
+        if allowed {
+            bucket.tokens -= 1.0;
+        }
+
+        let seconds_until = |tokens: f64| {
+            Duration::from_secs_f64((tokens.max(0.0) / bucket.refill_per_sec).max(0.0))
+        };
+
+        Decision {
+            allowed,
+            limit: quota.requests,
+            remaining: bucket.tokens.floor() as u32,
+            reset: seconds_until(bucket.capacity - bucket.tokens),
+            retry_after: seconds_until(1.0 - bucket.tokens),
+        }
+    }
+
+    /// Forgets buckets that have refilled completely; they would be recreated identically.
+    pub fn prune(&self) {
+        let now = Instant::now();
+        self.buckets.lock().unwrap().retain(|_, bucket| {
+            bucket.refill(now);
+            bucket.tokens < bucket.capacity
+        });
+    }
+}
+
+pub async fn rate_limit<B>(
+    State(data): State<Arc<AppState>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let config = &data.config;
+    if !config.rate_limit_enabled {
+        return next.run(req).await;
+    }
+
+    let (caller, quota) = caller(&data, &req).await;
+
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str())
+        .unwrap_or_else(|| req.uri().path());
+    let route = route.strip_prefix(API_PREFIX).unwrap_or(route);
+
+    let route_override = config.rate_limit_routes.iter().find(|r| {
+        r.path == route && r.method.as_ref().is_none_or(|method| method == req.method())
+    });
+    let (key, quota) = match route_override {
+        Some(r) => (format!("{}|{}", caller, r), r.quota),
+        None => (caller, quota),
+    };
+
+    let decision = data.rate_limiter.check(&key, quota);
+
+    if !decision.allowed {
+        let retry_after = ceil_secs(decision.retry_after);
+        let error_response = ErrorResponse {
+            status: "fail",
+            message: format!("Rate limit exceeded, please try again in {} seconds", retry_after),
+        };
+        let mut response = (StatusCode::TOO_MANY_REQUESTS, Json(error_response)).into_response();
+        insert_headers(response.headers_mut(), &decision);
+        response
+            .headers_mut()
+            .insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+        return response;
+    }
+
+    let mut response = next.run(req).await;
+    insert_headers(response.headers_mut(), &decision);
+    response
+}
+
+/// The bucket and quota for whoever sent `req`. The class comes from the
+/// credentials alone; a forged, expired or revoked one is simply treated as
+/// anonymous and rejected later by `jwt_auth`.
+async fn caller<B>(data: &AppState, req: &Request<B>) -> (String, Quota) {
+    let config = &data.config;
+    if let Some(key) = presented_api_key(req.headers()) {
+        match active_key_id(&data.db, &key).await {
+            Ok(Some(id)) => return (format!("api_key:{}", id), config.rate_limit_api_key),
+            Ok(None) => {}
+            Err(e) => tracing::warn!(error = %e, "could not look up an API key for rate limiting"),
+        }
+    } else {
+        let claims = request_token(&CookieJar::from_headers(req.headers()), req.headers())
+            .and_then(|token| decode_token(&token, data).ok())
+            .filter(|claims| claims.scope.is_none());
+        match claims {
+            Some(claims) if claims.role == "admin" => {
+                return (format!("user:{}", claims.sub), config.rate_limit_admin)
+            }
+            Some(claims) => return (format!("user:{}", claims.sub), config.rate_limit_authenticated),
+            None => {}
+        }
+    }
+    (
+        format!("ip:{}", client_ip(req.headers(), req.extensions(), config.trust_forwarded_headers)),
+        config.rate_limit_anonymous,
+    )
+}
+
+fn insert_headers(headers: &mut HeaderMap, decision: &Decision) {
+    headers.insert("ratelimit-limit", HeaderValue::from(decision.limit));
+    headers.insert("ratelimit-remaining", HeaderValue::from(decision.remaining));
+    headers.insert("ratelimit-reset", HeaderValue::from(ceil_secs(decision.reset)));
+}
+
+fn ceil_secs(duration: Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{example_config, ScratchDb};
+
+    fn quota(s: &str) -> Quota {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_quotas() {
+        assert_eq!(
+            quota("100/1m"),
+            Quota {
+                requests: 100,
+                period: Duration::from_secs(60),
+            }
+        );
+        assert!("100".parse::<Quota>().is_err());
+        assert!("0/1m".parse::<Quota>().is_err());
+        assert!("10/0s".parse::<Quota>().is_err());
+        assert!("ten/1m".parse::<Quota>().is_err());
+    }
+
+    #[test]
+    fn parses_route_quotas() {
+        let route: RouteQuota = "POST /auth/register=5/1h".parse().unwrap();
+        assert_eq!(route.method, Some(Method::POST));
+        assert_eq!(route.path, "/auth/register");
+        assert_eq!(route.quota, quota("5/1h"));
+        assert_eq!(route.to_string(), "POST /auth/register");
+
+        let any_method: RouteQuota = "/products=50/1m".parse().unwrap();
+        assert_eq!(any_method.method, None);
+        assert!("POST auth/register=5/1h".parse::<RouteQuota>().is_err());
+        assert!("/auth/register".parse::<RouteQuota>().is_err());
+    }
+
+    #[test]
+    fn allows_a_burst_of_the_quota_then_refuses() {
+        let limiter = RateLimiter::default();
+        let quota = quota("3/1h");
+
+        let remaining: Vec<u32> = (0..3)
+            .map(|_| limiter.check("ip:1", quota))
+            .inspect(|decision| assert!(decision.allowed))
+            .map(|decision| decision.remaining)
+            .collect();
+        assert_eq!(remaining, [2, 1, 0]);
+
+        let refused = limiter.check("ip:1", quota);
+        assert!(!refused.allowed);
+        assert_eq!(refused.limit, 3);
+        // One token comes back every 20 minutes, and three to fill the bucket.
+        assert!(refused.retry_after > Duration::from_secs(19 * 60));
+        assert!(refused.retry_after <= Duration::from_secs(20 * 60));
+        assert!(refused.reset > Duration::from_secs(59 * 60));
+
+        assert!(limiter.check("ip:2", quota).allowed, "callers have separate buckets");
+    }
+
+    #[test]
+    fn refills_at_the_quota_rate_up_to_capacity() {
+        let start = Instant::now();
+        let mut bucket = Bucket {
+            tokens: 0.0,
+            capacity: 10.0,
+            refill_per_sec: quota("10/1m").refill_per_sec(),
+            updated: start,
+        };
+
+        bucket.refill(start + Duration::from_secs(30));
+        assert!((bucket.tokens - 5.0).abs() < 1e-9);
+
+        bucket.refill(start + Duration::from_secs(600));
+        assert_eq!(bucket.tokens, 10.0);
+    }
+
+    #[test]
+    fn prunes_only_full_buckets() {
+        let limiter = RateLimiter::default();
+        limiter.check("ip:1", quota("3/1h"));
+        limiter.check("ip:2", quota("3/1h"));
+        limiter.buckets.lock().unwrap().get_mut("ip:2").unwrap().tokens = 3.0;
+
+        limiter.prune();
+
+        let buckets = limiter.buckets.lock().unwrap();
+        assert!(buckets.contains_key("ip:1"));
+        assert!(!buckets.contains_key("ip:2"));
+    }
+
+    #[tokio::test]
+    async fn buckets_api_keys_by_key_with_their_own_quota() {
+        let Some(db) = ScratchDb::migrated().await else {
+            return;
+        };
+        let mut config = example_config();
+        config.rate_limit_api_key = quota("5/1m");
+        let data = db.state(config);
+
+        let key = "shp_ratelimit_secret";
+        let id: i32 = sqlx::query_scalar(
+            "INSERT INTO api_keys (name, prefix, key_hash) VALUES ('ci', 'shp_ratelimit', encode(sha256($1::bytea), 'hex')) RETURNING id",
+        )
+        .bind(key.as_bytes())
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+        let request = |authorization: &str| {
+            Request::builder()
+                .header(header::AUTHORIZATION, authorization)
+                .body(())
+                .unwrap()
+        };
+
+        assert_eq!(
+            caller(&data, &request(&format!("ApiKey {}", key))).await,
+            (format!("api_key:{}", id), quota("5/1m"))
+        );
+        assert_eq!(
+            caller(&data, &request("ApiKey shp_unknown_secret")).await,
+            ("ip:0.0.0.0".to_string(), data.config.rate_limit_anonymous),
+            "an unknown key is anonymous"
+        );
+
+        sqlx::query("UPDATE api_keys SET revoked_at = NOW() WHERE id = $1")
+            .bind(id)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(
+            caller(&data, &request(&format!("ApiKey {}", key))).await.0,
+            "ip:0.0.0.0",
+            "a revoked key is anonymous"
+        );
+
+        db.drop().await;
+    }
+}
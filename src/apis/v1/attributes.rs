@@ -0,0 +1,432 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use sqlx::PgConnection;
+
+/// Query parameters of `GET /products` that filter on an attribute start with this.
+pub const FILTER_PREFIX: &str = "attr.";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AttributeKind {
+    /// One of the attribute's `options`.
+    Enum,
+    Number,
+    Bool,
+    /// Free text of up to 255 characters.
+    Text,
+}
+
+impl AttributeKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AttributeKind::Enum => "enum",
+            AttributeKind::Number => "number",
+            AttributeKind::Bool => "bool",
+            AttributeKind::Text => "text",
+        }
+    }
+}
+
+impl TryFrom<String> for AttributeKind {
+    type Error = String;
+
+    fn try_from(kind: String) -> Result<Self, Self::Error> {
+        match kind.as_str() {
+            "enum" => Ok(AttributeKind::Enum),
+            "number" => Ok(AttributeKind::Number),
+            "bool" => Ok(AttributeKind::Bool),
+            "text" => Ok(AttributeKind::Text),
+            _ => Err(format!("unknown attribute kind `{}`", kind)),
+        }
+    }
+}
+
+/// A typed attribute the products of one category may carry.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct AttributeDefinition {
+    pub id: i32,
+    pub key: String,
+    pub label: String,
+    #[sqlx(try_from = "String")]
+    pub kind: AttributeKind,
+    pub options: Vec<String>,
+    pub unit: Option<String>,
+    /// Whether the storefront gets facets for it.
+    pub filterable: bool,
+    pub position: i32,
+}
+
+impl AttributeDefinition {
+    /// Why `value` is not a valid value of this attribute, if it is not.
+    fn check(&self, value: &Value) -> Result<(), String> {
+        let valid = match self.kind {
+            AttributeKind::Enum => value
+                .as_str()
+                .is_some_and(|value| self.options.iter().any(|option| option == value)),
+            AttributeKind::Number => value.as_f64().is_some_and(f64::is_finite),
+            AttributeKind::Bool => value.is_boolean(),
+            AttributeKind::Text => value.as_str().is_some_and(|value| value.chars().count() <= 255),
+        };
+        if valid {
+            return Ok(());
+        }
+        Err(match self.kind {
+            AttributeKind::Enum => format!("{} must be one of {}", self.key, self.options.join(", ")),
+            AttributeKind::Number => format!("{} must be a number", self.key),
+            AttributeKind::Bool => format!("{} must be true or false", self.key),
+            AttributeKind::Text => format!("{} must be text of at most 255 characters", self.key),
+        })
+    }
+}
+
+pub enum AttributeError {
+    /// The values do not fit the category's definitions.
+    Invalid(String),
+    Database(sqlx::Error),
+}
+
+impl From<sqlx::Error> for AttributeError {
+    fn from(e: sqlx::Error) -> Self {
+        AttributeError::Database(e)
+    }
+}
+
+/// `^[a-z][a-z0-9_]*$`, at most 64 characters, so keys are safe in query parameters.
+pub fn valid_key(key: &str) -> bool {
+    key.len() <= 64
+        && key.bytes().next().is_some_and(|b| b.is_ascii_lowercase())
+        && key
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'_')
+}
+
+/// The attributes defined for the live category with this name, in display order.
+pub async fn definitions(
+    conn: &mut PgConnection,
+    category_name: &str,
+) -> Result<Vec<AttributeDefinition>, sqlx::Error> {
+    sqlx::query_as(
+        r#"SELECT a.* FROM category_attributes a
+        JOIN categories c ON c.id = a.category_id
+        WHERE c.name = $1 AND c.deleted_at IS NULL
+        ORDER BY a.position, a.id"#,
+    )
+    .bind(category_name)
+    .fetch_all(conn)
+    .await
+}
+
+/// Checks product attribute values against the definitions of the product's
+/// category. Every key must be defined there; none is required.
+pub async fn check_attributes(
+    conn: &mut PgConnection,
+    category_name: &str,
+    values: Option<&Map<String, Value>>,
+) -> Result<(), AttributeError> {
+    let Some(values) = values.filter(|values| !values.is_empty()) else {
+        return Ok(());
+    };
+    let definitions = definitions(conn, category_name).await?;
+    for (key, value) in values {
+        let definition = definitions
+            .iter()
+            .find(|definition| &definition.key == key)
+            .ok_or_else(|| {
+                AttributeError::Invalid(format!(
+                    "category {} has no attribute {}",
+                    category_name, key
+                ))
+            })?;
+        definition.check(value).map_err(AttributeError::Invalid)?;
+    }
+    Ok(())
+}
+
+/// One `attr.` query parameter of `GET /products`.
+#[derive(Debug)]
+pub struct Filter {
+    key: String,
+    condition: Condition,
+}
+
+#[derive(Debug)]
+enum Condition {
+    /// `attr.<key>=a,b`: the value is any of these.
+    AnyOf(Vec<Value>),
+    /// `attr.<key>.min=` and `attr.<key>.max=`, both inclusive.
+    Range { min: Option<f64>, max: Option<f64> },
+}
+
+impl Filter {
+    pub fn matches(&self, attributes: &Value) -> bool {
+        let Some(value) = attributes.get(&self.key) else {
+            return false;
+        };
+        match &self.condition {
+            Condition::AnyOf(values) => values.contains(value),
+            Condition::Range { min, max } => value.as_f64().is_some_and(|value| {
+                min.is_none_or(|min| value >= min) && max.is_none_or(|max| value <= max)
+            }),
+        }
+    }
+}
+
+/// Reads the `attr.` parameters against the category's definitions: enum,
+/// text and bool attributes take a comma-separated list of values, numbers a
+/// `.min` and/or `.max`.
+pub fn parse_filters(
+    params: &HashMap<String, String>,
+    definitions: &[AttributeDefinition],
+) -> Result<Vec<Filter>, String> {
+    let mut filters: Vec<Filter> = Vec::new();
+    for (name, raw) in params {
+        let Some(name) = name.strip_prefix(FILTER_PREFIX) else {
+            continue;
+        };
+        let (key, bound) = match name.rsplit_once('.') {
+            Some((key, bound)) => (key, Some(bound)),
+            None => (name, None),
+        };
+        let definition = definitions
+            .iter()
+            .find(|definition| definition.key == key)
+            .ok_or_else(|| format!("unknown attribute {}", key))?;
+
+        match (definition.kind, bound) {
+            (AttributeKind::Number, Some(bound @ ("min" | "max"))) => {
+                let limit: f64 = raw
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("{}{} must be a number", FILTER_PREFIX, name))?;
+                let position = filters.iter().position(|filter| filter.key == key);
+                let index = position.unwrap_or_else(|| {
+                    filters.push(Filter {
+                        key: key.to_string(),
+                        condition: Condition::Range { min: None, max: None },
+                    });
+                    filters.len() - 1
+                });
+                if let Condition::Range { min, max } = &mut filters[index].condition {
+                    if bound == "min" {
+                        *min = Some(limit);
+                    } else {
+                        *max = Some(limit);
+                    }
+                }
+            }
+            (AttributeKind::Number, _) => {
+                return Err(format!(
+                    "filter {} with {}{}.min or .max",
+                    key, FILTER_PREFIX, key
+                ))
+            }
+            (_, Some(_)) => return Err(format!("unknown attribute {}", name)),
+            (kind, None) => {
+                let values = raw
+                    .split(',')
+                    .map(str::trim)
+                    .map(|value| match kind {
+                        AttributeKind::Bool => value
+                            .parse::<bool>()
+                            .map(Value::Bool)
+                            .map_err(|_| format!("{}{} must be true or false", FILTER_PREFIX, key)),
+                        _ => Ok(Value::String(value.to_string())),
+                    })
+                    .collect::<Result<_, _>>()?;
+                filters.push(Filter {
+                    key: key.to_string(),
+                    condition: Condition::AnyOf(values),
+                });
+            }
+        }
+    }
+    Ok(filters)
+}
+
+/// Facet counts for each filterable attribute. Each attribute's counts apply
+/// every filter except its own, so the storefront can offer the alternatives to
+/// a value already picked.
+pub fn facets(
+    definitions: &[AttributeDefinition],
+    attributes: &[&Value],
+    filters: &[Filter],
+) -> Vec<Value> {
+    definitions
+        .iter()
+        .filter(|definition| definition.filterable)
+        .map(|definition| {
+            let values = attributes.iter().filter(|attributes| {
+                filters
+                    .iter()
+                    .filter(|filter| filter.key != definition.key)
+                    .all(|filter| filter.matches(attributes))
+            });
+            let values = values.filter_map(|attributes| attributes.get(&definition.key));
+            let mut facet = json!({
+                "key": definition.key,
+                "label": definition.label,
+                "kind": definition.kind,
+                "unit": definition.unit,
+            });
+            let summary = match definition.kind {
+                AttributeKind::Number => number_summary(values),
+                AttributeKind::Enum => {
+                    let mut counts = value_counts(values);
+                    let options = definition.options.iter().map(|option| {
+                        let count = counts.remove(&json!(option).to_string()).unwrap_or(0);
+                        json!({"value": option, "count": count})
+                    });
+                    json!({"values": options.collect::<Vec<_>>()})
+                }
+                AttributeKind::Bool | AttributeKind::Text => {
+                    let counts = value_counts(values);
+                    let values = counts.iter().map(|(value, count)| {
+                        let value = serde_json::from_str::<Value>(value).unwrap_or(Value::Null);
+                        json!({"value": value, "count": count})
+                    });
+                    json!({"values": values.collect::<Vec<_>>()})
+                }
+            };
+            if let (Some(facet), Value::Object(summary)) = (facet.as_object_mut(), summary) {
+                facet.extend(summary);
+            }
+            facet
+        })
+        .collect()
+}
+
+/// How many products carry each value, keyed by the value as JSON.
+fn value_counts<'a>(values: impl Iterator<Item = &'a Value>) -> BTreeMap<String, usize> {
+    let mut counts = BTreeMap::new();
+    for value in values {
+        *counts.entry(value.to_string()).or_insert(0) += 1;
+    }
+    counts
+}
+
+fn number_summary<'a>(values: impl Iterator<Item = &'a Value>) -> Value {
+    let numbers: Vec<f64> = values.filter_map(Value::as_f64).collect();
+    json!({
+        "count": numbers.len(),
+        "min": numbers.iter().copied().reduce(f64::min),
+        "max": numbers.iter().copied().reduce(f64::max),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn definition(key: &str, kind: AttributeKind, options: &[&str], filterable: bool) -> AttributeDefinition {
+        AttributeDefinition {
+            id: 0,
+            key: key.to_string(),
+            label: key.to_string(),
+            kind,
+            options: options.iter().map(|option| option.to_string()).collect(),
+            unit: None,
+            filterable,
+            position: 0,
+        }
+    }
+
+    fn definitions() -> Vec<AttributeDefinition> {
+        vec![
+            definition("color", AttributeKind::Enum, &["red", "blue", "green"], true),
+            definition("weight", AttributeKind::Number, &[], true),
+            definition("organic", AttributeKind::Bool, &[], true),
+            definition("note", AttributeKind::Text, &[], false),
+        ]
+    }
+
+    fn params(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn parses_value_and_range_filters() {
+        let filters = parse_filters(
+            &params(&[
+                ("attr.color", "red,blue"),
+                ("attr.weight.min", "1"),
+                ("attr.weight.max", "5"),
+                ("attr.organic", "true"),
+                ("page", "2"),
+            ]),
+            &definitions(),
+        )
+        .unwrap();
+        assert_eq!(filters.len(), 3);
+
+        let matches_all = |attributes: Value| filters.iter().all(|filter| filter.matches(&attributes));
+        assert!(matches_all(json!({"color": "blue", "weight": 5, "organic": true})));
+        assert!(!matches_all(json!({"color": "green", "weight": 3, "organic": true})));
+        assert!(!matches_all(json!({"color": "red", "weight": 5.5, "organic": true})));
+        assert!(!matches_all(json!({"color": "red", "weight": 3, "organic": false})));
+        assert!(!matches_all(json!({"color": "red", "organic": true})));
+    }
+
+    #[test]
+    fn trims_listed_values() {
+        let filters = parse_filters(
+            &params(&[("attr.color", " red , blue"), ("attr.note", "gift ")]),
+            &definitions(),
+        )
+        .unwrap();
+
+        let matches_all = |attributes: Value| filters.iter().all(|filter| filter.matches(&attributes));
+        assert!(matches_all(json!({"color": "blue", "note": "gift"})));
+        assert!(matches_all(json!({"color": "red", "note": "gift"})));
+    }
+
+    #[test]
+    fn rejects_filters_that_do_not_fit_the_definitions() {
+        let definitions = definitions();
+        for pair in [
+            ("attr.size", "large"),
+            ("attr.weight", "3"),
+            ("attr.weight.min", "heavy"),
+            ("attr.organic", "yes"),
+            ("attr.color.min", "red"),
+        ] {
+            assert!(parse_filters(&params(&[pair]), &definitions).is_err(), "{:?}", pair);
+        }
+    }
+
+    #[test]
+    fn counts_facets_without_each_attributes_own_filter() {
+        let definitions = definitions();
+        let products = [
+            json!({"color": "red", "weight": 1, "organic": true, "note": "a"}),
+            json!({"color": "red", "weight": 4, "organic": false}),
+            json!({"color": "blue", "weight": 9, "organic": true}),
+            json!({"weight": 2}),
+        ];
+        let attributes: Vec<&Value> = products.iter().collect();
+        let filters = parse_filters(&params(&[("attr.color", "red")]), &definitions).unwrap();
+
+        let facets = facets(&definitions, &attributes, &filters);
+
+        assert_eq!(facets.len(), 3, "only filterable attributes get facets");
+        assert_eq!(
+            facets[0]["values"],
+            json!([
+                {"value": "red", "count": 2},
+                {"value": "blue", "count": 1},
+                {"value": "green", "count": 0},
+            ])
+        );
+        assert_eq!(facets[1]["count"], 2);
+        assert_eq!(facets[1]["min"], 1.0);
+        assert_eq!(facets[1]["max"], 4.0);
+        assert_eq!(
+            facets[2]["values"],
+            json!([{"value": false, "count": 1}, {"value": true, "count": 1}])
+        );
+    }
+}
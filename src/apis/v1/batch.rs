@@ -0,0 +1,274 @@
+use axum::{async_trait, http::StatusCode, Json};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::{Connection, PgConnection, PgPool};
+
+use crate::apis::jwt_auth::Principal;
+use crate::apis::login::response::ErrorResponse;
+use crate::apis::v1::revisions::attribute;
+
+/// Larger syncs should be split into several requests.
+pub const MAX_OPERATIONS: usize = 1000;
+
+type HandlerError = (StatusCode, Json<ErrorResponse>);
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchMode {
+    /// Stop at the first failure and roll everything back.
+    #[default]
+    AllOrNothing,
+    /// Apply what can be applied; each failure only undoes its own operation.
+    BestEffort,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BatchRequest<T> {
+    #[serde(default)]
+    pub mode: BatchMode,
+    pub operations: Vec<T>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Created,
+    Updated,
+    Deleted,
+    Failed,
+    /// Succeeded, then undone because a later operation failed.
+    RolledBack,
+    /// Not attempted because an earlier operation failed.
+    Skipped,
+}
+
+/// What a successful operation did, and the resulting item if there is one.
+pub struct Applied {
+    pub outcome: Outcome,
+    pub data: Option<Value>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ItemResult {
+    pub index: usize,
+    pub outcome: Outcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[async_trait]
+pub trait BatchOperation: Send {
+    /// Runs inside the batch's transaction. An `Err` is reported for this item
+    /// and, depending on the mode, fails the batch.
+    async fn apply(self, conn: &mut PgConnection) -> Result<Applied, String>;
+}
+
+/// Runs every operation in one transaction and reports a result per item, in
+/// request order. An all-or-nothing batch that fails answers 422 and changes
+/// nothing.
+pub async fn execute<T: BatchOperation>(
+    db: &PgPool,
+    request: BatchRequest<T>,
+    actor: &Principal,
+) -> Result<(StatusCode, Json<Value>), HandlerError> {
+    let total = request.operations.len();
+    if total == 0 || total > MAX_OPERATIONS {
+        return Err(fail(
+            StatusCode::BAD_REQUEST,
+            &format!("A batch must have between 1 and {} operations", MAX_OPERATIONS),
+        ));
+    }
+
+    let mut tx = db.begin().await.map_err(database_error)?;
+    attribute(&mut tx, Some(actor), "batch").await.map_err(database_error)?;
+    let mut results = Vec::with_capacity(total);
+    let mut failed = false;
+
+    for (index, operation) in request.operations.into_iter().enumerate() {
+        if failed && request.mode == BatchMode::AllOrNothing {
+            results.push(ItemResult {
+                index,
+                outcome: Outcome::Skipped,
+                data: None,
+                error: None,
+            });
+            continue;
+        }
+
+        let result = match request.mode {
+            BatchMode::AllOrNothing => operation.apply(&mut tx).await,
+            BatchMode::BestEffort => {
+                // A savepoint, so a failed statement does not abort the whole transaction.
+                let mut savepoint = tx.begin().await.map_err(database_error)?;
+                let result = operation.apply(&mut savepoint).await;
+                if result.is_ok() {
+                    savepoint.commit().await.map_err(database_error)?;
+                } else {
+                    savepoint.rollback().await.map_err(database_error)?;
+                }
+                result
+            }
+        };
+
+        results.push(match result {
+            Ok(applied) => ItemResult {
+                index,
+                outcome: applied.outcome,
+                data: applied.data,
+                error: None,
+            },
+            Err(error) => {
+                failed = true;
+                ItemResult {
+                    index,
+                    outcome: Outcome::Failed,
+                    data: None,
+                    error: Some(error),
+                }
+            }
+        });
+    }
+
+    let succeeded = results
+        .iter()
+        .filter(|result| matches!(result.outcome, Outcome::Created | Outcome::Updated | Outcome::Deleted))
+        .count();
+
+    if !settle(request.mode, &mut results) {
+        tx.rollback().await.map_err(database_error)?;
+        let json_response = json!({
+            "status": "fail",
+            "message": "An operation failed; nothing was changed",
+            "mode": request.mode,
+            "results": results,
+        });
+        return Ok((StatusCode::UNPROCESSABLE_ENTITY, Json(json_response)));
+    }
+
+    tx.commit().await.map_err(database_error)?;
+
+    let json_response = json!({
+        "status": "success",
+        "mode": request.mode,
+        "succeeded": succeeded,
+        "failed": total - succeeded,
+        "results": results,
+    });
+    Ok((StatusCode::OK, Json(json_response)))
+}
+
+/// Whether the batch's changes are kept. When they are not, the operations
+/// that succeeded are reported as rolled back, without their data.
+fn settle(mode: BatchMode, results: &mut [ItemResult]) -> bool {
+    let failed = results.iter().any(|result| result.outcome == Outcome::Failed);
+    if !failed || mode == BatchMode::BestEffort {
+        return true;
+    }
+    for result in results {
+        if result.outcome != Outcome::Failed && result.outcome != Outcome::Skipped {
+            result.outcome = Outcome::RolledBack;
+            result.data = None;
+        }
+    }
+    false
+}
+
+/// Message for a failed statement inside a batch; constraint violations are
+/// the caller's fault and worth spelling out.
+pub fn item_error(e: sqlx::Error) -> String {
+    match e.as_database_error() {
+        Some(db_error) if db_error.code().as_deref() == Some("23505") => {
+            format!("conflicts with an existing row: {}", db_error.message())
+        }
+        _ => {
+            tracing::error!(error = %e, "batch operation failed");
+            "database error".to_string()
+        }
+    }
+}
+
+fn fail(status: StatusCode, message: &str) -> HandlerError {
+    let error_response = ErrorResponse {
+        status: "fail",
+        message: message.to_string(),
+    };
+    (status, Json(error_response))
+}
+
+fn database_error(e: sqlx::Error) -> HandlerError {
+    let error_response = ErrorResponse {
+        status: "error",
+        message: format!("Database error: {}", e),
+    };
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(index: usize, outcome: Outcome) -> ItemResult {
+        ItemResult {
+            index,
+            outcome,
+            data: (outcome != Outcome::Failed && outcome != Outcome::Skipped).then(|| json!({"id": index})),
+            error: (outcome == Outcome::Failed).then(|| "name is required".to_string()),
+        }
+    }
+
+    fn outcomes(results: &[ItemResult]) -> Vec<Outcome> {
+        results.iter().map(|result| result.outcome).collect()
+    }
+
+    #[test]
+    fn defaults_to_all_or_nothing() {
+        let request: BatchRequest<Value> = serde_json::from_value(json!({"operations": []})).unwrap();
+        assert_eq!(request.mode, BatchMode::AllOrNothing);
+
+        let request: BatchRequest<Value> =
+            serde_json::from_value(json!({"mode": "best_effort", "operations": []})).unwrap();
+        assert_eq!(request.mode, BatchMode::BestEffort);
+
+        assert!(serde_json::from_value::<BatchRequest<Value>>(json!({"mode": "some", "operations": []})).is_err());
+    }
+
+    #[test]
+    fn all_or_nothing_undoes_everything_after_a_failure() {
+        let mut results = vec![
+            result(0, Outcome::Created),
+            result(1, Outcome::Failed),
+            result(2, Outcome::Skipped),
+        ];
+
+        assert!(!settle(BatchMode::AllOrNothing, &mut results));
+
+        assert_eq!(outcomes(&results), [Outcome::RolledBack, Outcome::Failed, Outcome::Skipped]);
+        assert!(results[0].data.is_none());
+        assert!(results[1].error.is_some());
+    }
+
+    #[test]
+    fn best_effort_keeps_what_succeeded() {
+        let mut results = vec![
+            result(0, Outcome::Created),
+            result(1, Outcome::Failed),
+            result(2, Outcome::Deleted),
+        ];
+
+        assert!(settle(BatchMode::BestEffort, &mut results));
+
+        assert_eq!(outcomes(&results), [Outcome::Created, Outcome::Failed, Outcome::Deleted]);
+        assert!(results[0].data.is_some());
+    }
+
+    #[test]
+    fn keeps_batches_without_failures_in_either_mode() {
+        for mode in [BatchMode::AllOrNothing, BatchMode::BestEffort] {
+            let mut results = vec![result(0, Outcome::Updated), result(1, Outcome::Deleted)];
+            assert!(settle(mode, &mut results));
+            assert_eq!(outcomes(&results), [Outcome::Updated, Outcome::Deleted]);
+        }
+    }
+}
@@ -0,0 +1,42 @@
+use std::sync::Arc;
+
+use axum::{
+    middleware,
+    routing::{get, patch, post},
+    Router,
+};
+
+use crate::apis::{
+    jwt_auth::{auth, require_admin},
+    v1::category::{
+        category_archive_handler::{
+            archive_category_handler, list_categories_admin_handler, restore_category_handler,
+        },
+        category_attributes_handler::{
+            create_attribute_handler, delete_attribute_handler, list_attributes_handler,
+            update_attribute_handler,
+        },
+    },
+};
+
+use crate::AppState;
+
+/// Category lifecycle and attribute definitions for catalog managers; these
+/// need an admin session.
+pub fn category_admin_router(app_state: Arc<AppState>) -> Router {
+    Router::new()
+        .route("/admin/categories", get(list_categories_admin_handler))
+        .route("/admin/categories/:id/archive", post(archive_category_handler))
+        .route("/admin/categories/:id/restore", post(restore_category_handler))
+        .route(
+            "/admin/categories/:id/attributes",
+            get(list_attributes_handler).post(create_attribute_handler),
+        )
+        .route(
+            "/admin/categories/:id/attributes/:key",
+            patch(update_attribute_handler).delete(delete_attribute_handler),
+        )
+        .route_layer(middleware::from_fn(require_admin))
+        .route_layer(middleware::from_fn_with_state(app_state.clone(), auth))
+        .with_state(app_state)
+}
@@ -0,0 +1,193 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use chrono::Utc;
+use serde_json::json;
+use sqlx::PgPool;
+
+use crate::apis::jwt_auth::Principal;
+use crate::apis::login::{model::User, response::ErrorResponse};
+use crate::apis::v1::category::category_model::Category;
+use crate::apis::v1::lifecycle::{purge_rows, StateQuery};
+use crate::apis::v1::revisions::attribute;
+
+use crate::AppState;
+
+type HandlerError = (StatusCode, Json<ErrorResponse>);
+
+/// Every category in the given state, including the archived and deleted ones
+/// the storefront never shows.
+pub async fn list_categories_admin_handler(
+    State(data): State<Arc<AppState>>,
+    Query(query): Query<StateQuery>,
+) -> Result<impl IntoResponse, HandlerError> {
+    let condition = query.state.map_or("TRUE", |state| state.condition());
+    let categories: Vec<Category> =
+        sqlx::query_as(&format!("SELECT * FROM categories WHERE {} ORDER BY id", condition))
+            .fetch_all(&data.db)
+            .await
+            .map_err(database_error)?;
+
+    Ok(Json(json!({
+        "status": "success",
+        "results": categories.len(),
+        "data": categories,
+    })))
+}
+
+/// Hides a category from the storefront without deleting it.
+pub async fn archive_category_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Path(id): Path<i32>,
+) -> Result<impl IntoResponse, HandlerError> {
+    let mut tx = data.db.begin().await.map_err(database_error)?;
+    attribute(&mut tx, Some(&Principal::User(user)), "admin")
+        .await
+        .map_err(database_error)?;
+    let category: Category = sqlx::query_as(
+        "UPDATE categories SET archived_at = COALESCE(archived_at, NOW()), version = version + 1 WHERE id = $1 AND deleted_at IS NULL RETURNING *",
+    )
+    .bind(id)
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(database_error)?
+    .ok_or_else(category_not_found)?;
+    tx.commit().await.map_err(database_error)?;
+
+    tracing::info!(category_id = id, "category archived");
+    Ok(Json(json!({"status": "success", "data": category})))
+}
+
+/// Brings back an archived or deleted category that has not been purged yet.
+pub async fn restore_category_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Path(id): Path<i32>,
+) -> Result<impl IntoResponse, HandlerError> {
+    let mut tx = data.db.begin().await.map_err(database_error)?;
+    attribute(&mut tx, Some(&Principal::User(user)), "admin")
+        .await
+        .map_err(database_error)?;
+    let category: Category = sqlx::query_as(
+        "UPDATE categories SET archived_at = NULL, deleted_at = NULL, version = version + 1 WHERE id = $1 RETURNING *",
+    )
+    .bind(id)
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(database_error)?
+    .ok_or_else(category_not_found)?;
+    tx.commit().await.map_err(database_error)?;
+
+    tracing::info!(category_id = id, "category restored");
+    Ok(Json(json!({"status": "success", "data": category})))
+}
+
+/// Removes categories deleted more than `retention` ago that no product, in
+/// any state, still names. Returns how many were removed.
+pub async fn purge_deleted_categories(db: &PgPool, retention: Duration) -> Result<u64, sqlx::Error> {
+    let cutoff = Utc::now() - chrono::Duration::from_std(retention).unwrap_or(chrono::Duration::zero());
+    let mut tx = db.begin().await?;
+    attribute(&mut tx, None, "purge").await?;
+
+    // Products refer to categories by name, which the schema cannot enforce.
+    let due: Vec<i32> = sqlx::query_scalar(
+        r#"SELECT id FROM categories
+        WHERE deleted_at <= $1
+            AND NOT EXISTS (SELECT 1 FROM products WHERE products.category_name = categories.name)
+        FOR UPDATE SKIP LOCKED"#,
+    )
+    .bind(cutoff)
+    .fetch_all(&mut *tx)
+    .await?;
+    if due.is_empty() {
+        return Ok(0);
+    }
+
+    let purged = purge_rows(&mut tx, "categories", &due).await?;
+    tx.commit().await?;
+    Ok(purged.len() as u64)
+}
+
+fn category_not_found() -> HandlerError {
+    let error_response = ErrorResponse {
+        status: "fail",
+        message: "Category not found".to_string(),
+    };
+    (StatusCode::NOT_FOUND, Json(error_response))
+}
+
+fn database_error(e: sqlx::Error) -> HandlerError {
+    let error_response = ErrorResponse {
+        status: "error",
+        message: format!("Database error: {}", e),
+    };
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderMap;
+
+    use super::*;
+    use crate::apis::v1::category::category_handler::{delete_category, get_category};
+    use crate::test_support::{example_config, ScratchDb};
+
+    #[tokio::test]
+    async fn keeps_deleted_categories_that_products_still_name() {
+        let Some(db) = ScratchDb::migrated().await else {
+            return;
+        };
+        let data = db.state(example_config());
+        let admin = db.user("admin@example.com", "password", "admin").await;
+        let admin: User = sqlx::query_as("SELECT * FROM users WHERE id = $1")
+            .bind(admin)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        let id: i32 = sqlx::query_scalar("INSERT INTO categories (name) VALUES ('Lamps') RETURNING id")
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO products (name, description, price, category_name, deleted_at) VALUES ('Desk lamp', '', 30, 'Lamps', NOW())")
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        let on_storefront = || async { get_category(Path(id), State(data.clone())).await.is_ok() };
+
+        archive_category_handler(State(data.clone()), Extension(admin.clone()), Path(id))
+            .await
+            .map_err(|(status, _)| status)
+            .unwrap();
+        assert!(!on_storefront().await);
+        restore_category_handler(State(data.clone()), Extension(admin.clone()), Path(id))
+            .await
+            .map_err(|(status, _)| status)
+            .unwrap();
+        assert!(on_storefront().await);
+
+        let (status, _) = delete_category(Path(id), State(data.clone()), Extension(Principal::User(admin)), HeaderMap::new())
+            .await
+            .map_err(|_| "delete failed")
+            .unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert!(!on_storefront().await);
+        assert_eq!(purge_deleted_categories(&db.pool, Duration::ZERO).await.unwrap(), 0, "a deleted product still names it");
+
+        sqlx::query("DELETE FROM products").execute(&db.pool).await.unwrap();
+        assert_eq!(purge_deleted_categories(&db.pool, Duration::from_secs(3600)).await.unwrap(), 0, "deleted just now");
+        assert_eq!(purge_deleted_categories(&db.pool, Duration::ZERO).await.unwrap(), 1);
+        let left: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM categories")
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(left, 0);
+        db.drop().await;
+    }
+}
@@ -0,0 +1,302 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::PgConnection;
+
+use crate::apis::jwt_auth::Principal;
+use crate::apis::login::{model::User, response::ErrorResponse};
+use crate::apis::v1::attributes::{valid_key, AttributeDefinition, AttributeKind};
+use crate::apis::v1::revisions::attribute;
+
+use crate::AppState;
+
+type HandlerError = (StatusCode, Json<ErrorResponse>);
+
+#[derive(Debug, Deserialize)]
+pub struct NewAttributeSchema {
+    pub key: String,
+    pub label: String,
+    pub kind: AttributeKind,
+    /// Required for, and only allowed on, `enum` attributes.
+    #[serde(default)]
+    pub options: Vec<String>,
+    #[serde(default)]
+    pub unit: Option<String>,
+    #[serde(default)]
+    pub filterable: Option<bool>,
+    #[serde(default)]
+    pub position: Option<i32>,
+}
+
+/// The key and kind cannot change; values already stored depend on them.
+#[derive(Debug, Deserialize)]
+pub struct UpdateAttributeSchema {
+    pub label: Option<String>,
+    pub options: Option<Vec<String>>,
+    pub unit: Option<String>,
+    pub filterable: Option<bool>,
+    pub position: Option<i32>,
+}
+
+/// The attributes products in the category may carry, in display order.
+pub async fn list_attributes_handler(
+    State(data): State<Arc<AppState>>,
+    Path(id): Path<i32>,
+) -> Result<impl IntoResponse, HandlerError> {
+    let mut conn = data.db.acquire().await.map_err(database_error)?;
+    ensure_category(&mut conn, id).await?;
+    let attributes: Vec<AttributeDefinition> = sqlx::query_as(
+        "SELECT * FROM category_attributes WHERE category_id = $1 ORDER BY position, id",
+    )
+    .bind(id)
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(database_error)?;
+
+    Ok(Json(json!({
+        "status": "success",
+        "results": attributes.len(),
+        "data": attributes,
+    })))
+}
+
+pub async fn create_attribute_handler(
+    State(data): State<Arc<AppState>>,
+    Path(id): Path<i32>,
+    Json(body): Json<NewAttributeSchema>,
+) -> Result<impl IntoResponse, HandlerError> {
+    if !valid_key(&body.key) {
+        return Err(fail(
+            StatusCode::BAD_REQUEST,
+            "key must be 1 to 64 lowercase letters, digits or `_`, starting with a letter",
+        ));
+    }
+    let label = valid_label(&body.label)?;
+    let unit = valid_unit(body.unit.as_deref())?;
+    let options = valid_options(body.kind, body.options)?;
+
+    let mut conn = data.db.acquire().await.map_err(database_error)?;
+    ensure_category(&mut conn, id).await?;
+    let attribute: AttributeDefinition = sqlx::query_as(
+        r#"INSERT INTO category_attributes (category_id, key, label, kind, options, unit, filterable, position)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        RETURNING *"#,
+    )
+    .bind(id)
+    .bind(&body.key)
+    .bind(label)
+    .bind(body.kind.as_str())
+    .bind(&options)
+    .bind(unit)
+    .bind(body.filterable.unwrap_or(true))
+    .bind(body.position.unwrap_or(0))
+    .fetch_one(&mut *conn)
+    .await
+    .map_err(|e| match e.as_database_error().and_then(|e| e.code()) {
+        Some(code) if code == "23505" => fail(
+            StatusCode::CONFLICT,
+            "The category already has an attribute with this key",
+        ),
+        _ => database_error(e),
+    })?;
+
+    tracing::info!(category_id = id, key = %attribute.key, "category attribute created");
+    Ok((
+        StatusCode::CREATED,
+        Json(json!({"status": "success", "data": attribute})),
+    ))
+}
+
+/// Dropping an enum option is refused while products still use it.
+pub async fn update_attribute_handler(
+    State(data): State<Arc<AppState>>,
+    Path((id, key)): Path<(i32, String)>,
+    Json(body): Json<UpdateAttributeSchema>,
+) -> Result<impl IntoResponse, HandlerError> {
+    let label = body.label.as_deref().map(valid_label).transpose()?;
+    let unit = valid_unit(body.unit.as_deref())?;
+
+    let mut tx = data.db.begin().await.map_err(database_error)?;
+    let current: AttributeDefinition = sqlx::query_as(
+        "SELECT * FROM category_attributes WHERE category_id = $1 AND key = $2 FOR UPDATE",
+    )
+    .bind(id)
+    .bind(&key)
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(database_error)?
+    .ok_or_else(attribute_not_found)?;
+
+    let options = body
+        .options
+        .map(|options| valid_options(current.kind, options))
+        .transpose()?;
+    if let Some(options) = &options {
+        let dropped: Vec<&str> = current
+            .options
+            .iter()
+            .filter(|option| !options.contains(option))
+            .map(String::as_str)
+            .collect();
+        let in_use: i64 = sqlx::query_scalar(
+            r#"SELECT COUNT(*) FROM products p
+            JOIN categories c ON c.name = p.category_name
+            WHERE c.id = $1 AND p.attributes ->> $2 = ANY($3)"#,
+        )
+        .bind(id)
+        .bind(&key)
+        .bind(&dropped)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(database_error)?;
+        if in_use > 0 {
+            return Err(fail(
+                StatusCode::CONFLICT,
+                &format!("{} products still use an option being removed", in_use),
+            ));
+        }
+    }
+
+    let attribute: AttributeDefinition = sqlx::query_as(
+        r#"UPDATE category_attributes SET
+            label = COALESCE($3, label),
+            options = COALESCE($4, options),
+            unit = COALESCE($5, unit),
+            filterable = COALESCE($6, filterable),
+            position = COALESCE($7, position)
+        WHERE category_id = $1 AND key = $2
+        RETURNING *"#,
+    )
+    .bind(id)
+    .bind(&key)
+    .bind(label)
+    .bind(options)
+    .bind(unit)
+    .bind(body.filterable)
+    .bind(body.position)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(database_error)?;
+    tx.commit().await.map_err(database_error)?;
+
+    Ok(Json(json!({"status": "success", "data": attribute})))
+}
+
+/// Removes the attribute and its value from every product in the category.
+pub async fn delete_attribute_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Path((id, key)): Path<(i32, String)>,
+) -> Result<impl IntoResponse, HandlerError> {
+    let mut tx = data.db.begin().await.map_err(database_error)?;
+    attribute(&mut tx, Some(&Principal::User(user)), "admin")
+        .await
+        .map_err(database_error)?;
+    let deleted = sqlx::query("DELETE FROM category_attributes WHERE category_id = $1 AND key = $2")
+        .bind(id)
+        .bind(&key)
+        .execute(&mut *tx)
+        .await
+        .map_err(database_error)?;
+    if deleted.rows_affected() == 0 {
+        return Err(attribute_not_found());
+    }
+    let cleared = sqlx::query(
+        r#"UPDATE products p SET attributes = p.attributes - $2, version = p.version + 1
+        FROM categories c
+        WHERE c.id = $1 AND p.category_name = c.name AND p.attributes ? $2"#,
+    )
+    .bind(id)
+    .bind(&key)
+    .execute(&mut *tx)
+    .await
+    .map_err(database_error)?;
+    tx.commit().await.map_err(database_error)?;
+
+    tracing::info!(category_id = id, key, products = cleared.rows_affected(), "category attribute deleted");
+    Ok(Json(json!({
+        "status": "success",
+        "products_updated": cleared.rows_affected(),
+    })))
+}
+
+async fn ensure_category(conn: &mut PgConnection, id: i32) -> Result<(), HandlerError> {
+    let exists: bool =
+        sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM categories WHERE id = $1 AND deleted_at IS NULL)")
+            .bind(id)
+            .fetch_one(conn)
+            .await
+            .map_err(database_error)?;
+    if !exists {
+        return Err(fail(StatusCode::NOT_FOUND, "Category not found"));
+    }
+    Ok(())
+}
+
+fn valid_label(label: &str) -> Result<&str, HandlerError> {
+    let label = label.trim();
+    if label.is_empty() || label.chars().count() > 255 {
+        return Err(fail(StatusCode::BAD_REQUEST, "label must be between 1 and 255 characters"));
+    }
+    Ok(label)
+}
+
+fn valid_unit(unit: Option<&str>) -> Result<Option<&str>, HandlerError> {
+    let unit = unit.map(str::trim).filter(|unit| !unit.is_empty());
+    if unit.is_some_and(|unit| unit.chars().count() > 20) {
+        return Err(fail(StatusCode::BAD_REQUEST, "unit must be at most 20 characters"));
+    }
+    Ok(unit)
+}
+
+fn valid_options(kind: AttributeKind, options: Vec<String>) -> Result<Vec<String>, HandlerError> {
+    let options: Vec<String> = options.into_iter().map(|option| option.trim().to_string()).collect();
+    if kind != AttributeKind::Enum {
+        if !options.is_empty() {
+            return Err(fail(StatusCode::BAD_REQUEST, "only enum attributes take options"));
+        }
+        return Ok(options);
+    }
+    if options.is_empty() {
+        return Err(fail(StatusCode::BAD_REQUEST, "enum attributes need at least one option"));
+    }
+    for (i, option) in options.iter().enumerate() {
+        if option.is_empty() || option.chars().count() > 255 || option.contains(',') {
+            return Err(fail(
+                StatusCode::BAD_REQUEST,
+                "options must be 1 to 255 characters without commas",
+            ));
+        }
+        if options[..i].contains(option) {
+            return Err(fail(StatusCode::BAD_REQUEST, "options must be unique"));
+        }
+    }
+    Ok(options)
+}
+
+fn attribute_not_found() -> HandlerError {
+    fail(StatusCode::NOT_FOUND, "Attribute not found")
+}
+
+fn fail(status: StatusCode, message: &str) -> HandlerError {
+    let error_response = ErrorResponse {
+        status: "fail",
+        message: message.to_string(),
+    };
+    (status, Json(error_response))
+}
+
+fn database_error(e: sqlx::Error) -> HandlerError {
+    let error_response = ErrorResponse {
+        status: "error",
+        message: format!("Database error: {}", e),
+    };
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+}
@@ -5,72 +5,235 @@ use crate::apis::v1::category::category_model::{Category, NewCategory};
 
 // Implement similar functions for other CRUD operations
 
-use axum::{response::IntoResponse, extract::{Path, State}, http::StatusCode, Json};
+use axum::{async_trait, response::{IntoResponse, Response}, extract::{OriginalUri, Path, State}, Extension, http::{header, HeaderMap, StatusCode}, Json};
+use serde::Deserialize;
 use serde_json::{json, Value};
+use sqlx::PgConnection;
+use crate::apis::login::response::ErrorResponse;
+use crate::apis::v1::batch::{self, item_error, Applied, BatchOperation, BatchRequest, Outcome};
+use crate::apis::v1::lifecycle::VISIBLE;
+use crate::apis::jwt_auth::Principal;
+use crate::apis::v1::revisions::attribute;
+use crate::apis::v1::slugs::{moved_permanently, redirect_target};
+use crate::apis::v1::precondition::{etag, merge_patch, missing_or_stale, IfMatch, Write};
 
 pub async fn get_categories(State(pool): State<Arc<AppState>>) -> impl IntoResponse {
-    let sql = "SELECT * FROM categories".to_string();
+    let sql = format!("SELECT * FROM categories WHERE {}", VISIBLE);
     let category = sqlx::query_as::<_, Category>(&sql).fetch_all(&pool.db).await.unwrap();
 
     (StatusCode::OK, Json(category))
 }
 
-pub async fn get_category(Path(id): Path<i32>, State(pool): State<Arc<AppState>>) -> Result<Json<Category>, CustomError> {
-    let sql = "SELECT * FROM categories where id=$1".to_string();
+pub async fn get_category(Path(id): Path<i32>, State(pool): State<Arc<AppState>>) -> Result<impl IntoResponse, CustomError> {
+    let sql = format!("SELECT * FROM categories WHERE id = $1 AND {}", VISIBLE);
     let category : Category = sqlx::query_as(&sql).bind(id).fetch_one(&pool.db).await.map_err(|_| {
         CustomError::TaskNotFound
     })?;
 
-    Ok(Json(category))
+    Ok(([(header::ETAG, etag(category.version))], Json(category)))
 }
 
-#[axum_macros::debug_handler]
-pub async fn post_category(State(pool): State<Arc<AppState>>, Json(data): Json<NewCategory>) -> Result<(StatusCode, Json<NewCategory>), CustomError> {
-    let sql = "INSERT INTO categories (name) values ($1)".to_string();
-    let _  = sqlx::query(&sql)
-    .bind(&data.name)
-    .execute(&pool.db)
-    .await.map_err(|_| {
+/// Like [`get_category`]; a slug the category no longer uses answers 301 with
+/// the address under its current slug.
+pub async fn get_category_by_slug(Path(slug): Path<String>, OriginalUri(uri): OriginalUri, State(pool): State<Arc<AppState>>) -> Result<Response, CustomError> {
+    let sql = format!("SELECT * FROM categories WHERE slug = $1 AND {}", VISIBLE);
+    let category: Option<Category> = sqlx::query_as(&sql).bind(&slug).fetch_optional(&pool.db).await.map_err(|_| {
         CustomError::InternalServerError
     })?;
+    let Some(category) = category else {
+        let current = redirect_target(&pool.db, "category", "categories", VISIBLE, &slug).await.map_err(|_| {
+            CustomError::InternalServerError
+        })?;
+        return current.map(|current| moved_permanently(&uri, &current)).ok_or(CustomError::TaskNotFound);
+    };
 
-    Ok((StatusCode::CREATED, Json(data)))
+    Ok(([(header::ETAG, etag(category.version))], Json(category)).into_response())
 }
 
-pub async fn update_category(Path(id): Path<i32>, State(pool): State<Arc<AppState>>, Json(data): Json<NewCategory>) -> Result<(StatusCode, Json<NewCategory>), CustomError> {
-    let sql = "SELECT * FROM categories where id=$1".to_string();
-    let _ :Category = sqlx::query_as(&sql).bind(id).fetch_one(&pool.db).await.map_err(|_| {
-        CustomError::TaskNotFound
-    })?;
+#[axum_macros::debug_handler]
+pub async fn post_category(State(pool): State<Arc<AppState>>, Extension(principal): Extension<Principal>, Json(data): Json<NewCategory>) -> Result<impl IntoResponse, CustomError> {
+    let data = data.validate().map_err(|_| CustomError::BadRequest)?;
+    let mut tx = pool.db.begin().await.map_err(|_| CustomError::InternalServerError)?;
+    attribute(&mut tx, Some(&principal), "api").await.map_err(|_| CustomError::InternalServerError)?;
+    let category = insert_category(&mut tx, &data).await.map_err(write_error)?;
 
-    sqlx::query("UPDATE categories SET name = $1 WHERE id=$2")
-    .bind(&data.id)
-    .bind(&data.name)
-    .execute(&pool.db)
-    .await.map_err(|_| {
-        CustomError::InternalServerError
-    })?;
+    tx.commit().await.map_err(|_| CustomError::InternalServerError)?;
+
+    Ok((StatusCode::CREATED, [(header::ETAG, etag(category.version))], Json(category)))
+}
+
+/// With `If-Match`, answers 412 unless the category is still at one of the given versions.
+pub async fn update_category(Path(id): Path<i32>, State(pool): State<Arc<AppState>>, Extension(principal): Extension<Principal>, headers: HeaderMap, Json(data): Json<NewCategory>) -> Result<impl IntoResponse, CustomError> {
+    let if_match = IfMatch::from_headers(&headers).map_err(|_| CustomError::BadRequest)?;
+    let data = data.validate().map_err(|_| CustomError::BadRequest)?;
+    let mut tx = pool.db.begin().await.map_err(|_| CustomError::InternalServerError)?;
+    attribute(&mut tx, Some(&principal), "api").await.map_err(|_| CustomError::InternalServerError)?;
+    let category = match update_category_row(&mut tx, id, &data, &if_match).await.map_err(write_error)? {
+        Write::Done(category) => category,
+        Write::NotFound => return Err(CustomError::TaskNotFound),
+        Write::Stale => return Err(CustomError::PreconditionFailed),
+    };
+    tx.commit().await.map_err(|_| CustomError::InternalServerError)?;
 
-    Ok((StatusCode::OK, Json(data)))
+    Ok((StatusCode::OK, [(header::ETAG, etag(category.version))], Json(category)))
 }
 
-pub async fn delete_category(Path(id): Path<i32>, State(pool): State<Arc<AppState>>) -> Result<(StatusCode, Json<Value>), CustomError> {
-    let sql = "SELECT * FROM categories where id=$1".to_string();
-    let _ : Category = sqlx::query_as(&sql)
+/// Applies a JSON merge patch (`application/merge-patch+json`). Honours
+/// `If-Match` like [`update_category`].
+pub async fn patch_category(Path(id): Path<i32>, State(pool): State<Arc<AppState>>, Extension(principal): Extension<Principal>, headers: HeaderMap, Json(patch): Json<Value>) -> Result<impl IntoResponse, CustomError> {
+    let if_match = IfMatch::from_headers(&headers).map_err(|_| CustomError::BadRequest)?;
+    let fields = patch.as_object().ok_or(CustomError::BadRequest)?;
+    if fields.keys().any(|key| !PATCHABLE.contains(&key.as_str())) {
+        return Err(CustomError::BadRequest);
+    }
+
+    let mut tx = pool.db.begin().await.map_err(|_| CustomError::InternalServerError)?;
+    attribute(&mut tx, Some(&principal), "api").await.map_err(|_| CustomError::InternalServerError)?;
+    let current: Category = sqlx::query_as("SELECT * FROM categories WHERE id = $1 AND deleted_at IS NULL FOR UPDATE")
     .bind(id)
-    .fetch_one(&pool.db)
+    .fetch_optional(&mut *tx)
     .await
-    .map_err(|_| {
-        CustomError::TaskNotFound
+    .map_err(|_| CustomError::InternalServerError)?
+    .ok_or(CustomError::TaskNotFound)?;
+    if !if_match.matches(current.version) {
+        return Err(CustomError::PreconditionFailed);
+    }
+
+    let mut document = json!({
+        "name": current.name,
+        "slug": current.slug,
+        "meta_title": current.meta_title,
+        "meta_description": current.meta_description,
+    });
+    merge_patch(&mut document, &patch);
+    let data: NewCategory = serde_json::from_value(document).map_err(|_| CustomError::BadRequest)?;
+    let data = data.validate().map_err(|_| CustomError::BadRequest)?;
+
+    let category = replace_category_row(&mut tx, id, &data).await.map_err(write_error)?;
+    tx.commit().await.map_err(|_| CustomError::InternalServerError)?;
+
+    Ok((StatusCode::OK, [(header::ETAG, etag(category.version))], Json(category)))
+}
+
+pub async fn delete_category(Path(id): Path<i32>, State(pool): State<Arc<AppState>>, Extension(principal): Extension<Principal>, headers: HeaderMap) -> Result<(StatusCode, Json<Value>), CustomError> {
+    let if_match = IfMatch::from_headers(&headers).map_err(|_| CustomError::BadRequest)?;
+    let mut tx = pool.db.begin().await.map_err(|_| CustomError::InternalServerError)?;
+    attribute(&mut tx, Some(&principal), "api").await.map_err(|_| CustomError::InternalServerError)?;
+    let deleted = delete_category_row(&mut tx, id, &if_match).await.map_err(|_| {
+        CustomError::InternalServerError
     })?;
+    match deleted {
+        Write::Done(()) => {}
+        Write::NotFound => return Err(CustomError::TaskNotFound),
+        Write::Stale => return Err(CustomError::PreconditionFailed),
+    }
+    tx.commit().await.map_err(|_| CustomError::InternalServerError)?;
+
+    Ok((StatusCode::OK ,Json(json!({"msg": "Category Deleted"}))))
+}
+
+/// Creates, updates and deletes many categories in one transaction. See [`batch::execute`].
+pub async fn batch_categories(State(pool): State<Arc<AppState>>, Extension(principal): Extension<Principal>, Json(request): Json<BatchRequest<CategoryOperation>>) -> Result<impl IntoResponse, (StatusCode, Json<ErrorResponse>)> {
+    batch::execute(&pool.db, request, &principal).await
+}
 
-    sqlx::query("DELETE FROM categories WHERE id=$1")
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum CategoryOperation {
+    Create { data: NewCategory },
+    /// With `version`, fails unless the category is still at that version.
+    Update { id: i32, data: NewCategory, #[serde(default)] version: Option<i32> },
+    Delete { id: i32, #[serde(default)] version: Option<i32> },
+}
+
+#[async_trait]
+impl BatchOperation for CategoryOperation {
+    async fn apply(self, conn: &mut PgConnection) -> Result<Applied, String> {
+        match self {
+            CategoryOperation::Create { data } => {
+                let category = insert_category(conn, &data.validate()?).await.map_err(item_error)?;
+                Ok(Applied { outcome: Outcome::Created, data: Some(json!(category)) })
+            }
+            CategoryOperation::Update { id, data, version } => {
+                let data = data.validate()?;
+                match update_category_row(conn, id, &data, &IfMatch::from(version)).await.map_err(item_error)? {
+                    Write::Done(category) => Ok(Applied { outcome: Outcome::Updated, data: Some(json!(category)) }),
+                    Write::NotFound => Err(format!("category {} not found", id)),
+                    Write::Stale => Err(format!("category {} is no longer at version {}", id, version.unwrap_or_default())),
+                }
+            }
+            CategoryOperation::Delete { id, version } => {
+                match delete_category_row(conn, id, &IfMatch::from(version)).await.map_err(item_error)? {
+                    Write::Done(()) => Ok(Applied { outcome: Outcome::Deleted, data: None }),
+                    Write::NotFound => Err(format!("category {} not found", id)),
+                    Write::Stale => Err(format!("category {} is no longer at version {}", id, version.unwrap_or_default())),
+                }
+            }
+        }
+    }
+}
+
+async fn insert_category(conn: &mut PgConnection, data: &NewCategory) -> Result<Category, sqlx::Error> {
+    sqlx::query_as("INSERT INTO categories (name, slug, meta_title, meta_description) VALUES ($1, $2, NULLIF($3, ''), NULLIF($4, '')) RETURNING *")
+    .bind(&data.name)
+    .bind(&data.slug)
+    .bind(&data.meta_title)
+    .bind(&data.meta_description)
+    .fetch_one(conn)
+    .await
+}
+
+/// A missing slug or meta field leaves the stored value as it is.
+async fn update_category_row(conn: &mut PgConnection, id: i32, data: &NewCategory, if_match: &IfMatch) -> Result<Write<Category>, sqlx::Error> {
+    let category = sqlx::query_as("UPDATE categories SET name = $2, slug = COALESCE($4, slug), meta_title = NULLIF(COALESCE($5, meta_title), ''), meta_description = NULLIF(COALESCE($6, meta_description), ''), version = version + 1 WHERE id = $1 AND deleted_at IS NULL AND ($3::INTEGER[] IS NULL OR version = ANY($3)) RETURNING *")
     .bind(id)
-    .execute(&pool.db)
+    .bind(&data.name)
+    .bind(if_match.versions())
+    .bind(&data.slug)
+    .bind(&data.meta_title)
+    .bind(&data.meta_description)
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    match category {
+        Some(category) => Ok(Write::Done(category)),
+        None => missing_or_stale(conn, "categories", id).await,
+    }
+}
+
+/// Writes every field as given, including `None` meta fields; for callers that
+/// already hold the row lock. A missing slug is kept.
+async fn replace_category_row(conn: &mut PgConnection, id: i32, data: &NewCategory) -> Result<Category, sqlx::Error> {
+    sqlx::query_as("UPDATE categories SET name = $2, slug = COALESCE($3, slug), meta_title = NULLIF($4, ''), meta_description = NULLIF($5, ''), version = version + 1 WHERE id = $1 AND deleted_at IS NULL RETURNING *")
+    .bind(id)
+    .bind(&data.name)
+    .bind(&data.slug)
+    .bind(&data.meta_title)
+    .bind(&data.meta_description)
+    .fetch_one(conn)
     .await
-    .map_err(|_| {
-        CustomError::TaskNotFound
-    })?;
+}
 
-    Ok((StatusCode::OK ,Json(json!({"msg": "Category Deleted"}))))
+/// Only marks the row; the purge job removes it once the retention period has passed.
+async fn delete_category_row(conn: &mut PgConnection, id: i32, if_match: &IfMatch) -> Result<Write<()>, sqlx::Error> {
+    let result = sqlx::query("UPDATE categories SET deleted_at = NOW(), version = version + 1 WHERE id = $1 AND deleted_at IS NULL AND ($2::INTEGER[] IS NULL OR version = ANY($2))")
+    .bind(id)
+    .bind(if_match.versions())
+    .execute(&mut *conn)
+    .await?;
+
+    if result.rows_affected() > 0 {
+        return Ok(Write::Done(()));
+    }
+    missing_or_stale(conn, "categories", id).await
 }
+
+fn write_error(e: sqlx::Error) -> CustomError {
+    match e.as_database_error().and_then(|e| e.code()) {
+        Some(code) if code == "23505" => CustomError::Conflict,
+        _ => CustomError::InternalServerError,
+    }
+}
+
+/// Fields a merge patch may touch; `id` and `version` are read-only.
+const PATCHABLE: [&str; 4] = ["name", "slug", "meta_title", "meta_description"];
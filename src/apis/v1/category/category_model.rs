@@ -1,15 +1,62 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
+use crate::apis::v1::slugs::validate_seo;
+
 #[derive(sqlx::FromRow,Deserialize, Serialize)]
 
 pub struct Category {
     pub id: i32,
     pub name: String,
+    /// Unique; the category can also be looked up by it.
+    pub slug: String,
+    /// For search engines; the storefront falls back to the name.
+    pub meta_title: Option<String>,
+    pub meta_description: Option<String>,
+    /// Bumped on every write and sent as the `ETag`.
+    pub version: i32,
+    /// Only ever set on items listed by catalog administrators.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archived_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+/// The columns set when a revision is reverted.
+#[derive(Debug, Deserialize)]
+pub struct CategorySnapshot {
+    pub name: String,
+    pub slug: String,
+    pub meta_title: Option<String>,
+    pub meta_description: Option<String>,
+    pub archived_at: Option<DateTime<Utc>>,
+    pub deleted_at: Option<DateTime<Utc>>,
 }
 
 #[derive(sqlx::FromRow,Deserialize, Serialize)]
 
 pub struct NewCategory {
+    /// Ignored; ids are assigned by the database. Accepted so older clients keep working.
+    #[serde(default)]
     pub id: i32,
     pub name: String,
+    /// Generated from the name when missing on creation; see `NewProduct::slug`.
+    #[serde(default)]
+    pub slug: Option<String>,
+    /// An empty string clears it.
+    #[serde(default)]
+    pub meta_title: Option<String>,
+    #[serde(default)]
+    pub meta_description: Option<String>,
+}
+
+impl NewCategory {
+    pub fn validate(mut self) -> Result<NewCategory, String> {
+        self.name = self.name.trim().to_string();
+        if self.name.is_empty() || self.name.chars().count() > 255 {
+            return Err("name must be between 1 and 255 characters".to_string());
+        }
+        validate_seo(&mut self.slug, &mut self.meta_title, &mut self.meta_description)?;
+        Ok(self)
+    }
 }
\ No newline at end of file
@@ -0,0 +1,123 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+    Extension, Json,
+};
+use serde_json::json;
+
+use crate::apis::jwt_auth::Principal;
+use crate::apis::login::response::ErrorResponse;
+use crate::apis::v1::category::category_model::{Category, CategorySnapshot};
+use crate::apis::v1::precondition::etag;
+use crate::apis::v1::revisions::{
+    attribute, find_revision, find_revisions, reverted, revision_page, RevisionFilter,
+    RevisionsQuery,
+};
+
+use crate::AppState;
+
+type HandlerError = (StatusCode, Json<ErrorResponse>);
+
+/// Every recorded change to the category, newest first, including changes
+/// made before it was deleted or purged.
+pub async fn list_category_revisions_handler(
+    State(data): State<Arc<AppState>>,
+    Path(id): Path<i32>,
+    Query(query): Query<RevisionsQuery>,
+) -> Result<impl IntoResponse, HandlerError> {
+    let filter = RevisionFilter {
+        entity: Some("category"),
+        entity_id: Some(id),
+        ..RevisionFilter::default()
+    };
+    let revisions = find_revisions(&data.db, &filter, &query)
+        .await
+        .map_err(database_error)?;
+
+    Ok(Json(revision_page(&revisions)))
+}
+
+/// Undoes a revision: the fields it changed get their earlier values back.
+/// See [`revert_product_handler`](crate::apis::v1::products::product_revisions_handler::revert_product_handler).
+pub async fn revert_category_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(principal): Extension<Principal>,
+    Path((id, revision_id)): Path<(i32, i64)>,
+) -> Result<impl IntoResponse, HandlerError> {
+    let mut tx = data.db.begin().await.map_err(database_error)?;
+    attribute(&mut tx, Some(&principal), &format!("revert of revision {}", revision_id))
+        .await
+        .map_err(database_error)?;
+
+    let revision = find_revision(&mut tx, "category", id, revision_id)
+        .await
+        .map_err(database_error)?
+        .ok_or_else(|| fail(StatusCode::NOT_FOUND, "Revision not found"))?;
+    let current: serde_json::Value =
+        sqlx::query_scalar("SELECT to_jsonb(t) FROM categories t WHERE id = $1 FOR UPDATE")
+            .bind(id)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(database_error)?
+            .ok_or_else(|| fail(StatusCode::NOT_FOUND, "The category has been purged"))?;
+    let Some(reverted) = reverted(&revision, current) else {
+        return Err(fail(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "A creation cannot be reverted; delete the category instead",
+        ));
+    };
+    let snapshot: CategorySnapshot = serde_json::from_value(reverted).map_err(|e| {
+        tracing::error!(error = %e, revision_id, "unreadable category snapshot");
+        fail(StatusCode::INTERNAL_SERVER_ERROR, "The revision cannot be reverted")
+    })?;
+
+    let category: Category = sqlx::query_as(
+        r#"UPDATE categories SET
+            name = $2, archived_at = $3, deleted_at = $4, slug = $5, meta_title = $6,
+            meta_description = $7, version = version + 1
+        WHERE id = $1
+        RETURNING *"#,
+    )
+    .bind(id)
+    .bind(&snapshot.name)
+    .bind(snapshot.archived_at)
+    .bind(snapshot.deleted_at)
+    .bind(&snapshot.slug)
+    .bind(&snapshot.meta_title)
+    .bind(&snapshot.meta_description)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| match e.as_database_error().and_then(|e| e.code()) {
+        Some(code) if code == "23505" => fail(
+            StatusCode::CONFLICT,
+            "Another category now has this revision's slug",
+        ),
+        _ => database_error(e),
+    })?;
+    tx.commit().await.map_err(database_error)?;
+
+    tracing::info!(category_id = id, revision_id, %principal, "category revision reverted");
+    Ok((
+        [(header::ETAG, etag(category.version))],
+        Json(json!({"status": "success", "data": category})),
+    ))
+}
+
+fn fail(status: StatusCode, message: &str) -> HandlerError {
+    let error_response = ErrorResponse {
+        status: "fail",
+        message: message.to_string(),
+    };
+    (status, Json(error_response))
+}
+
+fn database_error(e: sqlx::Error) -> HandlerError {
+    let error_response = ErrorResponse {
+        status: "error",
+        message: format!("Database error: {}", e),
+    };
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+}
@@ -1,16 +1,50 @@
 use std::sync::Arc;
 
 use axum::{
-    routing::{get, post, put, delete},
+    middleware,
+    routing::{get, patch, post, put, delete},
     Router
 };
-use crate::{apis::v1::category::category_handler, AppState};
+use crate::{apis::{jwt_auth::require_catalog_manager, v1::category::{category_handler, category_revisions_handler}}, AppState};
 
 pub fn category_router(app_state: Arc<AppState>) -> Router {
     Router::new()
-        .route("/", post(category_handler::post_category))
+        .route(
+            "/batch",
+            post(category_handler::batch_categories)
+                .layer(middleware::from_fn(require_catalog_manager)),
+        )
+        .route(
+            "/",
+            post(category_handler::post_category)
+                .layer(middleware::from_fn(require_catalog_manager)),
+        )
         .route("/:id", get(category_handler::get_category))
-        .route("/:id", put(category_handler::update_category))
-        .route("/:id", delete(category_handler::delete_category))
+        .route("/slug/:slug", get(category_handler::get_category_by_slug))
+        .route(
+            "/:id",
+            put(category_handler::update_category)
+                .layer(middleware::from_fn(require_catalog_manager)),
+        )
+        .route(
+            "/:id",
+            patch(category_handler::patch_category)
+                .layer(middleware::from_fn(require_catalog_manager)),
+        )
+        .route(
+            "/:id",
+            delete(category_handler::delete_category)
+                .layer(middleware::from_fn(require_catalog_manager)),
+        )
+        .route(
+            "/:id/revisions",
+            get(category_revisions_handler::list_category_revisions_handler)
+                .layer(middleware::from_fn(require_catalog_manager)),
+        )
+        .route(
+            "/:id/revisions/:revision_id/revert",
+            post(category_revisions_handler::revert_category_handler)
+                .layer(middleware::from_fn(require_catalog_manager)),
+        )
         .with_state(app_state)
 }
\ No newline at end of file
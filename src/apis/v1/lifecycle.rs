@@ -0,0 +1,64 @@
+use serde::Deserialize;
+use sqlx::{Connection, PgConnection};
+
+/// Rows the storefront may show.
+pub const VISIBLE: &str = "archived_at IS NULL AND deleted_at IS NULL";
+/// Products the storefront may show: visible and currently published.
+pub const PUBLISHED: &str =
+    "status = 'published' AND archived_at IS NULL AND deleted_at IS NULL";
+/// Rows that can still be edited; archived items stay editable so they can be
+/// fixed up before they are restored.
+pub const NOT_DELETED: &str = "deleted_at IS NULL";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ItemState {
+    Active,
+    Archived,
+    Deleted,
+}
+
+impl ItemState {
+    /// SQL condition selecting the rows in this state.
+    pub fn condition(self) -> &'static str {
+        match self {
+            ItemState::Active => VISIBLE,
+            ItemState::Archived => "archived_at IS NOT NULL AND deleted_at IS NULL",
+            ItemState::Deleted => "deleted_at IS NOT NULL",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StateQuery {
+    /// Every item when absent.
+    pub state: Option<ItemState>,
+}
+
+/// Hard-deletes the rows of `table` with the given ids, each in its own
+/// savepoint. Rows still referenced through a foreign key are kept for a later
+/// run, so the caller only has to check references the schema cannot express.
+/// Returns the ids that were removed.
+pub async fn purge_rows(
+    conn: &mut PgConnection,
+    table: &'static str,
+    ids: &[i32],
+) -> Result<Vec<i32>, sqlx::Error> {
+    let sql = format!("DELETE FROM {} WHERE id = $1", table);
+    let mut purged = Vec::with_capacity(ids.len());
+    for &id in ids {
+        let mut savepoint = conn.begin().await?;
+        match sqlx::query(&sql).bind(id).execute(&mut *savepoint).await {
+            Ok(_) => {
+                savepoint.commit().await?;
+                purged.push(id);
+            }
+            Err(e) if e.as_database_error().and_then(|e| e.code()).as_deref() == Some("23503") => {
+                savepoint.rollback().await?;
+                tracing::debug!(table, id, "still referenced; not purged");
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(purged)
+}
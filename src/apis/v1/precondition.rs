@@ -0,0 +1,191 @@
+use axum::http::{header, HeaderMap, HeaderValue};
+use serde_json::{Map, Value};
+use sqlx::PgConnection;
+
+/// The `ETag` for a catalog item at `version`.
+pub fn etag(version: i32) -> HeaderValue {
+    HeaderValue::from_str(&format!("\"{}\"", version)).expect("a quoted number is a valid header")
+}
+
+/// The versions a write may apply to, taken from `If-Match`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IfMatch {
+    /// No header, or `*`.
+    Any,
+    Versions(Vec<i32>),
+}
+
+impl IfMatch {
+    /// `Err` when the header is present but is not a list of our ETags.
+    /// Weak validators never match, as RFC 9110 requires for `If-Match`.
+    pub fn from_headers(headers: &HeaderMap) -> Result<IfMatch, ()> {
+        let mut versions = Vec::new();
+        for value in headers.get_all(header::IF_MATCH) {
+            let value = value.to_str().map_err(|_| ())?;
+            for tag in value.split(',').map(str::trim).filter(|tag| !tag.is_empty()) {
+                if tag == "*" {
+                    return Ok(IfMatch::Any);
+                }
+                if tag.starts_with("W/") {
+                    continue;
+                }
+                let version = tag
+                    .strip_prefix('"')
+                    .and_then(|tag| tag.strip_suffix('"'))
+                    .ok_or(())?;
+                // A tag we never issued simply does not match.
+                if let Ok(version) = version.parse() {
+                    versions.push(version);
+                }
+            }
+        }
+
+        if versions.is_empty() && !headers.contains_key(header::IF_MATCH) {
+            Ok(IfMatch::Any)
+        } else {
+            Ok(IfMatch::Versions(versions))
+        }
+    }
+
+    pub fn matches(&self, version: i32) -> bool {
+        match self {
+            IfMatch::Any => true,
+            IfMatch::Versions(versions) => versions.contains(&version),
+        }
+    }
+
+    /// For binding as `$n::INTEGER[] IS NULL OR version = ANY($n)`.
+    pub fn versions(&self) -> Option<&[i32]> {
+        match self {
+            IfMatch::Any => None,
+            IfMatch::Versions(versions) => Some(versions),
+        }
+    }
+}
+
+/// The optional `version` of a batch operation.
+impl From<Option<i32>> for IfMatch {
+    fn from(version: Option<i32>) -> IfMatch {
+        match version {
+            Some(version) => IfMatch::Versions(vec![version]),
+            None => IfMatch::Any,
+        }
+    }
+}
+
+/// Result of a conditional write.
+pub enum Write<T> {
+    Done(T),
+    NotFound,
+    /// The item exists but its version did not match.
+    Stale,
+}
+
+/// Why a conditional write to `table` touched no row. Soft-deleted rows
+/// count as missing.
+pub async fn missing_or_stale<T>(
+    conn: &mut PgConnection,
+    table: &'static str,
+    id: i32,
+) -> Result<Write<T>, sqlx::Error> {
+    let exists: bool = sqlx::query_scalar(&format!(
+        "SELECT EXISTS (SELECT 1 FROM {} WHERE id = $1 AND deleted_at IS NULL)",
+        table
+    ))
+    .bind(id)
+    .fetch_one(conn)
+    .await?;
+
+    Ok(if exists { Write::Stale } else { Write::NotFound })
+}
+
+/// Applies a JSON merge patch (RFC 7396) to `target`: `null` removes a
+/// member, objects are merged recursively and anything else replaces it.
+pub fn merge_patch(target: &mut Value, patch: &Value) {
+    let Value::Object(patch) = patch else {
+        *target = patch.clone();
+        return;
+    };
+    if !target.is_object() {
+        *target = Value::Object(Map::new());
+    }
+    let target = target.as_object_mut().expect("just made an object");
+    for (key, value) in patch {
+        if value.is_null() {
+            target.remove(key);
+        } else {
+            merge_patch(target.entry(key.as_str()).or_insert(Value::Null), value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn if_match(values: &[&str]) -> Result<IfMatch, ()> {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append(header::IF_MATCH, HeaderValue::from_str(value).unwrap());
+        }
+        IfMatch::from_headers(&headers)
+    }
+
+    #[test]
+    fn reads_if_match_headers() {
+        assert_eq!(if_match(&[]), Ok(IfMatch::Any));
+        assert_eq!(if_match(&["*"]), Ok(IfMatch::Any));
+        assert_eq!(if_match(&["\"3\""]), Ok(IfMatch::Versions(vec![3])));
+        assert_eq!(if_match(&["\"3\", \"4\"", "\"5\""]), Ok(IfMatch::Versions(vec![3, 4, 5])));
+        assert_eq!(if_match(&["W/\"3\""]), Ok(IfMatch::Versions(vec![])));
+        assert_eq!(if_match(&["\"abc\""]), Ok(IfMatch::Versions(vec![])));
+        assert_eq!(if_match(&["3"]), Err(()));
+        assert_eq!(etag(3), "\"3\"");
+    }
+
+    #[test]
+    fn matches_only_listed_versions() {
+        let versions = IfMatch::Versions(vec![3, 4]);
+        assert!(versions.matches(4));
+        assert!(!versions.matches(5));
+        assert_eq!(versions.versions(), Some(&[3, 4][..]));
+
+        assert!(!IfMatch::Versions(vec![]).matches(1), "a weak or unknown tag matches nothing");
+        assert!(IfMatch::Any.matches(1));
+        assert_eq!(IfMatch::Any.versions(), None);
+
+        assert_eq!(IfMatch::from(Some(2)), IfMatch::Versions(vec![2]));
+        assert_eq!(IfMatch::from(None), IfMatch::Any);
+    }
+
+    #[test]
+    fn applies_merge_patches_as_in_rfc_7396() {
+        // From the examples in appendix A of the RFC.
+        let cases = [
+            (json!({"a": "b"}), json!({"a": "c"}), json!({"a": "c"})),
+            (json!({"a": "b"}), json!({"b": "c"}), json!({"a": "b", "b": "c"})),
+            (json!({"a": "b"}), json!({"a": null}), json!({})),
+            (json!({"a": "b", "b": "c"}), json!({"a": null}), json!({"b": "c"})),
+            (json!({"a": ["b"]}), json!({"a": "c"}), json!({"a": "c"})),
+            (json!({"a": "c"}), json!({"a": ["b"]}), json!({"a": ["b"]})),
+            (
+                json!({"a": {"b": "c"}}),
+                json!({"a": {"b": "d", "c": null}}),
+                json!({"a": {"b": "d"}}),
+            ),
+            (json!({"a": [{"b": "c"}]}), json!({"a": [1]}), json!({"a": [1]})),
+            (json!(["a", "b"]), json!(["c", "d"]), json!(["c", "d"])),
+            (json!({"a": "b"}), json!(["c"]), json!(["c"])),
+            (json!({"a": "foo"}), json!(null), json!(null)),
+            (json!({"e": null}), json!({"a": 1}), json!({"e": null, "a": 1})),
+            (json!([1, 2]), json!({"a": "b", "c": null}), json!({"a": "b"})),
+            (json!({}), json!({"a": {"bb": {"ccc": null}}}), json!({"a": {"bb": {}}})),
+        ];
+        for (mut target, patch, expected) in cases {
+            merge_patch(&mut target, &patch);
+            assert_eq!(target, expected, "patch {}", patch);
+        }
+    }
+}
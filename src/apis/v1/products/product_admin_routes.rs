@@ -0,0 +1,69 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::DefaultBodyLimit,
+    middleware,
+    routing::{delete, get, post, put},
+    Router,
+};
+
+use crate::apis::{
+    jwt_auth::{auth, authenticate, require_admin, require_catalog_manager},
+    v1::products::{
+        product_archive_handler::{
+            archive_product_handler, list_products_admin_handler, restore_product_handler,
+        },
+        product_files_handler::{
+            delete_product_file_handler, list_product_files_handler, upload_product_file_handler,
+        },
+        product_import_handler::{export_products_handler, import_products_handler},
+        product_publishing::list_product_events_handler,
+        product_relations_handler::{
+            get_bundle_handler, list_relations_handler, update_bundle_handler,
+            update_relations_handler,
+        },
+    },
+    v1::revisions::list_catalog_revisions_handler,
+    v1::v_route::catalog_scope,
+};
+
+use crate::AppState;
+
+/// Whole-catalog operations for catalog managers; these need an admin session,
+/// except for imports and exports, which API keys can run like batches.
+pub fn product_admin_router(app_state: Arc<AppState>) -> Router {
+    let transfer = Router::new()
+        .route(
+            "/admin/products/import",
+            post(import_products_handler)
+                .layer(DefaultBodyLimit::max(app_state.config.product_import_max_bytes)),
+        )
+        .route("/admin/products/export", get(export_products_handler))
+        .route_layer(middleware::from_fn(catalog_scope))
+        .route_layer(middleware::from_fn(require_catalog_manager))
+        .route_layer(middleware::from_fn_with_state(app_state.clone(), authenticate));
+
+    Router::new()
+        .route("/admin/products", get(list_products_admin_handler))
+        .route("/admin/products/events", get(list_product_events_handler))
+        .route("/admin/catalog/revisions", get(list_catalog_revisions_handler))
+        .route("/admin/products/:id/archive", post(archive_product_handler))
+        .route("/admin/products/:id/restore", post(restore_product_handler))
+        .route("/admin/products/:id/relations", get(list_relations_handler))
+        .route("/admin/products/:id/relations/:kind", put(update_relations_handler))
+        .route(
+            "/admin/products/:id/bundle",
+            get(get_bundle_handler).put(update_bundle_handler),
+        )
+        .route(
+            "/admin/products/:id/files",
+            get(list_product_files_handler)
+                .post(upload_product_file_handler)
+                .layer(DefaultBodyLimit::max(app_state.config.download_max_bytes)),
+        )
+        .route("/admin/products/:id/files/:file_id", delete(delete_product_file_handler))
+        .route_layer(middleware::from_fn(require_admin))
+        .route_layer(middleware::from_fn_with_state(app_state.clone(), auth))
+        .merge(transfer)
+        .with_state(app_state)
+}
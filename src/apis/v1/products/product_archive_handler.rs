@@ -0,0 +1,337 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use chrono::Utc;
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::PgPool;
+
+use crate::apis::jwt_auth::Principal;
+use crate::apis::login::{model::User, response::ErrorResponse};
+use crate::apis::v1::lifecycle::{purge_rows, ItemState};
+use crate::apis::v1::products::product_images_handler::images_for;
+use crate::apis::v1::products::products_model::{Product, ProductImage, ProductStatus};
+use crate::apis::v1::revisions::attribute;
+use crate::blob_store::BlobStore;
+
+use crate::AppState;
+
+type HandlerError = (StatusCode, Json<ErrorResponse>);
+
+#[derive(Debug, Deserialize)]
+pub struct ProductListQuery {
+    /// Every product when absent.
+    pub state: Option<ItemState>,
+    /// Any status when absent.
+    pub status: Option<ProductStatus>,
+}
+
+/// Every product in the given state and status, including the drafts and the
+/// archived and deleted products the storefront never shows.
+pub async fn list_products_admin_handler(
+    State(data): State<Arc<AppState>>,
+    Query(query): Query<ProductListQuery>,
+) -> Result<impl IntoResponse, HandlerError> {
+    let condition = query.state.map_or("TRUE", |state| state.condition());
+    let mut products: Vec<Product> = sqlx::query_as(&format!(
+        "SELECT * FROM products WHERE {} AND ($1::VARCHAR IS NULL OR status = $1) ORDER BY id",
+        condition
+    ))
+    .bind(query.status.map(ProductStatus::as_str))
+    .fetch_all(&data.db)
+    .await
+    .map_err(database_error)?;
+
+    let ids: Vec<i32> = products.iter().map(|product| product.id).collect();
+    let mut images = images_for(&data.db, &ids).await.map_err(database_error)?;
+    for product in &mut products {
+        product.images = images.remove(&product.id).unwrap_or_default();
+    }
+
+    Ok(Json(json!({
+        "status": "success",
+        "results": products.len(),
+        "data": products,
+    })))
+}
+
+/// Hides a product from the storefront without deleting it.
+pub async fn archive_product_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Path(id): Path<i32>,
+) -> Result<impl IntoResponse, HandlerError> {
+    let mut tx = data.db.begin().await.map_err(database_error)?;
+    attribute(&mut tx, Some(&Principal::User(user)), "admin")
+        .await
+        .map_err(database_error)?;
+    let product: Product = sqlx::query_as(
+        "UPDATE products SET archived_at = COALESCE(archived_at, NOW()), version = version + 1 WHERE id = $1 AND deleted_at IS NULL RETURNING *",
+    )
+    .bind(id)
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(database_error)?
+    .ok_or_else(product_not_found)?;
+    tx.commit().await.map_err(database_error)?;
+
+    tracing::info!(product_id = id, "product archived");
+    Ok(Json(json!({"status": "success", "data": product})))
+}
+
+/// Brings back an archived or deleted product that has not been purged yet.
+pub async fn restore_product_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Path(id): Path<i32>,
+) -> Result<impl IntoResponse, HandlerError> {
+    let mut tx = data.db.begin().await.map_err(database_error)?;
+    attribute(&mut tx, Some(&Principal::User(user)), "admin")
+        .await
+        .map_err(database_error)?;
+    let product: Product = sqlx::query_as(
+        "UPDATE products SET archived_at = NULL, deleted_at = NULL, version = version + 1 WHERE id = $1 RETURNING *",
+    )
+    .bind(id)
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(database_error)?
+    .ok_or_else(product_not_found)?;
+    tx.commit().await.map_err(database_error)?;
+
+    tracing::info!(product_id = id, "product restored");
+    Ok(Json(json!({"status": "success", "data": product})))
+}
+
+/// Removes products deleted more than `retention` ago, with their images and
+/// downloadable files. Returns how many were removed.
+pub async fn purge_deleted_products(
+    db: &PgPool,
+    blobs: &dyn BlobStore,
+    retention: Duration,
+) -> Result<u64, sqlx::Error> {
+    let cutoff = Utc::now() - chrono::Duration::from_std(retention).unwrap_or(chrono::Duration::zero());
+    let mut tx = db.begin().await?;
+    attribute(&mut tx, None, "purge").await?;
+
+    let due: Vec<i32> = sqlx::query_scalar(
+        "SELECT id FROM products WHERE deleted_at <= $1 FOR UPDATE SKIP LOCKED",
+    )
+    .bind(cutoff)
+    .fetch_all(&mut *tx)
+    .await?;
+    if due.is_empty() {
+        return Ok(0);
+    }
+
+    // Read before the rows cascade away with their products.
+    let images: Vec<ProductImage> =
+        sqlx::query_as("SELECT * FROM product_images WHERE product_id = ANY($1)")
+            .bind(&due)
+            .fetch_all(&mut *tx)
+            .await?;
+    let files: Vec<(i32, String)> =
+        sqlx::query_as("SELECT product_id, blob_key FROM product_files WHERE product_id = ANY($1)")
+            .bind(&due)
+            .fetch_all(&mut *tx)
+            .await?;
+
+    let purged = purge_rows(&mut tx, "products", &due).await?;
+    tx.commit().await?;
+
+    let image_keys = images
+        .iter()
+        .filter(|image| purged.contains(&image.product_id))
+        .flat_map(|image| image.keys());
+    let file_keys = files
+        .into_iter()
+        .filter(|(product_id, _)| purged.contains(product_id))
+        .map(|(_, key)| key);
+    for key in image_keys.chain(file_keys) {
+        if let Err(e) = blobs.delete(&key).await {
+            tracing::warn!(error = %e, key, "could not delete blob");
+        }
+    }
+
+    Ok(purged.len() as u64)
+}
+
+fn product_not_found() -> HandlerError {
+    let error_response = ErrorResponse {
+        status: "fail",
+        message: "Product not found".to_string(),
+    };
+    (StatusCode::NOT_FOUND, Json(error_response))
+}
+
+fn database_error(e: sqlx::Error) -> HandlerError {
+    let error_response = ErrorResponse {
+        status: "error",
+        message: format!("Database error: {}", e),
+    };
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderMap;
+
+    use super::*;
+    use crate::apis::v1::products::products_handler::{delete_product, get_product};
+    use crate::blob_store::PRIVATE_PREFIX;
+    use crate::test_support::{example_config, json_response, ScratchDb};
+
+    async fn admin_ids(data: &Arc<AppState>, state: ItemState) -> Vec<i64> {
+        let query = ProductListQuery { state: Some(state), status: None };
+        let response = list_products_admin_handler(State(data.clone()), Query(query))
+            .await
+            .map_err(|(status, _)| status)
+            .unwrap();
+        let (_, body) = json_response(response.into_response()).await;
+        body["data"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|product| product["id"].as_i64().unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn archives_deletes_restores_and_purges_a_product() {
+        let Some(db) = ScratchDb::migrated().await else {
+            return;
+        };
+        let data = db.state(example_config());
+        let admin = db.user("admin@example.com", "password", "admin").await;
+        let admin: User = sqlx::query_as("SELECT * FROM users WHERE id = $1")
+            .bind(admin)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO categories (name) VALUES ('Lamps')")
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        let id: i32 = sqlx::query_scalar(
+            "INSERT INTO products (name, description, price, category_name) VALUES ('Desk lamp', '', 30, 'Lamps') RETURNING id",
+        )
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+        let on_storefront = || async { get_product(Path(id), State(data.clone())).await.is_ok() };
+
+        archive_product_handler(State(data.clone()), Extension(admin.clone()), Path(id))
+            .await
+            .map_err(|(status, _)| status)
+            .unwrap();
+        assert!(!on_storefront().await);
+        assert_eq!(admin_ids(&data, ItemState::Archived).await, [id as i64]);
+        assert!(admin_ids(&data, ItemState::Active).await.is_empty());
+
+        restore_product_handler(State(data.clone()), Extension(admin.clone()), Path(id))
+            .await
+            .map_err(|(status, _)| status)
+            .unwrap();
+        assert!(on_storefront().await);
+
+        let (status, _) = delete_product(Path(id), State(data.clone()), Extension(Principal::User(admin.clone())), HeaderMap::new())
+            .await
+            .map_err(|_| "delete failed")
+            .unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert!(!on_storefront().await);
+        assert_eq!(admin_ids(&data, ItemState::Deleted).await, [id as i64]);
+        let Err((status, _)) = archive_product_handler(State(data.clone()), Extension(admin.clone()), Path(id)).await else {
+            panic!("a deleted product was archived");
+        };
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let retention = Duration::from_secs(3600);
+        assert_eq!(purge_deleted_products(&db.pool, data.blobs.as_ref(), retention).await.unwrap(), 0);
+        assert_eq!(purge_deleted_products(&db.pool, data.blobs.as_ref(), Duration::ZERO).await.unwrap(), 1);
+        let Err((status, _)) = restore_product_handler(State(data.clone()), Extension(admin), Path(id)).await else {
+            panic!("a purged product was restored");
+        };
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let actions: Vec<String> = sqlx::query_scalar(
+            "SELECT action FROM catalog_revisions WHERE entity = 'product' AND entity_id = $1 ORDER BY id",
+        )
+        .bind(id)
+        .fetch_all(&db.pool)
+        .await
+        .unwrap();
+        assert_eq!(actions, ["created", "archived", "restored", "deleted", "purged"]);
+        db.drop().await;
+    }
+
+    #[tokio::test]
+    async fn purges_deleted_products_with_their_files_unless_granted() {
+        let Some(db) = ScratchDb::migrated().await else {
+            return;
+        };
+        let data = db.state(example_config());
+        sqlx::query("INSERT INTO categories (name) VALUES ('Books')")
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        let user: i32 = sqlx::query_scalar(
+            "INSERT INTO users (name, email, password) VALUES ('Buyer', 'buyer@example.com', '') RETURNING id",
+        )
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+
+        let mut products = Vec::new();
+        for name in ["Unsold", "Sold"] {
+            let id: i32 = sqlx::query_scalar(
+                "INSERT INTO products (name, description, price, category_name, kind, deleted_at) VALUES ($1, '', 1, 'Books', 'digital', NOW() - interval '1 day') RETURNING id",
+            )
+            .bind(name)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+            let key = format!("{}downloads/{}/book", PRIVATE_PREFIX, id);
+            data.blobs.put(&key, "pdf".into()).await.unwrap();
+            sqlx::query(
+                "INSERT INTO product_files (product_id, blob_key, file_name, content_type, size_bytes, sha256) VALUES ($1, $2, 'book.pdf', 'application/pdf', 3, repeat('0', 64))",
+            )
+            .bind(id)
+            .bind(&key)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+            products.push((id, key));
+        }
+        let [(unsold, unsold_file), (sold, sold_file)] = &products[..] else {
+            unreachable!()
+        };
+        sqlx::query(
+            "INSERT INTO download_grants (user_id, product_id, order_reference, max_downloads) VALUES ($1, $2, 'order-1', 3)",
+        )
+        .bind(user)
+        .bind(sold)
+        .execute(&db.pool)
+        .await
+        .unwrap();
+
+        let purged = purge_deleted_products(&db.pool, data.blobs.as_ref(), Duration::from_secs(3600))
+            .await
+            .unwrap();
+
+        assert_eq!(purged, 1);
+        let left: Vec<i32> = sqlx::query_scalar("SELECT id FROM products")
+            .fetch_all(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(left, [*sold], "granted products are kept");
+        assert!(data.blobs.get(unsold_file).await.unwrap().is_none(), "product {}", unsold);
+        assert!(data.blobs.get(sold_file).await.unwrap().is_some());
+        db.drop().await;
+    }
+}
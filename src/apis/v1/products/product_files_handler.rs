@@ -0,0 +1,256 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{multipart::MultipartError, Multipart, Path, State},
+    http::{HeaderValue, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use futures_util::StreamExt;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::apis::downloads::model::ProductFile;
+use crate::apis::login::response::ErrorResponse;
+use crate::apis::v1::lifecycle::NOT_DELETED;
+use crate::blob_store::PRIVATE_PREFIX;
+
+use crate::AppState;
+
+const MAX_FILES: i64 = 20;
+const DEFAULT_CONTENT_TYPE: &str = "application/octet-stream";
+
+type HandlerError = (StatusCode, Json<ErrorResponse>);
+
+struct Upload {
+    file_name: String,
+    content_type: String,
+    size_bytes: i64,
+    sha256: String,
+}
+
+pub async fn list_product_files_handler(
+    State(data): State<Arc<AppState>>,
+    Path(product_id): Path<i32>,
+) -> Result<impl IntoResponse, HandlerError> {
+    ensure_digital(&data.db, product_id).await?;
+    let files: Vec<ProductFile> =
+        sqlx::query_as("SELECT * FROM product_files WHERE product_id = $1 ORDER BY id")
+            .bind(product_id)
+            .fetch_all(&data.db)
+            .await
+            .map_err(database_error)?;
+
+    Ok(Json(json!({
+        "status": "success",
+        "results": files.len(),
+        "data": files,
+    })))
+}
+
+/// Takes a multipart form with a `file` part. The file is stored under the
+/// blob store's private prefix, so `/media` never serves it; buyers fetch it
+/// through a signed download link. It is streamed to the store and hashed on
+/// the way, so it never has to fit in memory.
+pub async fn upload_product_file_handler(
+    State(data): State<Arc<AppState>>,
+    Path(product_id): Path<i32>,
+    mut multipart: Multipart,
+) -> Result<impl IntoResponse, HandlerError> {
+    ensure_digital(&data.db, product_id).await?;
+
+    let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM product_files WHERE product_id = $1")
+        .bind(product_id)
+        .fetch_one(&data.db)
+        .await
+        .map_err(database_error)?;
+    if count >= MAX_FILES {
+        return Err(fail(
+            StatusCode::CONFLICT,
+            &format!("A product can have at most {} files", MAX_FILES),
+        ));
+    }
+
+    let key = format!("{}downloads/{}/{}", PRIVATE_PREFIX, product_id, Uuid::new_v4());
+    let upload = store_upload(&data, &mut multipart, &key).await?;
+    if upload.size_bytes == 0 {
+        remove_blob(&data, &key).await;
+        return Err(fail(StatusCode::BAD_REQUEST, "The file is empty"));
+    }
+
+    let inserted = sqlx::query_as::<_, ProductFile>(
+        r#"INSERT INTO product_files (product_id, blob_key, file_name, content_type, size_bytes, sha256)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        RETURNING *"#,
+    )
+    .bind(product_id)
+    .bind(&key)
+    .bind(&upload.file_name)
+    .bind(&upload.content_type)
+    .bind(upload.size_bytes)
+    .bind(&upload.sha256)
+    .fetch_one(&data.db)
+    .await;
+    let file = match inserted {
+        Ok(file) => file,
+        Err(e) => {
+            remove_blob(&data, &key).await;
+            return Err(match e.as_database_error().and_then(|e| e.code()).as_deref() {
+                Some("23503") => product_not_found(),
+                _ => database_error(e),
+            });
+        }
+    };
+
+    tracing::info!(product_id, file_id = file.id, size_bytes = file.size_bytes, "product file uploaded");
+    Ok((
+        StatusCode::CREATED,
+        Json(json!({"status": "success", "data": file})),
+    ))
+}
+
+/// Links already handed out for the file stop working.
+pub async fn delete_product_file_handler(
+    State(data): State<Arc<AppState>>,
+    Path((product_id, file_id)): Path<(i32, i32)>,
+) -> Result<impl IntoResponse, HandlerError> {
+    let key: String = sqlx::query_scalar(
+        "DELETE FROM product_files WHERE id = $1 AND product_id = $2 RETURNING blob_key",
+    )
+    .bind(file_id)
+    .bind(product_id)
+    .fetch_optional(&data.db)
+    .await
+    .map_err(database_error)?
+    .ok_or_else(|| fail(StatusCode::NOT_FOUND, "File not found"))?;
+    remove_blob(&data, &key).await;
+
+    tracing::info!(product_id, file_id, "product file deleted");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn ensure_digital(db: &PgPool, product_id: i32) -> Result<(), HandlerError> {
+    let digital: Option<bool> = sqlx::query_scalar(&format!(
+        "SELECT kind = 'digital' FROM products WHERE id = $1 AND {}",
+        NOT_DELETED
+    ))
+    .bind(product_id)
+    .fetch_optional(db)
+    .await
+    .map_err(database_error)?;
+    match digital {
+        None => Err(product_not_found()),
+        Some(false) => Err(fail(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Only digital products have downloadable files",
+        )),
+        Some(true) => Ok(()),
+    }
+}
+
+/// Streams the form's `file` part to `key`. Nothing is stored if the upload
+/// fails part way, e.g. because it is larger than the body limit.
+async fn store_upload(data: &AppState, multipart: &mut Multipart, key: &str) -> Result<Upload, HandlerError> {
+    while let Some(field) = multipart.next_field().await.map_err(multipart_error)? {
+        if field.name() != Some("file") {
+            continue;
+        }
+        let file_name = clean_file_name(field.file_name().unwrap_or_default());
+        // Served back as a header, so only well-formed types are kept.
+        let content_type = field
+            .content_type()
+            .filter(|content_type| {
+                content_type.contains('/')
+                    && content_type.len() <= 255
+                    && HeaderValue::from_str(content_type).is_ok()
+            })
+            .unwrap_or(DEFAULT_CONTENT_TYPE)
+            .to_string();
+
+        let mut hasher = Sha256::new();
+        let mut size_bytes = 0;
+        let mut field_error = None;
+        let stream = field.map(|chunk| {
+            let chunk = chunk.map_err(|e| {
+                let error = std::io::Error::other(e.body_text());
+                field_error = Some(e);
+                error
+            })?;
+            hasher.update(&chunk);
+            size_bytes += chunk.len() as i64;
+            Ok(chunk)
+        });
+        let stored = data.blobs.put_stream(key, stream.boxed()).await;
+        if let Some(e) = field_error {
+            return Err(multipart_error(e));
+        }
+        stored.map_err(storage_error)?;
+
+        return Ok(Upload {
+            file_name,
+            content_type,
+            size_bytes,
+            sha256: hex::encode(hasher.finalize()),
+        });
+    }
+    Err(fail(StatusCode::BAD_REQUEST, "The form has no `file` part"))
+}
+
+/// Keeps only the last path segment of the name the client sent, without
+/// control characters.
+fn clean_file_name(file_name: &str) -> String {
+    let base = file_name.rsplit(['/', '\\']).next().unwrap_or_default();
+    let cleaned: String = base
+        .chars()
+        .filter(|c| !c.is_control())
+        .take(255)
+        .collect();
+    let cleaned = cleaned.trim();
+    if cleaned.is_empty() || cleaned == "." || cleaned == ".." {
+        "download".to_string()
+    } else {
+        cleaned.to_string()
+    }
+}
+
+/// Nothing points at the blob any more, so failing to remove it only wastes space.
+async fn remove_blob(data: &AppState, key: &str) {
+    if let Err(e) = data.blobs.delete(key).await {
+        tracing::warn!(error = %e, key, "could not delete blob");
+    }
+}
+
+fn product_not_found() -> HandlerError {
+    fail(StatusCode::NOT_FOUND, "Product not found")
+}
+
+fn multipart_error(e: MultipartError) -> HandlerError {
+    fail(e.status(), &e.body_text())
+}
+
+fn fail(status: StatusCode, message: &str) -> HandlerError {
+    let error_response = ErrorResponse {
+        status: "fail",
+        message: message.to_string(),
+    };
+    (status, Json(error_response))
+}
+
+fn storage_error(e: std::io::Error) -> HandlerError {
+    tracing::error!(error = %e, "could not store blob");
+    let error_response = ErrorResponse {
+        status: "error",
+        message: "Could not store the file".to_string(),
+    };
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+}
+
+fn database_error(e: sqlx::Error) -> HandlerError {
+    let error_response = ErrorResponse {
+        status: "error",
+        message: format!("Database error: {}", e),
+    };
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+}
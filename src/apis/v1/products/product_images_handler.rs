@@ -0,0 +1,416 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use axum::{
+    body::Bytes,
+    extract::{multipart::MultipartError, Multipart, Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use serde_json::json;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::apis::login::response::ErrorResponse;
+use crate::apis::v1::lifecycle::{NOT_DELETED, PUBLISHED};
+use crate::apis::v1::products::{
+    products_model::{ProductImage, ProductImageResponse, UpdateProductImageSchema},
+    thumbnails,
+};
+use crate::blob_store::image_extension;
+
+use crate::AppState;
+
+const MAX_IMAGES: usize = 20;
+const MAX_ALT_TEXT: usize = 255;
+
+type HandlerError = (StatusCode, Json<ErrorResponse>);
+
+struct Upload {
+    file: Bytes,
+    alt_text: String,
+    position: Option<i32>,
+}
+
+struct NewImage {
+    product_id: i32,
+    alt_text: String,
+    key_prefix: String,
+    original_ext: &'static str,
+    thumbnail_ext: &'static str,
+    thumbnail_sizes: Vec<i32>,
+    width: i32,
+    height: i32,
+}
+
+pub async fn list_product_images_handler(
+    State(data): State<Arc<AppState>>,
+    Path(product_id): Path<i32>,
+) -> Result<impl IntoResponse, HandlerError> {
+    ensure_product(&data.db, product_id, PUBLISHED).await?;
+    let images = images_for(&data.db, &[product_id])
+        .await
+        .map_err(database_error)?
+        .remove(&product_id)
+        .unwrap_or_default();
+
+    Ok(Json(json!({
+        "status": "success",
+        "results": images.len(),
+        "data": images,
+    })))
+}
+
+/// Takes a multipart form with a `file` part (PNG, JPEG, GIF or WebP) and
+/// optional `alt_text` and `position` parts. Without a position the image is
+/// added last. Thumbnails are generated before the image is saved.
+pub async fn upload_product_image_handler(
+    State(data): State<Arc<AppState>>,
+    Path(product_id): Path<i32>,
+    mut multipart: Multipart,
+) -> Result<impl IntoResponse, HandlerError> {
+    ensure_product(&data.db, product_id, NOT_DELETED).await?;
+    let upload = read_upload(&mut multipart).await?;
+
+    let original_ext = image_extension(&upload.file).ok_or_else(|| {
+        fail(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "The image must be a PNG, JPEG, GIF or WebP file",
+        )
+    })?;
+
+    let file = upload.file.clone();
+    let sizes = data.config.product_image_thumbnail_sizes.clone();
+    let rendered = tokio::task::spawn_blocking(move || thumbnails::render(&file, &sizes))
+        .await
+        .map_err(|e| {
+            tracing::error!(error = %e, "thumbnail task failed");
+            fail(StatusCode::INTERNAL_SERVER_ERROR, "Could not process the image")
+        })?
+        .map_err(|e| {
+            fail(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                &format!("The image could not be read: {}", e),
+            )
+        })?;
+
+    let key_prefix = format!("products/{}/{}", product_id, Uuid::new_v4());
+    let mut blobs = vec![(format!("{}/original.{}", key_prefix, original_ext), upload.file)];
+    for (size, bytes) in rendered.images {
+        blobs.push((
+            format!("{}/{}.{}", key_prefix, size, rendered.extension),
+            Bytes::from(bytes),
+        ));
+    }
+    let keys: Vec<String> = blobs.iter().map(|(key, _)| key.clone()).collect();
+
+    for (key, bytes) in blobs {
+        if let Err(e) = data.blobs.put(&key, bytes).await {
+            remove_blobs(&data, &keys).await;
+            return Err(storage_error(e));
+        }
+    }
+
+    let image = NewImage {
+        product_id,
+        alt_text: upload.alt_text,
+        key_prefix,
+        original_ext,
+        thumbnail_ext: rendered.extension,
+        thumbnail_sizes: data
+            .config
+            .product_image_thumbnail_sizes
+            .iter()
+            .map(|&size| size as i32)
+            .collect(),
+        width: rendered.width as i32,
+        height: rendered.height as i32,
+    };
+
+    match insert_image(&data.db, image, upload.position).await {
+        Ok(image) => {
+            tracing::info!(product_id, image_id = image.id, "product image uploaded");
+            Ok((
+                StatusCode::CREATED,
+                Json(json!({ "status": "success", "data": image.to_response() })),
+            ))
+        }
+        Err(e) => {
+            remove_blobs(&data, &keys).await;
+            Err(e)
+        }
+    }
+}
+
+/// Changes the alt text and/or moves the image to another position.
+pub async fn update_product_image_handler(
+    State(data): State<Arc<AppState>>,
+    Path((product_id, image_id)): Path<(i32, i32)>,
+    Json(body): Json<UpdateProductImageSchema>,
+) -> Result<impl IntoResponse, HandlerError> {
+    let alt_text = body.alt_text.map(validate_alt_text).transpose()?;
+
+    let mut tx = data.db.begin().await.map_err(database_error)?;
+    let mut order = lock_images(&mut tx, product_id).await?;
+    let Some(current) = order.iter().position(|&id| id == image_id) else {
+        return Err(image_not_found());
+    };
+
+    if let Some(position) = body.position {
+        if position < 0 {
+            return Err(fail(StatusCode::BAD_REQUEST, "position must not be negative"));
+        }
+        let id = order.remove(current);
+        order.insert((position as usize).min(order.len()), id);
+        write_positions(&mut tx, &order).await?;
+    }
+
+    let image: ProductImage = sqlx::query_as(
+        "UPDATE product_images SET alt_text = COALESCE($2, alt_text) WHERE id = $1 RETURNING *",
+    )
+    .bind(image_id)
+    .bind(&alt_text)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(database_error)?;
+
+    tx.commit().await.map_err(database_error)?;
+
+    Ok(Json(json!({ "status": "success", "data": image.to_response() })))
+}
+
+pub async fn delete_product_image_handler(
+    State(data): State<Arc<AppState>>,
+    Path((product_id, image_id)): Path<(i32, i32)>,
+) -> Result<impl IntoResponse, HandlerError> {
+    let mut tx = data.db.begin().await.map_err(database_error)?;
+    let mut order = lock_images(&mut tx, product_id).await?;
+    let Some(current) = order.iter().position(|&id| id == image_id) else {
+        return Err(image_not_found());
+    };
+
+    let image: ProductImage = sqlx::query_as("DELETE FROM product_images WHERE id = $1 RETURNING *")
+        .bind(image_id)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(database_error)?;
+    order.remove(current);
+    write_positions(&mut tx, &order).await?;
+
+    tx.commit().await.map_err(database_error)?;
+
+    remove_blobs(&data, &image.keys()).await;
+    tracing::info!(product_id, image_id, "product image deleted");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Images of each of `product_ids`, in display order.
+pub async fn images_for(
+    db: &PgPool,
+    product_ids: &[i32],
+) -> Result<HashMap<i32, Vec<ProductImageResponse>>, sqlx::Error> {
+    let images: Vec<ProductImage> = sqlx::query_as(
+        "SELECT * FROM product_images WHERE product_id = ANY($1) ORDER BY product_id, position",
+    )
+    .bind(product_ids)
+    .fetch_all(db)
+    .await?;
+
+    let mut by_product: HashMap<i32, Vec<ProductImageResponse>> = HashMap::new();
+    for image in images {
+        by_product
+            .entry(image.product_id)
+            .or_default()
+            .push(image.to_response());
+    }
+    Ok(by_product)
+}
+
+async fn read_upload(multipart: &mut Multipart) -> Result<Upload, HandlerError> {
+    let mut file = None;
+    let mut alt_text = String::new();
+    let mut position = None;
+
+    while let Some(field) = multipart.next_field().await.map_err(multipart_error)? {
+        match field.name() {
+            Some("file") => file = Some(field.bytes().await.map_err(multipart_error)?),
+            Some("alt_text") => {
+                alt_text = validate_alt_text(field.text().await.map_err(multipart_error)?)?
+            }
+            Some("position") => {
+                let value = field.text().await.map_err(multipart_error)?;
+                match value.trim().parse::<i32>() {
+                    Ok(value) if value >= 0 => position = Some(value),
+                    _ => {
+                        return Err(fail(
+                            StatusCode::BAD_REQUEST,
+                            "position must be a non-negative number",
+                        ))
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let file = file.ok_or_else(|| fail(StatusCode::BAD_REQUEST, "The form has no `file` part"))?;
+    Ok(Upload {
+        file,
+        alt_text,
+        position,
+    })
+}
+
+async fn insert_image(
+    db: &PgPool,
+    image: NewImage,
+    position: Option<i32>,
+) -> Result<ProductImage, HandlerError> {
+    let mut tx = db.begin().await.map_err(database_error)?;
+    let mut order = lock_images(&mut tx, image.product_id).await?;
+    if order.len() >= MAX_IMAGES {
+        return Err(fail(
+            StatusCode::CONFLICT,
+            &format!("A product can have at most {} images", MAX_IMAGES),
+        ));
+    }
+
+    let mut inserted: ProductImage = sqlx::query_as(
+        r#"INSERT INTO product_images
+            (product_id, position, alt_text, key_prefix, original_ext, thumbnail_ext, thumbnail_sizes, width, height)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+        RETURNING *"#,
+    )
+    .bind(image.product_id)
+    .bind(order.len() as i32)
+    .bind(&image.alt_text)
+    .bind(&image.key_prefix)
+    .bind(image.original_ext)
+    .bind(image.thumbnail_ext)
+    .bind(&image.thumbnail_sizes)
+    .bind(image.width)
+    .bind(image.height)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(database_error)?;
+
+    if let Some(position) = position.filter(|&position| (position as usize) < order.len()) {
+        order.insert(position as usize, inserted.id);
+        write_positions(&mut tx, &order).await?;
+        inserted.position = position;
+    }
+
+    tx.commit().await.map_err(database_error)?;
+    Ok(inserted)
+}
+
+/// Locks the product so concurrent changes to its images apply one at a
+/// time, and returns the ids of its images in display order.
+async fn lock_images(
+    tx: &mut Transaction<'_, Postgres>,
+    product_id: i32,
+) -> Result<Vec<i32>, HandlerError> {
+    let product: Option<i32> = sqlx::query_scalar("SELECT id FROM products WHERE id = $1 AND deleted_at IS NULL FOR UPDATE")
+        .bind(product_id)
+        .fetch_optional(&mut **tx)
+        .await
+        .map_err(database_error)?;
+    if product.is_none() {
+        return Err(product_not_found());
+    }
+
+    sqlx::query_scalar("SELECT id FROM product_images WHERE product_id = $1 ORDER BY position")
+        .bind(product_id)
+        .fetch_all(&mut **tx)
+        .await
+        .map_err(database_error)
+}
+
+/// Numbers the images from zero in the given order.
+async fn write_positions(
+    tx: &mut Transaction<'_, Postgres>,
+    order: &[i32],
+) -> Result<(), HandlerError> {
+    sqlx::query(
+        r#"UPDATE product_images AS image SET position = (o.position - 1)::INTEGER
+        FROM UNNEST($1::INTEGER[]) WITH ORDINALITY AS o(id, position)
+        WHERE image.id = o.id"#,
+    )
+    .bind(order)
+    .execute(&mut **tx)
+    .await
+    .map_err(database_error)?;
+    Ok(())
+}
+
+/// `condition` is one of the [`lifecycle`](crate::apis::v1::lifecycle) states.
+async fn ensure_product(db: &PgPool, product_id: i32, condition: &str) -> Result<(), HandlerError> {
+    let exists: bool = sqlx::query_scalar(&format!("SELECT EXISTS (SELECT 1 FROM products WHERE id = $1 AND {})", condition))
+        .bind(product_id)
+        .fetch_one(db)
+        .await
+        .map_err(database_error)?;
+    if exists {
+        Ok(())
+    } else {
+        Err(product_not_found())
+    }
+}
+
+/// Nothing points at the blobs any more, so failing to remove them only wastes space.
+async fn remove_blobs(data: &AppState, keys: &[String]) {
+    for key in keys {
+        if let Err(e) = data.blobs.delete(key).await {
+            tracing::warn!(error = %e, key, "could not delete blob");
+        }
+    }
+}
+
+fn validate_alt_text(alt_text: String) -> Result<String, HandlerError> {
+    let alt_text = alt_text.trim();
+    if alt_text.chars().count() > MAX_ALT_TEXT {
+        return Err(fail(
+            StatusCode::BAD_REQUEST,
+            &format!("alt_text must be at most {} characters", MAX_ALT_TEXT),
+        ));
+    }
+    Ok(alt_text.to_string())
+}
+
+fn product_not_found() -> HandlerError {
+    fail(StatusCode::NOT_FOUND, "Product not found")
+}
+
+fn image_not_found() -> HandlerError {
+    fail(StatusCode::NOT_FOUND, "Image not found")
+}
+
+fn multipart_error(e: MultipartError) -> HandlerError {
+    fail(e.status(), &e.body_text())
+}
+
+fn fail(status: StatusCode, message: &str) -> HandlerError {
+    let error_response = ErrorResponse {
+        status: "fail",
+        message: message.to_string(),
+    };
+    (status, Json(error_response))
+}
+
+fn storage_error(e: std::io::Error) -> HandlerError {
+    tracing::error!(error = %e, "could not store blob");
+    let error_response = ErrorResponse {
+        status: "error",
+        message: "Could not store the file".to_string(),
+    };
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+}
+
+fn database_error(e: sqlx::Error) -> HandlerError {
+    let error_response = ErrorResponse {
+        status: "error",
+        message: format!("Database error: {}", e),
+    };
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+}
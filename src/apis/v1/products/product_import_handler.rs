@@ -0,0 +1,492 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use axum::{
+    body::{Bytes, StreamBody},
+    extract::{Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::IntoResponse,
+    Extension, Json,
+};
+use futures_util::{Stream, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::PgPool;
+
+use crate::apis::jwt_auth::Principal;
+use crate::apis::login::response::ErrorResponse;
+use crate::apis::v1::revisions::attribute;
+use crate::apis::v1::products::products_model::{ExportRow, ImportRow};
+
+use crate::AppState;
+
+type HandlerError = (StatusCode, Json<ErrorResponse>);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileFormat {
+    Csv,
+    Jsonl,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ImportQuery {
+    /// Defaults to the request's `Content-Type`.
+    pub format: Option<FileFormat>,
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExportQuery {
+    pub format: Option<FileFormat>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RowError {
+    /// Line number in the uploaded file.
+    pub line: u64,
+    pub sku: Option<String>,
+    pub message: String,
+}
+
+/// Creates or updates products from a CSV file (with a header row) or from
+/// JSON Lines, matching on `sku`. Every row is validated first; if any row is
+/// invalid nothing is written and all problems are reported. With
+/// `dry_run=true` the upsert runs and is rolled back, so the counts are exact.
+pub async fn import_products_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(principal): Extension<Principal>,
+    Query(query): Query<ImportQuery>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<impl IntoResponse, HandlerError> {
+    let format = match query.format.or_else(|| format_from_content_type(&headers)) {
+        Some(format) => format,
+        None => {
+            return Err(fail(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "Send `text/csv` or `application/x-ndjson`, or pass `format=csv|jsonl`",
+            ))
+        }
+    };
+
+    let parsed = match format {
+        FileFormat::Csv => parse_csv(&body),
+        FileFormat::Jsonl => parse_jsonl(&body),
+    }
+    .map_err(|e| fail(StatusCode::BAD_REQUEST, &e))?;
+
+    let categories: HashSet<String> = sqlx::query_scalar("SELECT name FROM categories WHERE deleted_at IS NULL")
+        .fetch_all(&data.db)
+        .await
+        .map_err(database_error)?
+        .into_iter()
+        .collect();
+    // Their rows keep the SKU until they are purged, so they cannot be updated or recreated.
+    let deleted_skus: HashSet<String> =
+        sqlx::query_scalar("SELECT sku FROM products WHERE deleted_at IS NOT NULL AND sku IS NOT NULL")
+            .fetch_all(&data.db)
+            .await
+            .map_err(database_error)?
+            .into_iter()
+            .collect();
+
+    let mut rows = Vec::with_capacity(parsed.len());
+    let mut errors = Vec::new();
+    let mut seen = HashSet::new();
+    for ParsedRow { line, sku, row } in parsed {
+        let sku = sku.map(|sku| sku.trim().to_string());
+        let row = row.and_then(ImportRow::validate).and_then(|row| {
+            let row_sku = row.sku.clone().unwrap_or_default();
+            if !categories.contains(&row.category_name) {
+                Err(format!("unknown category `{}`", row.category_name))
+            } else if deleted_skus.contains(&row_sku) {
+                Err(format!("sku `{}` belongs to a deleted product; restore it first", row_sku))
+            } else if !seen.insert(row_sku.clone()) {
+                Err(format!("sku `{}` appears more than once", row_sku))
+            } else {
+                Ok(row)
+            }
+        });
+        match row {
+            Ok(row) => rows.push(row),
+            Err(message) => errors.push(RowError { line, sku, message }),
+        }
+    }
+
+    if !errors.is_empty() {
+        let json_response = json!({
+            "status": "fail",
+            "message": format!("{} row(s) have errors; nothing was imported", errors.len()),
+            "errors": errors,
+        });
+        return Ok((StatusCode::UNPROCESSABLE_ENTITY, Json(json_response)));
+    }
+
+    let mut tx = data.db.begin().await.map_err(database_error)?;
+    attribute(&mut tx, Some(&principal), "import")
+        .await
+        .map_err(database_error)?;
+    let inserted: Vec<bool> = sqlx::query_scalar(
+        r#"INSERT INTO products (sku, name, description, price, category_name, available)
+        SELECT * FROM UNNEST($1::VARCHAR[], $2::VARCHAR[], $3::TEXT[], $4::DOUBLE PRECISION[], $5::VARCHAR[], $6::BOOLEAN[])
+        ON CONFLICT (sku) DO UPDATE SET
+            name = EXCLUDED.name,
+            description = EXCLUDED.description,
+            price = EXCLUDED.price,
+            category_name = EXCLUDED.category_name,
+            available = EXCLUDED.available,
+            -- Attributes belong to a category and do not survive a move to another.
+            attributes = CASE WHEN products.category_name = EXCLUDED.category_name
+                THEN products.attributes ELSE '{}' END,
+            version = products.version + 1
+        RETURNING xmax = 0"#,
+    )
+    .bind(rows.iter().map(|row| row.sku.as_deref()).collect::<Vec<_>>())
+    .bind(rows.iter().map(|row| row.name.as_str()).collect::<Vec<_>>())
+    .bind(
+        rows.iter()
+            .map(|row| row.description.as_str())
+            .collect::<Vec<_>>(),
+    )
+    .bind(rows.iter().map(|row| row.price).collect::<Vec<_>>())
+    .bind(rows.iter().map(|row| row.category_name.as_str()).collect::<Vec<_>>())
+    .bind(rows.iter().map(|row| row.available.unwrap_or(true)).collect::<Vec<_>>())
+    .fetch_all(&mut *tx)
+    .await
+    .map_err(database_error)?;
+
+    if query.dry_run {
+        tx.rollback().await.map_err(database_error)?;
+    } else {
+        tx.commit().await.map_err(database_error)?;
+    }
+
+    let created = inserted.iter().filter(|&&inserted| inserted).count();
+    let updated = inserted.len() - created;
+    tracing::info!(%principal, rows = rows.len(), created, updated, dry_run = query.dry_run, "products imported");
+
+    let json_response = json!({
+        "status": "success",
+        "dry_run": query.dry_run,
+        "rows": rows.len(),
+        "created": created,
+        "updated": updated,
+    });
+    Ok((StatusCode::OK, Json(json_response)))
+}
+
+/// Streams the whole catalog, archived products included, as CSV (default) or JSON Lines, one row at a
+/// time, in a format [`import_products_handler`] accepts.
+pub async fn export_products_handler(
+    State(data): State<Arc<AppState>>,
+    Query(query): Query<ExportQuery>,
+) -> impl IntoResponse {
+    let format = query.format.unwrap_or(FileFormat::Csv);
+    let (content_type, extension) = match format {
+        FileFormat::Csv => ("text/csv; charset=utf-8", "csv"),
+        FileFormat::Jsonl => ("application/x-ndjson", "jsonl"),
+    };
+    let disposition = format!("attachment; filename=\"products.{}\"", extension);
+
+    (
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        StreamBody::new(export_stream(data.db.clone(), format)),
+    )
+}
+
+/// Reads the products with a cursor, so memory use does not grow with the catalog.
+fn export_stream(
+    db: PgPool,
+    format: FileFormat,
+) -> impl Stream<Item = std::io::Result<Bytes>> + Send + 'static {
+    async_stream::try_stream! {
+        if format == FileFormat::Csv {
+            yield Bytes::from(csv_line(ExportRow::COLUMNS)?);
+        }
+
+        let mut rows = sqlx::query_as::<_, ExportRow>(
+            "SELECT id, sku, name, description, price, category_name, available FROM products WHERE deleted_at IS NULL ORDER BY id",
+        )
+        .fetch(&db);
+
+        while let Some(row) = rows.try_next().await.map_err(std::io::Error::other)? {
+            let line = match format {
+                FileFormat::Csv => csv_line(&row)?,
+                FileFormat::Jsonl => {
+                    let mut line = serde_json::to_vec(&row)?;
+                    line.push(b'\n');
+                    line
+                }
+            };
+            yield Bytes::from(line);
+        }
+    }
+}
+
+fn csv_line<T: Serialize>(record: T) -> std::io::Result<Vec<u8>> {
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(Vec::new());
+    writer.serialize(record)?;
+    writer.into_inner().map_err(|e| e.into_error())
+}
+
+fn format_from_content_type(headers: &HeaderMap) -> Option<FileFormat> {
+    let content_type = headers.get(header::CONTENT_TYPE)?.to_str().ok()?;
+    let mime = content_type.split(';').next()?.trim();
+    match mime {
+        "text/csv" => Some(FileFormat::Csv),
+        "application/x-ndjson" | "application/jsonl" | "application/x-jsonlines" => {
+            Some(FileFormat::Jsonl)
+        }
+        _ => None,
+    }
+}
+
+struct ParsedRow {
+    line: u64,
+    /// Read separately so rows that fail to parse can still be identified.
+    sku: Option<String>,
+    row: Result<ImportRow, String>,
+}
+
+type ParsedRows = Vec<ParsedRow>;
+
+/// Only a malformed header fails the whole file; bad rows are reported per line.
+fn parse_csv(body: &[u8]) -> Result<ParsedRows, String> {
+    let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::Headers).from_reader(body);
+    let headers = reader
+        .headers()
+        .map_err(|e| format!("Could not read the header row: {}", e))?
+        .clone();
+
+    for column in headers.iter() {
+        if !ImportRow::COLUMNS.contains(&column) {
+            return Err(format!("Unknown column `{}`", column));
+        }
+    }
+
+    let sku_column = headers.iter().position(|column| column == "sku");
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        let parsed = match record {
+            Ok(record) => ParsedRow {
+                line: record.position().map_or(0, |position| position.line()),
+                sku: sku_column.and_then(|index| record.get(index)).map(str::to_string),
+                row: record
+                    .deserialize::<ImportRow>(Some(&headers))
+                    .map_err(|e| csv_message(&e, &headers)),
+            },
+            Err(e) => ParsedRow {
+                line: e.position().map_or(0, |position| position.line()),
+                sku: None,
+                row: Err(csv_message(&e, &headers)),
+            },
+        };
+        rows.push(parsed);
+    }
+    Ok(rows)
+}
+
+fn parse_jsonl(body: &[u8]) -> Result<ParsedRows, String> {
+    let text = std::str::from_utf8(body).map_err(|_| "The file is not valid UTF-8".to_string())?;
+    Ok(text
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| ParsedRow {
+            line: index as u64 + 1,
+            sku: serde_json::from_str::<serde_json::Value>(line)
+                .ok()
+                .and_then(|value| value.get("sku")?.as_str().map(str::to_string)),
+            // Each document is a single line, so only the column is worth reporting.
+            row: serde_json::from_str::<ImportRow>(line).map_err(|e| {
+                let message = e.to_string();
+                let message = message.rsplit_once(" at line ").map_or(&*message, |(m, _)| m);
+                format!("{} at column {}", message, e.column())
+            }),
+        })
+        .collect())
+}
+
+/// The csv crate's messages repeat the position, which we report separately.
+fn csv_message(e: &csv::Error, headers: &csv::StringRecord) -> String {
+    match e.kind() {
+        csv::ErrorKind::Deserialize { err, .. } => {
+            match err.field().and_then(|field| headers.get(field as usize)) {
+                Some(column) => format!("{}: {}", column, err.kind()),
+                None => err.kind().to_string(),
+            }
+        }
+        csv::ErrorKind::UnequalLengths { expected_len, len, .. } => {
+            format!("expected {} fields, found {}", expected_len, len)
+        }
+        _ => e.to_string(),
+    }
+}
+
+fn fail(status: StatusCode, message: &str) -> HandlerError {
+    let error_response = ErrorResponse {
+        status: "fail",
+        message: message.to_string(),
+    };
+    (status, Json(error_response))
+}
+
+fn database_error(e: sqlx::Error) -> HandlerError {
+    let error_response = ErrorResponse {
+        status: "error",
+        message: format!("Database error: {}", e),
+    };
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apis::v1::products::product_admin_routes::product_admin_router;
+    use crate::test_support::{example_config, serve, session_token, ScratchDb};
+
+    fn errors(rows: &ParsedRows) -> Vec<(u64, Option<&str>, &str)> {
+        rows.iter()
+            .filter_map(|row| {
+                let message = row.row.as_ref().err()?;
+                Some((row.line, row.sku.as_deref(), message.as_str()))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reads_csv_rows_and_reports_bad_ones_by_line() {
+        let rows = parse_csv(
+            b"sku, name ,price,category_name,available\n\
+              A-1,Lamp,12.5,Home,true\n\
+              A-2,Chair,cheap,Home,\n\
+              A-3,Desk\n",
+        )
+        .unwrap();
+
+        assert_eq!(rows.len(), 3);
+        let lamp = rows[0].row.as_ref().unwrap();
+        assert_eq!((lamp.sku.as_str(), lamp.price, lamp.available), ("A-1", 12.5, Some(true)));
+        assert_eq!(
+            errors(&rows),
+            [
+                (3, Some("A-2"), "price: invalid float literal"),
+                (4, None, "expected 5 fields, found 2"),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_a_csv_file_with_an_unknown_column() {
+        let Err(message) = parse_csv(b"sku,name,colour\n") else {
+            panic!("the header was accepted");
+        };
+        assert_eq!(message, "Unknown column `colour`");
+    }
+
+    #[test]
+    fn reads_json_lines_and_skips_blank_ones() {
+        let rows = parse_jsonl(
+            br#"{"sku": "A-1", "name": "Lamp", "price": 12.5, "category_name": "Home"}
+
+{"sku": "A-2", "name": "Chair", "price": 3, "category_name": "Home", "colour": "red"}
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(rows.len(), 2);
+        assert!(rows[0].row.is_ok());
+        let (line, sku, message) = errors(&rows)[0];
+        assert_eq!((line, sku), (3, Some("A-2")));
+        assert!(message.starts_with("unknown field `colour`"), "{}", message);
+        assert!(message.contains(" at column "), "{}", message);
+        assert!(parse_jsonl(b"\xff").is_err());
+    }
+
+    #[test]
+    fn validates_rows_like_new_products() {
+        let row = |sku: &str, name: &str, price: f64| ImportRow {
+            sku: sku.to_string(),
+            name: name.to_string(),
+            description: None,
+            price,
+            category_name: "Home".to_string(),
+            available: None,
+            _id: None,
+        };
+
+        assert!(row("A-1", "Lamp", 12.5).validate().is_ok());
+        assert!(row("A-1", "", 12.5).validate().is_err());
+        assert!(row("A-1", "Lamp", -1.0).validate().is_err());
+    }
+
+    #[test]
+    fn picks_the_format_from_the_content_type() {
+        let headers = |content_type: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::CONTENT_TYPE, content_type.parse().unwrap());
+            headers
+        };
+
+        assert_eq!(format_from_content_type(&headers("text/csv; charset=utf-8")), Some(FileFormat::Csv));
+        assert_eq!(format_from_content_type(&headers("application/x-ndjson")), Some(FileFormat::Jsonl));
+        assert_eq!(format_from_content_type(&headers("application/json")), None);
+        assert_eq!(format_from_content_type(&HeaderMap::new()), None);
+    }
+
+    #[tokio::test]
+    async fn lets_api_keys_import_and_export_within_their_scopes() {
+        let Some(db) = ScratchDb::migrated().await else {
+            return;
+        };
+        let data = db.state(example_config());
+        let url = serve(product_admin_router(data.clone())).await;
+        sqlx::query("INSERT INTO categories (name) VALUES ('Home')")
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        let customer = db.user("customer@example.com", "password", "user").await;
+        let (_, reader) = db.api_key(&["catalog:read"]).await;
+        let (writer_id, writer) = db.api_key(&["catalog:write"]).await;
+
+        let client = reqwest::Client::new();
+        let import = |authorization: String| {
+            client
+                .post(format!("{}/admin/products/import", url))
+                .header("authorization", authorization)
+                .header("content-type", "text/csv")
+                .body("sku,name,price,category_name\nA-1,Lamp,12.5,Home\n")
+                .send()
+        };
+        let export = |authorization: String| {
+            client
+                .get(format!("{}/admin/products/export", url))
+                .header("authorization", authorization)
+                .send()
+        };
+
+        assert_eq!(import(format!("ApiKey {}", reader)).await.unwrap().status(), 403);
+        let customer_token = session_token(&data, customer, "user");
+        assert_eq!(import(format!("Bearer {}", customer_token)).await.unwrap().status(), 403);
+        assert_eq!(import(format!("ApiKey {}", writer)).await.unwrap().status(), 200);
+
+        let response = export(format!("ApiKey {}", reader)).await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert!(response.text().await.unwrap().contains("A-1,Lamp,"));
+        assert_eq!(export(format!("ApiKey {}", writer)).await.unwrap().status(), 403);
+
+        let actor: String = sqlx::query_scalar("SELECT actor FROM catalog_revisions WHERE reason = 'import'")
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(actor, format!("api_key:{}", writer_id));
+        db.drop().await;
+    }
+}
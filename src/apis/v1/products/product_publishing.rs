@@ -0,0 +1,200 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::PgPool;
+
+use crate::apis::login::response::ErrorResponse;
+use crate::apis::v1::products::products_model::ProductStatus;
+use crate::apis::v1::revisions::attribute;
+
+use crate::AppState;
+
+const MAX_EVENTS: i64 = 1000;
+
+type HandlerError = (StatusCode, Json<ErrorResponse>);
+
+/// A product status change, recorded by the `products_status_change` trigger.
+#[derive(Debug, sqlx::FromRow, Serialize)]
+pub struct ProductEvent {
+    pub id: i64,
+    pub product_id: i32,
+    #[sqlx(try_from = "String")]
+    pub status: ProductStatus,
+    /// `None` when the product was created with `status`.
+    pub previous_status: Option<String>,
+    pub occurred_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EventsQuery {
+    /// Only events with a larger id; pass the previous response's `next`.
+    #[serde(default)]
+    pub after: i64,
+    pub limit: Option<i64>,
+}
+
+/// Publishes and unpublishes the products whose `publish_at` or
+/// `unpublish_at` has passed, clearing the timestamp that fired. Returns how
+/// many products were published and unpublished.
+pub async fn run_due_transitions(db: &PgPool) -> Result<(u64, u64), sqlx::Error> {
+    let mut tx = db.begin().await?;
+    attribute(&mut tx, None, "schedule").await?;
+
+    let published = sqlx::query(
+        r#"UPDATE products SET status = 'published', publish_at = NULL, version = version + 1
+        WHERE publish_at <= NOW() AND status <> 'published' AND deleted_at IS NULL"#,
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+
+    // After publishing, so a product whose whole window has passed ends up unpublished.
+    let unpublished = sqlx::query(
+        r#"UPDATE products SET status = 'unpublished', unpublish_at = NULL, version = version + 1
+        WHERE unpublish_at <= NOW() AND status = 'published' AND deleted_at IS NULL"#,
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+
+    tx.commit().await?;
+    Ok((published, unpublished))
+}
+
+/// Status changes in the order they happened, for consumers that poll rather
+/// than `LISTEN` on the `product_events` channel.
+pub async fn list_product_events_handler(
+    State(data): State<Arc<AppState>>,
+    Query(query): Query<EventsQuery>,
+) -> Result<impl IntoResponse, HandlerError> {
+    let limit = query.limit.unwrap_or(100).clamp(1, MAX_EVENTS);
+    let events: Vec<ProductEvent> =
+        sqlx::query_as("SELECT * FROM product_events WHERE id > $1 ORDER BY id LIMIT $2")
+            .bind(query.after)
+            .bind(limit)
+            .fetch_all(&data.db)
+            .await
+            .map_err(database_error)?;
+
+    let next = events.last().map_or(query.after, |event| event.id);
+    Ok(Json(json!({
+        "status": "success",
+        "results": events.len(),
+        "next": next,
+        "data": events,
+    })))
+}
+
+fn database_error(e: sqlx::Error) -> HandlerError {
+    let error_response = ErrorResponse {
+        status: "error",
+        message: format!("Database error: {}", e),
+    };
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+    use crate::apis::v1::products::products_model::Product;
+    use crate::test_support::{example_config, json_response, ScratchDb};
+
+    #[test]
+    fn reads_stored_statuses() {
+        assert_eq!(ProductStatus::try_from("unpublished".to_string()), Ok(ProductStatus::Unpublished));
+        assert!(ProductStatus::try_from("archived".to_string()).is_err());
+    }
+
+    #[tokio::test]
+    async fn publishes_and_unpublishes_products_that_are_due() {
+        let Some(db) = ScratchDb::migrated().await else {
+            return;
+        };
+        sqlx::query("INSERT INTO categories (name) VALUES ('Books')")
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        let mut ids = Vec::new();
+        for (name, status, publish_at, unpublish_at, deleted) in [
+            ("due", "draft", Some("-1 hour"), None, false),
+            ("ending", "published", None, Some("-1 hour"), false),
+            ("window passed", "draft", Some("-2 hours"), Some("-1 hour"), false),
+            ("later", "draft", Some("1 hour"), None, false),
+            ("deleted", "draft", Some("-1 hour"), None, true),
+        ] {
+            let id: i32 = sqlx::query_scalar(
+                r#"INSERT INTO products (name, description, price, category_name, status, publish_at, unpublish_at, deleted_at)
+                VALUES ($1, '', 1, 'Books', $2, NOW() + $3::INTERVAL, NOW() + $4::INTERVAL, CASE WHEN $5 THEN NOW() END)
+                RETURNING id"#,
+            )
+            .bind(name)
+            .bind(status)
+            .bind(publish_at)
+            .bind(unpublish_at)
+            .bind(deleted)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+            ids.push(id);
+        }
+
+        assert_eq!(run_due_transitions(&db.pool).await.unwrap(), (2, 2));
+        assert_eq!(run_due_transitions(&db.pool).await.unwrap(), (0, 0), "nothing is due twice");
+
+        let products: Vec<Product> = sqlx::query_as("SELECT * FROM products ORDER BY id")
+            .fetch_all(&db.pool)
+            .await
+            .unwrap();
+        let statuses: Vec<_> = products.iter().map(|product| product.status).collect();
+        assert_eq!(
+            statuses,
+            [
+                ProductStatus::Published,
+                ProductStatus::Unpublished,
+                ProductStatus::Unpublished,
+                ProductStatus::Draft,
+                ProductStatus::Draft,
+            ]
+        );
+        assert!(products[..3]
+            .iter()
+            .all(|product| product.publish_at.is_none() && product.unpublish_at.is_none()));
+        assert!(products[3].publish_at.is_some());
+
+        let data = db.state(example_config());
+        let response = list_product_events_handler(
+            State(data),
+            Query(EventsQuery { after: 0, limit: None }),
+        )
+        .await
+        .map_err(|(status, _)| status)
+        .unwrap();
+        let (_, body) = json_response(response.into_response()).await;
+        let passed: Vec<_> = body["data"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|event| event["product_id"] == ids[2])
+            .map(|event| (event["previous_status"].clone(), event["status"].clone()))
+            .collect();
+        assert_eq!(
+            passed,
+            [
+                (Value::Null, json!("draft")),
+                (json!("draft"), json!("published")),
+                (json!("published"), json!("unpublished")),
+            ]
+        );
+        db.drop().await;
+    }
+}
@@ -0,0 +1,288 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use serde_json::json;
+use sqlx::{PgConnection, PgPool};
+
+use crate::apis::login::response::ErrorResponse;
+use crate::apis::v1::lifecycle::{NOT_DELETED, PUBLISHED};
+use crate::apis::v1::products::products_model::{
+    Bundle, BundleItem, ProductSummary, RelatedProduct, RelationKind, UpdateBundleSchema,
+    UpdateRelationsSchema,
+};
+
+use crate::AppState;
+
+type HandlerError = (StatusCode, Json<ErrorResponse>);
+
+/// Per relation kind, and per bundle.
+const MAX_LINKED: usize = 50;
+const MAX_QUANTITY: i32 = 1000;
+
+/// Every relation of the product, including links to products the storefront
+/// does not show.
+pub async fn list_relations_handler(
+    State(data): State<Arc<AppState>>,
+    Path(id): Path<i32>,
+) -> Result<impl IntoResponse, HandlerError> {
+    let mut conn = data.db.acquire().await.map_err(database_error)?;
+    lock_product(&mut conn, id, false).await?;
+    let relations = relations_for(&data.db, id, NOT_DELETED)
+        .await
+        .map_err(database_error)?;
+
+    Ok(Json(json!({"status": "success", "data": relations})))
+}
+
+pub async fn update_relations_handler(
+    State(data): State<Arc<AppState>>,
+    Path((id, kind)): Path<(i32, RelationKind)>,
+    Json(body): Json<UpdateRelationsSchema>,
+) -> Result<impl IntoResponse, HandlerError> {
+    let ids = body.product_ids;
+    check_links(id, &ids)?;
+
+    let mut tx = data.db.begin().await.map_err(database_error)?;
+    lock_product(&mut tx, id, true).await?;
+    ensure_products(&mut tx, &ids).await?;
+
+    sqlx::query("DELETE FROM product_relations WHERE product_id = $1 AND kind = $2")
+        .bind(id)
+        .bind(kind.as_str())
+        .execute(&mut *tx)
+        .await
+        .map_err(database_error)?;
+    sqlx::query(
+        r#"INSERT INTO product_relations (product_id, kind, related_id, position)
+        SELECT $1, $2, related_id, position::INTEGER - 1
+        FROM UNNEST($3::INTEGER[]) WITH ORDINALITY AS t (related_id, position)"#,
+    )
+    .bind(id)
+    .bind(kind.as_str())
+    .bind(&ids)
+    .execute(&mut *tx)
+    .await
+    .map_err(database_error)?;
+    tx.commit().await.map_err(database_error)?;
+
+    let relations = relations_for(&data.db, id, NOT_DELETED)
+        .await
+        .map_err(database_error)?;
+    tracing::info!(product_id = id, kind = kind.as_str(), count = ids.len(), "product relations updated");
+    Ok(Json(json!({"status": "success", "data": relations})))
+}
+
+pub async fn get_bundle_handler(
+    State(data): State<Arc<AppState>>,
+    Path(id): Path<i32>,
+) -> Result<impl IntoResponse, HandlerError> {
+    let mut conn = data.db.acquire().await.map_err(database_error)?;
+    lock_product(&mut conn, id, false).await?;
+    let bundle = bundle_for(&data.db, id).await.map_err(database_error)?;
+
+    Ok(Json(json!({"status": "success", "data": bundle})))
+}
+
+/// Bundles cannot nest: a component may not be a bundle itself, and a product
+/// that is part of a bundle cannot become one.
+pub async fn update_bundle_handler(
+    State(data): State<Arc<AppState>>,
+    Path(id): Path<i32>,
+    Json(body): Json<UpdateBundleSchema>,
+) -> Result<impl IntoResponse, HandlerError> {
+    let ids: Vec<i32> = body.items.iter().map(|item| item.product_id).collect();
+    let quantities: Vec<i32> = body.items.iter().map(|item| item.quantity).collect();
+    check_links(id, &ids)?;
+    if quantities.iter().any(|quantity| !(1..=MAX_QUANTITY).contains(quantity)) {
+        return Err(fail(
+            StatusCode::BAD_REQUEST,
+            &format!("quantity must be between 1 and {}", MAX_QUANTITY),
+        ));
+    }
+
+    let mut tx = data.db.begin().await.map_err(database_error)?;
+    lock_product(&mut tx, id, true).await?;
+    ensure_products(&mut tx, &ids).await?;
+    if !ids.is_empty() {
+        let nested: bool = sqlx::query_scalar(
+            r#"SELECT EXISTS (SELECT 1 FROM bundle_items WHERE component_id = $1)
+                OR EXISTS (SELECT 1 FROM bundle_items WHERE bundle_id = ANY($2))"#,
+        )
+        .bind(id)
+        .bind(&ids)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(database_error)?;
+        if nested {
+            return Err(fail(StatusCode::CONFLICT, "Bundles cannot contain other bundles"));
+        }
+    }
+
+    sqlx::query("DELETE FROM bundle_items WHERE bundle_id = $1")
+        .bind(id)
+        .execute(&mut *tx)
+        .await
+        .map_err(database_error)?;
+    sqlx::query(
+        r#"INSERT INTO bundle_items (bundle_id, component_id, quantity, position)
+        SELECT $1, component_id, quantity, position::INTEGER - 1
+        FROM UNNEST($2::INTEGER[], $3::INTEGER[]) WITH ORDINALITY AS t (component_id, quantity, position)"#,
+    )
+    .bind(id)
+    .bind(&ids)
+    .bind(&quantities)
+    .execute(&mut *tx)
+    .await
+    .map_err(database_error)?;
+    tx.commit().await.map_err(database_error)?;
+
+    let bundle = bundle_for(&data.db, id).await.map_err(database_error)?;
+    tracing::info!(product_id = id, components = ids.len(), "product bundle updated");
+    Ok(Json(json!({"status": "success", "data": bundle})))
+}
+
+/// The products linked from `product_id` that match `condition`, by kind and
+/// in display order.
+pub async fn relations_for(
+    db: &PgPool,
+    product_id: i32,
+    condition: &str,
+) -> Result<BTreeMap<RelationKind, Vec<ProductSummary>>, sqlx::Error> {
+    let related: Vec<RelatedProduct> = sqlx::query_as(&format!(
+        r#"SELECT r.kind, p.id, p.name, p.slug, p.price, p.available
+        FROM product_relations r
+        JOIN products p ON p.id = r.related_id
+        WHERE r.product_id = $1 AND {}
+        ORDER BY r.kind, r.position"#,
+        condition
+    ))
+    .bind(product_id)
+    .fetch_all(db)
+    .await?;
+
+    let mut by_kind: BTreeMap<RelationKind, Vec<ProductSummary>> = BTreeMap::new();
+    for related in related {
+        by_kind.entry(related.kind).or_default().push(related.product);
+    }
+    Ok(by_kind)
+}
+
+/// `None` unless the product is a bundle. Components the storefront does not
+/// show are still listed, as unavailable, so the bundle's contents stay honest.
+pub async fn bundle_for(db: &PgPool, product_id: i32) -> Result<Option<Bundle>, sqlx::Error> {
+    let items: Vec<BundleItem> = sqlx::query_as(&format!(
+        r#"SELECT b.quantity, p.id, p.name, p.slug, p.price, p.available AND ({}) AS available
+        FROM bundle_items b
+        JOIN products p ON p.id = b.component_id
+        WHERE b.bundle_id = $1
+        ORDER BY b.position"#,
+        PUBLISHED
+    ))
+    .bind(product_id)
+    .fetch_all(db)
+    .await?;
+    if items.is_empty() {
+        return Ok(None);
+    }
+
+    let price: f64 = sqlx::query_scalar("SELECT price FROM products WHERE id = $1")
+        .bind(product_id)
+        .fetch_one(db)
+        .await?;
+    let components_price: f64 = items
+        .iter()
+        .map(|item| item.product.price * f64::from(item.quantity))
+        .sum();
+    Ok(Some(Bundle {
+        items,
+        components_price,
+        savings: (components_price - price).max(0.0),
+    }))
+}
+
+fn check_links(id: i32, ids: &[i32]) -> Result<(), HandlerError> {
+    if ids.len() > MAX_LINKED {
+        return Err(fail(
+            StatusCode::BAD_REQUEST,
+            &format!("at most {} products can be linked", MAX_LINKED),
+        ));
+    }
+    if ids.contains(&id) {
+        return Err(fail(StatusCode::BAD_REQUEST, "a product cannot be linked to itself"));
+    }
+    if ids.iter().enumerate().any(|(i, product_id)| ids[..i].contains(product_id)) {
+        return Err(fail(StatusCode::BAD_REQUEST, "each product can be linked only once"));
+    }
+    Ok(())
+}
+
+/// With `for_update`, keeps concurrent edits of the same product's links apart.
+async fn lock_product(conn: &mut PgConnection, id: i32, for_update: bool) -> Result<(), HandlerError> {
+    let sql = format!(
+        "SELECT id FROM products WHERE id = $1 AND {}{}",
+        NOT_DELETED,
+        if for_update { " FOR UPDATE" } else { "" }
+    );
+    sqlx::query_scalar::<_, i32>(&sql)
+        .bind(id)
+        .fetch_optional(conn)
+        .await
+        .map_err(database_error)?
+        .ok_or_else(|| fail(StatusCode::NOT_FOUND, "Product not found"))?;
+    Ok(())
+}
+
+/// Share-locks the products, so two bundles cannot be made parts of each other
+/// at the same time.
+async fn ensure_products(conn: &mut PgConnection, ids: &[i32]) -> Result<(), HandlerError> {
+    let found: i64 = sqlx::query_scalar(&format!(
+        "SELECT COUNT(*) FROM (SELECT id FROM products WHERE id = ANY($1) AND {} FOR SHARE) t",
+        NOT_DELETED
+    ))
+    .bind(ids)
+    .fetch_one(conn)
+    .await
+    .map_err(database_error)?;
+    if found != ids.len() as i64 {
+        return Err(fail(StatusCode::UNPROCESSABLE_ENTITY, "Some of the products do not exist"));
+    }
+    Ok(())
+}
+
+fn fail(status: StatusCode, message: &str) -> HandlerError {
+    let error_response = ErrorResponse {
+        status: "fail",
+        message: message.to_string(),
+    };
+    (status, Json(error_response))
+}
+
+fn database_error(e: sqlx::Error) -> HandlerError {
+    let error_response = ErrorResponse {
+        status: "error",
+        message: format!("Database error: {}", e),
+    };
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_links_to_itself_duplicates_and_too_many() {
+        assert!(check_links(1, &[2, 3]).is_ok());
+        assert!(check_links(1, &[]).is_ok());
+
+        for ids in [vec![2, 1], vec![2, 3, 2], (2..MAX_LINKED as i32 + 3).collect()] {
+            let (status, _) = check_links(1, &ids).unwrap_err();
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{:?}", ids);
+        }
+    }
+}
@@ -0,0 +1,347 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::IntoResponse,
+    Extension, Json,
+};
+use serde_json::json;
+
+use crate::apis::jwt_auth::Principal;
+use crate::apis::login::response::ErrorResponse;
+use crate::apis::v1::attributes::{check_attributes, AttributeError};
+use crate::apis::v1::precondition::{etag, IfMatch};
+use crate::apis::v1::products::products_model::{Product, ProductSnapshot};
+use crate::apis::v1::revisions::{
+    attribute, find_revision, find_revisions, reverted, revision_page, RevisionFilter,
+    RevisionsQuery,
+};
+
+use crate::AppState;
+
+type HandlerError = (StatusCode, Json<ErrorResponse>);
+
+/// Every recorded change to the product, newest first, including changes made
+/// before it was deleted or purged.
+pub async fn list_product_revisions_handler(
+    State(data): State<Arc<AppState>>,
+    Path(id): Path<i32>,
+    Query(query): Query<RevisionsQuery>,
+) -> Result<impl IntoResponse, HandlerError> {
+    let filter = RevisionFilter {
+        entity: Some("product"),
+        entity_id: Some(id),
+        ..RevisionFilter::default()
+    };
+    let revisions = find_revisions(&data.db, &filter, &query)
+        .await
+        .map_err(database_error)?;
+
+    Ok(Json(revision_page(&revisions)))
+}
+
+/// Undoes a revision: the fields it changed get their earlier values back,
+/// while later changes to other fields are kept. This is itself recorded as a
+/// new revision. Reverting a deletion restores the product; a creation cannot
+/// be reverted.
+///
+/// The result must pass the checks of a regular update, so a revert cannot
+/// bring back values that are no longer allowed or move the product into a
+/// category that has since been deleted. Honours `If-Match` like
+/// [`patch_product`](super::products_handler::patch_product).
+pub async fn revert_product_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(principal): Extension<Principal>,
+    Path((id, revision_id)): Path<(i32, i64)>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, HandlerError> {
+    let if_match = IfMatch::from_headers(&headers)
+        .map_err(|_| fail(StatusCode::BAD_REQUEST, "If-Match must list ETags"))?;
+    let mut tx = data.db.begin().await.map_err(database_error)?;
+    attribute(&mut tx, Some(&principal), &format!("revert of revision {}", revision_id))
+        .await
+        .map_err(database_error)?;
+
+    let revision = find_revision(&mut tx, "product", id, revision_id)
+        .await
+        .map_err(database_error)?
+        .ok_or_else(|| fail(StatusCode::NOT_FOUND, "Revision not found"))?;
+    let current: serde_json::Value =
+        sqlx::query_scalar("SELECT to_jsonb(t) FROM products t WHERE id = $1 FOR UPDATE")
+            .bind(id)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(database_error)?
+            .ok_or_else(|| fail(StatusCode::NOT_FOUND, "The product has been purged"))?;
+    let version = current["version"].as_i64().unwrap_or_default();
+    if !if_match.matches(version as i32) {
+        return Err(fail(
+            StatusCode::PRECONDITION_FAILED,
+            "The product has changed since it was read",
+        ));
+    }
+    let category_name = current["category_name"].clone();
+    let Some(reverted) = reverted(&revision, current) else {
+        return Err(fail(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "A creation cannot be reverted; delete the product instead",
+        ));
+    };
+    let snapshot: ProductSnapshot = serde_json::from_value(reverted).map_err(|e| {
+        tracing::error!(error = %e, revision_id, "unreadable product snapshot");
+        fail(StatusCode::INTERNAL_SERVER_ERROR, "The revision cannot be reverted")
+    })?;
+    let snapshot = snapshot
+        .validate()
+        .map_err(|message| fail(StatusCode::UNPROCESSABLE_ENTITY, &message))?;
+    if category_name != snapshot.category_name.as_str() {
+        let exists: bool = sqlx::query_scalar(
+            "SELECT EXISTS(SELECT 1 FROM categories WHERE name = $1 AND deleted_at IS NULL)",
+        )
+        .bind(&snapshot.category_name)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(database_error)?;
+        if !exists {
+            return Err(fail(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                &format!("category {} no longer exists", snapshot.category_name),
+            ));
+        }
+    }
+    check_attributes(&mut tx, &snapshot.category_name, Some(&snapshot.attributes))
+        .await
+        .map_err(|e| match e {
+            AttributeError::Invalid(message) => fail(StatusCode::UNPROCESSABLE_ENTITY, &message),
+            AttributeError::Database(e) => database_error(e),
+        })?;
+
+    let product: Product = sqlx::query_as(
+        r#"UPDATE products SET
+            sku = $2, name = $3, description = $4, price = $5, category_name = $6,
+            available = $7, status = $8, publish_at = $9, unpublish_at = $10,
+            archived_at = $11, deleted_at = $12, attributes = $13, slug = $14,
+            meta_title = $15, meta_description = $16, kind = $17, version = version + 1
+        WHERE id = $1
+        RETURNING *"#,
+    )
+    .bind(id)
+    .bind(&snapshot.sku)
+    .bind(&snapshot.name)
+    .bind(&snapshot.description)
+    .bind(snapshot.price)
+    .bind(&snapshot.category_name)
+    .bind(snapshot.available)
+    .bind(snapshot.status.as_str())
+    .bind(snapshot.publish_at)
+    .bind(snapshot.unpublish_at)
+    .bind(snapshot.archived_at)
+    .bind(snapshot.deleted_at)
+    .bind(sqlx::types::Json(&snapshot.attributes))
+    .bind(&snapshot.slug)
+    .bind(&snapshot.meta_title)
+    .bind(&snapshot.meta_description)
+    .bind(snapshot.kind.as_str())
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(write_error)?;
+    tx.commit().await.map_err(database_error)?;
+
+    tracing::info!(product_id = id, revision_id, %principal, "product revision reverted");
+    Ok((
+        [(header::ETAG, etag(product.version))],
+        Json(json!({"status": "success", "data": product})),
+    ))
+}
+
+fn write_error(e: sqlx::Error) -> HandlerError {
+    match e.as_database_error().and_then(|e| e.code()) {
+        Some(code) if code == "23505" => fail(
+            StatusCode::CONFLICT,
+            "Another product now has this revision's SKU or slug",
+        ),
+        _ => database_error(e),
+    }
+}
+
+fn fail(status: StatusCode, message: &str) -> HandlerError {
+    let error_response = ErrorResponse {
+        status: "fail",
+        message: message.to_string(),
+    };
+    (status, Json(error_response))
+}
+
+fn database_error(e: sqlx::Error) -> HandlerError {
+    let error_response = ErrorResponse {
+        status: "error",
+        message: format!("Database error: {}", e),
+    };
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+    use crate::apis::login::model::User;
+    use crate::test_support::{example_config, json_response, ScratchDb};
+
+    async fn admin(db: &ScratchDb) -> Principal {
+        let id = db.user("admin@example.com", "password", "admin").await;
+        let user: User = sqlx::query_as("SELECT * FROM users WHERE id = $1")
+            .bind(id)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        Principal::User(user)
+    }
+
+    /// A product in `Books`, and the revision of the update `change` makes to it.
+    async fn changed_product(db: &ScratchDb, change: &str) -> (i32, i64) {
+        sqlx::query("INSERT INTO categories (name) VALUES ('Books'), ('Comics')")
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        let id: i32 = sqlx::query_scalar(
+            "INSERT INTO products (name, description, price, category_name) VALUES ('Atlas', '', 5, 'Books') RETURNING id",
+        )
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+        sqlx::query(&format!("UPDATE products SET {}, version = version + 1 WHERE id = $1", change))
+            .bind(id)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        let revision_id = sqlx::query_scalar(
+            "SELECT id FROM catalog_revisions WHERE entity = 'product' AND entity_id = $1 AND action = 'updated'",
+        )
+        .bind(id)
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+        (id, revision_id)
+    }
+
+    fn if_match(version: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_MATCH, HeaderValue::from_str(version).unwrap());
+        headers
+    }
+
+    #[tokio::test]
+    async fn reverts_a_change_unless_the_product_moved_on() {
+        let Some(db) = ScratchDb::migrated().await else {
+            return;
+        };
+        let data = db.state(example_config());
+        let principal = admin(&db).await;
+        let (id, revision_id) = changed_product(&db, "price = 6").await;
+
+        let Err((status, _)) = revert_product_handler(
+            State(data.clone()),
+            Extension(principal.clone()),
+            Path((id, revision_id)),
+            if_match("\"1\""),
+        )
+        .await
+        else {
+            panic!("a stale If-Match was accepted");
+        };
+        assert_eq!(status, StatusCode::PRECONDITION_FAILED);
+
+        let response = revert_product_handler(
+            State(data),
+            Extension(principal),
+            Path((id, revision_id)),
+            if_match("\"2\""),
+        )
+        .await
+        .map_err(|(status, _)| status)
+        .unwrap()
+        .into_response();
+        assert_eq!(response.headers()[header::ETAG], "\"3\"");
+        let (status, body) = json_response(response).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["price"], 5.0);
+        assert_eq!(body["data"]["name"], "Atlas");
+        db.drop().await;
+    }
+
+    #[tokio::test]
+    async fn rejects_a_revert_into_a_deleted_category() {
+        let Some(db) = ScratchDb::migrated().await else {
+            return;
+        };
+        let data = db.state(example_config());
+        let principal = admin(&db).await;
+        let (id, revision_id) = changed_product(&db, "category_name = 'Comics'").await;
+        sqlx::query("UPDATE categories SET deleted_at = NOW() WHERE name = 'Books'")
+            .execute(&db.pool)
+            .await
+            .unwrap();
+
+        let Err((status, body)) = revert_product_handler(
+            State(data),
+            Extension(principal),
+            Path((id, revision_id)),
+            HeaderMap::new(),
+        )
+        .await
+        else {
+            panic!("the product was moved into a deleted category");
+        };
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body.message, "category Books no longer exists");
+        db.drop().await;
+    }
+
+    #[tokio::test]
+    async fn rejects_a_revert_to_values_that_are_no_longer_valid() {
+        let Some(db) = ScratchDb::migrated().await else {
+            return;
+        };
+        let data = db.state(example_config());
+        let principal = admin(&db).await;
+        sqlx::query("INSERT INTO categories (name) VALUES ('Maps')")
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        // Written before names had to be non-blank.
+        let id: i32 = sqlx::query_scalar(
+            "INSERT INTO products (name, description, price, category_name) VALUES ('  ', '', 5, 'Maps') RETURNING id",
+        )
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+        sqlx::query("UPDATE products SET name = 'Atlas', version = version + 1 WHERE id = $1")
+            .bind(id)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        let revision_id: i64 = sqlx::query_scalar(
+            "SELECT id FROM catalog_revisions WHERE entity_id = $1 AND action = 'updated'",
+        )
+        .bind(id)
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+
+        let Err((status, body)) = revert_product_handler(
+            State(data),
+            Extension(principal),
+            Path((id, revision_id)),
+            HeaderMap::new(),
+        )
+        .await
+        else {
+            panic!("a blank name was restored");
+        };
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body.message, "name must be between 1 and 255 characters");
+        db.drop().await;
+    }
+}
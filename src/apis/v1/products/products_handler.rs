@@ -1,83 +1,410 @@
 use crate::errors::CustomError;
-use crate::apis::v1::products::products_model::{Product, NewProduct};
+use crate::apis::v1::products::products_model::{Product, NewProduct, ProductKind, ProductStatus};
+use crate::apis::v1::products::product_images_handler::images_for;
+use crate::apis::v1::products::product_relations_handler::{bundle_for, relations_for};
 
 // Implement similar functions for other CRUD operations
 
-use axum::{response::IntoResponse, extract::{Path,State}, http::StatusCode, Json};
+use axum::{async_trait, response::{IntoResponse, Response}, extract::{OriginalUri, Path,Query,State}, Extension, http::{header, HeaderMap, StatusCode}, Json};
+use serde::Deserialize;
 use serde_json::{json, Value};
+use sqlx::PgConnection;
+use crate::apis::login::response::ErrorResponse;
+use crate::apis::v1::attributes::{self, check_attributes, parse_filters, AttributeError, FILTER_PREFIX};
+use crate::apis::v1::batch::{self, item_error, Applied, BatchOperation, BatchRequest, Outcome};
+use crate::apis::v1::lifecycle::PUBLISHED;
+use crate::apis::jwt_auth::Principal;
+use crate::apis::v1::revisions::attribute;
+use crate::apis::v1::slugs::{moved_permanently, redirect_target};
+use crate::apis::v1::precondition::{etag, merge_patch, missing_or_stale, IfMatch, Write};
 use crate::AppState;
+use std::collections::HashMap;
 use std::sync::Arc;
 
-pub async fn get_products(State(pool): State<Arc<AppState>>) -> impl IntoResponse {
-    let sql = "SELECT * FROM products".to_string();
-    let product = sqlx::query_as::<_, Product>(&sql).fetch_all(&pool.db).await.unwrap();
+/// `?category=` limits the list to one category, whose attributes can then be
+/// filtered on with `attr.<key>=a,b` or `attr.<key>.min=`/`.max=`. With
+/// `facets=true`, `facets` holds the counts for the current filters; it is
+/// `null` otherwise.
+pub async fn get_products(State(pool): State<Arc<AppState>>, Query(params): Query<HashMap<String, String>>) -> Result<impl IntoResponse, CustomError> {
+    let category = params.get("category").map(|category| category.trim());
+    let with_facets = params.get("facets").is_some_and(|facets| facets == "true");
+    let filtered = params.keys().any(|name| name.starts_with(FILTER_PREFIX));
+    // Attributes are defined per category, so they mean nothing without one.
+    if category.is_none() && (with_facets || filtered) {
+        return Err(CustomError::BadRequest);
+    }
 
-    (StatusCode::OK, Json(product))
+    let mut conn = pool.db.acquire().await.map_err(|_| CustomError::InternalServerError)?;
+    let sql = format!("SELECT * FROM products WHERE {} AND ($1::VARCHAR IS NULL OR category_name = $1)", PUBLISHED);
+    let mut product = sqlx::query_as::<_, Product>(&sql).bind(category).fetch_all(&mut *conn).await.map_err(|_| {
+        CustomError::InternalServerError
+    })?;
+
+    let definitions = match category {
+        Some(category) => attributes::definitions(&mut conn, category).await.map_err(|_| CustomError::InternalServerError)?,
+        None => Vec::new(),
+    };
+    let filters = parse_filters(&params, &definitions).map_err(|_| CustomError::BadRequest)?;
+    let facets = with_facets.then(|| {
+        let values: Vec<&Value> = product.iter().map(|p| &p.attributes).collect();
+        attributes::facets(&definitions, &values, &filters)
+    });
+    product.retain(|p| filters.iter().all(|filter| filter.matches(&p.attributes)));
+
+    let ids: Vec<i32> = product.iter().map(|p| p.id).collect();
+    let mut images = images_for(&pool.db, &ids).await.map_err(|_| CustomError::InternalServerError)?;
+    for p in &mut product {
+        p.images = images.remove(&p.id).unwrap_or_default();
+    }
+
+    Ok(Json(json!({"status": "success", "results": product.len(), "data": product, "facets": facets})))
 }
 
-pub async fn get_product(Path(id): Path<i32>, State(pool): State<Arc<AppState>>) -> Result<Json<Product>, CustomError> {
-    let sql = "SELECT * FROM products where id=$1".to_string();
-    let product : Product = sqlx::query_as(&sql).bind(id).fetch_one(&pool.db).await.map_err(|_| {
+pub async fn get_product(Path(id): Path<i32>, State(pool): State<Arc<AppState>>) -> Result<impl IntoResponse, CustomError> {
+    let sql = format!("SELECT * FROM products WHERE id = $1 AND {}", PUBLISHED);
+    let mut product : Product = sqlx::query_as(&sql).bind(id).fetch_one(&pool.db).await.map_err(|_| {
         CustomError::TaskNotFound
     })?;
 
-    Ok(Json(product))
+    product.images = images_for(&pool.db, &[id]).await.map_err(|_| {
+        CustomError::InternalServerError
+    })?.remove(&id).unwrap_or_default();
+    with_links(&pool.db, &mut product).await.map_err(|_| CustomError::InternalServerError)?;
+
+    Ok(([(header::ETAG, etag(product.version))], Json(product)))
 }
 
-#[axum_macros::debug_handler]
-pub async fn post_product(State(pool): State<Arc<AppState>>, Json(data): Json<NewProduct>) -> Result<(StatusCode, Json<NewProduct>), CustomError> {
-    let sql = "INSERT INTO product (id, name, category_name, description, available, price) values ($1, $2, $3, $4, $5, $6)".to_string();
-    let _  = sqlx::query(&sql)
-    .bind(&data.id)
-    .bind(&data.name)
-    .bind(&data.category_name)
-    .bind(&data.description)
-    .bind(&data.price)
-    .execute(&pool.db)
-    .await.map_err(|_| {
+/// Like [`get_product`]; a slug the product no longer uses answers 301 with
+/// the address under its current slug.
+pub async fn get_product_by_slug(Path(slug): Path<String>, OriginalUri(uri): OriginalUri, State(pool): State<Arc<AppState>>) -> Result<Response, CustomError> {
+    let sql = format!("SELECT * FROM products WHERE slug = $1 AND {}", PUBLISHED);
+    let product: Option<Product> = sqlx::query_as(&sql).bind(&slug).fetch_optional(&pool.db).await.map_err(|_| {
         CustomError::InternalServerError
     })?;
+    let Some(mut product) = product else {
+        let current = redirect_target(&pool.db, "product", "products", PUBLISHED, &slug).await.map_err(|_| {
+            CustomError::InternalServerError
+        })?;
+        return current.map(|current| moved_permanently(&uri, &current)).ok_or(CustomError::TaskNotFound);
+    };
+
+    product.images = images_for(&pool.db, &[product.id]).await.map_err(|_| {
+        CustomError::InternalServerError
+    })?.remove(&product.id).unwrap_or_default();
+    with_links(&pool.db, &mut product).await.map_err(|_| CustomError::InternalServerError)?;
 
-    Ok((StatusCode::CREATED, Json(data)))
+    Ok(([(header::ETAG, etag(product.version))], Json(product)).into_response())
 }
 
-pub async fn update_product(Path(id): Path<i32>, State(pool): State<Arc<AppState>>, Json(data): Json<NewProduct>) -> Result<(StatusCode, Json<NewProduct>), CustomError> {
-    let sql = "SELECT * FROM product where id=$1".to_string();
-    let _ :Product = sqlx::query_as(&sql).bind(id).fetch_one(&pool.db).await.map_err(|_| {
-        CustomError::TaskNotFound
+/// Fills in the related products the storefront may show, and the bundle contents.
+async fn with_links(db: &sqlx::PgPool, product: &mut Product) -> Result<(), sqlx::Error> {
+    product.relations = Some(relations_for(db, product.id, PUBLISHED).await?);
+    product.bundle = bundle_for(db, product.id).await?;
+    Ok(())
+}
+
+#[axum_macros::debug_handler]
+pub async fn post_product(State(pool): State<Arc<AppState>>, Extension(principal): Extension<Principal>, Json(data): Json<NewProduct>) -> Result<impl IntoResponse, CustomError> {
+    let data = data.validate().map_err(|_| CustomError::BadRequest)?;
+    let mut tx = pool.db.begin().await.map_err(|_| CustomError::InternalServerError)?;
+    attribute(&mut tx, Some(&principal), "api").await.map_err(|_| CustomError::InternalServerError)?;
+    check_attributes(&mut tx, &data.category_name, data.attributes.as_ref()).await.map_err(attribute_error)?;
+    let product = insert_product(&mut tx, &data).await.map_err(write_error)?;
+
+    tx.commit().await.map_err(|_| CustomError::InternalServerError)?;
+
+    Ok((StatusCode::CREATED, [(header::ETAG, etag(product.version))], Json(product)))
+}
+
+/// With `If-Match`, answers 412 unless the product is still at one of the given versions.
+pub async fn update_product(Path(id): Path<i32>, State(pool): State<Arc<AppState>>, Extension(principal): Extension<Principal>, headers: HeaderMap, Json(data): Json<NewProduct>) -> Result<impl IntoResponse, CustomError> {
+    let if_match = IfMatch::from_headers(&headers).map_err(|_| CustomError::BadRequest)?;
+    let data = data.validate().map_err(|_| CustomError::BadRequest)?;
+    let mut tx = pool.db.begin().await.map_err(|_| CustomError::InternalServerError)?;
+    attribute(&mut tx, Some(&principal), "api").await.map_err(|_| CustomError::InternalServerError)?;
+    check_attributes(&mut tx, &data.category_name, data.attributes.as_ref()).await.map_err(attribute_error)?;
+    let product = match update_product_row(&mut tx, id, &data, &if_match).await.map_err(write_error)? {
+        Write::Done(product) => product,
+        Write::NotFound => return Err(CustomError::TaskNotFound),
+        Write::Stale => return Err(CustomError::PreconditionFailed),
+    };
+    tx.commit().await.map_err(|_| CustomError::InternalServerError)?;
+
+    Ok((StatusCode::OK, [(header::ETAG, etag(product.version))], Json(product)))
+}
+
+/// Applies a JSON merge patch (`application/merge-patch+json`) to the product's
+/// editable fields; `"sku": null` removes the SKU and a `null` `publish_at` or
+/// `unpublish_at` cancels that step, and `attributes` are merged key by key.
+/// Honours `If-Match` like [`update_product`].
+pub async fn patch_product(Path(id): Path<i32>, State(pool): State<Arc<AppState>>, Extension(principal): Extension<Principal>, headers: HeaderMap, Json(patch): Json<Value>) -> Result<impl IntoResponse, CustomError> {
+    let if_match = IfMatch::from_headers(&headers).map_err(|_| CustomError::BadRequest)?;
+    let fields = patch.as_object().ok_or(CustomError::BadRequest)?;
+    if fields.keys().any(|key| !PATCHABLE.contains(&key.as_str())) {
+        return Err(CustomError::BadRequest);
+    }
+
+    let mut tx = pool.db.begin().await.map_err(|_| CustomError::InternalServerError)?;
+    attribute(&mut tx, Some(&principal), "api").await.map_err(|_| CustomError::InternalServerError)?;
+    // Locked, so nobody can write between our read and our update.
+    let current: Product = sqlx::query_as("SELECT * FROM products WHERE id = $1 AND deleted_at IS NULL FOR UPDATE")
+    .bind(id)
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(|_| CustomError::InternalServerError)?
+    .ok_or(CustomError::TaskNotFound)?;
+    if !if_match.matches(current.version) {
+        return Err(CustomError::PreconditionFailed);
+    }
+
+    let mut document = json!({
+        "sku": current.sku,
+        "name": current.name,
+        "description": current.description,
+        "price": current.price,
+        "category_name": current.category_name,
+        "available": current.available,
+        "status": current.status,
+        "kind": current.kind,
+        "publish_at": current.publish_at,
+        "unpublish_at": current.unpublish_at,
+        "attributes": current.attributes,
+        "slug": current.slug,
+        "meta_title": current.meta_title,
+        "meta_description": current.meta_description,
+    });
+    merge_patch(&mut document, &patch);
+    let data: NewProduct = serde_json::from_value(document).map_err(|_| CustomError::BadRequest)?;
+    let data = data.validate().map_err(|_| CustomError::BadRequest)?;
+    check_attributes(&mut tx, &data.category_name, data.attributes.as_ref()).await.map_err(attribute_error)?;
+
+    let mut product = replace_product_row(&mut tx, id, &data).await.map_err(write_error)?;
+    tx.commit().await.map_err(|_| CustomError::InternalServerError)?;
+
+    product.images = images_for(&pool.db, &[id]).await.map_err(|_| {
+        CustomError::InternalServerError
+    })?.remove(&id).unwrap_or_default();
+
+    Ok((StatusCode::OK, [(header::ETAG, etag(product.version))], Json(product)))
+}
+
+pub async fn delete_product(Path(id): Path<i32>, State(pool): State<Arc<AppState>>, Extension(principal): Extension<Principal>, headers: HeaderMap) -> Result<(StatusCode, Json<Value>), CustomError> {
+    let if_match = IfMatch::from_headers(&headers).map_err(|_| CustomError::BadRequest)?;
+    let mut tx = pool.db.begin().await.map_err(|_| CustomError::InternalServerError)?;
+    attribute(&mut tx, Some(&principal), "api").await.map_err(|_| CustomError::InternalServerError)?;
+    let deleted = delete_product_row(&mut tx, id, &if_match).await.map_err(|_| {
+        CustomError::InternalServerError
     })?;
+    match deleted {
+        Write::Done(()) => {}
+        Write::NotFound => return Err(CustomError::TaskNotFound),
+        Write::Stale => return Err(CustomError::PreconditionFailed),
+    }
+    tx.commit().await.map_err(|_| CustomError::InternalServerError)?;
+
+    Ok((StatusCode::OK ,Json(json!({"msg": "Product Deleted"}))))
+}
 
-    sqlx::query("UPDATE product SET name = $1, category_name = $2, description = $, available = $4, price = $5 WHERE id=$6")
-    .bind(&data.id)
+/// Creates, updates and deletes many products in one transaction. See [`batch::execute`].
+pub async fn batch_products(State(pool): State<Arc<AppState>>, Extension(principal): Extension<Principal>, Json(request): Json<BatchRequest<ProductOperation>>) -> Result<impl IntoResponse, (StatusCode, Json<ErrorResponse>)> {
+    batch::execute(&pool.db, request, &principal).await
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum ProductOperation {
+    Create { data: NewProduct },
+    /// With `version`, fails unless the product is still at that version.
+    Update { id: i32, data: NewProduct, #[serde(default)] version: Option<i32> },
+    Delete { id: i32, #[serde(default)] version: Option<i32> },
+}
+
+#[async_trait]
+impl BatchOperation for ProductOperation {
+    async fn apply(self, conn: &mut PgConnection) -> Result<Applied, String> {
+        match self {
+            ProductOperation::Create { data } => {
+                let data = data.validate()?;
+                check_attributes(conn, &data.category_name, data.attributes.as_ref()).await.map_err(attribute_item_error)?;
+                let product = insert_product(conn, &data).await.map_err(item_error)?;
+                Ok(Applied { outcome: Outcome::Created, data: Some(json!(product)) })
+            }
+            ProductOperation::Update { id, data, version } => {
+                let data = data.validate()?;
+                check_attributes(conn, &data.category_name, data.attributes.as_ref()).await.map_err(attribute_item_error)?;
+                match update_product_row(conn, id, &data, &IfMatch::from(version)).await.map_err(item_error)? {
+                    Write::Done(product) => Ok(Applied { outcome: Outcome::Updated, data: Some(json!(product)) }),
+                    Write::NotFound => Err(format!("product {} not found", id)),
+                    Write::Stale => Err(format!("product {} is no longer at version {}", id, version.unwrap_or_default())),
+                }
+            }
+            ProductOperation::Delete { id, version } => {
+                match delete_product_row(conn, id, &IfMatch::from(version)).await.map_err(item_error)? {
+                    Write::Done(()) => Ok(Applied { outcome: Outcome::Deleted, data: None }),
+                    Write::NotFound => Err(format!("product {} not found", id)),
+                    Write::Stale => Err(format!("product {} is no longer at version {}", id, version.unwrap_or_default())),
+                }
+            }
+        }
+    }
+}
+
+async fn insert_product(conn: &mut PgConnection, data: &NewProduct) -> Result<Product, sqlx::Error> {
+    sqlx::query_as("INSERT INTO products (sku, name, description, price, category_name, available, status, publish_at, unpublish_at, attributes, slug, meta_title, meta_description, kind) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, COALESCE($10, '{}'), $11, NULLIF($12, ''), NULLIF($13, ''), $14) RETURNING *")
+    .bind(&data.sku)
     .bind(&data.name)
+    .bind(&data.description)
+    .bind(data.price)
     .bind(&data.category_name)
+    .bind(data.available.unwrap_or(true))
+    .bind(data.initial_status().as_str())
+    .bind(data.publish_at)
+    .bind(data.unpublish_at)
+    .bind(data.attributes.as_ref().map(sqlx::types::Json))
+    .bind(&data.slug)
+    .bind(&data.meta_title)
+    .bind(&data.meta_description)
+    .bind(data.kind.unwrap_or_default().as_str())
+    .fetch_one(conn)
+    .await
+}
+
+/// A single statement, so a sync touching thousands of products makes no extra round trips.
+/// A missing `sku`, `available`, `status`, `kind`, schedule, slug or meta field leaves the stored value as it is;
+/// missing attributes are kept too, unless the product moves to another category.
+async fn update_product_row(conn: &mut PgConnection, id: i32, data: &NewProduct, if_match: &IfMatch) -> Result<Write<Product>, sqlx::Error> {
+    let product = sqlx::query_as("UPDATE products SET sku = COALESCE($2, sku), name = $3, description = $4, price = $5, category_name = $6, available = COALESCE($7, available), status = COALESCE($9, status), publish_at = COALESCE($10, publish_at), unpublish_at = COALESCE($11, unpublish_at), attributes = COALESCE($12, CASE WHEN category_name = $6 THEN attributes ELSE '{}' END), slug = COALESCE($13, slug), meta_title = NULLIF(COALESCE($14, meta_title), ''), meta_description = NULLIF(COALESCE($15, meta_description), ''), kind = COALESCE($16, kind), version = version + 1 WHERE id = $1 AND deleted_at IS NULL AND ($8::INTEGER[] IS NULL OR version = ANY($8)) RETURNING *")
+    .bind(id)
+    .bind(&data.sku)
+    .bind(&data.name)
     .bind(&data.description)
-    .bind(&data.price)
-    .execute(&pool.db)
-    .await.map_err(|_| {
-        CustomError::InternalServerError
-    })?;
+    .bind(data.price)
+    .bind(&data.category_name)
+    .bind(data.available)
+    .bind(if_match.versions())
+    .bind(data.status.map(ProductStatus::as_str))
+    .bind(data.publish_at)
+    .bind(data.unpublish_at)
+    .bind(data.attributes.as_ref().map(sqlx::types::Json))
+    .bind(&data.slug)
+    .bind(&data.meta_title)
+    .bind(&data.meta_description)
+    .bind(data.kind.map(ProductKind::as_str))
+    .fetch_optional(&mut *conn)
+    .await?;
 
-    Ok((StatusCode::OK, Json(data)))
+    match product {
+        Some(product) => Ok(Write::Done(product)),
+        None => missing_or_stale(conn, "products", id).await,
+    }
 }
 
-pub async fn delete_product(Path(id): Path<i32>, State(pool): State<Arc<AppState>>) -> Result<(StatusCode, Json<Value>), CustomError> {
-    let sql = "SELECT * FROM product where id=$1".to_string();
-    let _ : Product = sqlx::query_as(&sql)
+/// Writes every field as given, including a `None` SKU or schedule; for
+/// callers that already hold the row lock. A missing status, kind or slug is kept.
+async fn replace_product_row(conn: &mut PgConnection, id: i32, data: &NewProduct) -> Result<Product, sqlx::Error> {
+    sqlx::query_as("UPDATE products SET sku = $2, name = $3, description = $4, price = $5, category_name = $6, available = $7, status = COALESCE($8, status), publish_at = $9, unpublish_at = $10, attributes = COALESCE($11, '{}'), slug = COALESCE($12, slug), meta_title = NULLIF($13, ''), meta_description = NULLIF($14, ''), kind = COALESCE($15, kind), version = version + 1 WHERE id = $1 AND deleted_at IS NULL RETURNING *")
     .bind(id)
-    .fetch_one(&pool.db)
+    .bind(&data.sku)
+    .bind(&data.name)
+    .bind(&data.description)
+    .bind(data.price)
+    .bind(&data.category_name)
+    .bind(data.available.unwrap_or(true))
+    .bind(data.status.map(ProductStatus::as_str))
+    .bind(data.publish_at)
+    .bind(data.unpublish_at)
+    .bind(data.attributes.as_ref().map(sqlx::types::Json))
+    .bind(&data.slug)
+    .bind(&data.meta_title)
+    .bind(&data.meta_description)
+    .bind(data.kind.map(ProductKind::as_str))
+    .fetch_one(conn)
     .await
-    .map_err(|_| {
-        CustomError::TaskNotFound
-    })?;
+}
 
-    sqlx::query("DELETE FROM employee WHERE id=$1")
+/// Only marks the row; the purge job removes it once the retention period has passed.
+async fn delete_product_row(conn: &mut PgConnection, id: i32, if_match: &IfMatch) -> Result<Write<()>, sqlx::Error> {
+    let result = sqlx::query("UPDATE products SET deleted_at = NOW(), version = version + 1 WHERE id = $1 AND deleted_at IS NULL AND ($2::INTEGER[] IS NULL OR version = ANY($2))")
     .bind(id)
-    .execute(&pool.db)
-    .await
-    .map_err(|_| {
-        CustomError::TaskNotFound
-    })?;
+    .bind(if_match.versions())
+    .execute(&mut *conn)
+    .await?;
+
+    if result.rows_affected() > 0 {
+        return Ok(Write::Done(()));
+    }
+    missing_or_stale(conn, "products", id).await
+}
+
+fn write_error(e: sqlx::Error) -> CustomError {
+    match e.as_database_error().and_then(|e| e.code()) {
+        Some(code) if code == "23505" => CustomError::Conflict,
+        _ => CustomError::InternalServerError,
+    }
+}
+
+fn attribute_error(e: AttributeError) -> CustomError {
+    match e {
+        AttributeError::Invalid(_) => CustomError::BadRequest,
+        AttributeError::Database(_) => CustomError::InternalServerError,
+    }
+}
+
+fn attribute_item_error(e: AttributeError) -> String {
+    match e {
+        AttributeError::Invalid(message) => message,
+        AttributeError::Database(e) => item_error(e),
+    }
+}
+
+/// Fields a merge patch may touch; `id`, `version` and `images` are read-only.
+const PATCHABLE: [&str; 14] = ["sku", "name", "description", "price", "category_name", "available", "status", "kind", "publish_at", "unpublish_at", "attributes", "slug", "meta_title", "meta_description"];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{example_config, json_response, ScratchDb};
+
+    #[tokio::test]
+    async fn lists_products_in_one_shape_with_or_without_facets() {
+        let Some(db) = ScratchDb::migrated().await else {
+            return;
+        };
+        let data = db.state(example_config());
+        let category: i32 = sqlx::query_scalar("INSERT INTO categories (name) VALUES ('Boots') RETURNING id")
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO category_attributes (category_id, key, label, kind, options, filterable) VALUES ($1, 'color', 'Color', 'enum', '{red,blue}', true)")
+            .bind(category)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO products (name, description, price, category_name, attributes) VALUES ('Red boot', '', 5, 'Boots', '{\"color\": \"red\"}'), ('Blue boot', '', 5, 'Boots', '{\"color\": \"blue\"}')")
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        let list = |query: &[(&str, &str)]| {
+            let params = query.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect();
+            let data = data.clone();
+            async move {
+                let response = get_products(State(data), Query(params)).await.map_err(|_| "failed").unwrap();
+                json_response(response.into_response()).await
+            }
+        };
+
+        let (status, plain) = list(&[]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(plain["results"], 2);
+        assert_eq!(plain["facets"], Value::Null);
 
-    Ok((StatusCode::OK ,Json(json!({"msg": "Profile Deleted"}))))
+        let (status, faceted) = list(&[("category", "Boots"), ("facets", "true"), ("attr.color", " red ")]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(faceted["results"], 1);
+        assert_eq!(faceted["data"][0]["name"], "Red boot");
+        assert_eq!(faceted["facets"][0]["values"], json!([{"value": "red", "count": 1}, {"value": "blue", "count": 1}]));
+        let keys = |body: &Value| body.as_object().unwrap().keys().cloned().collect::<Vec<_>>();
+        assert_eq!(keys(&plain), keys(&faceted));
+        db.drop().await;
+    }
 }
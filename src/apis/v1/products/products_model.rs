@@ -1,20 +1,513 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::apis::v1::slugs::validate_seo;
+use crate::blob_store;
 
 #[derive(sqlx::FromRow,Deserialize, Serialize)]
 
 pub struct Product {
     pub id: i32,
+    pub sku: Option<String>,
     pub name: String,
+    /// Unique; the product can also be looked up by it.
+    pub slug: String,
     pub description: String,
     pub price: f64,
     pub category_name: String,  // Foreign key reference to the Category table
+    pub available: bool,
+    #[sqlx(try_from = "String")]
+    pub status: ProductStatus,
+    #[sqlx(try_from = "String")]
+    pub kind: ProductKind,
+    /// When the publishing scheduler will publish the product.
+    pub publish_at: Option<DateTime<Utc>>,
+    /// When the publishing scheduler will unpublish the product.
+    pub unpublish_at: Option<DateTime<Utc>>,
+    /// Values of the attributes defined for the product's category, by key.
+    pub attributes: Value,
+    /// For search engines; the storefront falls back to the name and description.
+    pub meta_title: Option<String>,
+    pub meta_description: Option<String>,
+    /// Bumped on every write and sent as the `ETag`.
+    pub version: i32,
+    /// Only ever set on items listed by catalog administrators.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archived_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime<Utc>>,
+    #[sqlx(skip)]
+    #[serde(default)]
+    pub images: Vec<ProductImageResponse>,
+    /// Only filled in when a single product is fetched.
+    #[sqlx(skip)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub relations: Option<BTreeMap<RelationKind, Vec<ProductSummary>>>,
+    /// Set when the product is a bundle; only filled in when a single product
+    /// is fetched.
+    #[sqlx(skip)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bundle: Option<Bundle>,
 }
 
 #[derive(sqlx::FromRow,Deserialize, Serialize)]
 pub struct NewProduct {
+    /// Ignored; ids are assigned by the database. Accepted so older clients keep working.
+    #[serde(default)]
     pub id: i32,
+    #[serde(default)]
+    pub sku: Option<String>,
     pub name: String,
     pub description: String,
     pub price: f64,
     pub category_name: String,  // Foreign key reference to the Category table
-}
\ No newline at end of file
+    #[serde(default)]
+    pub available: Option<bool>,
+    /// Defaults to `draft` for new products with a `publish_at`, else `published`.
+    #[serde(default)]
+    pub status: Option<ProductStatus>,
+    /// Defaults to `physical` for new products.
+    #[serde(default)]
+    pub kind: Option<ProductKind>,
+    #[serde(default)]
+    pub publish_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub unpublish_at: Option<DateTime<Utc>>,
+    /// Checked against the category's attribute definitions. On update, a
+    /// missing map keeps the stored values unless the category changes.
+    #[serde(default)]
+    pub attributes: Option<Map<String, Value>>,
+    /// Generated from the name when a product is created without one, and
+    /// kept when the name changes. Old slugs redirect to the new one.
+    #[serde(default)]
+    pub slug: Option<String>,
+    /// An empty string clears it.
+    #[serde(default)]
+    pub meta_title: Option<String>,
+    #[serde(default)]
+    pub meta_description: Option<String>,
+}
+
+impl NewProduct {
+    pub fn validate(mut self) -> Result<NewProduct, String> {
+        if let Some(sku) = &self.sku {
+            let sku = sku.trim().to_string();
+            if !valid_sku(&sku) {
+                return Err(SKU_RULES.to_string());
+            }
+            self.sku = Some(sku);
+        }
+
+        self.name = self.name.trim().to_string();
+        if self.name.is_empty() || self.name.chars().count() > 255 {
+            return Err("name must be between 1 and 255 characters".to_string());
+        }
+        validate_seo(&mut self.slug, &mut self.meta_title, &mut self.meta_description)?;
+
+        if !self.price.is_finite() || self.price < 0.0 {
+            return Err("price must be a number of at least 0".to_string());
+        }
+
+        self.category_name = self.category_name.trim().to_string();
+        if self.category_name.is_empty() {
+            return Err("category_name must not be empty".to_string());
+        }
+
+        if let (Some(publish_at), Some(unpublish_at)) = (self.publish_at, self.unpublish_at) {
+            if unpublish_at <= publish_at {
+                return Err("unpublish_at must be after publish_at".to_string());
+            }
+        }
+
+        Ok(self)
+    }
+
+    /// The status of a product created from this data.
+    pub fn initial_status(&self) -> ProductStatus {
+        match (self.status, self.publish_at) {
+            (Some(status), _) => status,
+            (None, Some(_)) => ProductStatus::Draft,
+            (None, None) => ProductStatus::Published,
+        }
+    }
+}
+
+/// The columns set when a revision is reverted.
+#[derive(Debug, Deserialize)]
+pub struct ProductSnapshot {
+    pub sku: Option<String>,
+    pub name: String,
+    pub slug: String,
+    pub description: String,
+    pub price: f64,
+    pub category_name: String,
+    pub available: bool,
+    pub status: ProductStatus,
+    /// Absent from revisions recorded before digital products existed.
+    #[serde(default)]
+    pub kind: ProductKind,
+    pub publish_at: Option<DateTime<Utc>>,
+    pub unpublish_at: Option<DateTime<Utc>>,
+    /// Absent from revisions recorded before attributes existed.
+    #[serde(default)]
+    pub attributes: Map<String, Value>,
+    pub meta_title: Option<String>,
+    pub meta_description: Option<String>,
+    pub archived_at: Option<DateTime<Utc>>,
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+impl ProductSnapshot {
+    /// Applies the rules of [`NewProduct::validate`], which may have tightened
+    /// since the revision was recorded.
+    pub fn validate(self) -> Result<ProductSnapshot, String> {
+        let data = NewProduct {
+            id: 0,
+            sku: self.sku,
+            name: self.name,
+            description: self.description,
+            price: self.price,
+            category_name: self.category_name,
+            available: Some(self.available),
+            status: Some(self.status),
+            kind: Some(self.kind),
+            publish_at: self.publish_at,
+            unpublish_at: self.unpublish_at,
+            attributes: Some(self.attributes),
+            slug: Some(self.slug),
+            meta_title: self.meta_title,
+            meta_description: self.meta_description,
+        }
+        .validate()?;
+
+        Ok(ProductSnapshot {
+            sku: data.sku,
+            name: data.name,
+            slug: data.slug.unwrap_or_default(),
+            description: data.description,
+            price: data.price,
+            category_name: data.category_name,
+            available: self.available,
+            status: self.status,
+            kind: self.kind,
+            publish_at: data.publish_at,
+            unpublish_at: data.unpublish_at,
+            attributes: data.attributes.unwrap_or_default(),
+            meta_title: data.meta_title.filter(|title| !title.is_empty()),
+            meta_description: data.meta_description.filter(|description| !description.is_empty()),
+            archived_at: self.archived_at,
+            deleted_at: self.deleted_at,
+        })
+    }
+}
+
+/// Only `published` products are shown on the storefront.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProductStatus {
+    Draft,
+    Published,
+    Unpublished,
+}
+
+impl ProductStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ProductStatus::Draft => "draft",
+            ProductStatus::Published => "published",
+            ProductStatus::Unpublished => "unpublished",
+        }
+    }
+}
+
+impl TryFrom<String> for ProductStatus {
+    type Error = String;
+
+    fn try_from(status: String) -> Result<Self, Self::Error> {
+        match status.as_str() {
+            "draft" => Ok(ProductStatus::Draft),
+            "published" => Ok(ProductStatus::Published),
+            "unpublished" => Ok(ProductStatus::Unpublished),
+            _ => Err(format!("unknown product status `{}`", status)),
+        }
+    }
+}
+
+const SKU_RULES: &str = "sku must be 1 to 64 letters, digits, `.`, `_` or `-`";
+
+pub fn valid_sku(sku: &str) -> bool {
+    !sku.is_empty()
+        && sku.len() <= 64
+        && sku
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'.' | b'_' | b'-'))
+}
+
+/// Digital products are delivered as files through download links.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProductKind {
+    #[default]
+    Physical,
+    Digital,
+}
+
+impl ProductKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ProductKind::Physical => "physical",
+            ProductKind::Digital => "digital",
+        }
+    }
+}
+
+impl TryFrom<String> for ProductKind {
+    type Error = String;
+
+    fn try_from(kind: String) -> Result<Self, Self::Error> {
+        match kind.as_str() {
+            "physical" => Ok(ProductKind::Physical),
+            "digital" => Ok(ProductKind::Digital),
+            _ => Err(format!("unknown product kind `{}`", kind)),
+        }
+    }
+}
+
+/// How a product is linked to the product whose page lists it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RelationKind {
+    Related,
+    /// A pricier alternative.
+    Upsell,
+    /// Often bought together with it.
+    CrossSell,
+    Accessory,
+}
+
+impl RelationKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RelationKind::Related => "related",
+            RelationKind::Upsell => "upsell",
+            RelationKind::CrossSell => "cross_sell",
+            RelationKind::Accessory => "accessory",
+        }
+    }
+}
+
+impl TryFrom<String> for RelationKind {
+    type Error = String;
+
+    fn try_from(kind: String) -> Result<Self, Self::Error> {
+        match kind.as_str() {
+            "related" => Ok(RelationKind::Related),
+            "upsell" => Ok(RelationKind::Upsell),
+            "cross_sell" => Ok(RelationKind::CrossSell),
+            "accessory" => Ok(RelationKind::Accessory),
+            _ => Err(format!("unknown relation kind `{}`", kind)),
+        }
+    }
+}
+
+/// A product as listed on another product's page.
+#[derive(Debug, Deserialize, Serialize, sqlx::FromRow)]
+pub struct ProductSummary {
+    pub id: i32,
+    pub name: String,
+    pub slug: String,
+    pub price: f64,
+    pub available: bool,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct RelatedProduct {
+    #[sqlx(try_from = "String")]
+    pub kind: RelationKind,
+    #[sqlx(flatten)]
+    pub product: ProductSummary,
+}
+
+/// The products a bundle is made of. The bundle's own `price` is what it sells for.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Bundle {
+    pub items: Vec<BundleItem>,
+    /// What the components would cost bought one by one.
+    pub components_price: f64,
+    pub savings: f64,
+}
+
+#[derive(Debug, Deserialize, Serialize, sqlx::FromRow)]
+pub struct BundleItem {
+    pub quantity: i32,
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub product: ProductSummary,
+}
+
+/// Replaces the products of one relation kind; the order given is the display order.
+#[derive(Debug, Deserialize)]
+pub struct UpdateRelationsSchema {
+    pub product_ids: Vec<i32>,
+}
+
+/// Replaces the bundle's components; no items turns it back into a plain product.
+#[derive(Debug, Deserialize)]
+pub struct UpdateBundleSchema {
+    pub items: Vec<BundleItemSchema>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BundleItemSchema {
+    pub product_id: i32,
+    #[serde(default = "one")]
+    pub quantity: i32,
+}
+
+fn one() -> i32 {
+    1
+}
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct ProductImage {
+    pub id: i32,
+    pub product_id: i32,
+    pub position: i32,
+    pub alt_text: String,
+    pub key_prefix: String,
+    pub original_ext: String,
+    pub thumbnail_ext: String,
+    pub thumbnail_sizes: Vec<i32>,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl ProductImage {
+    pub fn original_key(&self) -> String {
+        format!("{}/original.{}", self.key_prefix, self.original_ext)
+    }
+
+    pub fn thumbnail_key(&self, size: i32) -> String {
+        format!("{}/{}.{}", self.key_prefix, size, self.thumbnail_ext)
+    }
+
+    /// Every blob stored for this image.
+    pub fn keys(&self) -> Vec<String> {
+        std::iter::once(self.original_key())
+            .chain(self.thumbnail_sizes.iter().map(|&size| self.thumbnail_key(size)))
+            .collect()
+    }
+
+    pub fn to_response(&self) -> ProductImageResponse {
+        ProductImageResponse {
+            id: self.id,
+            position: self.position,
+            alt_text: self.alt_text.clone(),
+            width: self.width,
+            height: self.height,
+            url: blob_store::url(&self.original_key()),
+            thumbnails: self
+                .thumbnail_sizes
+                .iter()
+                .map(|&size| (size.to_string(), blob_store::url(&self.thumbnail_key(size))))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ProductImageResponse {
+    pub id: i32,
+    pub position: i32,
+    pub alt_text: String,
+    pub width: i32,
+    pub height: i32,
+    pub url: String,
+    /// Keyed by the longest edge in pixels.
+    pub thumbnails: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateProductImageSchema {
+    pub alt_text: Option<String>,
+    /// Zero-based; the other images shift to make room.
+    pub position: Option<i32>,
+}
+
+/// One line of a bulk import. Products are matched on `sku`; the `id`
+/// column written by the export is accepted and ignored.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ImportRow {
+    pub sku: String,
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    pub price: f64,
+    pub category_name: String,
+    #[serde(default)]
+    pub available: Option<bool>,
+    #[serde(default, rename = "id")]
+    pub _id: Option<i32>,
+}
+
+impl ImportRow {
+    pub const COLUMNS: &'static [&'static str] = &[
+        "sku",
+        "name",
+        "description",
+        "price",
+        "category_name",
+        "available",
+        "id",
+    ];
+
+    /// The sku is required here, since it is what rows are matched on.
+    pub fn validate(self) -> Result<NewProduct, String> {
+        NewProduct {
+            id: 0,
+            sku: Some(self.sku),
+            name: self.name,
+            description: self.description.unwrap_or_default(),
+            price: self.price,
+            category_name: self.category_name,
+            available: self.available,
+            status: None,
+            kind: None,
+            publish_at: None,
+            unpublish_at: None,
+            attributes: None,
+            slug: None,
+            meta_title: None,
+            meta_description: None,
+        }
+        .validate()
+    }
+}
+
+/// A product as written by the export, in a shape the import reads back.
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct ExportRow {
+    pub id: i32,
+    pub sku: Option<String>,
+    pub name: String,
+    pub description: String,
+    pub price: f64,
+    pub category_name: String,
+    pub available: bool,
+}
+
+impl ExportRow {
+    pub const COLUMNS: &'static [&'static str] = &[
+        "id",
+        "sku",
+        "name",
+        "description",
+        "price",
+        "category_name",
+        "available",
+    ];
+}
@@ -1,16 +1,64 @@
 use std::sync::Arc;
 
 use axum::{
-    routing::{get, post, put, delete},
+    extract::DefaultBodyLimit,
+    middleware,
+    routing::{get, patch, post, put, delete},
     Router
 };
-use crate::{apis::v1::products::products_handler, AppState};
+use crate::{apis::{jwt_auth::require_catalog_manager, v1::products::{products_handler, product_images_handler, product_revisions_handler}}, AppState};
 
 pub fn products_router(app_state: Arc<AppState>) -> Router {
     Router::new()
-        .route("/", post(products_handler::post_product))
+        .route(
+            "/batch",
+            post(products_handler::batch_products)
+                .layer(middleware::from_fn(require_catalog_manager)),
+        )
+        .route(
+            "/",
+            post(products_handler::post_product)
+                .layer(middleware::from_fn(require_catalog_manager)),
+        )
         .route("/:id", get(products_handler::get_product))
-        .route("/:id", put(products_handler::update_product))
-        .route("/:id", delete(products_handler::delete_product))
+        .route("/slug/:slug", get(products_handler::get_product_by_slug))
+        .route(
+            "/:id",
+            put(products_handler::update_product)
+                .layer(middleware::from_fn(require_catalog_manager)),
+        )
+        .route(
+            "/:id",
+            patch(products_handler::patch_product)
+                .layer(middleware::from_fn(require_catalog_manager)),
+        )
+        .route(
+            "/:id",
+            delete(products_handler::delete_product)
+                .layer(middleware::from_fn(require_catalog_manager)),
+        )
+        .route(
+            "/:id/revisions",
+            get(product_revisions_handler::list_product_revisions_handler)
+                .layer(middleware::from_fn(require_catalog_manager)),
+        )
+        .route(
+            "/:id/revisions/:revision_id/revert",
+            post(product_revisions_handler::revert_product_handler)
+                .layer(middleware::from_fn(require_catalog_manager)),
+        )
+        .route("/:id/images", get(product_images_handler::list_product_images_handler))
+        .route(
+            "/:id/images",
+            post(product_images_handler::upload_product_image_handler)
+                .layer(DefaultBodyLimit::max(app_state.config.upload_max_bytes))
+                .layer(middleware::from_fn(require_catalog_manager)),
+        )
+        .route(
+            "/:id/images/:image_id",
+            patch(product_images_handler::update_product_image_handler)
+                .delete(product_images_handler::delete_product_image_handler)
+                .layer(middleware::from_fn(require_catalog_manager)),
+        )
         .with_state(app_state)
 }
\ No newline at end of file
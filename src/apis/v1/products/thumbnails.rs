@@ -0,0 +1,69 @@
+use std::io::Cursor;
+
+use image::{
+    codecs::{jpeg::JpegEncoder, png::PngEncoder},
+    imageops::FilterType,
+    io::{Limits, Reader},
+    DynamicImage, ImageResult,
+};
+
+/// Larger images are rejected before they are decoded.
+const MAX_DIMENSION: u32 = 12_000;
+const MAX_DECODE_BYTES: u64 = 256 * 1024 * 1024;
+const JPEG_QUALITY: u8 = 85;
+
+pub struct Thumbnails {
+    pub width: u32,
+    pub height: u32,
+    /// `png` for images with transparency, `jpg` otherwise.
+    pub extension: &'static str,
+    /// One encoded image per requested size, in the same order.
+    pub images: Vec<(u32, Vec<u8>)>,
+}
+
+/// Scales the image so its longest edge is at most each of `sizes`. Images
+/// are never enlarged, so small uploads get thumbnails at their own size.
+///
+/// CPU bound; call from `spawn_blocking`.
+pub fn render(bytes: &[u8], sizes: &[u32]) -> ImageResult<Thumbnails> {
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_DIMENSION);
+    limits.max_image_height = Some(MAX_DIMENSION);
+    limits.max_alloc = Some(MAX_DECODE_BYTES);
+
+    let mut reader = Reader::new(Cursor::new(bytes)).with_guessed_format()?;
+    reader.limits(limits);
+    let image = reader.decode()?;
+
+    let has_alpha = image.color().has_alpha();
+    let mut images = Vec::with_capacity(sizes.len());
+    for &size in sizes {
+        let scaled = if image.width().max(image.height()) <= size {
+            image.clone()
+        } else {
+            image.resize(size, size, FilterType::CatmullRom)
+        };
+        images.push((size, encode(&scaled, has_alpha)?));
+    }
+
+    Ok(Thumbnails {
+        width: image.width(),
+        height: image.height(),
+        extension: if has_alpha { "png" } else { "jpg" },
+        images,
+    })
+}
+
+fn encode(image: &DynamicImage, has_alpha: bool) -> ImageResult<Vec<u8>> {
+    let mut encoded = Vec::new();
+    if has_alpha {
+        image
+            .to_rgba8()
+            .write_with_encoder(PngEncoder::new(&mut encoded))?;
+    } else {
+        image
+            .to_rgb8()
+            .write_with_encoder(JpegEncoder::new_with_quality(&mut encoded, JPEG_QUALITY))?;
+    }
+    Ok(encoded)
+}
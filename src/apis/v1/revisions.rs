@@ -0,0 +1,300 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use sqlx::{PgConnection, PgPool};
+
+use crate::apis::jwt_auth::Principal;
+use crate::apis::login::response::ErrorResponse;
+
+use crate::AppState;
+
+const MAX_REVISIONS: i64 = 500;
+
+/// A change recorded by the `record_catalog_revision` trigger.
+#[derive(Debug, sqlx::FromRow)]
+pub struct Revision {
+    pub id: i64,
+    pub entity: String,
+    pub entity_id: i32,
+    /// `created`, `updated`, `archived`, `deleted`, `restored` or `purged`.
+    pub action: String,
+    /// `user:<id>` or `api_key:<id>`; `None` for background jobs.
+    pub actor: Option<String>,
+    pub actor_name: Option<String>,
+    pub reason: Option<String>,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+    pub changed_at: DateTime<Utc>,
+}
+
+impl Revision {
+    pub fn to_response(&self) -> Value {
+        json!({
+            "id": self.id,
+            "entity": self.entity,
+            "entity_id": self.entity_id,
+            "action": self.action,
+            "actor": self.actor,
+            "actor_name": self.actor_name,
+            "reason": self.reason,
+            "changed_at": self.changed_at,
+            "changes": changes(self.before.as_ref(), self.after.as_ref()),
+            "before": self.before,
+            "after": self.after,
+        })
+    }
+}
+
+/// Newest first. Pass the previous page's `next` as `before` for the next one.
+#[derive(Debug, Deserialize)]
+pub struct RevisionsQuery {
+    pub before: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+/// Filters for [`list_catalog_revisions_handler`].
+#[derive(Debug, Deserialize)]
+pub struct CatalogRevisionsQuery {
+    /// `product` or `category`.
+    pub entity: Option<String>,
+    pub entity_id: Option<i32>,
+    /// e.g. `user:3` or `api_key:5`.
+    pub actor: Option<String>,
+    /// Only revisions that changed this field, e.g. `price`.
+    pub field: Option<String>,
+    pub before: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Default)]
+pub struct RevisionFilter<'a> {
+    pub entity: Option<&'a str>,
+    pub entity_id: Option<i32>,
+    pub actor: Option<&'a str>,
+    /// Only revisions that changed this field.
+    pub field: Option<&'a str>,
+}
+
+/// Changes across the whole catalog, newest first; answers questions like
+/// "who changed prices last week" with `field=price`.
+pub async fn list_catalog_revisions_handler(
+    State(data): State<Arc<AppState>>,
+    Query(query): Query<CatalogRevisionsQuery>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ErrorResponse>)> {
+    let filter = RevisionFilter {
+        entity: query.entity.as_deref(),
+        entity_id: query.entity_id,
+        actor: query.actor.as_deref(),
+        field: query.field.as_deref(),
+    };
+    let page = RevisionsQuery {
+        before: query.before,
+        limit: query.limit,
+    };
+    let revisions = find_revisions(&data.db, &filter, &page).await.map_err(|e| {
+        let error_response = ErrorResponse {
+            status: "error",
+            message: format!("Database error: {}", e),
+        };
+        (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+    })?;
+
+    Ok(Json(revision_page(&revisions)))
+}
+
+/// Tags the changes made in the rest of the current transaction, so the
+/// revisions the trigger records say who made them and why. Must run inside
+/// a transaction; the settings end with it.
+pub async fn attribute(
+    conn: &mut PgConnection,
+    actor: Option<&Principal>,
+    reason: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("SELECT set_config('shopping.actor', $1, true), set_config('shopping.change_reason', $2, true)")
+        .bind(actor.map(Principal::to_string).unwrap_or_default())
+        .bind(reason)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+pub async fn find_revisions(
+    db: &PgPool,
+    filter: &RevisionFilter<'_>,
+    query: &RevisionsQuery,
+) -> Result<Vec<Revision>, sqlx::Error> {
+    sqlx::query_as(
+        r#"SELECT r.*, COALESCE(u.name, k.name) AS actor_name
+        FROM catalog_revisions r
+        LEFT JOIN users u ON r.actor = 'user:' || u.id
+        LEFT JOIN api_keys k ON r.actor = 'api_key:' || k.id
+        WHERE ($1::VARCHAR IS NULL OR r.entity = $1)
+            AND ($2::INTEGER IS NULL OR r.entity_id = $2)
+            AND ($3::VARCHAR IS NULL OR r.actor = $3)
+            AND ($4::TEXT IS NULL OR r.before -> $4 IS DISTINCT FROM r.after -> $4)
+            AND ($5::BIGINT IS NULL OR r.id < $5)
+        ORDER BY r.id DESC
+        LIMIT $6"#,
+    )
+    .bind(filter.entity)
+    .bind(filter.entity_id)
+    .bind(filter.actor)
+    .bind(filter.field)
+    .bind(query.before)
+    .bind(query.limit.unwrap_or(50).clamp(1, MAX_REVISIONS))
+    .fetch_all(db)
+    .await
+}
+
+/// The revision, if it belongs to the given item.
+pub async fn find_revision(
+    conn: &mut PgConnection,
+    entity: &str,
+    entity_id: i32,
+    revision_id: i64,
+) -> Result<Option<Revision>, sqlx::Error> {
+    sqlx::query_as(
+        r#"SELECT r.*, COALESCE(u.name, k.name) AS actor_name
+        FROM catalog_revisions r
+        LEFT JOIN users u ON r.actor = 'user:' || u.id
+        LEFT JOIN api_keys k ON r.actor = 'api_key:' || k.id
+        WHERE r.id = $1 AND r.entity = $2 AND r.entity_id = $3"#,
+    )
+    .bind(revision_id)
+    .bind(entity)
+    .bind(entity_id)
+    .fetch_optional(conn)
+    .await
+}
+
+/// `current` with every field the revision changed set back to its value
+/// before the revision, so later changes to other fields survive. `None` for
+/// a creation, which has nothing to go back to.
+pub fn reverted(revision: &Revision, mut current: Value) -> Option<Value> {
+    let before = revision.before.as_ref()?;
+    let changed = changes(Some(before), revision.after.as_ref());
+    if let Some(current) = current.as_object_mut() {
+        for field in changed.keys() {
+            current.insert(field.clone(), before.get(field).cloned().unwrap_or(Value::Null));
+        }
+    }
+    Some(current)
+}
+
+/// The response body for a page of revisions.
+pub fn revision_page(revisions: &[Revision]) -> Value {
+    json!({
+        "status": "success",
+        "results": revisions.len(),
+        "next": revisions.last().map(|revision| revision.id),
+        "data": revisions.iter().map(Revision::to_response).collect::<Vec<_>>(),
+    })
+}
+
+/// `{field: {"from": ..., "to": ...}}` for every field that differs. The
+/// version always moves, so it is left out.
+fn changes(before: Option<&Value>, after: Option<&Value>) -> Map<String, Value> {
+    let empty = Map::new();
+    let before = before.and_then(Value::as_object).unwrap_or(&empty);
+    let after = after.and_then(Value::as_object).unwrap_or(&empty);
+
+    let mut changes = Map::new();
+    for field in before.keys().chain(after.keys()) {
+        if field == "version" || changes.contains_key(field) {
+            continue;
+        }
+        let from = before.get(field).unwrap_or(&Value::Null);
+        let to = after.get(field).unwrap_or(&Value::Null);
+        if from != to {
+            changes.insert(field.clone(), json!({"from": from, "to": to}));
+        }
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn revision(before: Option<Value>, after: Option<Value>) -> Revision {
+        Revision {
+            id: 1,
+            entity: "product".to_string(),
+            entity_id: 1,
+            action: "updated".to_string(),
+            actor: None,
+            actor_name: None,
+            reason: None,
+            before,
+            after,
+            changed_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn lists_changed_fields_without_the_version() {
+        let changes = changes(
+            Some(&json!({"name": "Mug", "price": 5.0, "sku": "MUG-1", "version": 1})),
+            Some(&json!({"name": "Mug", "price": 6.0, "slug": "mug", "version": 2})),
+        );
+
+        assert_eq!(
+            Value::Object(changes),
+            json!({
+                "price": {"from": 5.0, "to": 6.0},
+                "sku": {"from": "MUG-1", "to": null},
+                "slug": {"from": null, "to": "mug"},
+            })
+        );
+    }
+
+    #[test]
+    fn a_creation_or_deletion_changes_every_field() {
+        let row = json!({"name": "Mug", "version": 1});
+
+        assert_eq!(changes(None, Some(&row)).len(), 1);
+        assert_eq!(changes(Some(&row), None)["name"], json!({"from": "Mug", "to": null}));
+    }
+
+    #[test]
+    fn reverts_only_the_fields_the_revision_changed() {
+        let revision = revision(
+            Some(json!({"name": "Mug", "price": 5.0, "version": 1})),
+            Some(json!({"name": "Mug", "price": 6.0, "version": 2})),
+        );
+        let current = json!({"name": "Big mug", "price": 6.0, "version": 3});
+
+        assert_eq!(
+            reverted(&revision, current),
+            Some(json!({"name": "Big mug", "price": 5.0, "version": 3}))
+        );
+    }
+
+    #[test]
+    fn reverting_clears_fields_the_revision_added() {
+        let revision = revision(
+            Some(json!({"name": "Mug"})),
+            Some(json!({"name": "Mug", "sku": "MUG-1"})),
+        );
+
+        assert_eq!(
+            reverted(&revision, json!({"name": "Mug", "sku": "MUG-1"})),
+            Some(json!({"name": "Mug", "sku": null}))
+        );
+    }
+
+    #[test]
+    fn a_creation_cannot_be_reverted() {
+        let revision = revision(None, Some(json!({"name": "Mug"})));
+
+        assert_eq!(reverted(&revision, json!({"name": "Mug"})), None);
+    }
+}
@@ -0,0 +1,129 @@
+use axum::{
+    http::{header, StatusCode, Uri},
+    response::{IntoResponse, Response},
+};
+use sqlx::PgPool;
+
+pub const SLUG_RULES: &str =
+    "slug must be 1 to 255 lowercase letters and digits, separated by single `-`";
+
+pub fn valid_slug(slug: &str) -> bool {
+    !slug.is_empty()
+        && slug.len() <= 255
+        && slug
+            .split('-')
+            .all(|part| !part.is_empty() && part.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit()))
+}
+
+/// Trims the optional slug and search-engine fields of a write. An empty meta
+/// field clears the stored one.
+pub fn validate_seo(
+    slug: &mut Option<String>,
+    meta_title: &mut Option<String>,
+    meta_description: &mut Option<String>,
+) -> Result<(), String> {
+    if let Some(value) = slug {
+        *value = value.trim().to_string();
+        if !valid_slug(value) {
+            return Err(SLUG_RULES.to_string());
+        }
+    }
+    if let Some(value) = meta_title {
+        *value = value.trim().to_string();
+        if value.chars().count() > 255 {
+            return Err("meta_title must be at most 255 characters".to_string());
+        }
+    }
+    if let Some(value) = meta_description {
+        *value = value.trim().to_string();
+        if value.chars().count() > 1000 {
+            return Err("meta_description must be at most 1000 characters".to_string());
+        }
+    }
+    Ok(())
+}
+
+/// The current slug of the item that used to be at `slug`, if that item is
+/// still shown under `condition`.
+pub async fn redirect_target(
+    db: &PgPool,
+    entity: &str,
+    table: &str,
+    condition: &str,
+    slug: &str,
+) -> Result<Option<String>, sqlx::Error> {
+    sqlx::query_scalar(&format!(
+        r#"SELECT t.slug FROM slug_redirects r
+        JOIN {} t ON t.id = r.entity_id
+        WHERE r.entity = $1 AND r.old_slug = $2 AND {}"#,
+        table, condition
+    ))
+    .bind(entity)
+    .bind(slug)
+    .fetch_optional(db)
+    .await
+}
+
+/// A 301 to the same address with its last segment, the old slug, replaced.
+pub fn moved_permanently(uri: &Uri, slug: &str) -> Response {
+    let base = uri.path().rsplit_once('/').map_or("", |(base, _)| base);
+    let location = format!("{}/{}", base, slug);
+    (StatusCode::MOVED_PERMANENTLY, [(header::LOCATION, location)]).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::ScratchDb;
+
+    use super::*;
+
+    /// Version of the migration that added slugs.
+    const SLUGS_MIGRATION: i64 = 20240311000000;
+
+    #[test]
+    fn accepts_only_dashed_lowercase_slugs() {
+        assert!(valid_slug("winter-jacket-2"));
+        for slug in ["", "Winter", "winter--jacket", "-winter", "winter-", "winter jacket", "wintér"] {
+            assert!(!valid_slug(slug), "{:?}", slug);
+        }
+        assert!(!valid_slug(&"a".repeat(256)));
+    }
+
+    #[tokio::test]
+    async fn backfills_distinct_slugs_for_names_that_slugify_alike() {
+        let Some(db) = ScratchDb::create().await else {
+            return;
+        };
+        db.migrate_before(SLUGS_MIGRATION).await;
+        for name in ["Foo", "foo!", "Foo 2", "Foo-2", "!!!"] {
+            sqlx::query("INSERT INTO categories (name) VALUES ($1)")
+                .bind(name)
+                .execute(&db.pool)
+                .await
+                .unwrap();
+            sqlx::query(
+                "INSERT INTO products (name, description, price, category_name) VALUES ($1, '', 1, 'Foo')",
+            )
+            .bind(name)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        }
+
+        db.migrate().await.unwrap();
+
+        for table in ["categories", "products"] {
+            let slugs: Vec<String> = sqlx::query_scalar(&format!("SELECT slug FROM {} ORDER BY id", table))
+                .fetch_all(&db.pool)
+                .await
+                .unwrap();
+            assert_eq!(slugs, ["foo", "foo-2", "foo-2-2", "foo-2-3", "item"], "{}", table);
+        }
+        let redirects: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM slug_redirects")
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(redirects, 0, "backfilled slugs replace nothing");
+        db.drop().await;
+    }
+}
@@ -1,10 +1,13 @@
 use std::sync::Arc;
 
 use axum::{
+    http::{Method, Request, StatusCode},
+    middleware::{self, Next},
+    response::Response,
     routing::get,
-    Router, middleware
+    Extension, Json, Router,
 };
-use crate::apis::{v1::{products::{products_routes, products_handler}, category::{category_routes, category_handler}}, jwt_auth::auth};
+use crate::apis::{v1::{products::{products_routes, products_handler}, category::{category_routes, category_handler}}, jwt_auth::{authenticate, Principal}, login::response::ErrorResponse};
 use crate::AppState;
 
 pub fn v1_routes(app_state: Arc<AppState>) -> Router {
@@ -14,5 +17,24 @@ pub fn v1_routes(app_state: Arc<AppState>) -> Router {
         .with_state(app_state.clone())
         .nest("/products", products_routes::products_router(app_state.clone()))
         .nest("/categories", category_routes::category_router(app_state.clone()))
-        .route_layer(middleware::from_fn_with_state(app_state.clone(), auth))
+        .route_layer(middleware::from_fn(catalog_scope))
+        .route_layer(middleware::from_fn_with_state(app_state.clone(), authenticate))
+}
+
+/// Reads need `catalog:read`, everything else `catalog:write`.
+pub async fn catalog_scope<B>(
+    Extension(principal): Extension<Principal>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    let scope = if req.method() == Method::GET { "catalog:read" } else { "catalog:write" };
+    if !principal.has_scope(scope) {
+        tracing::info!(%principal, scope, "missing scope");
+        let json_error = ErrorResponse {
+            status: "fail",
+            message: format!("This API key lacks the `{}` scope", scope),
+        };
+        return Err((StatusCode::FORBIDDEN, Json(json_error)));
+    }
+    Ok(next.run(req).await)
 }
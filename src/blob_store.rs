@@ -0,0 +1,318 @@
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use axum::{
+    async_trait,
+    body::Bytes,
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+
+use futures_util::{stream::BoxStream, StreamExt};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use crate::apis::config::Config;
+use crate::apis::login::response::ErrorResponse;
+use crate::routes::API_PREFIX;
+use crate::AppState;
+
+mod s3;
+
+pub use s3::S3BlobStore;
+
+/// A blob's contents, a chunk at a time.
+pub type ByteStream<'a> = BoxStream<'a, std::io::Result<Bytes>>;
+
+/// Size of the chunks [`LocalBlobStore`] reads.
+const READ_CHUNK: usize = 64 * 1024;
+
+/// Where uploaded files live. Keys are `/`-separated relative paths such as
+/// `avatars/12/<uuid>.png`; the extension determines the served content type.
+#[async_trait]
+pub trait BlobStore: Send + Sync {
+    async fn put(&self, key: &str, bytes: Bytes) -> std::io::Result<()>;
+
+    /// `Ok(None)` when nothing is stored under `key`.
+    async fn get(&self, key: &str) -> std::io::Result<Option<Bytes>>;
+
+    /// Like [`BlobStore::put`], for blobs too large to hold in memory. Nothing is
+    /// stored under `key` if the stream fails.
+    async fn put_stream(&self, key: &str, stream: ByteStream<'_>) -> std::io::Result<()>;
+
+    /// Like [`BlobStore::get`], for blobs too large to hold in memory.
+    async fn get_stream(&self, key: &str) -> std::io::Result<Option<ByteStream<'static>>>;
+
+    /// Deleting a missing blob is not an error.
+    async fn delete(&self, key: &str) -> std::io::Result<()>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlobStoreKind {
+    Local,
+    S3,
+}
+
+impl FromStr for BlobStoreKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "local" => Ok(BlobStoreKind::Local),
+            "s3" => Ok(BlobStoreKind::S3),
+            _ => Err("expected `local` or `s3`".to_string()),
+        }
+    }
+}
+
+pub fn from_config(config: &Config) -> Arc<dyn BlobStore> {
+    match config.blob_store_kind {
+        BlobStoreKind::Local => Arc::new(LocalBlobStore {
+            root: config.blob_store_path.clone(),
+        }),
+        BlobStoreKind::S3 => Arc::new(S3BlobStore::new(
+            config
+                .blob_store_s3
+                .clone()
+                .expect("the s3 settings are required when blob_store_kind is s3"),
+        )),
+    }
+}
+
+/// Files under a directory on the local disk. Only suitable for a single
+/// instance, or for a directory shared between instances.
+pub struct LocalBlobStore {
+    root: PathBuf,
+}
+
+impl LocalBlobStore {
+    fn path(&self, key: &str) -> std::io::Result<PathBuf> {
+        if !is_valid_key(key) {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid blob key `{}`", key),
+            ));
+        }
+        Ok(self.root.join(key))
+    }
+}
+
+#[async_trait]
+impl BlobStore for LocalBlobStore {
+    async fn put(&self, key: &str, bytes: Bytes) -> std::io::Result<()> {
+        let path = self.path(key)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        // Write next to the target and rename, so readers never see half a file.
+        let partial = path.with_extension("partial");
+        tokio::fs::write(&partial, &bytes).await?;
+        tokio::fs::rename(&partial, &path).await
+    }
+
+    async fn get(&self, key: &str) -> std::io::Result<Option<Bytes>> {
+        match tokio::fs::read(self.path(key)?).await {
+            Ok(contents) => Ok(Some(Bytes::from(contents))),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn put_stream(&self, key: &str, mut stream: ByteStream<'_>) -> std::io::Result<()> {
+        let path = self.path(key)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let partial = path.with_extension("partial");
+        let written = async {
+            let mut file = tokio::fs::File::create(&partial).await?;
+            while let Some(chunk) = stream.next().await {
+                file.write_all(&chunk?).await?;
+            }
+            file.flush().await
+        }
+        .await;
+        if let Err(e) = written {
+            let _ = tokio::fs::remove_file(&partial).await;
+            return Err(e);
+        }
+        tokio::fs::rename(&partial, &path).await
+    }
+
+    async fn get_stream(&self, key: &str) -> std::io::Result<Option<ByteStream<'static>>> {
+        let mut file = match tokio::fs::File::open(self.path(key)?).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let stream = async_stream::try_stream! {
+            let mut buffer = vec![0; READ_CHUNK];
+            loop {
+                let read = file.read(&mut buffer).await?;
+                if read == 0 {
+                    break;
+                }
+                yield Bytes::copy_from_slice(&buffer[..read]);
+            }
+        };
+        Ok(Some(stream.boxed()))
+    }
+
+    async fn delete(&self, key: &str) -> std::io::Result<()> {
+        match tokio::fs::remove_file(self.path(key)?).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Keeps keys from escaping the store, e.g. through `..` or absolute paths.
+fn is_valid_key(key: &str) -> bool {
+    !key.is_empty()
+        && key.split('/').all(|segment| {
+            !segment.is_empty()
+                && !segment.starts_with('.')
+                && segment
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        })
+}
+
+/// Blobs under this prefix are never served by [`media_handler`]; the API
+/// hands them out itself, after checking the caller may have them.
+pub const PRIVATE_PREFIX: &str = "private/";
+
+/// Public URL of a stored blob.
+pub fn url(key: &str) -> String {
+    format!("{}/media/{}", API_PREFIX, key)
+}
+
+/// Serves stored blobs. Keys are never reused, so responses can be cached forever.
+pub async fn media_handler(
+    State(data): State<Arc<AppState>>,
+    Path(key): Path<String>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ErrorResponse>)> {
+    let not_found = || {
+        let error_response = ErrorResponse {
+            status: "fail",
+            message: "File not found".to_string(),
+        };
+        (StatusCode::NOT_FOUND, Json(error_response))
+    };
+
+    if !is_valid_key(&key) || key.starts_with(PRIVATE_PREFIX) {
+        return Err(not_found());
+    }
+    let bytes = data
+        .blobs
+        .get(&key)
+        .await
+        .map_err(|e| {
+            tracing::error!(error = %e, key, "could not read blob");
+            let error_response = ErrorResponse {
+                status: "error",
+                message: "Could not read file".to_string(),
+            };
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+        })?
+        .ok_or_else(not_found)?;
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type(&key)),
+            (header::CACHE_CONTROL, "public, max-age=31536000, immutable"),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff"),
+        ],
+        bytes,
+    ))
+}
+
+/// Content type of a stored blob, judged by its key's extension.
+pub fn content_type(key: &str) -> &'static str {
+    match key.rsplit_once('.').map(|(_, ext)| ext) {
+        Some("png") => "image/png",
+        Some("jpg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        _ => "application/octet-stream",
+    }
+}
+
+/// File extension for the image formats we accept, judged by the file's
+/// contents rather than what the client claims.
+pub fn image_extension(bytes: &[u8]) -> Option<&'static str> {
+    match bytes {
+        [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, ..] => Some("png"),
+        [0xFF, 0xD8, 0xFF, ..] => Some("jpg"),
+        [b'G', b'I', b'F', b'8', b'7' | b'9', b'a', ..] => Some("gif"),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some("webp"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::{stream, TryStreamExt};
+
+    use super::*;
+
+    fn store() -> LocalBlobStore {
+        LocalBlobStore {
+            root: std::env::temp_dir().join(format!("blob-store-{}", uuid::Uuid::new_v4())),
+        }
+    }
+
+    fn chunks(parts: &[&'static [u8]]) -> ByteStream<'static> {
+        let parts: Vec<std::io::Result<Bytes>> =
+            parts.iter().map(|part| Ok(Bytes::from_static(part))).collect();
+        stream::iter(parts).boxed()
+    }
+
+    #[tokio::test]
+    async fn streams_blobs_in_and_out() {
+        let store = store();
+
+        store.put_stream("private/a/file", chunks(&[b"hello ", b"world"])).await.unwrap();
+        let read: Vec<Bytes> = store
+            .get_stream("private/a/file")
+            .await
+            .unwrap()
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+
+        assert_eq!(read.concat(), b"hello world");
+        assert!(store.get_stream("private/a/missing").await.unwrap().is_none());
+        tokio::fs::remove_dir_all(&store.root).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn keeps_nothing_from_a_failed_stream() {
+        let store = store();
+        let failing = stream::iter([
+            Ok(Bytes::from_static(b"partial")),
+            Err(std::io::Error::other("client went away")),
+        ])
+        .boxed();
+
+        assert!(store.put_stream("a/file", failing).await.is_err());
+
+        assert!(store.get("a/file").await.unwrap().is_none());
+        let mut left = tokio::fs::read_dir(store.root.join("a")).await.unwrap();
+        assert!(left.next_entry().await.unwrap().is_none());
+        tokio::fs::remove_dir_all(&store.root).await.unwrap();
+    }
+
+    #[test]
+    fn rejects_keys_outside_the_store() {
+        assert!(is_valid_key("products/1/original.png"));
+        assert!(!is_valid_key("../etc/passwd"));
+        assert!(!is_valid_key("/etc/passwd"));
+        assert!(!is_valid_key("products//1"));
+        assert!(!is_valid_key("products/.hidden"));
+    }
+}
@@ -0,0 +1,457 @@
+use std::io::ErrorKind;
+
+use axum::{async_trait, body::Bytes};
+use chrono::Utc;
+use futures_util::StreamExt;
+use hmac::{Hmac, Mac};
+use reqwest::{Method, StatusCode, Url};
+use sha2::{Digest, Sha256};
+
+use crate::apis::config::S3Config;
+use crate::blob_store::{content_type, is_valid_key, BlobStore, ByteStream};
+
+/// Parts of a multipart upload, except the last, must be at least 5 MiB.
+const PART_SIZE: usize = 8 * 1024 * 1024;
+
+/// Objects in a bucket of an S3-compatible service (AWS, MinIO, R2, ...),
+/// using signature version 4 request signing.
+pub struct S3BlobStore {
+    client: reqwest::Client,
+    config: S3Config,
+}
+
+impl S3BlobStore {
+    pub fn new(config: S3Config) -> Self {
+        S3BlobStore {
+            client: reqwest::Client::new(),
+            config,
+        }
+    }
+
+    /// Path style (`endpoint/bucket/key`) suits local stand-ins; virtual-hosted
+    /// style (`bucket.endpoint/key`) is what AWS expects for new buckets.
+    fn object_url(&self, key: &str) -> std::io::Result<Url> {
+        if !is_valid_key(key) {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid blob key `{}`", key),
+            ));
+        }
+        let mut url = Url::parse(&self.config.endpoint).map_err(std::io::Error::other)?;
+        if self.config.path_style {
+            url.set_path(&format!("/{}/{}", self.config.bucket, key));
+        } else {
+            let host = format!("{}.{}", self.config.bucket, url.host_str().unwrap_or_default());
+            url.set_host(Some(&host)).map_err(std::io::Error::other)?;
+            url.set_path(&format!("/{}", key));
+        }
+        Ok(url)
+    }
+
+    async fn send(
+        &self,
+        method: Method,
+        key: &str,
+        query: &[(&str, &str)],
+        body: Bytes,
+    ) -> std::io::Result<reqwest::Response> {
+        let mut url = self.object_url(key)?;
+        let mut query: Vec<String> = query
+            .iter()
+            .map(|(name, value)| format!("{}={}", uri_encode(name), uri_encode(value)))
+            .collect();
+        query.sort();
+        let query = query.join("&");
+        if !query.is_empty() {
+            url.set_query(Some(&query));
+        }
+        let payload_hash = hex::encode(Sha256::digest(&body));
+        let now = Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let host = match url.port() {
+            Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+            None => url.host_str().unwrap_or_default().to_string(),
+        };
+
+        let canonical_request = format!(
+            "{}\n{}\n{}\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+            method,
+            url.path(),
+            query,
+            host,
+            payload_hash,
+            amz_date,
+            SIGNED_HEADERS,
+            payload_hash
+        );
+        let scope = format!("{}/{}/s3/aws4_request", date, self.config.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+
+        let signing_key = [date.as_str(), &self.config.region, "s3", "aws4_request"]
+            .iter()
+            .fold(
+                format!("AWS4{}", self.config.secret_access_key).into_bytes(),
+                |key, part| hmac(&key, part.as_bytes()),
+            );
+        let signature = hex::encode(hmac(&signing_key, string_to_sign.as_bytes()));
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.config.access_key_id, scope, SIGNED_HEADERS, signature
+        );
+
+        let mut request = self
+            .client
+            .request(method.clone(), url)
+            .header("x-amz-content-sha256", payload_hash)
+            .header("x-amz-date", amz_date)
+            .header("authorization", authorization);
+        // Objects take their type from the request that creates them.
+        if method == Method::PUT || query == "uploads=" {
+            request = request.header("content-type", content_type(key));
+        }
+        request
+            .body(body)
+            .send()
+            .await
+            .map_err(std::io::Error::other)
+    }
+
+    /// Uploads `first` and the rest of `stream` in parts, so no more than one
+    /// part is held in memory.
+    async fn put_multipart(&self, key: &str, first: Bytes, stream: &mut ByteStream<'_>) -> std::io::Result<()> {
+        let response = check(self.send(Method::POST, key, &[("uploads", "")], Bytes::new()).await?).await?;
+        let body = response.text().await.map_err(std::io::Error::other)?;
+        let upload_id = xml_value(&body, "UploadId")
+            .ok_or_else(|| std::io::Error::other("object store did not return an upload id"))?;
+
+        let uploaded = async {
+            let mut etags = Vec::new();
+            let mut part = Some(first);
+            while let Some(bytes) = part {
+                let number = (etags.len() + 1).to_string();
+                let response = self
+                    .send(Method::PUT, key, &[("partNumber", &number), ("uploadId", &upload_id)], bytes)
+                    .await?;
+                let etag = check(response)
+                    .await?
+                    .headers()
+                    .get("etag")
+                    .and_then(|etag| etag.to_str().ok())
+                    .map(str::to_string)
+                    .ok_or_else(|| std::io::Error::other("object store did not return an ETag"))?;
+                etags.push(etag);
+                part = next_part(stream).await?;
+            }
+
+            let parts: String = etags
+                .iter()
+                .enumerate()
+                .map(|(i, etag)| format!("<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>", i + 1, etag))
+                .collect();
+            let complete = format!("<CompleteMultipartUpload>{}</CompleteMultipartUpload>", parts);
+            let response = self
+                .send(Method::POST, key, &[("uploadId", &upload_id)], Bytes::from(complete))
+                .await?;
+            // S3 can report a failed completion in the body of a 200 response.
+            let body = check(response).await?.text().await.map_err(std::io::Error::other)?;
+            if body.contains("<Error>") {
+                return Err(std::io::Error::other(format!("object store could not complete the upload: {}", body)));
+            }
+            Ok(())
+        }
+        .await;
+
+        if uploaded.is_err() {
+            // Otherwise the parts already sent are kept, and billed, indefinitely.
+            if let Err(e) = self
+                .send(Method::DELETE, key, &[("uploadId", &upload_id)], Bytes::new())
+                .await
+            {
+                tracing::warn!(error = %e, key, "could not abort multipart upload");
+            }
+        }
+        uploaded
+    }
+}
+
+/// Reads up to [`PART_SIZE`] bytes; `None` once the stream is done.
+async fn next_part(stream: &mut ByteStream<'_>) -> std::io::Result<Option<Bytes>> {
+    let mut part = Vec::new();
+    while part.len() < PART_SIZE {
+        match stream.next().await {
+            Some(chunk) => part.extend_from_slice(&chunk?),
+            None => break,
+        }
+    }
+    Ok((!part.is_empty()).then(|| Bytes::from(part)))
+}
+
+/// The text of the first `<tag>` element; enough for S3's small responses.
+fn xml_value(xml: &str, tag: &str) -> Option<String> {
+    let start = xml.find(&format!("<{}>", tag))? + tag.len() + 2;
+    let end = start + xml[start..].find(&format!("</{}>", tag))?;
+    Some(xml[start..end].to_string())
+}
+
+/// Percent-encodes everything but unreserved characters, as signing requires.
+fn uri_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+const SIGNED_HEADERS: &str = "host;x-amz-content-sha256;x-amz-date";
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Turns a non-success response into an error carrying the service's message.
+async fn check(response: reqwest::Response) -> std::io::Result<reqwest::Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.text().await.unwrap_or_default();
+    let kind = match status {
+        StatusCode::FORBIDDEN | StatusCode::UNAUTHORIZED => ErrorKind::PermissionDenied,
+        _ => ErrorKind::Other,
+    };
+    Err(std::io::Error::new(kind, format!("object store returned {}: {}", status, body)))
+}
+
+#[async_trait]
+impl BlobStore for S3BlobStore {
+    async fn put(&self, key: &str, bytes: Bytes) -> std::io::Result<()> {
+        check(self.send(Method::PUT, key, &[], bytes).await?).await?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> std::io::Result<Option<Bytes>> {
+        let response = self.send(Method::GET, key, &[], Bytes::new()).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let bytes = check(response)
+            .await?
+            .bytes()
+            .await
+            .map_err(std::io::Error::other)?;
+        Ok(Some(bytes))
+    }
+
+    async fn put_stream(&self, key: &str, mut stream: ByteStream<'_>) -> std::io::Result<()> {
+        match next_part(&mut stream).await? {
+            Some(first) if first.len() >= PART_SIZE => self.put_multipart(key, first, &mut stream).await,
+            first => self.put(key, first.unwrap_or_default()).await,
+        }
+    }
+
+    async fn get_stream(&self, key: &str) -> std::io::Result<Option<ByteStream<'static>>> {
+        let response = self.send(Method::GET, key, &[], Bytes::new()).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let mut response = check(response).await?;
+        let stream = async_stream::try_stream! {
+            while let Some(chunk) = response.chunk().await.map_err(std::io::Error::other)? {
+                yield chunk;
+            }
+        };
+        Ok(Some(stream.boxed()))
+    }
+
+    async fn delete(&self, key: &str) -> std::io::Result<()> {
+        // S3 answers 204 whether or not the object existed; some stand-ins say 404.
+        let response = self.send(Method::DELETE, key, &[], Bytes::new()).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(());
+        }
+        check(response).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap};
+    use std::sync::{Arc, Mutex};
+
+    use axum::{
+        extract::{DefaultBodyLimit, RawQuery, State},
+        http::{HeaderMap, Method, StatusCode, Uri},
+        response::IntoResponse,
+        Router,
+    };
+    use futures_util::{stream, TryStreamExt};
+
+    use super::*;
+    use crate::apis::config::S3Config;
+
+    /// Just enough of S3 to store objects whole or in parts.
+    #[derive(Default)]
+    struct Bucket {
+        objects: HashMap<String, Bytes>,
+        uploads: HashMap<String, BTreeMap<u32, Bytes>>,
+        parts_received: usize,
+    }
+
+    type SharedBucket = Arc<Mutex<Bucket>>;
+
+    async fn s3(
+        State(bucket): State<SharedBucket>,
+        method: Method,
+        uri: Uri,
+        RawQuery(query): RawQuery,
+        body: Bytes,
+    ) -> impl IntoResponse {
+        let mut bucket = bucket.lock().unwrap();
+        let key = uri.path().to_string();
+        let query: HashMap<String, String> = query
+            .unwrap_or_default()
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+                (name.to_string(), value.to_string())
+            })
+            .collect();
+        let mut headers = HeaderMap::new();
+
+        let (status, body) = match (method, query.get("uploadId")) {
+            (Method::POST, None) if query.contains_key("uploads") => {
+                let id = format!("upload-{}", bucket.uploads.len() + 1);
+                bucket.uploads.insert(id.clone(), BTreeMap::new());
+                (StatusCode::OK, format!("<Result><UploadId>{}</UploadId></Result>", id))
+            }
+            (Method::PUT, Some(id)) => {
+                let number: u32 = query["partNumber"].parse().unwrap();
+                bucket.uploads.get_mut(id).unwrap().insert(number, body);
+                bucket.parts_received += 1;
+                headers.insert("etag", format!("\"etag-{}\"", number).parse().unwrap());
+                (StatusCode::OK, String::new())
+            }
+            (Method::POST, Some(id)) => {
+                let parts = bucket.uploads.remove(id).unwrap();
+                assert_eq!(
+                    String::from_utf8_lossy(&body).matches("<Part>").count(),
+                    parts.len()
+                );
+                let object: Vec<u8> = parts.values().flat_map(|part| part.to_vec()).collect();
+                bucket.objects.insert(key, Bytes::from(object));
+                (StatusCode::OK, "<CompleteMultipartUploadResult/>".to_string())
+            }
+            (Method::DELETE, Some(id)) => {
+                bucket.uploads.remove(id);
+                (StatusCode::NO_CONTENT, String::new())
+            }
+            (Method::PUT, None) => {
+                bucket.objects.insert(key, body);
+                (StatusCode::OK, String::new())
+            }
+            (Method::GET, None) => match bucket.objects.get(&key) {
+                Some(object) => return (StatusCode::OK, headers, object.clone()).into_response(),
+                None => (StatusCode::NOT_FOUND, String::new()),
+            },
+            _ => (StatusCode::NOT_IMPLEMENTED, String::new()),
+        };
+        (status, headers, body).into_response()
+    }
+
+    async fn stand_in() -> (S3BlobStore, SharedBucket) {
+        let bucket = SharedBucket::default();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let app = Router::new()
+            .fallback(s3)
+            .layer(DefaultBodyLimit::disable())
+            .with_state(bucket.clone());
+        tokio::spawn(axum::Server::from_tcp(listener).unwrap().serve(app.into_make_service()));
+
+        let store = S3BlobStore::new(S3Config {
+            endpoint,
+            bucket: "test".to_string(),
+            region: "us-east-1".to_string(),
+            access_key_id: "key".to_string(),
+            secret_access_key: "secret".to_string(),
+            path_style: true,
+        });
+        (store, bucket)
+    }
+
+    fn chunked(len: usize) -> (Vec<u8>, ByteStream<'static>) {
+        let data: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+        let chunks: Vec<std::io::Result<Bytes>> = data
+            .chunks(1024 * 1024)
+            .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+            .collect();
+        (data, stream::iter(chunks).boxed())
+    }
+
+    #[tokio::test]
+    async fn uploads_large_streams_in_parts() {
+        let (store, bucket) = stand_in().await;
+        let (data, stream) = chunked(2 * PART_SIZE + 123);
+
+        store.put_stream("private/big", stream).await.unwrap();
+
+        assert_eq!(bucket.lock().unwrap().parts_received, 3);
+        let read: Vec<Bytes> = store
+            .get_stream("private/big")
+            .await
+            .unwrap()
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(read.concat(), data);
+    }
+
+    #[tokio::test]
+    async fn uploads_small_streams_whole() {
+        let (store, bucket) = stand_in().await;
+        let (data, stream) = chunked(1000);
+
+        store.put_stream("private/small", stream).await.unwrap();
+
+        assert_eq!(bucket.lock().unwrap().parts_received, 0);
+        assert_eq!(store.get("private/small").await.unwrap().unwrap(), data);
+        assert!(store.get_stream("private/missing").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn aborts_a_failed_multipart_upload() {
+        let (store, bucket) = stand_in().await;
+        let (_, first) = chunked(PART_SIZE);
+        let failing = first
+            .chain(stream::iter([Err(std::io::Error::other("client went away"))]))
+            .boxed();
+
+        assert!(store.put_stream("private/broken", failing).await.is_err());
+
+        let bucket = bucket.lock().unwrap();
+        assert!(bucket.uploads.is_empty());
+        assert!(bucket.objects.is_empty());
+    }
+
+    #[test]
+    fn encodes_query_values_for_signing() {
+        assert_eq!(uri_encode("a b/c~d"), "a%20b%2Fc~d");
+        assert_eq!(
+            xml_value("<R><UploadId>abc</UploadId></R>", "UploadId").as_deref(),
+            Some("abc")
+        );
+    }
+}
@@ -2,8 +2,10 @@ use axum::{http::StatusCode, response::IntoResponse, Json};
 use serde_json::json;
 
 pub enum CustomError {
-    _BadRequest,
+    BadRequest,
     TaskNotFound,
+    Conflict,
+    PreconditionFailed,
     InternalServerError
 }
 
@@ -14,8 +16,10 @@ impl IntoResponse for CustomError {
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "Internal Server Error",
             ),
-            Self::_BadRequest => (StatusCode::BAD_REQUEST, "Bad Request"),
-            Self::TaskNotFound => (StatusCode::NOT_FOUND, "Information Not Found")
+            Self::BadRequest => (StatusCode::BAD_REQUEST, "Bad Request"),
+            Self::TaskNotFound => (StatusCode::NOT_FOUND, "Information Not Found"),
+            Self::Conflict => (StatusCode::CONFLICT, "Conflict"),
+            Self::PreconditionFailed => (StatusCode::PRECONDITION_FAILED, "Precondition Failed")
         };
         (status, Json(json!({"Error": error_message}))).into_response()
     }
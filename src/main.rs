@@ -1,62 +1,240 @@
 // use axum::handler::get;
-use axum::Router;
+use axum::{middleware, routing::get, Router};
 use anyhow::Context;
-use sqlx::postgres::PgPoolOptions;
-use sqlx::PgPool;
+use sqlx::migrate::Migrator;
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+use sqlx::{ConnectOptions, PgPool};
 
 mod routes;
 mod errors;
 mod apis;
+mod blob_store;
+mod metrics;
+mod shutdown;
+mod telemetry;
+#[cfg(test)]
+mod test_support;
 
 use apis::config::Config;
+use apis::health::handler::EmailProbe;
+use apis::jwt_keys::JwtKeys;
+use apis::login::oidc::OidcClient;
+use apis::login::throttle::LoginThrottle;
+use apis::rate_limit::RateLimiter;
+use blob_store::BlobStore;
+use metrics::Metrics;
+use shutdown::Supervisor;
 
 use std::sync::Arc;
 
 use axum::http::{
-    header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE},
-    HeaderValue, Method,
+    header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE, RETRY_AFTER},
+    HeaderName, Method,
 };
 use dotenv::dotenv;
-use tower_http::cors::CorsLayer;
+use tower_http::{
+    cors::CorsLayer,
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+    trace::TraceLayer,
+};
+
+pub static MIGRATOR: Migrator = sqlx::migrate!();
 
 pub struct AppState {
     db: PgPool,
     config: Config,
+    jwt_keys: JwtKeys,
+    metrics: Metrics,
+    login_throttle: LoginThrottle,
+    oidc: OidcClient,
+    rate_limiter: RateLimiter,
+    blobs: Arc<dyn BlobStore>,
+    email_probe: EmailProbe,
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
 
     dotenv().ok();
-    let config = Config::init();
+    let config = Config::load()?;
+    telemetry::init(&config);
 
     // let env = fs::read_to_string(".env").unwrap();
     // let (_, database_url) = env.split_once("=").unwrap();
 
+    let connect_options = config
+        .database_url
+        .parse::<PgConnectOptions>()
+        .context("Invalid database_url")?
+        .log_statements(log::LevelFilter::Debug)
+        .log_slow_statements(log::LevelFilter::Warn, config.database_slow_statement_threshold);
+
     let pool = PgPoolOptions::new()
-    .max_connections(50)
-    .connect(&config.database_url)
+    .max_connections(config.database_max_connections)
+    .connect_with(connect_options)
     .await
     .context("Could not connect to the database_url")?;
 
+    if config.database_run_migrations {
+        MIGRATOR.run(&pool).await.context("Could not run database migrations")?;
+    }
+    if config.download_secret.is_none() {
+        tracing::warn!("download_secret is not set; download links are disabled");
+    }
+
     let cors = CorsLayer::new()
-        .allow_origin("http://localhost:3000".parse::<HeaderValue>().unwrap())
+        .allow_origin(config.cors_origins.clone())
         .allow_methods([Method::GET, Method::POST, Method::PATCH, Method::DELETE])
         .allow_credentials(true)
-        .allow_headers([AUTHORIZATION, ACCEPT, CONTENT_TYPE]);
-
-    let app = Router::new().nest("/api", routes::create_router(
-        Arc::new(AppState {
-            db: pool.clone(),
-            config: config.clone()
-        })
-    ))
+        .allow_headers([AUTHORIZATION, ACCEPT, CONTENT_TYPE])
+        .expose_headers([
+            HeaderName::from_static(telemetry::REQUEST_ID_HEADER),
+            HeaderName::from_static("ratelimit-limit"),
+            HeaderName::from_static("ratelimit-remaining"),
+            HeaderName::from_static("ratelimit-reset"),
+            RETRY_AFTER,
+        ]);
+
+    let app_state = Arc::new(AppState {
+        db: pool.clone(),
+        config: config.clone(),
+        jwt_keys: JwtKeys::new(&config).context("Could not load the JWT keys")?,
+        metrics: Metrics::new().context("Could not register metrics")?,
+        login_throttle: LoginThrottle::new(&config, pool.clone()),
+        oidc: OidcClient::new(config.oidc.clone()),
+        rate_limiter: RateLimiter::default(),
+        blobs: blob_store::from_config(&config),
+        email_probe: EmailProbe::default(),
+    });
+
+    let app = Router::new()
+    .route("/metrics", get(metrics::metrics_handler))
+    .route("/.well-known/jwks.json", get(apis::jwt_keys::jwks_handler))
+    .with_state(app_state.clone())
+    .nest(routes::API_PREFIX, routes::create_router(app_state.clone()))
+    .layer(middleware::from_fn_with_state(app_state.clone(), metrics::track_http))
+    .layer(
+        TraceLayer::new_for_http()
+            .make_span_with(telemetry::MakeRequestSpan)
+            .on_response(telemetry::RecordResponse),
+    )
+    .layer(PropagateRequestIdLayer::x_request_id())
+    .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
     .layer(cors);
 
-    let addr = "127.0.0.1:8000".parse().unwrap();
-    axum::Server::bind(&addr)
-        .serve(app.into_make_service())
-        .await
-        .unwrap();
+    let mut supervisor = Supervisor::new();
+
+    let login_throttle = app_state.login_throttle.clone();
+    supervisor.spawn("login-throttle-prune", |shutdown| async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(60));
+        loop {
+            tokio::select! {
+                _ = shutdown.clone().recv() => break,
+                _ = interval.tick() => {
+                    if let Err(e) = login_throttle.prune().await {
+                        tracing::warn!(error = %e, "failed to prune login attempts");
+                    }
+                }
+            }
+        }
+    });
+
+    let rate_limiter = app_state.rate_limiter.clone();
+    supervisor.spawn("rate-limit-prune", |shutdown| async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(60));
+        loop {
+            tokio::select! {
+                _ = shutdown.clone().recv() => break,
+                _ = interval.tick() => rate_limiter.prune(),
+            }
+        }
+    });
+
+    let db = pool.clone();
+    let blobs = app_state.blobs.clone();
+    supervisor.spawn("account-purge", |shutdown| async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(60 * 60));
+        loop {
+            tokio::select! {
+                _ = shutdown.clone().recv() => break,
+                _ = interval.tick() => {
+                    match apis::account::handler::purge_due_accounts(&db, blobs.as_ref()).await {
+                        Ok(0) => {}
+                        Ok(purged) => tracing::info!(purged, "anonymized deleted accounts"),
+                        Err(e) => tracing::warn!(error = %e, "failed to purge deleted accounts"),
+                    }
+                }
+            }
+        }
+    });
+
+    let db = pool.clone();
+    let publish_interval = config.product_publish_interval;
+    supervisor.spawn("product-publishing", |shutdown| async move {
+        let mut interval = tokio::time::interval(publish_interval);
+        loop {
+            tokio::select! {
+                _ = shutdown.clone().recv() => break,
+                _ = interval.tick() => {
+                    match apis::v1::products::product_publishing::run_due_transitions(&db).await {
+                        Ok((0, 0)) => {}
+                        Ok((published, unpublished)) => {
+                            tracing::info!(published, unpublished, "applied scheduled product status changes")
+                        }
+                        Err(e) => tracing::warn!(error = %e, "failed to apply scheduled product status changes"),
+                    }
+                }
+            }
+        }
+    });
+
+    let db = pool.clone();
+    let blobs = app_state.blobs.clone();
+    let retention = config.catalog_purge_retention;
+    supervisor.spawn("catalog-purge", |shutdown| async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(60 * 60));
+        loop {
+            tokio::select! {
+                _ = shutdown.clone().recv() => break,
+                _ = interval.tick() => {
+                    match apis::v1::products::product_archive_handler::purge_deleted_products(&db, blobs.as_ref(), retention).await {
+                        Ok(0) => {}
+                        Ok(purged) => tracing::info!(purged, "purged deleted products"),
+                        Err(e) => tracing::warn!(error = %e, "failed to purge deleted products"),
+                    }
+                    // After the products, so categories they emptied can go in the same run.
+                    match apis::v1::category::category_archive_handler::purge_deleted_categories(&db, retention).await {
+                        Ok(0) => {}
+                        Ok(purged) => tracing::info!(purged, "purged deleted categories"),
+                        Err(e) => tracing::warn!(error = %e, "failed to purge deleted categories"),
+                    }
+                }
+            }
+        }
+    });
+
+    tracing::info!(addr = %config.bind_addr, "listening");
+    let server = axum::Server::bind(&config.bind_addr)
+        .serve(app.into_make_service_with_connect_info::<std::net::SocketAddr>())
+        .with_graceful_shutdown(supervisor.signal().recv());
+    tokio::pin!(server);
+
+    // Stop accepting connections on the first signal, then give in-flight
+    // requests `shutdown_timeout` to finish before we stop waiting on them.
+    let drain_deadline = async {
+        shutdown::wait_for_signal().await;
+        supervisor.trigger();
+        tokio::time::sleep(config.shutdown_timeout).await;
+    };
+
+    tokio::select! {
+        result = &mut server => result.context("Server error")?,
+        _ = drain_deadline => tracing::warn!("in-flight requests did not finish in time"),
+    }
+
+    supervisor.shutdown(config.shutdown_timeout).await;
+    pool.close().await;
+    tracing::info!("shutdown complete");
+
     Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,191 @@
+use std::sync::Arc;
+use std::time::Instant;
+
+use axum::{
+    extract::{MatchedPath, State},
+    http::{header, Request, StatusCode},
+    middleware::Next,
+    response::IntoResponse,
+};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+
+use crate::AppState;
+
+/// Prometheus collectors shared by the whole process, exposed on `/metrics`.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_request_duration: HistogramVec,
+    db_pool_connections: IntGauge,
+    db_pool_idle_connections: IntGauge,
+    db_pool_max_connections: IntGauge,
+    emails_sent: IntCounterVec,
+    pub registrations: IntCounter,
+    logins: IntCounterVec,
+}
+
+impl Metrics {
+    pub fn new() -> prometheus::Result<Self> {
+        let registry = Registry::new_custom(Some("shopping".to_string()), None)?;
+
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests handled"),
+            &["method", "route", "status"],
+        )?;
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new("http_request_duration_seconds", "HTTP request latency"),
+            &["method", "route"],
+        )?;
+        let db_pool_connections = IntGauge::new(
+            "db_pool_connections",
+            "Connections currently open in the Postgres pool",
+        )?;
+        let db_pool_idle_connections = IntGauge::new(
+            "db_pool_idle_connections",
+            "Idle connections in the Postgres pool",
+        )?;
+        let db_pool_max_connections = IntGauge::new(
+            "db_pool_max_connections",
+            "Configured maximum size of the Postgres pool",
+        )?;
+        let emails_sent = IntCounterVec::new(
+            Opts::new("emails_sent_total", "Emails handed to the SMTP relay"),
+            &["outcome"],
+        )?;
+        let registrations =
+            IntCounter::new("user_registrations_total", "Accounts registered")?;
+        let logins = IntCounterVec::new(
+            Opts::new("user_logins_total", "Login attempts"),
+            &["outcome"],
+        )?;
+
+        registry.register(Box::new(http_requests.clone()))?;
+        registry.register(Box::new(http_request_duration.clone()))?;
+        registry.register(Box::new(db_pool_connections.clone()))?;
+        registry.register(Box::new(db_pool_idle_connections.clone()))?;
+        registry.register(Box::new(db_pool_max_connections.clone()))?;
+        registry.register(Box::new(emails_sent.clone()))?;
+        registry.register(Box::new(registrations.clone()))?;
+        registry.register(Box::new(logins.clone()))?;
+
+        Ok(Metrics {
+            registry,
+            http_requests,
+            http_request_duration,
+            db_pool_connections,
+            db_pool_idle_connections,
+            db_pool_max_connections,
+            emails_sent,
+            registrations,
+            logins,
+        })
+    }
+
+    pub fn record_email(&self, sent: bool) {
+        let outcome = if sent { "success" } else { "failure" };
+        self.emails_sent.with_label_values(&[outcome]).inc();
+    }
+
+    pub fn record_login(&self, outcome: &str) {
+        self.logins.with_label_values(&[outcome]).inc();
+    }
+}
+
+pub async fn track_http<B>(
+    State(data): State<Arc<AppState>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> impl IntoResponse {
+    let method = req.method().to_string();
+    // Label by route template, not raw path, to keep cardinality bounded.
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_owned())
+        .unwrap_or_else(|| "unmatched".to_string());
+
+    let start = Instant::now();
+    let response = next.run(req).await;
+    let elapsed = start.elapsed().as_secs_f64();
+
+    data.metrics
+        .http_requests
+        .with_label_values(&[&method, &route, response.status().as_str()])
+        .inc();
+    data.metrics
+        .http_request_duration
+        .with_label_values(&[&method, &route])
+        .observe(elapsed);
+
+    response
+}
+
+pub async fn metrics_handler(State(data): State<Arc<AppState>>) -> impl IntoResponse {
+    let metrics = &data.metrics;
+    metrics.db_pool_connections.set(data.db.size() as i64);
+    metrics.db_pool_idle_connections.set(data.db.num_idle() as i64);
+    metrics
+        .db_pool_max_connections
+        .set(data.config.database_max_connections as i64);
+
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+    if let Err(e) = encoder.encode(&metrics.registry.gather(), &mut buffer) {
+        tracing::error!(error = %e, "failed to encode metrics");
+        return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
+    }
+
+    (
+        [(header::CONTENT_TYPE, encoder.format_type().to_string())],
+        buffer,
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{middleware, routing::get, Router};
+
+    use super::*;
+    use crate::test_support::{example_config, serve, ScratchDb};
+
+    #[tokio::test]
+    async fn counts_requests_by_route_template_and_exposes_them() {
+        let Some(db) = ScratchDb::migrated().await else {
+            return;
+        };
+        let data = db.state(example_config());
+        let app = Router::new()
+            .route("/items/:id", get(|| async { "item" }))
+            .route("/metrics", get(metrics_handler))
+            .with_state(data.clone())
+            .layer(middleware::from_fn_with_state(data.clone(), track_http));
+        let url = serve(app).await;
+        data.metrics.record_login("success");
+        data.metrics.record_email(false);
+
+        let client = reqwest::Client::new();
+        for path in ["/items/1", "/items/2", "/nowhere"] {
+            client.get(format!("{}{}", url, path)).send().await.unwrap();
+        }
+        let response = client.get(format!("{}/metrics", url)).send().await.unwrap();
+        assert!(response.headers()["content-type"].to_str().unwrap().starts_with("text/plain"));
+        let text = response.text().await.unwrap();
+
+        for line in [
+            r#"shopping_http_requests_total{method="GET",route="/items/:id",status="200"} 2"#,
+            r#"shopping_http_requests_total{method="GET",route="unmatched",status="404"} 1"#,
+            r#"shopping_http_request_duration_seconds_count{method="GET",route="/items/:id"} 2"#,
+            r#"shopping_user_logins_total{outcome="success"} 1"#,
+            r#"shopping_emails_sent_total{outcome="failure"} 1"#,
+            "shopping_db_pool_max_connections 50",
+        ] {
+            assert!(text.lines().any(|l| l == line), "missing `{}` in\n{}", line, text);
+        }
+        db.drop().await;
+    }
+}
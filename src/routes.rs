@@ -1,16 +1,42 @@
 use std::sync::Arc;
 
-use axum::Router;
+use axum::{middleware, routing::get, Router};
 
 use crate::apis::{
+    account::account_route,
+    addresses::addresses_route,
+    api_keys::api_keys_route,
+    downloads::downloads_route,
+    health::health_route,
     login::login_route,
-    v1::v_route
+    rate_limit::rate_limit,
+    v1::{category::category_admin_routes, products::product_admin_routes, v_route},
 };
 
+use crate::blob_store;
 use crate::AppState;
 
+/// Where `create_router` is mounted in `main`.
+pub const API_PREFIX: &str = "/api";
+
 pub fn create_router(app_state: Arc<AppState>) -> Router {
     Router::new()
         .nest("", login_route::login_router(app_state.clone()))
         .nest("", v_route::v1_routes(app_state.clone()))
+        .nest("", account_route::account_router(app_state.clone()))
+        .nest("", addresses_route::addresses_router(app_state.clone()))
+        .nest("", api_keys_route::api_keys_router(app_state.clone()))
+        .nest("", downloads_route::downloads_router(app_state.clone()))
+        .nest("", product_admin_routes::product_admin_router(app_state.clone()))
+        .nest("", category_admin_routes::category_admin_router(app_state.clone()))
+        .layer(middleware::from_fn_with_state(app_state.clone(), rate_limit))
+        // Added after the rate limit so orchestrator probes are never throttled,
+        // nor pages that show many images at once.
+        .nest("", health_route::health_router(app_state.clone()))
+        .nest(
+            "",
+            Router::new()
+                .route("/media/*key", get(blob_store::media_handler))
+                .with_state(app_state),
+        )
 }
\ No newline at end of file
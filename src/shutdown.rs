@@ -0,0 +1,180 @@
+use std::future::Future;
+use std::time::Duration;
+
+use tokio::sync::watch;
+use tokio::task::{AbortHandle, JoinSet};
+
+/// Cloneable handle that background workers and the HTTP server poll to learn
+/// that the process is shutting down.
+#[derive(Clone)]
+pub struct ShutdownSignal(watch::Receiver<bool>);
+
+impl ShutdownSignal {
+    pub fn is_shutdown(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Resolves once shutdown has been requested.
+    pub async fn recv(mut self) {
+        // An error means the supervisor is gone, which is as good as a shutdown.
+        let _ = self.0.wait_for(|stop| *stop).await;
+    }
+}
+
+/// Owns the long-running background workers so they can be stopped together.
+pub struct Supervisor {
+    stop: watch::Sender<bool>,
+    tasks: JoinSet<()>,
+    workers: Vec<AbortHandle>,
+}
+
+impl Supervisor {
+    pub fn new() -> Self {
+        let (stop, _) = watch::channel(false);
+        Supervisor {
+            stop,
+            tasks: JoinSet::new(),
+            workers: Vec::new(),
+        }
+    }
+
+    pub fn signal(&self) -> ShutdownSignal {
+        ShutdownSignal(self.stop.subscribe())
+    }
+
+    pub fn trigger(&self) {
+        self.stop.send_replace(true);
+    }
+
+    /// Spawns a named worker. The worker gets a [`ShutdownSignal`] and is expected to
+    /// return promptly once it fires; exiting early or panicking is logged.
+    pub fn spawn<F, Fut>(&mut self, name: &'static str, worker: F)
+    where
+        F: FnOnce(ShutdownSignal) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let signal = self.signal();
+        let handle = tokio::spawn(worker(self.signal()));
+        self.workers.push(handle.abort_handle());
+
+        self.tasks.spawn(async move {
+            match handle.await {
+                Ok(()) if signal.is_shutdown() => tracing::info!(worker = name, "worker stopped"),
+                Ok(()) => tracing::error!(worker = name, "worker exited before shutdown"),
+                Err(e) if e.is_cancelled() => tracing::warn!(worker = name, "worker aborted"),
+                Err(e) => tracing::error!(worker = name, error = %e, "worker panicked"),
+            }
+        });
+    }
+
+    /// Signals every worker to stop and waits up to `timeout` for them, aborting stragglers.
+    pub async fn shutdown(mut self, timeout: Duration) {
+        self.trigger();
+
+        let drained = tokio::time::timeout(timeout, async {
+            while self.tasks.join_next().await.is_some() {}
+        })
+        .await;
+
+        if drained.is_err() {
+            tracing::warn!(
+                remaining = self.tasks.len(),
+                "background workers did not stop in time, aborting"
+            );
+            for worker in &self.workers {
+                worker.abort();
+            }
+            while self.tasks.join_next().await.is_some() {}
+        }
+    }
+}
+
+impl Default for Supervisor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Resolves on Ctrl+C (SIGINT) or, on Unix, SIGTERM.
+pub async fn wait_for_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!(error = %e, "failed to listen for Ctrl+C");
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                tracing::error!(error = %e, "failed to listen for SIGTERM");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => tracing::info!("received SIGINT"),
+        _ = terminate => tracing::info!("received SIGTERM"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::Instant;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn stops_workers_that_watch_the_signal() {
+        let mut supervisor = Supervisor::new();
+        let signal = supervisor.signal();
+        let stopped = Arc::new(AtomicBool::new(false));
+        let flag = stopped.clone();
+        supervisor.spawn("watcher", |shutdown| async move {
+            shutdown.recv().await;
+            flag.store(true, Ordering::SeqCst);
+        });
+        assert!(!signal.is_shutdown());
+
+        supervisor.shutdown(Duration::from_secs(5)).await;
+
+        assert!(signal.is_shutdown());
+        assert!(stopped.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn aborts_workers_that_outlive_the_timeout() {
+        let mut supervisor = Supervisor::new();
+        let finished = Arc::new(AtomicBool::new(false));
+        let flag = finished.clone();
+        supervisor.spawn("stubborn", |_| async move {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            flag.store(true, Ordering::SeqCst);
+        });
+
+        let start = Instant::now();
+        supervisor.shutdown(Duration::from_millis(50)).await;
+
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(!finished.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn treats_a_dropped_supervisor_as_shutdown() {
+        let supervisor = Supervisor::new();
+        let signal = supervisor.signal();
+        drop(supervisor);
+
+        tokio::time::timeout(Duration::from_secs(5), signal.recv()).await.unwrap();
+    }
+}
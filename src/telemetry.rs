@@ -0,0 +1,139 @@
+use std::time::Duration;
+
+use axum::{
+    extract::MatchedPath,
+    http::{Request, Response},
+};
+use tower_http::trace::{MakeSpan, OnResponse};
+use tracing::{field::Empty, Span};
+use tracing_subscriber::EnvFilter;
+
+use crate::apis::config::{Config, LogFormat};
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+pub fn init(config: &Config) {
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::new(&config.log_level))
+        .with_target(true);
+
+    match config.log_format {
+        LogFormat::Json => subscriber
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(false)
+            .init(),
+        LogFormat::Pretty => subscriber.init(),
+    }
+}
+
+/// Opens one span per request. `status` and `latency_ms` are filled in by
+/// [`RecordResponse`], `user_id` or `api_key_id` by `jwt_auth` once the caller is known.
+#[derive(Clone, Copy, Debug)]
+pub struct MakeRequestSpan;
+
+impl<B> MakeSpan<B> for MakeRequestSpan {
+    fn make_span(&mut self, request: &Request<B>) -> Span {
+        let route = request
+            .extensions()
+            .get::<MatchedPath>()
+            .map(|path| path.as_str())
+            .unwrap_or_else(|| request.uri().path());
+        let request_id = request
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+
+        tracing::info_span!(
+            "request",
+            method = %request.method(),
+            route = %route,
+            request_id = %request_id,
+            status = Empty,
+            latency_ms = Empty,
+            user_id = Empty,
+            api_key_id = Empty,
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RecordResponse;
+
+impl<B> OnResponse<B> for RecordResponse {
+    fn on_response(self, response: &Response<B>, latency: Duration, span: &Span) {
+        span.record("status", response.status().as_u16());
+        span.record("latency_ms", latency.as_millis() as u64);
+        tracing::info!("request completed");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    use tracing_subscriber::fmt::MakeWriter;
+
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct Logs(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Logs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> MakeWriter<'a> for Logs {
+        type Writer = Logs;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    #[test]
+    fn logs_each_request_with_its_id_route_status_and_latency() {
+        let logs = Logs::default();
+        let subscriber = tracing_subscriber::fmt()
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(false)
+            .with_writer(logs.clone())
+            .finish();
+
+        tracing::subscriber::with_default(subscriber, || {
+            let request = Request::builder()
+                .method("POST")
+                .uri("/api/products?dry_run=true")
+                .header(REQUEST_ID_HEADER, "req-1")
+                .body(())
+                .unwrap();
+            let span = MakeRequestSpan.make_span(&request);
+            let _entered = span.enter();
+            span.record("user_id", 7);
+            let response = Response::builder().status(201).body(()).unwrap();
+            RecordResponse.on_response(&response, Duration::from_millis(12), &span);
+        });
+
+        let logs = logs.0.lock().unwrap();
+        let line: serde_json::Value = serde_json::from_slice(logs.split(|&b| b == b'\n').next().unwrap()).unwrap();
+        assert_eq!(line["message"], "request completed");
+        let span = &line["span"];
+        assert_eq!(span["method"], "POST");
+        assert_eq!(span["route"], "/api/products");
+        assert_eq!(span["request_id"], "req-1");
+        assert_eq!(span["status"], 201);
+        assert_eq!(span["latency_ms"], 12);
+        assert_eq!(span["user_id"], 7);
+    }
+}
@@ -0,0 +1,186 @@
+//! Shared setup for tests: the example configuration, and throwaway databases
+//! for tests that need Postgres. Each database is created on the server
+//! `DATABASE_URL` points at; tests skip themselves when it is not set.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use argon2::password_hash::{rand_core::OsRng, PasswordHasher, SaltString};
+use argon2::Argon2;
+use axum::body::HttpBody;
+use axum::http::StatusCode;
+use axum::response::Response;
+use axum::Router;
+use sha2::{Digest, Sha256};
+use sqlx::migrate::Migrate;
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+use sqlx::{ConnectOptions, Connection, PgConnection, PgPool};
+
+use crate::apis::config::Config;
+use crate::apis::health::handler::EmailProbe;
+use crate::apis::jwt_keys::JwtKeys;
+use crate::apis::login::model::TokenClaims;
+use crate::apis::login::oidc::OidcClient;
+use crate::apis::login::throttle::LoginThrottle;
+use crate::apis::rate_limit::RateLimiter;
+use crate::blob_store;
+use crate::metrics::Metrics;
+use crate::{AppState, MIGRATOR};
+
+/// `config.example.toml` alone, whatever the environment running the tests has set.
+pub fn example_config() -> Config {
+    let args = ["--config".to_string(), "config.example.toml".to_string()];
+    Config::load_from_env(&args, &HashMap::new()).unwrap_or_else(|e| panic!("{}", e))
+}
+
+/// Serves `app` on a free local port; returns its base URL.
+pub async fn serve(app: Router) -> String {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(axum::Server::from_tcp(listener).unwrap().serve(app.into_make_service()));
+    url
+}
+
+/// The status and JSON body of a handler's response.
+pub async fn json_response(response: Response) -> (StatusCode, serde_json::Value) {
+    let status = response.status();
+    let mut body = response.into_body();
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        bytes.extend_from_slice(&chunk.unwrap());
+    }
+    (status, serde_json::from_slice(&bytes).unwrap())
+}
+
+/// A session token for user `id`, as a login would issue.
+pub fn session_token(data: &AppState, id: i32, role: &str) -> String {
+    let iat = chrono::Utc::now().timestamp() as usize;
+    let claims = TokenClaims {
+        sub: id,
+        role: role.to_string(),
+        scope: None,
+        iss: data.jwt_keys.issuer().to_string(),
+        aud: data.jwt_keys.audience().to_string(),
+        exp: iat + 3600,
+        iat,
+    };
+    data.jwt_keys.encode(&claims).unwrap()
+}
+
+pub struct ScratchDb {
+    pub pool: PgPool,
+    server: PgConnectOptions,
+    name: String,
+}
+
+impl ScratchDb {
+    /// An empty database, or `None` when `DATABASE_URL` is not set.
+    pub async fn create() -> Option<ScratchDb> {
+        let Ok(url) = std::env::var("DATABASE_URL") else {
+            eprintln!("DATABASE_URL is not set; skipping a test that needs Postgres");
+            return None;
+        };
+        let server = PgConnectOptions::from_str(&url)
+            .expect("DATABASE_URL is a Postgres URL")
+            .disable_statement_logging();
+        let name = format!("test_{}", uuid::Uuid::new_v4().simple());
+
+        let mut conn = PgConnection::connect_with(&server).await.unwrap();
+        sqlx::query(&format!("CREATE DATABASE {}", name))
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        conn.close().await.unwrap();
+
+        let pool = PgPoolOptions::new()
+            .max_connections(5)
+            .connect_with(server.clone().database(&name))
+            .await
+            .unwrap();
+        Some(ScratchDb { pool, server, name })
+    }
+
+    /// Like [`ScratchDb::create`], with every migration applied.
+    pub async fn migrated() -> Option<ScratchDb> {
+        let db = Self::create().await?;
+        db.migrate().await.unwrap();
+        Some(db)
+    }
+
+    /// The application's state on this database. Blobs go to a fresh
+    /// directory under the system's temporary directory.
+    pub fn state(&self, mut config: Config) -> Arc<AppState> {
+        config.blob_store_path = std::env::temp_dir().join(&self.name);
+        Arc::new(AppState {
+            db: self.pool.clone(),
+            jwt_keys: JwtKeys::new(&config).unwrap(),
+            metrics: Metrics::new().unwrap(),
+            login_throttle: LoginThrottle::new(&config, self.pool.clone()),
+            oidc: OidcClient::new(config.oidc.clone()),
+            rate_limiter: RateLimiter::default(),
+            blobs: blob_store::from_config(&config),
+            email_probe: EmailProbe::default(),
+            config,
+        })
+    }
+
+    /// A verified user with `role` whose password is `password`; returns their id.
+    pub async fn user(&self, email: &str, password: &str, role: &str) -> i32 {
+        let salt = SaltString::generate(&mut OsRng);
+        let hash = Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .unwrap()
+            .to_string();
+        sqlx::query_scalar(
+            "INSERT INTO users (name, email, password, verified, role) VALUES ($1, $1, $2, TRUE, $3) RETURNING id",
+        )
+        .bind(email)
+        .bind(hash)
+        .bind(role)
+        .fetch_one(&self.pool)
+        .await
+        .unwrap()
+    }
+
+    /// An API key with `scopes`; returns its id and the key itself.
+    pub async fn api_key(&self, scopes: &[&str]) -> (i32, String) {
+        let prefix = format!("shp_{}", &uuid::Uuid::new_v4().simple().to_string()[..8]);
+        let key = format!("{}_secret", prefix);
+        let id = sqlx::query_scalar(
+            "INSERT INTO api_keys (name, prefix, key_hash, scopes) VALUES ('test', $1, $2, $3) RETURNING id",
+        )
+        .bind(&prefix)
+        .bind(hex::encode(Sha256::digest(key.as_bytes())))
+        .bind(scopes)
+        .fetch_one(&self.pool)
+        .await
+        .unwrap();
+        (id, key)
+    }
+
+    /// Applies the migrations older than `version`, so a test can set up the
+    /// data a later migration has to cope with.
+    pub async fn migrate_before(&self, version: i64) {
+        let mut conn = self.pool.acquire().await.unwrap();
+        conn.ensure_migrations_table().await.unwrap();
+        for migration in MIGRATOR.iter().filter(|migration| migration.version < version) {
+            conn.apply(migration).await.unwrap();
+        }
+    }
+
+    /// Applies the migrations not applied yet.
+    pub async fn migrate(&self) -> Result<(), sqlx::migrate::MigrateError> {
+        MIGRATOR.run(&self.pool).await
+    }
+
+    pub async fn drop(self) {
+        self.pool.close().await;
+        let _ = std::fs::remove_dir_all(std::env::temp_dir().join(&self.name));
+        let mut conn = PgConnection::connect_with(&self.server).await.unwrap();
+        sqlx::query(&format!("DROP DATABASE {} WITH (FORCE)", self.name))
+            .execute(&mut conn)
+            .await
+            .unwrap();
+    }
+}